@@ -0,0 +1,120 @@
+//! Benchmarks for reading stack traces out of a Ruby process's memory, which is what each sample
+//! spends most of its time on. They read the core dumps that the tests use, so the numbers don't
+//! include the cost of copying memory out of a live process.
+//!
+//! Run them with `cargo bench`, or e.g. `cargo bench -- 3.3.0` for a single Ruby version.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rbspy::bench::{get_stack_trace, FrameCache};
+use rbspy_testdata::*;
+
+struct Dump {
+    version: &'static str,
+    source: CoreDump,
+    current_thread_addr: usize,
+    vm_addr: usize,
+    global_symbols_addr: Option<usize>,
+}
+
+// The addresses are the ones that the tests in src/core/ruby_version.rs use
+fn dumps() -> Vec<Dump> {
+    vec![
+        Dump {
+            version: "1.9.3",
+            source: coredump_1_9_3(),
+            current_thread_addr: 0x823930,
+            vm_addr: 0,
+            global_symbols_addr: None,
+        },
+        Dump {
+            version: "2.1.6",
+            source: coredump_2_1_6(),
+            current_thread_addr: 0x562658abd7f0,
+            vm_addr: 0,
+            global_symbols_addr: None,
+        },
+        Dump {
+            version: "2.4.0",
+            source: coredump_2_4_0(),
+            current_thread_addr: 0x55df44959920,
+            vm_addr: 0,
+            global_symbols_addr: None,
+        },
+        Dump {
+            version: "2.5.0",
+            source: coredump_2_5_0(),
+            current_thread_addr: 0x55dd8c3b7758,
+            vm_addr: 0,
+            global_symbols_addr: None,
+        },
+        Dump {
+            version: "2.7.2",
+            source: coredump_2_7_2(),
+            current_thread_addr: 0x7fdd8d626070,
+            vm_addr: 0,
+            global_symbols_addr: Some(0x7fdd8d60eb80),
+        },
+        Dump {
+            version: "3.0.0",
+            source: coredump_3_0_0(),
+            current_thread_addr: 0,
+            vm_addr: 0x7fdacdab7470,
+            global_symbols_addr: Some(0x7fdacdaa9d80),
+        },
+        Dump {
+            version: "3.1.0",
+            source: coredump_3_1_0(),
+            current_thread_addr: 0,
+            vm_addr: 0x7f0dc0c83c58,
+            global_symbols_addr: Some(0x7f0dc0c75e80),
+        },
+        Dump {
+            version: "3.2.0",
+            source: coredump_3_2_0(),
+            current_thread_addr: 0,
+            vm_addr: 0xffffb8034578,
+            global_symbols_addr: Some(0xffffb8025340),
+        },
+        Dump {
+            version: "3.3.0",
+            source: coredump_3_3_0(),
+            current_thread_addr: 0,
+            vm_addr: 0x7f7ff21f1868,
+            global_symbols_addr: Some(0x7f7ff21e0c60),
+        },
+    ]
+}
+
+fn bench_get_stack_trace(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_stack_trace");
+    for dump in dumps() {
+        let read = |cache: &mut FrameCache| {
+            get_stack_trace(
+                dump.version,
+                dump.current_thread_addr,
+                dump.vm_addr,
+                dump.global_symbols_addr,
+                &dump.source,
+                cache,
+            )
+            .expect("couldn't read stack trace")
+            .expect("no stack trace")
+        };
+
+        // Every frame's name and path are read, like the first time that a stack is sampled
+        group.bench_function(BenchmarkId::new("uncached", dump.version), |b| {
+            b.iter(|| read(&mut FrameCache::new()))
+        });
+
+        // The frames have been seen before, like most samples of a long-running process
+        let mut cache = FrameCache::new();
+        read(&mut cache);
+        group.bench_function(BenchmarkId::new("cached", dump.version), |b| {
+            b.iter(|| read(&mut cache))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_get_stack_trace);
+criterion_main!(benches);
@@ -15,15 +15,43 @@ fn main() {
     let config = RecordConfig {
         format: OutputFormat::flamegraph,
         raw_path: Some(std::path::PathBuf::from("rbspy-raw.txt")),
+        raw_compression: rbspy::RawCompression::default(),
         out_path: Some(out_path.clone()),
+        extra_outputs: vec![],
+        per_thread: false,
         pid: process.id() as rbspy::Pid,
         with_subprocesses: false,
+        subprocess_poll_interval: std::time::Duration::from_secs(1),
         sample_rate: 99,
         maybe_duration: Some(std::time::Duration::from_secs(1)),
+        max_samples: None,
         flame_min_width: 10.0,
+        flame_title: None,
+        flame_subtitle: None,
+        flame_reverse: false,
+        flame_color_by: rbspy::ColorBy::none,
+        summary_format: rbspy::SummaryFormat::text,
         lock_process: true,
+        lock_retries: 0,
         force_version: None,
+        ruby_binary: None,
         on_cpu_only: false,
+        idle_samples: false,
+        force_on_cpu_samples: false,
+        attach_timeout: rbspy::sampler::DEFAULT_ATTACH_TIMEOUT,
+        burst_rate: None,
+        burst_duration: std::time::Duration::from_secs(10),
+        adaptive: false,
+        thread_ids: vec![],
+        all_threads: false,
+        with_rss: false,
+        error_tolerance: rbspy::sampler::ErrorTolerance::default(),
+        filter: rbspy::TraceFilter::default(),
+        live_flamegraph: false,
+        outputter: None,
+        rotate_interval: None,
+        rotate_output: false,
+        defer_symbolization: false,
     };
     let recorder = Recorder::new(config);
     match recorder.record() {
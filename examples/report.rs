@@ -15,7 +15,8 @@ fn main() {
     };
     match rbspy::report(
         rbspy::OutputFormat::flamegraph,
-        &mut sample_trace().as_slice(),
+        &rbspy::ReportOptions::default(),
+        &mut [&mut sample_trace().as_slice()],
         &mut output,
     ) {
         Ok(()) => println!("Report written to {}", output_filename),
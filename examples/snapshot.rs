@@ -11,7 +11,7 @@ fn main() {
         .unwrap();
     let pid = process.id() as rbspy::Pid;
 
-    match snapshot(pid, true, None, false) {
+    match snapshot(pid, true, None, None, false, false) {
         Ok(Some(s)) => println!("{}", s),
         Ok(None) => println!("No stack trace was captured"),
         Err(e) => println!("Failed to get snapshot: {:?}", e),
@@ -0,0 +1,44 @@
+//! Internals that the benchmarks in `benches/` need. This isn't part of rbspy's API and can
+//! change at any time.
+
+use anyhow::{format_err, Result};
+
+use crate::core::process::{Pid, ProcessMemory};
+use crate::core::ruby_version;
+use crate::core::types::StackTrace;
+
+pub use crate::core::types::FrameCache;
+
+/// Reads the stack trace of the thread that's running Ruby code from `source`, like sampling a
+/// process does, with the functions for Ruby `version`. Only the versions that there are test
+/// core dumps for are supported.
+pub fn get_stack_trace<T: ProcessMemory>(
+    version: &str,
+    current_thread_addr: usize,
+    vm_addr: usize,
+    global_symbols_addr: Option<usize>,
+    source: &T,
+    cache: &mut FrameCache,
+) -> Result<Option<StackTrace>> {
+    let get_stack_trace = match version {
+        "1.9.3" => ruby_version::ruby_1_9_3_0::get_stack_trace::<T>,
+        "2.1.6" => ruby_version::ruby_2_1_6::get_stack_trace::<T>,
+        "2.4.0" => ruby_version::ruby_2_4_0::get_stack_trace::<T>,
+        "2.5.0" => ruby_version::ruby_2_5_0::get_stack_trace::<T>,
+        "2.7.2" => ruby_version::ruby_2_7_2::get_stack_trace::<T>,
+        "3.0.0" => ruby_version::ruby_3_0_0::get_stack_trace::<T>,
+        "3.1.0" => ruby_version::ruby_3_1_0::get_stack_trace::<T>,
+        "3.2.0" => ruby_version::ruby_3_2_0::get_stack_trace::<T>,
+        "3.3.0" => ruby_version::ruby_3_3_0::get_stack_trace::<T>,
+        _ => return Err(format_err!("No benchmarks for Ruby {}", version)),
+    };
+    get_stack_trace(
+        current_thread_addr,
+        vm_addr,
+        global_symbols_addr,
+        source,
+        0 as Pid,
+        false,
+        cache,
+    )
+}
@@ -4,7 +4,10 @@ use remoteprocess::{Process, ProcessMemory};
 use semver::Version;
 use spytools::binary_parser::BinaryInfo;
 use spytools::ProcessInfo;
+use std::io::Read;
+use std::path::Path;
 
+use super::coredump::{CoreDump, MappedFile};
 use super::supported_ruby_versions::RubyVersion;
 
 pub struct RubyVM {
@@ -12,6 +15,10 @@ pub struct RubyVM {
     pub current_thread_addr_location: usize,
     pub ruby_vm_addr_location: usize,
     pub global_symbols_addr_location: Option<usize>,
+    /// Whether the process has anonymous executable memory, which is usually code compiled by a
+    /// JIT like YJIT. Frames in JIT-compiled code are still read from the Ruby VM's stack, but
+    /// native tools won't find symbols for them.
+    pub jit_code_detected: bool,
 }
 
 /// Inspect a running Ruby process, finding key memory addresses that are needed for profiling
@@ -19,17 +26,59 @@ pub fn inspect_ruby_process(
     process: &Process,
     process_info: &ProcessInfo,
     force_version: Option<String>,
+    ruby_binary: Option<&Path>,
 ) -> Result<RubyVM> {
+    let pinned_binary = match ruby_binary {
+        Some(path) => Some(find_binary(process_info, path)?),
+        None => None,
+    };
+    let get_symbol = |symbol: &str| -> Option<usize> {
+        match pinned_binary {
+            Some(binary) => binary.symbols.get(symbol).map(|&addr| addr as usize),
+            None => process_info.get_symbol(symbol).map(|&addr| addr as usize),
+        }
+    };
+
+    // Without this check, a process that isn't running Ruby fails later on with an error about a
+    // missing symbol, which doesn't say what's actually wrong
+    if pinned_binary.is_none()
+        && !has_ruby_mapping(&process_info.maps)
+        && get_symbol(&ruby_version_symbol()).is_none()
+    {
+        return Err(format_err!(
+            "Process {} doesn't look like a Ruby process: neither the ruby binary nor libruby is loaded (executable: {}). If Ruby is linked into another binary, pass its path with --ruby-binary",
+            process.pid,
+            process_info.path.display()
+        ));
+    }
+    let ruby_files: Vec<&Path> = match pinned_binary {
+        Some(binary) => vec![binary.filename.as_path()],
+        None => [process_info.binary.as_ref(), process_info.library.as_ref()]
+            .iter()
+            .flatten()
+            .map(|binary| binary.filename.as_path())
+            .collect(),
+    };
+    for path in ruby_files {
+        check_pointer_width(path, binary_pointer_width(path))?;
+    }
+
+    let jit_code_detected = has_anonymous_executable_mapping(&process_info.maps);
+    if jit_code_detected {
+        info!("Found anonymous executable memory, which is probably JIT-compiled code (e.g. YJIT)");
+    }
+
     let version = match force_version {
         Some(ref v) => {
             info!("Assuming Ruby version is {}", v);
-            Version::parse(v)?
+            Version::parse(v).context(format!("Invalid Ruby version {}", v))?
         }
-        None => {
-            let version_addr = process_info
-                .get_symbol(&ruby_version_symbol())
-                .context("Failed to locate Ruby version symbol");
-            if let Err(e) = version_addr {
+        None => match read_ruby_version(process, get_symbol(&ruby_version_symbol())) {
+            Ok(version) => {
+                info!("Found ruby version {}", version);
+                version
+            }
+            Err(e) => {
                 match e.root_cause().downcast_ref::<std::io::Error>() {
                     Some(root_cause)
                         if root_cause.kind() == std::io::ErrorKind::PermissionDenied =>
@@ -38,49 +87,62 @@ pub fn inspect_ruby_process(
                     }
                     _ => {}
                 }
-                return Err(anyhow::format_err!("Couldn't get ruby version: {:?}", e));
-            };
-            let version_addr = version_addr.unwrap();
-            let raw_version: [u8; 15] = process
-                .copy_struct(*version_addr as usize)
-                .context("Failed to read Ruby version symbol")?;
-            let raw_version: Vec<u8> = match raw_version.iter().position(|c| *c == 0) {
-                Some(pos) => raw_version[0..=pos].to_vec(),
-                None => {
-                    return Err(anyhow!(
-                        "Version data doesn't seem to contain a valid string"
-                    ))
+                // Static and stripped builds may not have the symbol, but the version is often
+                // in the name of libruby or the directory Ruby is installed in
+                let ruby_files: Vec<&Path> = match pinned_binary {
+                    Some(binary) => vec![binary.filename.as_path()],
+                    None => process_info
+                        .maps
+                        .iter()
+                        .filter_map(|map| map.filename())
+                        .filter(|path| is_ruby_file(path))
+                        .collect(),
+                };
+                match version_from_ruby_files(&ruby_files) {
+                    Some(version) => {
+                        warn!(
+                            "Couldn't read the Ruby version from the process ({:#}), so assuming it's {} from the names of its files. Use --force-version if that's wrong",
+                            e, version
+                        );
+                        version
+                    }
+                    None => {
+                        return Err(anyhow::format_err!(
+                            "Couldn't get ruby version: {:?}. If you know which version the process is running, pass it with --force-version",
+                            e
+                        ))
+                    }
                 }
-            };
-            let version = std::ffi::CStr::from_bytes_with_nul(&raw_version)?
-                .to_str()
-                .context("Failed to convert ruby version from raw string")?
-                .to_owned();
-            let version = Version::parse(&version)?;
-            info!("Found ruby version {}", version);
-            version
-        }
+            }
+        },
     };
 
-    let ruby_version = crate::core::supported_ruby_versions::get(&version.to_string())?;
-    let ruby_vm_address = match process_info.get_symbol(&ruby_current_vm_symbol(&version)) {
-        Some(addr) => *addr as usize,
+    let ruby_version = crate::core::supported_ruby_versions::get(&version)?;
+    let ruby_vm_address = match get_symbol(&ruby_current_vm_symbol(&version)) {
+        Some(addr) => addr,
         None => return Err(anyhow::format_err!("Couldn't find Ruby VM address")),
     };
-    let current_thread_address =
-        get_current_thread_address(process_info, process, &ruby_version, ruby_vm_address)?;
-    let global_symbols_address = match process_info.get_symbol(&ruby_globals_symbol(&version)) {
-        Some(addr) => Some(*addr as usize),
-        // The global symbols address lookup is allowed to fail (e.g. on older rubies)
-        None => None,
-    };
+    let current_thread_address = get_current_thread_address(
+        process_info,
+        pinned_binary,
+        process,
+        &ruby_version,
+        ruby_vm_address,
+    )?;
+    // The global symbols address lookup is allowed to fail (e.g. on older rubies)
+    let global_symbols_address = get_symbol(&ruby_globals_symbol(&version));
 
     let addresses_status = format!(
         "version: {:x?}\n\
         current thread address: {:#x?}\n\
         VM address: {:#x?}\n\
-        global symbols address: {:#x?}\n",
-        version, &current_thread_address, &ruby_vm_address, global_symbols_address
+        global symbols address: {:#x?}\n\
+        JIT code detected: {}\n",
+        version,
+        &current_thread_address,
+        &ruby_vm_address,
+        global_symbols_address,
+        jit_code_detected
     );
 
     info!("Ruby VM addresses: {}", addresses_status);
@@ -89,25 +151,246 @@ pub fn inspect_ruby_process(
         current_thread_addr_location: current_thread_address,
         ruby_vm_addr_location: ruby_vm_address,
         global_symbols_addr_location: global_symbols_address,
+        jit_code_detected,
     });
 }
 
+// Reads the version string that Ruby keeps at the `ruby_version` symbol
+fn read_ruby_version(process: &Process, version_addr: Option<usize>) -> Result<Version> {
+    let version_addr = version_addr.context("Failed to locate Ruby version symbol")?;
+    let raw_version: [u8; 15] = process
+        .copy_struct(version_addr)
+        .context("Failed to read Ruby version symbol")?;
+    let raw_version: Vec<u8> = match raw_version.iter().position(|c| *c == 0) {
+        Some(pos) => raw_version[0..=pos].to_vec(),
+        None => {
+            return Err(anyhow!(
+                "Version data doesn't seem to contain a valid string"
+            ))
+        }
+    };
+    let version = std::ffi::CStr::from_bytes_with_nul(&raw_version)?
+        .to_str()
+        .context("Failed to convert ruby version from raw string")?
+        .to_owned();
+    Version::parse(&version).context(format!("Failed to parse Ruby version {:?}", version))
+}
+
+// Guesses the Ruby version from the paths of the ruby binary and libruby, e.g.
+// `/home/me/.rbenv/versions/3.3.0/bin/ruby` or `/usr/lib/libruby.so.3.3`. A full version in the
+// path is preferred; otherwise the major and minor version in a file name like `libruby.so.3.3`
+// or `ruby3.0` is used, since Ruby's ABI (and so the VM's layout) doesn't change in patch
+// releases.
+fn version_from_ruby_files(paths: &[&Path]) -> Option<Version> {
+    let full_version = paths
+        .iter()
+        .find_map(|path| crate::core::process_list::version_from_path(path))
+        .and_then(|version| Version::parse(&version).ok());
+    full_version.or_else(|| paths.iter().find_map(|path| minor_version_from_name(path)))
+}
+
+fn minor_version_from_name(path: &Path) -> Option<Version> {
+    let name = path.file_name()?.to_string_lossy();
+    name.split(|c: char| !c.is_ascii_digit() && c != '.')
+        .map(|part| part.trim_matches('.'))
+        .find_map(|part| {
+            let mut numbers = part.split('.').map(|number| number.parse::<u64>());
+            match (numbers.next(), numbers.next()) {
+                (Some(Ok(major)), Some(Ok(minor))) => Some(Version::new(major, minor, 0)),
+                _ => None,
+            }
+        })
+}
+
+// Whether the ruby binary or libruby is mapped into the process, going by the names of its files
+pub(crate) fn has_ruby_mapping(maps: &[MapRange]) -> bool {
+    maps.iter()
+        .filter_map(|map| map.filename())
+        .any(is_ruby_file)
+}
+
+// Matches the ruby binary and libruby on every platform, e.g. `ruby`, `ruby3.3`,
+// `libruby.so.3.3` and `x64-msvcrt-ruby330.dll`
+pub(crate) fn is_ruby_file(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_lowercase().contains("ruby"))
+        .unwrap_or(false)
+}
+
+// Ruby's structs are read with the pointer size that rbspy was built with, so profiling a Ruby
+// that was built for a different one (e.g. 32-bit Ruby on a 64-bit machine) would only give
+// garbage stacks. A binary whose pointer size can't be read is assumed to be fine.
+fn check_pointer_width(path: &Path, width: Option<usize>) -> Result<()> {
+    let rbspy_width = std::mem::size_of::<usize>() * 8;
+    match width {
+        Some(width) if width != rbspy_width => Err(format_err!(
+            "{} is {}-bit Ruby, which {}-bit rbspy can't profile. {}-bit Ruby targets are not supported",
+            path.display(),
+            width,
+            rbspy_width,
+            width
+        )),
+        _ => Ok(()),
+    }
+}
+
+// The pointer size in bits of an ELF or Mach-O binary, from its header
+fn binary_pointer_width(path: &Path) -> Option<usize> {
+    let mut header = [0u8; 5];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .ok()?;
+    pointer_width_from_header(&header)
+}
+
+fn pointer_width_from_header(header: &[u8]) -> Option<usize> {
+    match header {
+        [0x7f, b'E', b'L', b'F', class, ..] => match class {
+            1 => Some(32),
+            2 => Some(64),
+            _ => None,
+        },
+        [0xce, 0xfa, 0xed, 0xfe, ..] => Some(32),
+        [0xcf, 0xfa, 0xed, 0xfe, ..] => Some(64),
+        _ => None,
+    }
+}
+
+// Code generated at runtime lives in executable memory that isn't backed by a file
+fn has_anonymous_executable_mapping(maps: &[MapRange]) -> bool {
+    maps.iter()
+        .any(|map| map.is_exec() && map.filename().is_none())
+}
+
+/// Finds the key memory addresses of the Ruby process that a core dump came from. The symbols are
+/// read from the ruby binary or libruby that the process had loaded, or from `ruby_binary` if it's
+/// given (e.g. because the core dump is being analyzed on a different machine).
+pub fn inspect_core_dump(
+    core: &CoreDump,
+    version: &str,
+    ruby_binary: Option<&Path>,
+) -> Result<RubyVM> {
+    let version = Version::parse(version).context(format!("Invalid Ruby version {}", version))?;
+    let ruby_version = crate::core::supported_ruby_versions::get(&version)?;
+    let mapping = find_core_dump_binary(core.mapped_files(), ruby_binary)?;
+    let binary = ruby_binary.unwrap_or(&mapping.path);
+    check_pointer_width(&mapping.path, Some(core.pointer_width()))?;
+    info!("Reading Ruby symbols from {}", binary.display());
+
+    let version = &ruby_version.semver_version;
+    let vm_symbol = ruby_current_vm_symbol(version);
+    let globals_symbol = ruby_globals_symbol(version);
+    let thread_symbol = ruby_execution_context_symbol(version);
+    let mut symbols = vec![vm_symbol.as_str(), globals_symbol.as_str()];
+    symbols.extend(thread_symbol.as_deref());
+    let addrs = super::coredump::find_symbols(binary, mapping, &symbols)?;
+
+    let ruby_vm_address = addrs[0]
+        .ok_or_else(|| format_err!("Couldn't find Ruby VM address in {}", binary.display()))?;
+    // Ruby 3+ finds the current thread through the VM, so it doesn't need a thread address
+    let current_thread_address = match thread_symbol {
+        Some(symbol) => addrs[2]
+            .ok_or_else(|| format_err!("Couldn't find {} in {}", symbol, binary.display()))?,
+        None => 0,
+    };
+    Ok(RubyVM {
+        ruby_version,
+        current_thread_addr_location: current_thread_address,
+        ruby_vm_addr_location: ruby_vm_address,
+        global_symbols_addr_location: addrs[1],
+        jit_code_detected: false,
+    })
+}
+
+// Picks the mapping of the ruby binary or libruby out of the files mapped into a core dump's
+// process. Only the mapping of the start of each file is considered, since that's where the file
+// was loaded.
+fn find_core_dump_binary<'a>(
+    files: &'a [MappedFile],
+    ruby_binary: Option<&Path>,
+) -> Result<&'a MappedFile> {
+    let loaded: Vec<&MappedFile> = files.iter().filter(|f| f.offset == 0).collect();
+    let file_name = |f: &MappedFile| {
+        f.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let found = match ruby_binary {
+        Some(path) => {
+            let wanted = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned());
+            loaded
+                .iter()
+                .find(|f| f.path == path || Some(file_name(f)) == wanted)
+        }
+        None => loaded
+            .iter()
+            .find(|f| file_name(f).starts_with("libruby"))
+            .or_else(|| loaded.iter().find(|f| file_name(f).starts_with("ruby"))),
+    };
+    found.copied().ok_or_else(|| match ruby_binary {
+        Some(path) => format_err!(
+            "{} isn't mapped into the process that the core dump came from",
+            path.display()
+        ),
+        None => format_err!(
+            "Couldn't find the ruby binary or libruby in the core dump. Try passing its path with --ruby-binary"
+        ),
+    })
+}
+
+/// Finds the binary (the ruby executable or libruby) that the user told us to use with
+/// `--ruby-binary`, so that symbols are only looked up in that file.
+fn find_binary<'a>(process_info: &'a ProcessInfo, path: &Path) -> Result<&'a BinaryInfo> {
+    let wanted = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let candidates = [process_info.binary.as_ref(), process_info.library.as_ref()];
+    for binary in candidates.iter().flatten() {
+        let filename = binary
+            .filename
+            .canonicalize()
+            .unwrap_or_else(|_| binary.filename.clone());
+        if filename == wanted {
+            return Ok(binary);
+        }
+    }
+    let loaded: Vec<String> = candidates
+        .iter()
+        .flatten()
+        .map(|binary| binary.filename.display().to_string())
+        .collect();
+    Err(format_err!(
+        "Ruby binary {} isn't loaded by the process. Found: {}",
+        path.display(),
+        if loaded.is_empty() {
+            "none".to_string()
+        } else {
+            loaded.join(", ")
+        }
+    ))
+}
+
 fn get_current_thread_address(
     process_info: &ProcessInfo,
+    pinned_binary: Option<&BinaryInfo>,
     process: &remoteprocess::Process,
     ruby_version: &RubyVersion,
     vm_address: usize,
 ) -> Result<usize> {
-    if ruby_version.semver_version >= Version::new(3, 0, 0) {
+    let symbol = match ruby_execution_context_symbol(&ruby_version.semver_version) {
+        Some(symbol) => symbol,
         // Current thread is not directly accessible on Ruby 3+, so get it from the VM
-        return (ruby_version.get_execution_context_fn)(0, vm_address, process);
-    }
-
-    let symbol = ruby_execution_context_symbol(&ruby_version.semver_version);
+        None => return (ruby_version.get_execution_context_fn)(0, vm_address, process),
+    };
+    let symbol_addr = match pinned_binary {
+        Some(binary) => binary.symbols.get(&symbol),
+        None => process_info.get_symbol(&symbol),
+    };
 
     // get the address of the current ruby thread from loaded symbols if we can
     // (this tends to be faster than scanning through the bss section)
-    if let Some(&addr) = process_info.get_symbol(&symbol) {
+    if let Some(&addr) = symbol_addr {
         #[cfg(windows)]
         return Ok(addr as usize);
 
@@ -127,6 +410,18 @@ fn get_current_thread_address(
             }
         };
     }
+    if let Some(binary) = pinned_binary {
+        info!(
+            "Failed to get current thread address from symbols, so scanning BSS section from {}",
+            binary.filename.display()
+        );
+        return get_thread_address_from_binary(
+            binary,
+            &process_info.maps,
+            process,
+            ruby_version.is_maybe_thread_fn,
+        );
+    }
     info!("Failed to get current thread address from symbols, so scanning BSS section from main binary");
 
     // Try scanning the BSS section of the binary for things that might be a thread
@@ -156,7 +451,11 @@ fn get_current_thread_address(
             Err(lib_err) => Err(err).unwrap_or(Err(lib_err)),
         }
     } else {
-        err.expect("Both ruby and libruby are invalid.")
+        err.unwrap_or_else(|| {
+            Err(format_err!(
+                "Couldn't find the ruby binary or libruby in the process"
+            ))
+        })
     }
 }
 
@@ -244,12 +543,129 @@ fn ruby_current_vm_symbol(version: &Version) -> String {
     }
 }
 
-fn ruby_execution_context_symbol(version: &Version) -> String {
+// The symbol for the current thread (or execution context), which Ruby 3+ doesn't have
+fn ruby_execution_context_symbol(version: &Version) -> Option<String> {
     if *version >= Version::new(3, 0, 0) {
-        panic!("Current thread is not directly accessible on ruby 3+");
+        None
     } else if *version >= Version::new(2, 5, 0) {
-        "ruby_current_execution_context_ptr".to_string()
+        Some("ruby_current_execution_context_ptr".to_string())
     } else {
-        "ruby_current_thread".to_string()
+        Some("ruby_current_thread".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::address_finder::*;
+
+    #[test]
+    fn test_version_from_ruby_files() {
+        // The files mapped by a process whose libruby doesn't have the `ruby_version` symbol
+        let maps = include_str!("../../ci/testdata/libruby-3.3.maps");
+        let ruby_files: Vec<&Path> = maps
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(5))
+            .map(Path::new)
+            .filter(|path| is_ruby_file(path))
+            .collect();
+        assert_eq!(
+            version_from_ruby_files(&ruby_files),
+            Some(Version::new(3, 3, 0))
+        );
+
+        let version = |paths: &[&str]| {
+            let paths: Vec<&Path> = paths.iter().map(Path::new).collect();
+            version_from_ruby_files(&paths)
+        };
+        assert_eq!(
+            version(&[
+                "/usr/bin/ruby3.0",
+                "/home/me/.rbenv/versions/3.1.4/lib/libruby.so.3.1"
+            ]),
+            Some(Version::new(3, 1, 4))
+        );
+        assert_eq!(
+            version(&["/usr/lib/x86_64-linux-gnu/libruby-2.7.so.2.7"]),
+            Some(Version::new(2, 7, 0))
+        );
+        assert_eq!(version(&["/usr/bin/ruby3.0"]), Some(Version::new(3, 0, 0)));
+        assert_eq!(version(&["/usr/local/bin/ruby"]), None);
+        assert_eq!(
+            version(&["C:\\Ruby32-x64\\bin\\x64-ucrt-ruby320.dll"]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_symbols_for_every_supported_version() {
+        let bindings = include_str!("../../ruby-structs/src/lib.rs");
+        let mut count = 0;
+        for module in bindings
+            .lines()
+            .filter_map(|line| line.strip_prefix("pub mod ruby_"))
+        {
+            let parts: Vec<&str> = module.trim_end_matches(';').split('_').take(3).collect();
+            let version = Version::parse(&parts.join(".")).unwrap();
+            crate::core::supported_ruby_versions::get(&version).unwrap();
+            ruby_current_vm_symbol(&version);
+            ruby_globals_symbol(&version);
+            assert_eq!(
+                ruby_execution_context_symbol(&version).is_none(),
+                version.major >= 3
+            );
+            count += 1;
+        }
+        assert!(count > 100);
+
+        // Versions are compared numerically, not as strings
+        let version = Version::parse("3.10.0").unwrap();
+        assert_eq!(ruby_execution_context_symbol(&version), None);
+        assert_eq!(ruby_current_vm_symbol(&version), "ruby_current_vm_ptr");
+        let version = Version::parse("2.10.0").unwrap();
+        assert_eq!(
+            ruby_execution_context_symbol(&version).as_deref(),
+            Some("ruby_current_execution_context_ptr")
+        );
+    }
+
+    #[test]
+    fn test_pointer_width() {
+        assert_eq!(pointer_width_from_header(b"\x7fELF\x01"), Some(32));
+        assert_eq!(pointer_width_from_header(b"\x7fELF\x02"), Some(64));
+        assert_eq!(
+            pointer_width_from_header(&[0xce, 0xfa, 0xed, 0xfe, 7]),
+            Some(32)
+        );
+        assert_eq!(
+            pointer_width_from_header(&[0xcf, 0xfa, 0xed, 0xfe, 7]),
+            Some(64)
+        );
+        assert_eq!(pointer_width_from_header(b"MZ\x90\x00\x03"), None);
+        assert_eq!(pointer_width_from_header(b"\x7fEL"), None);
+
+        let rbspy_width = std::mem::size_of::<usize>() * 8;
+        let path = Path::new("/usr/lib/libruby.so.3.3");
+        assert!(check_pointer_width(path, Some(rbspy_width)).is_ok());
+        assert!(check_pointer_width(path, None).is_ok());
+        let other_width = if rbspy_width == 64 { 32 } else { 64 };
+        let err = check_pointer_width(path, Some(other_width)).unwrap_err();
+        assert!(format!("{}", err).contains(&format!(
+            "{}-bit Ruby targets are not supported",
+            other_width
+        )));
+    }
+
+    #[test]
+    fn test_is_ruby_file() {
+        assert!(is_ruby_file(Path::new("/usr/bin/ruby")));
+        assert!(is_ruby_file(Path::new(
+            "/home/me/.rbenv/versions/3.3.0/bin/ruby3.3"
+        )));
+        assert!(is_ruby_file(Path::new("/usr/lib/libruby.so.3.3.0")));
+        assert!(is_ruby_file(Path::new("x64-msvcrt-ruby330.dll")));
+        assert!(!is_ruby_file(Path::new("/usr/bin/python3")));
+        assert!(!is_ruby_file(Path::new(
+            "/usr/lib/ruby/3.3.0/x86_64-linux/etc.so"
+        )));
     }
 }
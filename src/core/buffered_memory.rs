@@ -0,0 +1,134 @@
+/// Reading process memory a page at a time, so that small reads that land near each other cost a
+/// single read of the target process.
+///
+/// Taking a sample means copying many small structs out of the Ruby process: the thread, each
+/// control frame's iseq and iseq body, the line number tables, and so on. Ruby allocates a lot of
+/// these out of the same heap pages, so reading whole pages and serving later reads from them
+/// saves a `process_vm_readv` (or platform equivalent) call per struct.
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use crate::core::process::ProcessMemory;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Wraps a source of process memory, keeping the pages it reads. This is meant to live for the
+/// duration of a single sample: pages are never refreshed, so reads through it see memory as it
+/// was when each page was first read.
+pub struct BufferedMemory<'a, T: ProcessMemory> {
+    source: &'a T,
+    pages: RefCell<HashMap<usize, Vec<u8>>>,
+}
+
+impl<'a, T: ProcessMemory> BufferedMemory<'a, T> {
+    pub fn new(source: &'a T) -> Self {
+        Self {
+            source,
+            pages: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: ProcessMemory> ProcessMemory for BufferedMemory<'_, T> {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), remoteprocess::Error> {
+        // Large reads, like the control frame vector, are already a single read
+        if buf.len() > PAGE_SIZE {
+            return self.source.read(addr, buf);
+        }
+        let mut pages = self.pages.borrow_mut();
+        let mut copied = 0;
+        while copied < buf.len() {
+            let start = addr + copied;
+            let page = start & !(PAGE_SIZE - 1);
+            let offset = start - page;
+            let len = (buf.len() - copied).min(PAGE_SIZE - offset);
+            let data = match pages.entry(page) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    let mut data = vec![0; PAGE_SIZE];
+                    if self.source.read(page, &mut data).is_err() {
+                        // Some sources, like core dumps, don't always have whole pages. Fall back
+                        // to reading exactly what was asked for, which also gives the caller the
+                        // original error if that fails too.
+                        return self.source.read(addr, buf);
+                    }
+                    e.insert(data)
+                }
+            };
+            buf[copied..copied + len].copy_from_slice(&data[offset..offset + len]);
+            copied += len;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::buffered_memory::*;
+    use std::cell::Cell;
+
+    const BASE: usize = 0x10000;
+
+    // Memory from BASE to BASE + len, where each byte holds the low bits of its address
+    struct Memory {
+        len: usize,
+        reads: Cell<usize>,
+    }
+
+    impl ProcessMemory for Memory {
+        fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), remoteprocess::Error> {
+            self.reads.set(self.reads.get() + 1);
+            if addr < BASE || addr + buf.len() > BASE + self.len {
+                return Err(remoteprocess::Error::Other(format!(
+                    "bad address {:#x}",
+                    addr
+                )));
+            }
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = (addr + i) as u8;
+            }
+            Ok(())
+        }
+    }
+
+    fn expected(addr: usize, len: usize) -> Vec<u8> {
+        (addr..addr + len).map(|a| a as u8).collect()
+    }
+
+    #[test]
+    fn test_reads_are_served_from_pages() {
+        let memory = Memory {
+            len: 4 * PAGE_SIZE,
+            reads: Cell::new(0),
+        };
+        let buffered = BufferedMemory::new(&memory);
+        for addr in [BASE + 8, BASE + 100, BASE + 1000] {
+            assert_eq!(buffered.copy(addr, 64).unwrap(), expected(addr, 64));
+        }
+        assert_eq!(memory.reads.get(), 1);
+
+        // A read that spans two pages needs the second page
+        let addr = BASE + PAGE_SIZE - 10;
+        assert_eq!(buffered.copy(addr, 20).unwrap(), expected(addr, 20));
+        assert_eq!(memory.reads.get(), 2);
+
+        // Large reads go straight to the source
+        assert_eq!(
+            buffered.copy(BASE, 2 * PAGE_SIZE).unwrap(),
+            expected(BASE, 2 * PAGE_SIZE)
+        );
+        assert_eq!(memory.reads.get(), 3);
+    }
+
+    #[test]
+    fn test_partial_page() {
+        let memory = Memory {
+            len: 100,
+            reads: Cell::new(0),
+        };
+        let buffered = BufferedMemory::new(&memory);
+        assert_eq!(buffered.copy(BASE + 10, 8).unwrap(), expected(BASE + 10, 8));
+        assert!(buffered.copy(BASE + 96, 8).is_err());
+    }
+}
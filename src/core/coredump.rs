@@ -0,0 +1,316 @@
+/// Reading the memory of a crashed or hung process from an ELF core dump
+use anyhow::{format_err, Context, Result};
+use goblin::elf::program_header::{PT_LOAD, PT_NOTE};
+use goblin::elf::Elf;
+use memmap2::Mmap;
+use remoteprocess::ProcessMemory;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::core::types::{FrameCache, StackTrace};
+
+// The note that lists the files that were mapped into the process
+const NT_FILE: u32 = 0x4649_4c45;
+
+/// A memory-mapped core dump. Reading from it works like reading from a live process, for
+/// addresses whose contents were included in the dump.
+pub struct CoreDump {
+    data: Mmap,
+    segments: Vec<Segment>,
+    files: Vec<MappedFile>,
+    pointer_width: usize,
+}
+
+// A range of process memory that was saved in the core dump
+struct Segment {
+    addr: usize,
+    size: usize,
+    offset: usize,
+}
+
+/// A file that was mapped into the process's memory
+#[derive(Clone, Debug, PartialEq)]
+pub struct MappedFile {
+    pub start: usize,
+    pub end: usize,
+    /// Offset in the file of the start of the mapping
+    pub offset: usize,
+    pub path: PathBuf,
+}
+
+impl CoreDump {
+    pub fn open(path: &Path) -> Result<CoreDump> {
+        let file =
+            File::open(path).context(format!("Failed to open core dump {}", path.display()))?;
+        // Safety: the core dump isn't expected to change while we're reading it
+        let data = unsafe { Mmap::map(&file)? };
+        let (segments, files, pointer_width) = {
+            let elf = Elf::parse(&data).context("Failed to parse core dump as an ELF file")?;
+            if elf.header.e_type != goblin::elf::header::ET_CORE {
+                return Err(format_err!("{} isn't a core dump", path.display()));
+            }
+            let segments = elf
+                .program_headers
+                .iter()
+                .filter(|h| h.p_type == PT_LOAD && h.p_filesz > 0)
+                .map(|h| Segment {
+                    addr: h.p_vaddr as usize,
+                    size: h.p_filesz as usize,
+                    offset: h.p_offset as usize,
+                })
+                .collect();
+            let mut files = vec![];
+            if elf.program_headers.iter().any(|h| h.p_type == PT_NOTE) {
+                for note in elf.iter_note_headers(&data).into_iter().flatten() {
+                    let note = note.context("Failed to read core dump notes")?;
+                    if note.n_type == NT_FILE {
+                        files = parse_file_note(note.desc, elf.is_64)?;
+                    }
+                }
+            }
+            (segments, files, if elf.is_64 { 64 } else { 32 })
+        };
+        Ok(CoreDump {
+            data,
+            segments,
+            files,
+            pointer_width,
+        })
+    }
+
+    /// The files that were mapped into the process, e.g. the ruby binary and shared libraries
+    pub fn mapped_files(&self) -> &[MappedFile] {
+        &self.files
+    }
+
+    /// The size in bits of a pointer in the process, e.g. 32 for a 32-bit process
+    pub fn pointer_width(&self) -> usize {
+        self.pointer_width
+    }
+}
+
+impl ProcessMemory for CoreDump {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), remoteprocess::Error> {
+        // The addresses and sizes come from the core file, so they can't be trusted not to overflow
+        let overflow = |addr: usize| {
+            remoteprocess::Error::Other(format!(
+                "Address {:#x} is out of range of the core dump",
+                addr
+            ))
+        };
+        // A read can span more than one segment, so copy a segment at a time
+        let mut copied = 0;
+        while copied < buf.len() {
+            let start = addr.checked_add(copied).ok_or_else(|| overflow(addr))?;
+            let mut found = None;
+            for s in &self.segments {
+                let end = s.addr.checked_add(s.size).ok_or_else(|| overflow(s.addr))?;
+                if s.addr <= start && start < end {
+                    found = Some((s, end));
+                    break;
+                }
+            }
+            let (segment, end) = found.ok_or_else(|| {
+                remoteprocess::Error::Other(format!("Address {:#x} isn't in the core dump", start))
+            })?;
+            let len = (buf.len() - copied).min(end - start);
+            let offset = segment.offset.checked_add(start - segment.addr);
+            let data = offset
+                .and_then(|offset| self.data.get(offset..offset.checked_add(len)?))
+                .ok_or_else(|| {
+                    remoteprocess::Error::Other(format!(
+                        "Address {:#x} is past the end of the core dump, which may be truncated",
+                        start
+                    ))
+                })?;
+            buf[copied..copied + len].copy_from_slice(data);
+            copied += len;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the stack trace of the thread that was running Ruby code when the core dump was taken
+pub fn get_stack_trace(
+    path: &Path,
+    ruby_version: &str,
+    ruby_binary: Option<&Path>,
+) -> Result<Option<StackTrace>> {
+    let core = CoreDump::open(path)?;
+    let vm = super::address_finder::inspect_core_dump(&core, ruby_version, ruby_binary)?;
+    (vm.ruby_version.get_core_dump_stack_trace_fn)(
+        vm.current_thread_addr_location,
+        vm.ruby_vm_addr_location,
+        vm.global_symbols_addr_location,
+        &core,
+        0,
+        false,
+        &mut FrameCache::new(),
+    )
+}
+
+// Parses an NT_FILE note: a count and page size, followed by (start, end, page offset) for each
+// mapping, followed by the mappings' NUL-terminated file names.
+fn parse_file_note(desc: &[u8], is_64: bool) -> Result<Vec<MappedFile>> {
+    let word_size = if is_64 { 8 } else { 4 };
+    let word = |i: usize| -> Result<usize> {
+        let bytes = desc
+            .get(i * word_size..(i + 1) * word_size)
+            .ok_or_else(|| format_err!("NT_FILE note is truncated"))?;
+        Ok(if is_64 {
+            u64::from_ne_bytes(bytes.try_into().unwrap()) as usize
+        } else {
+            u32::from_ne_bytes(bytes.try_into().unwrap()) as usize
+        })
+    };
+    let count = word(0)?;
+    let page_size = word(1)?;
+    // The count comes from the core file, so make sure the note is big enough for that many
+    // mappings before allocating room for them
+    if count > desc.len() / (3 * word_size) {
+        return Err(format_err!("NT_FILE note is truncated"));
+    }
+    let names_start = count
+        .checked_mul(3)
+        .and_then(|words| words.checked_add(2))
+        .and_then(|words| words.checked_mul(word_size))
+        .ok_or_else(|| format_err!("NT_FILE note is truncated"))?;
+    let names = desc
+        .get(names_start..)
+        .ok_or_else(|| format_err!("NT_FILE note is truncated"))?
+        .split(|&b| b == 0);
+    let mut files = Vec::with_capacity(count);
+    for (i, name) in (0..count).zip(names) {
+        files.push(MappedFile {
+            start: word(2 + i * 3)?,
+            end: word(3 + i * 3)?,
+            offset: word(4 + i * 3)?
+                .checked_mul(page_size)
+                .ok_or_else(|| format_err!("NT_FILE note has an invalid file offset"))?,
+            path: PathBuf::from(String::from_utf8_lossy(name).into_owned()),
+        });
+    }
+    Ok(files)
+}
+
+/// Looks up `symbols` in a binary on disk, returning their addresses in the process that the core
+/// dump came from. `mapping` is where the start of the binary was mapped into that process.
+pub fn find_symbols(
+    binary: &Path,
+    mapping: &MappedFile,
+    symbols: &[&str],
+) -> Result<Vec<Option<usize>>> {
+    let data = std::fs::read(binary).context(format!("Failed to read {}", binary.display()))?;
+    let elf = Elf::parse(&data).context(format!("Failed to parse {}", binary.display()))?;
+    // Shared libraries and position-independent executables are linked at address 0 and can be
+    // loaded anywhere, so symbol addresses are relative to where the first segment was loaded
+    let first_segment = elf
+        .program_headers
+        .iter()
+        .filter(|h| h.p_type == PT_LOAD && h.p_offset == 0)
+        .map(|h| h.p_vaddr as usize)
+        .min()
+        .unwrap_or(0);
+    let bias = mapping.start.wrapping_sub(first_segment);
+
+    let mut found = vec![None; symbols.len()];
+    let tables = [(&elf.syms, &elf.strtab), (&elf.dynsyms, &elf.dynstrtab)];
+    for (syms, strtab) in tables {
+        for sym in syms.iter() {
+            if sym.st_value == 0 {
+                continue;
+            }
+            let name = match strtab.get_at(sym.st_name) {
+                Some(name) => name,
+                None => continue,
+            };
+            if let Some(i) = symbols.iter().position(|&s| s == name) {
+                found[i].get_or_insert(bias.wrapping_add(sym.st_value as usize));
+            }
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::coredump::*;
+
+    fn file_note(mappings: &[(u64, u64, u64, &str)]) -> Vec<u8> {
+        let mut desc = vec![];
+        desc.extend_from_slice(&(mappings.len() as u64).to_ne_bytes());
+        desc.extend_from_slice(&4096u64.to_ne_bytes());
+        for (start, end, page_offset, _) in mappings {
+            desc.extend_from_slice(&start.to_ne_bytes());
+            desc.extend_from_slice(&end.to_ne_bytes());
+            desc.extend_from_slice(&page_offset.to_ne_bytes());
+        }
+        for (_, _, _, name) in mappings {
+            desc.extend_from_slice(name.as_bytes());
+            desc.push(0);
+        }
+        desc
+    }
+
+    #[test]
+    fn test_parse_file_note() {
+        let desc = file_note(&[
+            (0x5000, 0x6000, 0, "/usr/bin/ruby"),
+            (0x7000, 0x9000, 2, "/usr/lib/libruby.so.3.3"),
+        ]);
+        assert_eq!(
+            parse_file_note(&desc, true).unwrap(),
+            vec![
+                MappedFile {
+                    start: 0x5000,
+                    end: 0x6000,
+                    offset: 0,
+                    path: PathBuf::from("/usr/bin/ruby"),
+                },
+                MappedFile {
+                    start: 0x7000,
+                    end: 0x9000,
+                    offset: 0x2000,
+                    path: PathBuf::from("/usr/lib/libruby.so.3.3"),
+                },
+            ]
+        );
+        assert!(parse_file_note(&desc[..20], true).is_err());
+
+        // A count that's too big for the note, or that overflows, is rejected before it's used
+        let mut huge = desc.clone();
+        huge[..8].copy_from_slice(&u64::MAX.to_ne_bytes());
+        assert!(parse_file_note(&huge, true).is_err());
+        huge[..8].copy_from_slice(&5u64.to_ne_bytes());
+        assert!(parse_file_note(&huge, true).is_err());
+    }
+
+    #[test]
+    fn test_read_out_of_range() {
+        let mut data = memmap2::MmapMut::map_anon(0x100).unwrap();
+        data[0x10] = 0xab;
+        let core = CoreDump {
+            data: data.make_read_only().unwrap(),
+            segments: vec![
+                Segment {
+                    addr: 0x1000,
+                    size: 0x100,
+                    offset: 0,
+                },
+                Segment {
+                    addr: usize::MAX - 0x10,
+                    size: 0x100,
+                    offset: 0,
+                },
+            ],
+            files: vec![],
+            pointer_width: 64,
+        };
+        let mut buf = [0u8; 1];
+        core.read(0x1010, &mut buf).unwrap();
+        assert_eq!(buf, [0xab]);
+        let mut buf = [0u8; 0x20];
+        assert!(core.read(usize::MAX - 0x8, &mut buf).is_err());
+        assert!(core.read(0x1000, &mut [0u8; 0x200]).is_err());
+    }
+}
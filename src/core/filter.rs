@@ -0,0 +1,629 @@
+/// Rewriting of stack traces before they're aggregated into formatted output
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::core::gems;
+use crate::core::types::{StackFrame, StackTrace};
+
+/// The C functions that `TraceFilter::hide_idle` treats as waiting rather than working, when
+/// they're the innermost frame of a trace. These are where Ruby code usually waits: sleeping
+/// (including `Mutex#sleep` and `ConditionVariable#wait`), waiting for I/O or connections, and
+/// waiting for other threads and processes. Blocking methods that share a name with quick ones,
+/// like `Queue#pop` and `Array#pop`, aren't included, but can be added with
+/// `TraceFilter::idle_functions`.
+pub const IDLE_FUNCTIONS: &[&str] = &[
+    "sleep",
+    "select",
+    "poll",
+    "epoll_wait",
+    "wait",
+    "wait_readable",
+    "wait_writable",
+    "wait_priority",
+    "accept",
+    "join",
+    "waitpid",
+];
+
+/// Options that change which frames appear in formatted output (flamegraphs, summaries, etc).
+/// Raw data files always contain the original, unfiltered traces.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TraceFilter {
+    /// Replaces each run of frames from the same gem with a single frame named after the gem.
+    pub collapse_gems: bool,
+    /// Replaces each run of frames from Ruby's standard library with a single frame.
+    pub collapse_stdlib: bool,
+    /// Additional path prefixes that should be treated as part of the standard library, e.g. for
+    /// rubies installed in unusual locations.
+    pub stdlib_paths: Vec<String>,
+    /// Removes C function frames, so that their time is attributed to the Ruby code that called
+    /// them.
+    pub collapse_c_functions: bool,
+    /// Removes C function frames without attributing their time to anything: traces whose
+    /// innermost frame is a C function are dropped (see `hides`), and C functions further up the
+    /// stack are cut out, so that the Ruby code they called appears to have been called by the
+    /// nearest Ruby frame above them.
+    pub only_ruby: bool,
+    /// If not empty, only frames whose path matches one of these glob patterns are kept. See
+    /// `path_matches` for the pattern syntax.
+    pub include_paths: Vec<String>,
+    /// Frames whose path matches one of these glob patterns are removed, so that the frames they
+    /// called appear to have been called by their caller.
+    pub exclude_paths: Vec<String>,
+    /// If not empty, only frames that match one of these patterns are shown, and each run of
+    /// frames that don't is replaced with a single `<other>` frame. A pattern matches a frame if
+    /// it matches the frame's method name (e.g. `Foo#bar` or `*Controller#*`) or its path (see
+    /// `path_matches`). See `read_focus_file` for reading them from a file.
+    pub focus_patterns: Vec<String>,
+    /// Drops traces that were waiting rather than working: the `(idle)` traces recorded with idle
+    /// samples, and traces whose innermost frame is a C function in `IDLE_FUNCTIONS` or
+    /// `idle_functions`. See `hides`.
+    pub hide_idle: bool,
+    /// More C functions that count as idle for `hide_idle`, by method name (e.g. `pop`).
+    pub idle_functions: Vec<String>,
+    /// Renames the frames of blocks and `rescue`/`ensure` clauses (e.g. `block in foo`) after the
+    /// method they're in, and merges them into that method's frame when it called them directly.
+    pub normalize_frames: bool,
+}
+
+impl TraceFilter {
+    /// Returns the trace as it should be aggregated.
+    pub fn apply(&self, trace: &StackTrace) -> StackTrace {
+        if !self.collapse_gems
+            && !self.collapse_stdlib
+            && !self.collapse_c_functions
+            && !self.only_ruby
+            && self.include_paths.is_empty()
+            && self.exclude_paths.is_empty()
+            && self.focus_patterns.is_empty()
+            && !self.normalize_frames
+        {
+            return trace.clone();
+        }
+        let mut frames: Vec<StackFrame> = Vec::with_capacity(trace.trace.len());
+        // Whether the last frame in `frames` was renamed by `normalize_frames`
+        let mut last_normalized = false;
+        for frame in trace.trace.iter().filter(|f| self.keep(f)) {
+            if !self.focus_patterns.is_empty() && !self.focuses_on(frame) {
+                if frames.last() != Some(&StackFrame::other()) {
+                    frames.push(StackFrame::other());
+                }
+                last_normalized = false;
+                continue;
+            }
+            let method = match self.normalize_frames {
+                true => enclosing_method(&frame.name),
+                false => None,
+            };
+            let frame = match method {
+                Some(name) => self.collapse(&StackFrame {
+                    name: name.to_string(),
+                    ..frame.clone()
+                }),
+                None => self.collapse(frame),
+            };
+            if let Some(last) = frames.last() {
+                if *last == frame && Self::is_collapsed(&frame) {
+                    continue;
+                }
+                // Keep the innermost line, which is the one that was running
+                if (last_normalized || method.is_some())
+                    && last.name == frame.name
+                    && last.relative_path == frame.relative_path
+                {
+                    last_normalized = method.is_some();
+                    continue;
+                }
+            }
+            last_normalized = method.is_some();
+            frames.push(frame);
+        }
+        if frames.is_empty() && !trace.trace.is_empty() {
+            // Keep the sample, so that the share of time spent in the frames that are left is
+            // still accurate
+            frames.push(StackFrame::filtered());
+        }
+        StackTrace {
+            trace: frames,
+            ..trace.clone()
+        }
+    }
+
+    /// Returns whether the trace should be left out of the formatted output entirely. This looks
+    /// at the original trace, before `apply` removes or collapses any of its frames.
+    ///
+    /// Traces' `on_cpu` isn't used, since it's only recorded reliably with `--on-cpu`, `--idle`
+    /// and `--all-threads`.
+    pub fn hides(&self, trace: &StackTrace) -> bool {
+        if self.only_ruby && trace.trace.first().map_or(false, is_c_frame) {
+            return true;
+        }
+        if !self.hide_idle {
+            return false;
+        }
+        match trace.trace.first() {
+            Some(frame) if *frame == StackFrame::idle() => true,
+            Some(frame) => frame
+                .name
+                .strip_suffix(" [c function]")
+                .map_or(false, |name| {
+                    IDLE_FUNCTIONS.contains(&name) || self.idle_functions.iter().any(|f| f == name)
+                }),
+            None => false,
+        }
+    }
+
+    /// Reads focus patterns for `focus_patterns` from a file with one pattern per line. Blank
+    /// lines and lines starting with `#` are ignored.
+    pub fn read_focus_file(path: &Path) -> Result<Vec<String>> {
+        let contents = std::fs::read_to_string(path)
+            .context(format!("Failed to read focus file {}", path.display()))?;
+        Ok(contents
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.to_string())
+            .collect())
+    }
+
+    fn keep(&self, frame: &StackFrame) -> bool {
+        if self.collapse_c_functions && frame.is_c_function() {
+            return false;
+        }
+        if self.only_ruby && is_c_frame(frame) {
+            return false;
+        }
+        (self.include_paths.is_empty() || path_matches_any(&self.include_paths, frame))
+            && !path_matches_any(&self.exclude_paths, frame)
+    }
+
+    fn focuses_on(&self, frame: &StackFrame) -> bool {
+        self.focus_patterns
+            .iter()
+            .any(|pattern| glob_matches(pattern.as_bytes(), frame.name.as_bytes()))
+            || path_matches_any(&self.focus_patterns, frame)
+    }
+
+    fn collapse(&self, frame: &StackFrame) -> StackFrame {
+        if self.collapse_gems {
+            if let Some(gem) = gems::gem_name(frame) {
+                return StackFrame {
+                    name: gem,
+                    relative_path: "(gem)".to_string(),
+                    absolute_path: None,
+                    lineno: None,
+                };
+            }
+        }
+        if self.collapse_stdlib && gems::is_stdlib(frame, &self.stdlib_paths) {
+            return StackFrame {
+                name: "ruby".to_string(),
+                relative_path: "(stdlib)".to_string(),
+                absolute_path: None,
+                lineno: None,
+            };
+        }
+        frame.clone()
+    }
+
+    fn is_collapsed(frame: &StackFrame) -> bool {
+        frame.absolute_path.is_none()
+            && (frame.relative_path == "(gem)" || frame.relative_path == "(stdlib)")
+    }
+}
+
+/// Returns the name of the method that the block, `rescue` clause or `ensure` clause named `name`
+/// is in, e.g. `foo` for `rescue in block (2 levels) in foo`, or `None` for other frames.
+fn enclosing_method(name: &str) -> Option<&str> {
+    let mut method = name;
+    loop {
+        let rest = ["block in ", "rescue in ", "ensure in "]
+            .iter()
+            .find_map(|prefix| method.strip_prefix(prefix))
+            .or_else(|| {
+                let (levels, rest) = method.strip_prefix("block (")?.split_once(" levels) in ")?;
+                levels.bytes().all(|b| b.is_ascii_digit()).then(|| rest)
+            });
+        match rest {
+            Some(rest) => method = rest,
+            None => break,
+        }
+    }
+    (method.len() < name.len()).then(|| method)
+}
+
+fn path_matches_any(patterns: &[String], frame: &StackFrame) -> bool {
+    patterns.iter().any(|pattern| {
+        path_matches(pattern, &frame.relative_path)
+            || frame
+                .absolute_path
+                .as_ref()
+                .map_or(false, |path| path_matches(pattern, path))
+    })
+}
+
+// C functions don't have a file of their own, unlike the Ruby code that `only_ruby` keeps
+fn is_c_frame(frame: &StackFrame) -> bool {
+    frame.absolute_path.is_none() && frame.is_c_function()
+}
+
+/// Returns whether `path`, or one of the directories it's in, matches the glob `pattern`. In
+/// patterns, `*` matches anything except a path separator, `**` matches anything, and `?` matches
+/// any one character except a path separator. So `/app/vendor` and `**/gems/rack-*` both match
+/// `/app/vendor/bundle/ruby/3.3.0/gems/rack-3.0.8/lib/rack.rb`.
+pub fn path_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, path) = (pattern.as_bytes(), path.as_bytes());
+    (0..=path.len())
+        .filter(|&end| end == path.len() || is_separator(path[end]))
+        .any(|end| glob_matches(pattern, &path[..end]))
+}
+
+fn glob_matches(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| glob_matches(rest, &path[i..])),
+        [b'*', rest @ ..] => (0..=path.len())
+            .take_while(|&i| i == 0 || !is_separator(path[i - 1]))
+            .any(|i| glob_matches(rest, &path[i..])),
+        [b'?', rest @ ..] => match path {
+            [c, path @ ..] if !is_separator(*c) => glob_matches(rest, path),
+            _ => false,
+        },
+        [p, rest @ ..] => match path {
+            [c, path @ ..] if c == p || (is_separator(*c) && is_separator(*p)) => {
+                glob_matches(rest, path)
+            }
+            _ => false,
+        },
+    }
+}
+
+fn is_separator(c: u8) -> bool {
+    c == b'/' || c == b'\\'
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::filter::*;
+
+    fn f(name: &str, path: &str) -> StackFrame {
+        StackFrame {
+            name: name.to_string(),
+            relative_path: path.to_string(),
+            absolute_path: Some(path.to_string()),
+            lineno: Some(1),
+        }
+    }
+
+    fn trace(frames: Vec<StackFrame>) -> StackTrace {
+        StackTrace {
+            trace: frames,
+            pid: Some(1),
+            thread_id: Some(1),
+            time: None,
+            on_cpu: None,
+            holds_gvl: None,
+            thread_name: None,
+            rss: None,
+            monotonic_ns: None,
+        }
+    }
+
+    const RACK: &str = "/gems/3.3.0/gems/rack-3.0.8/lib/rack/builder.rb";
+    const RAILS: &str = "/gems/3.3.0/gems/railties-7.1.2/lib/rails.rb";
+    const SET: &str = "/usr/lib/ruby/3.3.0/set.rb";
+
+    fn example() -> StackTrace {
+        trace(vec![
+            f("each", "<internal:array>"),
+            f("merge", SET),
+            f("helper", "/app/lib/helper.rb"),
+            f("call", RACK),
+            f("call", RACK),
+            f("run", RAILS),
+            f("main", "/app/bin/server"),
+        ])
+    }
+
+    #[test]
+    fn test_no_filter() {
+        assert_eq!(TraceFilter::default().apply(&example()), example());
+    }
+
+    #[test]
+    fn test_collapse_gems() {
+        let filter = TraceFilter {
+            collapse_gems: true,
+            ..Default::default()
+        };
+        let names: Vec<String> = filter
+            .apply(&example())
+            .trace
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "each - <internal:array>:1",
+                "merge - /usr/lib/ruby/3.3.0/set.rb:1",
+                "helper - /app/lib/helper.rb:1",
+                "rack - (gem)",
+                "railties - (gem)",
+                "main - /app/bin/server:1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collapse_stdlib() {
+        let filter = TraceFilter {
+            collapse_stdlib: true,
+            ..Default::default()
+        };
+        let names: Vec<String> = filter
+            .apply(&example())
+            .trace
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "ruby - (stdlib)",
+                "helper - /app/lib/helper.rb:1",
+                "call - /gems/3.3.0/gems/rack-3.0.8/lib/rack/builder.rb:1",
+                "call - /gems/3.3.0/gems/rack-3.0.8/lib/rack/builder.rb:1",
+                "run - /gems/3.3.0/gems/railties-7.1.2/lib/rails.rb:1",
+                "main - /app/bin/server:1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collapse_c_functions() {
+        let mut trace = example();
+        trace
+            .trace
+            .insert(0, f("sleep [c function]", "/app/lib/helper.rb"));
+        trace.trace.insert(3, StackFrame::unknown_c_function());
+        let filter = TraceFilter {
+            collapse_c_functions: true,
+            exclude_paths: vec!["**/gems".to_string()],
+            ..Default::default()
+        };
+        let names: Vec<String> = filter
+            .apply(&trace)
+            .trace
+            .iter()
+            .map(|f| f.name.clone())
+            .collect();
+        assert_eq!(names, vec!["each", "merge", "helper", "main"]);
+    }
+
+    #[test]
+    fn test_only_ruby() {
+        let c_function = |name: &str| StackFrame {
+            name: format!("{} [c function]", name),
+            relative_path: "(unknown)".to_string(),
+            absolute_path: None,
+            lineno: None,
+        };
+        let filter = TraceFilter {
+            only_ruby: true,
+            ..Default::default()
+        };
+        let mut trace = example();
+        trace.trace.insert(2, c_function("each"));
+        let names: Vec<String> = filter
+            .apply(&trace)
+            .trace
+            .iter()
+            .map(|f| f.name.clone())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["each", "merge", "helper", "call", "call", "run", "main"]
+        );
+        assert!(!filter.hides(&trace));
+
+        // The time spent in C functions isn't given to their callers
+        let mut in_c_function = example();
+        in_c_function.trace.insert(0, c_function("sleep"));
+        assert!(filter.hides(&in_c_function));
+        assert!(!TraceFilter::default().hides(&in_c_function));
+    }
+
+    #[test]
+    fn test_path_matches() {
+        for (pattern, path, expected) in [
+            ("/app/lib/helper.rb", "/app/lib/helper.rb", true),
+            ("/app/lib", "/app/lib/helper.rb", true),
+            ("/app/li", "/app/lib/helper.rb", false),
+            ("/app/*.rb", "/app/lib/helper.rb", false),
+            ("/app/*/*.rb", "/app/lib/helper.rb", true),
+            ("/app/**.rb", "/app/lib/helper.rb", true),
+            ("**/gems/rack-*", RACK, true),
+            ("**/gems/rails*", RACK, false),
+            ("<internal:*>", "<internal:array>", true),
+            ("/app/lib/helper.r?", "/app/lib/helper.rb", true),
+            ("C:\\app\\*.rb", "C:\\app\\helper.rb", true),
+        ] {
+            assert_eq!(
+                path_matches(pattern, path),
+                expected,
+                "{} {}",
+                pattern,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_exclude_paths() {
+        let filter = TraceFilter {
+            exclude_paths: vec!["**/gems".to_string(), "<internal:*>".to_string()],
+            ..Default::default()
+        };
+        let names: Vec<String> = filter
+            .apply(&example())
+            .trace
+            .iter()
+            .map(|f| f.name.clone())
+            .collect();
+        assert_eq!(names, vec!["merge", "helper", "main"]);
+    }
+
+    #[test]
+    fn test_include_paths() {
+        let filter = TraceFilter {
+            include_paths: vec!["/app".to_string()],
+            ..Default::default()
+        };
+        let names: Vec<String> = filter
+            .apply(&example())
+            .trace
+            .iter()
+            .map(|f| f.name.clone())
+            .collect();
+        assert_eq!(names, vec!["helper", "main"]);
+
+        let filter = TraceFilter {
+            include_paths: vec!["/elsewhere".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(filter.apply(&example()).trace, vec![StackFrame::filtered()]);
+    }
+
+    #[test]
+    fn test_focus_patterns() {
+        let filter = TraceFilter {
+            focus_patterns: vec!["helper".to_string(), "**/gems/rack-*".to_string()],
+            ..Default::default()
+        };
+        let names: Vec<String> = filter
+            .apply(&example())
+            .trace
+            .iter()
+            .map(|f| f.name.clone())
+            .collect();
+        assert_eq!(names, vec!["<other>", "helper", "call", "call", "<other>"]);
+
+        // Patterns match paths as well as method names, and are applied before gems are collapsed
+        let filter = TraceFilter {
+            focus_patterns: vec!["/app".to_string(), "**/gems/rack-*".to_string()],
+            collapse_gems: true,
+            ..Default::default()
+        };
+        let names: Vec<String> = filter
+            .apply(&example())
+            .trace
+            .iter()
+            .map(|f| f.name.clone())
+            .collect();
+        assert_eq!(names, vec!["<other>", "helper", "rack", "<other>", "main"]);
+    }
+
+    #[test]
+    fn test_read_focus_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("focus.txt");
+        std::fs::write(
+            &path,
+            "# The app's own code\n/app/**\n\n  UsersController#*  \n",
+        )
+        .unwrap();
+        assert_eq!(
+            TraceFilter::read_focus_file(&path).unwrap(),
+            vec!["/app/**", "UsersController#*"]
+        );
+        assert!(TraceFilter::read_focus_file(&dir.path().join("missing.txt")).is_err());
+    }
+
+    #[test]
+    fn test_hide_idle() {
+        let with_innermost = |frame: StackFrame| {
+            let mut trace = example();
+            trace.trace.insert(0, frame);
+            trace
+        };
+        let sleeping = with_innermost(f("sleep [c function]", "/app/lib/helper.rb"));
+        let popping = with_innermost(f("pop [c function]", "/app/lib/helper.rb"));
+        let idle = trace(vec![StackFrame::idle()]);
+
+        assert!(!TraceFilter::default().hides(&sleeping));
+        assert!(!TraceFilter::default().hides(&idle));
+
+        let filter = TraceFilter {
+            hide_idle: true,
+            ..Default::default()
+        };
+        assert!(filter.hides(&sleeping));
+        assert!(filter.hides(&idle));
+        assert!(!filter.hides(&popping));
+        assert!(!filter.hides(&example()));
+        // Only the innermost frame counts
+        let mut called_sleep = example();
+        called_sleep
+            .trace
+            .insert(1, f("sleep [c function]", "/app/lib/helper.rb"));
+        assert!(!filter.hides(&called_sleep));
+
+        let filter = TraceFilter {
+            hide_idle: true,
+            idle_functions: vec!["pop".to_string()],
+            ..Default::default()
+        };
+        assert!(filter.hides(&popping));
+    }
+
+    #[test]
+    fn test_enclosing_method() {
+        assert_eq!(enclosing_method("block in foo"), Some("foo"));
+        assert_eq!(
+            enclosing_method("block (2 levels) in <main>"),
+            Some("<main>")
+        );
+        assert_eq!(
+            enclosing_method("rescue in block in Foo#bar"),
+            Some("Foo#bar")
+        );
+        assert_eq!(enclosing_method("ensure in foo"), Some("foo"));
+        assert_eq!(enclosing_method("foo"), None);
+        assert_eq!(enclosing_method("block (many levels) in foo"), None);
+        assert_eq!(enclosing_method("each [c function]"), None);
+    }
+
+    #[test]
+    fn test_normalize_frames() {
+        let line = |name: &str, lineno: usize| StackFrame {
+            lineno: Some(lineno),
+            ..f(name, "/app/lib/helper.rb")
+        };
+        let filter = TraceFilter {
+            normalize_frames: true,
+            ..Default::default()
+        };
+        let normalized = filter.apply(&trace(vec![
+            line("rescue in block (2 levels) in foo", 9),
+            line("block (2 levels) in foo", 7),
+            f("each [c function]", "/app/lib/helper.rb"),
+            line("block in foo", 5),
+            line("foo", 4),
+            line("foo", 2),
+            f("<main>", "/app/bin/server"),
+        ]));
+        assert_eq!(
+            normalized.trace,
+            vec![
+                line("foo", 9),
+                f("each [c function]", "/app/lib/helper.rb"),
+                line("foo", 5),
+                // Recursive calls aren't merged
+                line("foo", 2),
+                f("<main>", "/app/bin/server"),
+            ]
+        );
+        assert_eq!(filter.apply(&example()), example());
+    }
+}
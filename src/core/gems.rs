@@ -0,0 +1,213 @@
+/// Classification of stack frames by the gem or library that their code belongs to
+use crate::core::types::StackFrame;
+
+/// The group for frames that don't belong to any gem.
+pub const APPLICATION: &str = "(application)";
+
+/// Returns the name of the gem that a frame's file belongs to, if any.
+///
+/// Gems are installed in a directory named after the gem and its version, inside a `gems`
+/// directory, e.g. `vendor/bundle/ruby/3.2.0/gems/activerecord-7.1.2/lib/active_record.rb`.
+/// Gems installed from git by bundler live in `bundler/gems/<name>-<revision>` instead.
+pub fn gem_name(frame: &StackFrame) -> Option<String> {
+    let components: Vec<&str> = frame.path().split(|c| c == '/' || c == '\\').collect();
+    // Gem homes can be nested inside a directory called `gems` (e.g.
+    // `/usr/local/lib/ruby/gems/3.2.0/gems/...`), and gems can have `gems` directories of their
+    // own, so use the last `gems` directory that's followed by a gem's directory
+    (0..components.len().saturating_sub(2))
+        .rev()
+        .filter(|&i| components[i] == "gems")
+        .find_map(|i| {
+            let from_git = i > 0 && components[i - 1] == "bundler";
+            strip_version(components[i + 1], from_git)
+        })
+        .map(|name| name.to_string())
+}
+
+/// Returns the group that a frame is attributed to: its gem name, or `APPLICATION`.
+pub fn group_name(frame: &StackFrame) -> String {
+    gem_name(frame).unwrap_or_else(|| APPLICATION.to_string())
+}
+
+/// Returns whether a frame belongs to Ruby's standard library. This covers code that's built
+/// into the interpreter (e.g. `<internal:kernel>`), files in Ruby's `lib/ruby/<version>`
+/// directory, and files under any of `extra_paths`.
+pub fn is_stdlib(frame: &StackFrame, extra_paths: &[String]) -> bool {
+    let path = frame.path();
+    if path.starts_with("<internal:") {
+        return true;
+    }
+    if extra_paths
+        .iter()
+        .any(|prefix| path.starts_with(prefix.as_str()))
+    {
+        return true;
+    }
+    if gem_name(frame).is_some() {
+        return false;
+    }
+    let components: Vec<&str> = path.split(|c| c == '/' || c == '\\').collect();
+    components.windows(3).any(|w| {
+        // Some distributions add an ABI suffix to the version directory, e.g. `3.3.0+0`
+        let version = w[2].split('+').next().unwrap_or_default();
+        w[0] == "lib"
+            && w[1] == "ruby"
+            && version.split('.').count() == 3
+            && version.split('.').all(|n| n.parse::<u32>().is_ok())
+    })
+}
+
+/// Where a frame's code comes from, e.g. for coloring flamegraphs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Origin {
+    Application,
+    Gem(String),
+    Stdlib,
+    CFunction,
+}
+
+/// Classifies a frame by its path. Frames that a `TraceFilter` has collapsed into a single gem or
+/// standard library frame keep their origin.
+pub fn origin(frame: &StackFrame) -> Origin {
+    if frame.is_c_function() {
+        return Origin::CFunction;
+    }
+    match frame.relative_path.as_str() {
+        "(gem)" => return Origin::Gem(frame.name.clone()),
+        "(stdlib)" => return Origin::Stdlib,
+        _ => {}
+    }
+    if let Some(gem) = gem_name(frame) {
+        return Origin::Gem(gem);
+    }
+    if is_stdlib(frame, &[]) {
+        return Origin::Stdlib;
+    }
+    Origin::Application
+}
+
+// Returns the gem name of a gem's directory, or `None` if `dir` doesn't look like one
+fn strip_version(dir: &str, from_git: bool) -> Option<&str> {
+    if from_git {
+        // git checkouts are suffixed with an abbreviated commit hash
+        return match dir.rsplit_once('-') {
+            Some((name, hash))
+                if !name.is_empty()
+                    && !hash.is_empty()
+                    && hash.bytes().all(|b| b.is_ascii_hexdigit()) =>
+            {
+                Some(name)
+            }
+            _ => None,
+        };
+    }
+    // The version is everything after the first dash that's followed by a digit. This handles
+    // dashes in gem names (`net-http-0.4.1`) and platform suffixes (`nokogiri-1.15.4-x86_64-linux`).
+    let bytes = dir.as_bytes();
+    (1..bytes.len().saturating_sub(1))
+        .find(|&i| bytes[i] == b'-' && bytes[i + 1].is_ascii_digit())
+        .map(|i| &dir[..i])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::gems::*;
+
+    fn f(path: &str) -> StackFrame {
+        StackFrame {
+            name: "foo".to_string(),
+            relative_path: path.to_string(),
+            absolute_path: Some(path.to_string()),
+            lineno: Some(1),
+        }
+    }
+
+    #[test]
+    fn test_gem_name() {
+        let cases = vec![
+            (
+                "/app/vendor/bundle/ruby/3.2.0/gems/activerecord-7.1.2/lib/active_record.rb",
+                Some("activerecord"),
+            ),
+            (
+                "/usr/local/lib/ruby/gems/3.2.0/gems/net-http-0.4.1/lib/net/http.rb",
+                Some("net-http"),
+            ),
+            (
+                "/home/me/.gem/ruby/3.3.0/gems/nokogiri-1.15.4-x86_64-linux/lib/nokogiri.rb",
+                Some("nokogiri"),
+            ),
+            (
+                "/app/vendor/bundle/ruby/3.2.0/bundler/gems/rails-4f2a8b1c9d0e/railties/lib/rails.rb",
+                Some("rails"),
+            ),
+            (
+                "C:\\Ruby32-x64\\lib\\ruby\\gems\\3.2.0\\gems\\rack-3.0.8\\lib\\rack.rb",
+                Some("rack"),
+            ),
+            // A gem's own `gems` directory doesn't make its files part of another gem
+            (
+                "/usr/local/lib/ruby/gems/3.2.0/gems/foo-1.0/lib/foo/gems/bar.rb",
+                Some("foo"),
+            ),
+            (
+                "/usr/local/lib/ruby/gems/3.2.0/gems/foo-1.0/lib/foo/gems/bar/x.rb",
+                Some("foo"),
+            ),
+            ("/app/lib/gems/helper.rb", None),
+            ("/app/lib/gems/bar/x.rb", None),
+            ("/app/app/models/user.rb", None),
+            ("<internal:kernel>", None),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(gem_name(&f(path)).as_deref(), expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_is_stdlib() {
+        let extra = vec!["/opt/ruby-head/".to_string()];
+        assert!(is_stdlib(&f("<internal:kernel>"), &[]));
+        assert!(is_stdlib(&f("/usr/lib/ruby/3.3.0/json/common.rb"), &[]));
+        assert!(is_stdlib(&f("/usr/lib/ruby/3.1.0+2/set.rb"), &[]));
+        assert!(is_stdlib(
+            &f("C:\\Ruby32-x64\\lib\\ruby\\3.2.0\\set.rb"),
+            &[]
+        ));
+        assert!(is_stdlib(&f("/opt/ruby-head/set.rb"), &extra));
+        assert!(!is_stdlib(
+            &f("/usr/lib/ruby/gems/3.3.0/gems/rack-3.0.8/lib/rack.rb"),
+            &[]
+        ));
+        assert!(!is_stdlib(&f("/app/lib/ruby/helpers.rb"), &[]));
+        assert!(!is_stdlib(&f("/opt/ruby-head/set.rb"), &[]));
+    }
+
+    #[test]
+    fn test_group_name() {
+        assert_eq!(
+            group_name(&f("/gems/3.2.0/gems/rack-3.0.8/lib/rack.rb")),
+            "rack"
+        );
+        assert_eq!(group_name(&f("/app/app/models/user.rb")), APPLICATION);
+    }
+
+    #[test]
+    fn test_origin() {
+        assert_eq!(
+            origin(&f("/gems/3.2.0/gems/rack-3.0.8/lib/rack.rb")),
+            Origin::Gem("rack".to_string())
+        );
+        assert_eq!(origin(&f("/usr/lib/ruby/3.3.0/set.rb")), Origin::Stdlib);
+        assert_eq!(origin(&f("<internal:kernel>")), Origin::Stdlib);
+        assert_eq!(origin(&f("/app/app/models/user.rb")), Origin::Application);
+        assert_eq!(origin(&StackFrame::unknown_c_function()), Origin::CFunction);
+        let collapsed = StackFrame {
+            name: "rails".to_string(),
+            relative_path: "(gem)".to_string(),
+            absolute_path: None,
+            lineno: None,
+        };
+        assert_eq!(origin(&collapsed), Origin::Gem("rails".to_string()));
+    }
+}
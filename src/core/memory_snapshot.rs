@@ -0,0 +1,138 @@
+/// Process memory that was captured while recording, for naming stack frames after the process
+/// has moved on (or exited).
+///
+/// With deferred symbolization, each sample records only the addresses of its frames. The first
+/// time a frame is seen, the frame is named once through a `CapturingMemory`, which keeps a copy of
+/// everything that was read, and those copies go into the raw data file. When the file is
+/// reported on, the same reads are served from a `MemorySnapshot` instead of the process.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::core::process::ProcessMemory;
+
+/// Memory captured from a process, as the bytes that were read at each address
+#[derive(Debug, Default)]
+pub struct MemorySnapshot {
+    ranges: BTreeMap<usize, Vec<u8>>,
+}
+
+impl MemorySnapshot {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds `bytes` that were read at `addr`. They replace the start of anything that was added at
+    /// the same address before, since they were read more recently. Ranges that start elsewhere
+    /// are kept as they are, and a read is served from the closest range that starts at or before
+    /// it and covers all of it.
+    pub fn add(&mut self, addr: usize, bytes: Vec<u8>) {
+        match self.ranges.get_mut(&addr) {
+            Some(existing) if existing.len() > bytes.len() => {
+                existing[..bytes.len()].copy_from_slice(&bytes);
+            }
+            _ => {
+                self.ranges.insert(addr, bytes);
+            }
+        }
+    }
+}
+
+impl ProcessMemory for MemorySnapshot {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), remoteprocess::Error> {
+        // Reads are replayed exactly as they were captured, so the range that starts at `addr`
+        // usually has all of it. If it's too short, look further back.
+        let end = addr + buf.len();
+        let range = self
+            .ranges
+            .range(..=addr)
+            .rev()
+            .find(|(start, bytes)| *start + bytes.len() >= end)
+            .ok_or_else(|| {
+                remoteprocess::Error::Other(format!(
+                    "Address {:#x} wasn't captured while recording",
+                    addr
+                ))
+            })?;
+        let offset = addr - range.0;
+        buf.copy_from_slice(&range.1[offset..offset + buf.len()]);
+        Ok(())
+    }
+}
+
+/// Wraps a source of process memory, keeping a copy of everything that's read through it
+pub struct CapturingMemory<'a, T: ProcessMemory> {
+    source: &'a T,
+    captured: RefCell<Vec<(usize, Vec<u8>)>>,
+}
+
+impl<'a, T: ProcessMemory> CapturingMemory<'a, T> {
+    pub fn new(source: &'a T) -> Self {
+        Self {
+            source,
+            captured: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The (address, bytes) pairs that were read, in the order they were read
+    pub fn into_captured(self) -> Vec<(usize, Vec<u8>)> {
+        self.captured.into_inner()
+    }
+}
+
+impl<T: ProcessMemory> ProcessMemory for CapturingMemory<'_, T> {
+    fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), remoteprocess::Error> {
+        self.source.read(addr, buf)?;
+        self.captured.borrow_mut().push((addr, buf.to_vec()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::memory_snapshot::*;
+
+    // Memory where each byte holds the low bits of its address
+    struct Memory;
+
+    impl ProcessMemory for Memory {
+        fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), remoteprocess::Error> {
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = (addr + i) as u8;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_captured_reads_can_be_replayed() {
+        let capturing = CapturingMemory::new(&Memory);
+        let first = capturing.copy(0x1000, 16).unwrap();
+        let second = capturing.copy(0x2000, 4).unwrap();
+
+        let mut snapshot = MemorySnapshot::new();
+        for (addr, bytes) in capturing.into_captured() {
+            snapshot.add(addr, bytes);
+        }
+        assert_eq!(snapshot.copy(0x1000, 16).unwrap(), first);
+        assert_eq!(snapshot.copy(0x2000, 4).unwrap(), second);
+        // Reads inside a captured range work too, but nothing outside of one does
+        assert_eq!(snapshot.copy(0x1004, 4).unwrap(), vec![4, 5, 6, 7]);
+        assert!(snapshot.copy(0x100c, 8).is_err());
+        assert!(snapshot.copy(0x3000, 1).is_err());
+    }
+
+    #[test]
+    fn test_newer_reads_replace_older_ones() {
+        let mut snapshot = MemorySnapshot::new();
+        snapshot.add(0x1000, vec![1; 8]);
+        snapshot.add(0x1000, vec![2; 4]);
+        assert_eq!(
+            snapshot.copy(0x1000, 8).unwrap(),
+            vec![2, 2, 2, 2, 1, 1, 1, 1]
+        );
+        snapshot.add(0x1004, vec![3; 2]);
+        assert_eq!(snapshot.copy(0x1004, 2).unwrap(), vec![3, 3]);
+        // A read that the newer range doesn't cover comes from the older one
+        assert_eq!(snapshot.copy(0x1004, 4).unwrap(), vec![1, 1, 1, 1]);
+    }
+}
@@ -1,6 +1,13 @@
 mod address_finder;
+mod buffered_memory;
+pub mod coredump;
+pub mod filter;
+pub mod gems;
+pub mod memory_snapshot;
 pub mod process;
+pub mod process_list;
 pub mod ruby_spy;
-mod ruby_version;
+pub(crate) mod ruby_version;
+mod succ_index_table;
 pub mod supported_ruby_versions;
 pub mod types;
@@ -27,6 +27,94 @@ impl ProcessRetry for remoteprocess::Process {
     }
 }
 
+/// The context of the error from `lock_with_retry` when every attempt fails, which tells it apart
+/// from errors reading the process's memory.
+#[derive(Debug)]
+pub struct LockFailed {
+    pub retries: u32,
+}
+
+impl std::fmt::Display for LockFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "failed to lock process after {} retries", self.retries)
+    }
+}
+
+/// Locks the process, retrying up to `retries` times if locking fails. Locking can fail when
+/// threads start or exit while the process is being locked, so waiting a little and trying again
+/// usually works. Each retry waits 1ms longer than the one before, and is counted in `retried`.
+pub fn lock_with_retry(process: &Process, retries: u32, retried: &mut usize) -> Result<impl Sized> {
+    let mut retry = 0;
+    loop {
+        match process.lock() {
+            Ok(lock) => return Ok(lock),
+            Err(e) => {
+                if retry == retries {
+                    return Err(anyhow::Error::from(e).context(LockFailed { retries }));
+                }
+                retry += 1;
+                *retried += 1;
+                debug!("Failed to lock process {}; will retry: {}", process.pid, e);
+                std::thread::sleep(std::time::Duration::from_millis(retry as u64));
+            }
+        }
+    }
+}
+
+/// The resident set size (RSS) of process `pid`, in bytes
+#[cfg(target_os = "linux")]
+pub fn resident_memory(pid: Pid) -> Result<u64> {
+    // The second field is the number of resident pages. See proc(5).
+    let statm = std::fs::read_to_string(format!("/proc/{}/statm", pid))?;
+    let pages: u64 = statm
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| anyhow::format_err!("unexpected /proc/{}/statm: {}", pid, statm))?
+        .parse()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Ok(pages * page_size as u64)
+}
+
+/// The resident set size (RSS) of process `pid`, in bytes
+#[cfg(target_os = "macos")]
+pub fn resident_memory(pid: Pid) -> Result<u64> {
+    use libproc::libproc::proc_pid::pidinfo;
+    use libproc::libproc::task_info::TaskInfo;
+
+    let info = pidinfo::<TaskInfo>(pid, 0)
+        .map_err(|e| anyhow::format_err!("couldn't get task info for process {}: {}", pid, e))?;
+    Ok(info.pti_resident_size)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn resident_memory(_pid: Pid) -> Result<u64> {
+    Err(anyhow::format_err!(
+        "reading a process's memory usage isn't supported on this platform"
+    ))
+}
+
+/// The current time on the system's monotonic clock (`CLOCK_MONOTONIC`), in nanoseconds. It's
+/// the clock that tools like `perf` timestamp their events with, so samples can be lined up with
+/// them.
+#[cfg(unix)]
+pub fn monotonic_ns() -> Result<u64> {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64)
+}
+
+#[cfg(not(unix))]
+pub fn monotonic_ns() -> Result<u64> {
+    Err(anyhow::format_err!(
+        "reading the monotonic clock isn't supported on this platform"
+    ))
+}
+
 #[cfg(test)]
 pub mod tests {
     use crate::core::process::{Pid, Process};
@@ -109,4 +197,20 @@ pub mod tests {
             &mut self.process
         }
     }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_resident_memory() {
+        let rss = super::resident_memory(std::process::id() as Pid).unwrap();
+        assert!(rss > 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_monotonic_ns() {
+        let first = super::monotonic_ns().unwrap();
+        let second = super::monotonic_ns().unwrap();
+        assert!(first > 0);
+        assert!(second >= first);
+    }
 }
@@ -0,0 +1,320 @@
+/// Finding the Ruby processes that are running on this machine, for `rbspy list`,
+/// `--process-name` and `--namespace-pid`
+use anyhow::{format_err, Result};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::core::address_finder::{has_ruby_mapping, is_ruby_file};
+use crate::core::process::{Pid, Process};
+
+/// A running process that has the ruby binary or libruby loaded.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RubyProcess {
+    pub pid: Pid,
+    /// The name that `--process-name` matches, e.g. `ruby` or `puma`. See `process_name`.
+    pub name: Option<String>,
+    pub executable: Option<String>,
+    /// The Ruby version, if it's part of the path of the ruby binary or libruby (e.g. with rbenv
+    /// or `libruby.so.3.3.0`). Run `rbspy inspect` to read it from the process's memory instead.
+    pub ruby_version: Option<String>,
+}
+
+/// Lists the processes that look like they're running Ruby, going by the files they have mapped
+/// into memory. Processes that we're not allowed to look at are skipped.
+pub fn list_ruby_processes() -> Result<Vec<RubyProcess>> {
+    let own_pid = std::process::id() as Pid;
+    let mut processes = vec![];
+    for pid in all_pids()? {
+        if pid == own_pid {
+            continue;
+        }
+        let maps = match proc_maps::get_process_maps(pid) {
+            Ok(maps) => maps,
+            Err(e) => {
+                debug!("Skipping process {}: {}", pid, e);
+                continue;
+            }
+        };
+        if !has_ruby_mapping(&maps) {
+            continue;
+        }
+        let ruby_version = maps
+            .iter()
+            .filter_map(|map| map.filename())
+            .filter(|path| is_ruby_file(path))
+            .find_map(version_from_path);
+        let process = Process::new(pid).ok();
+        let executable = process.as_ref().and_then(|p| p.exe().ok());
+        let cmdline = process
+            .as_ref()
+            .and_then(|p| p.cmdline().ok())
+            .unwrap_or_default();
+        processes.push(RubyProcess {
+            pid,
+            name: process_name(&cmdline).or_else(|| executable.as_deref().and_then(file_stem)),
+            executable,
+            ruby_version,
+        });
+    }
+    processes.sort_by_key(|process| process.pid);
+    Ok(processes)
+}
+
+/// Finds the PID of the only Ruby process whose name (see `process_name`) or executable is `name`.
+pub fn find_ruby_process_by_name(name: &str) -> Result<Pid> {
+    let matches: Vec<RubyProcess> = list_ruby_processes()?
+        .into_iter()
+        .filter(|process| {
+            process.name.as_deref() == Some(name)
+                || process.executable.as_deref().and_then(file_stem).as_deref() == Some(name)
+        })
+        .collect();
+    match matches.as_slice() {
+        [process] => Ok(process.pid),
+        [] => Err(format_err!(
+            "Couldn't find a Ruby process named {}. Run `rbspy list` to see the Ruby processes that rbspy can find",
+            name
+        )),
+        _ => Err(format_err!(
+            "Found {} Ruby processes named {} (PIDs {}). Please choose one with --pid",
+            matches.len(),
+            name,
+            matches
+                .iter()
+                .map(|process| process.pid.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Waits up to `timeout` for the process `pid` or one of its descendants to load Ruby, and returns
+/// the PID of the first one that does. This finds the Ruby process behind a wrapper that starts it
+/// as a child instead of exec'ing it, e.g. a shell script or `bundle exec` on Windows. If nothing
+/// loads Ruby in time, `pid` is returned so that the caller can report why it can't be profiled.
+pub fn wait_for_ruby_process(pid: Pid, timeout: Duration) -> Pid {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(ruby_pid) = ruby_process_in_tree(pid) {
+            if ruby_pid != pid {
+                debug!("Found Ruby process {} under process {}", ruby_pid, pid);
+            }
+            return ruby_pid;
+        }
+        if Instant::now() >= deadline {
+            return pid;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn ruby_process_in_tree(pid: Pid) -> Option<Pid> {
+    let is_ruby = |pid| {
+        proc_maps::get_process_maps(pid)
+            .map(|maps| has_ruby_mapping(&maps))
+            .unwrap_or(false)
+    };
+    if is_ruby(pid) {
+        return Some(pid);
+    }
+    Process::new(pid)
+        .ok()?
+        .child_processes()
+        .ok()?
+        .into_iter()
+        .map(|(child, _parent)| child)
+        .find(|&child| is_ruby(child))
+}
+
+/// The program in a process's command line, without its directory or extension. Ruby programs
+/// often set this to something more specific than `ruby`, e.g. puma's process title is
+/// `puma 6.4.0 (tcp://0.0.0.0:3000) [app]`, so its name is `puma`.
+fn process_name(cmdline: &[String]) -> Option<String> {
+    let program = cmdline.first()?.split_whitespace().next()?;
+    file_stem(program)
+}
+
+fn file_stem(path: &str) -> Option<String> {
+    Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+}
+
+// Finds a full version number in a path like `/home/me/.rbenv/versions/3.3.0/bin/ruby` or
+// `/usr/lib/libruby.so.3.3.0`
+pub(crate) fn version_from_path(path: &Path) -> Option<String> {
+    path.to_string_lossy()
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .map(|part| part.trim_matches('.'))
+        .find(|part| semver::Version::parse(part).is_ok())
+        .map(String::from)
+}
+
+/// Finds the PID of the process that has PID `pid` inside the container whose root filesystem is at
+/// `root` on this machine (e.g. `/var/lib/docker/overlay2/<ID>/merged`), so that a process in a
+/// container can be profiled from the host. The container's processes are the ones whose root
+/// directory is `root`. rbspy already reads the Ruby binaries of containerized processes through
+/// `/proc/<PID>/root`, so nothing else needs to change once the PID is known.
+#[cfg(target_os = "linux")]
+pub fn find_process_in_namespace(pid: Pid, root: &Path) -> Result<Pid> {
+    use anyhow::Context;
+    use std::os::unix::fs::MetadataExt;
+
+    let root_dir = std::fs::metadata(root).context(format!("Failed to read {}", root.display()))?;
+    let matches: Vec<Pid> = all_pids()?
+        .into_iter()
+        .filter(|&host_pid| {
+            std::fs::metadata(format!("/proc/{}/root", host_pid)).map_or(false, |dir| {
+                dir.dev() == root_dir.dev() && dir.ino() == root_dir.ino()
+            }) && namespace_pids(host_pid).last() == Some(&pid)
+        })
+        .collect();
+    match matches.as_slice() {
+        [host_pid] => Ok(*host_pid),
+        [] => Err(format_err!(
+            "Couldn't find a process with PID {} in the container whose root filesystem is {}",
+            pid,
+            root.display()
+        )),
+        _ => Err(format_err!(
+            "Found {} processes with PID {} whose root filesystem is {} (PIDs {}). Please choose one with --pid",
+            matches.len(),
+            pid,
+            root.display(),
+            matches
+                .iter()
+                .map(|pid| pid.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        )),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn find_process_in_namespace(_pid: Pid, _root: &Path) -> Result<Pid> {
+    Err(format_err!("--namespace-pid is only supported on Linux"))
+}
+
+/// The PIDs that process `pid` has in each of the PID namespaces it's in, from the one that
+/// `/proc` belongs to down to its own, read from the `NSpid` line of `/proc/<PID>/status`.
+#[cfg(target_os = "linux")]
+fn namespace_pids(pid: Pid) -> Vec<Pid> {
+    std::fs::read_to_string(format!("/proc/{}/status", pid))
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix("NSpid:"))
+                .map(|pids| {
+                    pids.split_whitespace()
+                        .filter_map(|p| p.parse().ok())
+                        .collect()
+                })
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn all_pids() -> Result<Vec<Pid>> {
+    // Every process is a descendant of init, since orphaned processes are adopted by it
+    let mut pids: Vec<Pid> = Process::new(1)?
+        .child_processes()?
+        .into_iter()
+        .map(|(pid, _ppid)| pid)
+        .collect();
+    pids.push(1);
+    Ok(pids)
+}
+
+#[cfg(windows)]
+fn all_pids() -> Result<Vec<Pid>> {
+    use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+    use winapi::um::tlhelp32::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+        TH32CS_SNAPPROCESS,
+    };
+
+    let mut pids = vec![];
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+        if snapshot == INVALID_HANDLE_VALUE {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let mut entry: PROCESSENTRY32W = std::mem::zeroed();
+        entry.dwSize = std::mem::size_of::<PROCESSENTRY32W>() as u32;
+        let mut found = Process32FirstW(snapshot, &mut entry);
+        while found != 0 {
+            pids.push(entry.th32ProcessID as Pid);
+            found = Process32NextW(snapshot, &mut entry);
+        }
+        CloseHandle(snapshot);
+    }
+    Ok(pids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_name() {
+        let cmdline = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            process_name(&cmdline(&["/usr/bin/ruby", "app.rb"])),
+            Some("ruby".to_string())
+        );
+        assert_eq!(
+            process_name(&cmdline(&["puma 6.4.0 (tcp://0.0.0.0:3000) [app]"])),
+            Some("puma".to_string())
+        );
+        assert_eq!(process_name(&[]), None);
+    }
+
+    #[test]
+    fn test_version_from_path() {
+        let version = |path: &str| version_from_path(Path::new(path));
+        assert_eq!(
+            version("/home/me/.rbenv/versions/3.3.0/bin/ruby"),
+            Some("3.3.0".to_string())
+        );
+        assert_eq!(
+            version("/usr/lib/x86_64-linux-gnu/libruby-3.0.so.3.0.2"),
+            Some("3.0.2".to_string())
+        );
+        assert_eq!(version("/usr/bin/ruby3.0"), None);
+        assert_eq!(version("C:\\Ruby32-x64\\bin\\x64-ucrt-ruby320.dll"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wait_for_ruby_process_without_ruby() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("5")
+            .spawn()
+            .unwrap();
+        let pid = child.id() as Pid;
+        assert_eq!(wait_for_ruby_process(pid, Duration::from_millis(50)), pid);
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_find_process_in_namespace() {
+        let own_pid = std::process::id() as Pid;
+        assert_eq!(namespace_pids(own_pid).last(), Some(&own_pid));
+        assert_eq!(
+            find_process_in_namespace(own_pid, Path::new("/")).unwrap(),
+            own_pid
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(find_process_in_namespace(own_pid, dir.path()).is_err());
+        assert!(find_process_in_namespace(own_pid, &dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_all_pids() {
+        assert!(all_pids().unwrap().contains(&(std::process::id() as Pid)));
+    }
+}
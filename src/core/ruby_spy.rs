@@ -1,10 +1,14 @@
-#[cfg(windows)]
-use anyhow::format_err;
-use anyhow::{Context, Error, Result};
+use anyhow::{format_err, Context, Error, Result};
 use spytools::ProcessInfo;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::SystemTime;
 
-use crate::core::process::{Pid, Process, ProcessRetry};
-use crate::core::types::{MemoryCopyError, StackTrace};
+use crate::core::process::{lock_with_retry, Pid, Process, ProcessRetry};
+use crate::core::types::{
+    DeferredSample, FrameCache, FrameLocals, MemoryCopyError, RawFrame, RawStackTrace, StackFrame,
+    StackTrace,
+};
 
 use super::address_finder::RubyVM;
 
@@ -12,10 +16,38 @@ pub struct RubySpy {
     process: Process,
     vm: super::address_finder::RubyVM,
     on_cpu_only: bool,
+    idle_samples: bool,
+    force_on_cpu_samples: bool,
+    lock_retries: u32,
+    lock_retries_used: usize,
+    frame_cache: FrameCache,
+    // With deferred symbolization, the frames whose memory has already been captured, and
+    // whether the Ruby version has been sent
+    captured_frames: HashSet<RawFrame>,
+    sent_ruby: bool,
+}
+
+/// How to attach to a Ruby process and read its stack traces
+#[derive(Clone, Debug, Default)]
+pub struct RubySpyConfig {
+    /// Assumes that the process runs this Ruby version instead of detecting it. Default: none.
+    pub force_version: Option<String>,
+    /// Reads symbols from this Ruby binary instead of the process's own. Default: none.
+    pub ruby_binary: Option<PathBuf>,
+    /// Skips traces of threads that aren't running on the CPU. Default: `false`.
+    pub on_cpu_only: bool,
+    /// Returns an `(idle)` placeholder trace instead of skipping threads that aren't running on
+    /// the CPU. Default: `false`.
+    pub idle_samples: bool,
+    /// Trusts the OS's check of whether the process is running, and skips checking the Ruby
+    /// thread's status. Default: `false`.
+    pub force_on_cpu_samples: bool,
+    /// How many times to retry locking the process before giving up on a sample. Default: 0.
+    pub lock_retries: u32,
 }
 
 impl RubySpy {
-    pub fn new(pid: Pid, force_version: Option<String>, on_cpu_only: bool) -> Result<Self> {
+    pub fn new(pid: Pid, config: &RubySpyConfig) -> Result<Self> {
         #[cfg(all(windows, target_arch = "x86_64"))]
         if is_wow64_process(pid).context("check wow64 process")? {
             return Err(format_err!(
@@ -30,18 +62,27 @@ impl RubySpy {
         let vm = crate::core::address_finder::inspect_ruby_process(
             &process,
             &process_info,
-            force_version,
+            config.force_version.clone(),
+            config.ruby_binary.as_deref(),
         )
         .context("get ruby VM state")?;
 
         Ok(Self {
             process,
             vm,
-            on_cpu_only,
+            on_cpu_only: config.on_cpu_only,
+            idle_samples: config.idle_samples,
+            force_on_cpu_samples: config.force_on_cpu_samples,
+            lock_retries: config.lock_retries,
+            lock_retries_used: 0,
+            frame_cache: FrameCache::new(),
+            captured_frames: HashSet::new(),
+            sent_ruby: false,
         })
     }
 
-    /// Creates a RubySpy object, retrying up to max_retries times.
+    /// Creates a RubySpy object, retrying until `attach_timeout` has passed. It's always tried at
+    /// least once.
     ///
     /// Retrying is useful for a few reasons:
     /// a) Sometimes rbenv takes a while to exec the right Ruby binary.
@@ -51,13 +92,12 @@ impl RubySpy {
     ///    Mach port for the process, which is how rbspy communicates with it
     pub fn retry_new(
         pid: Pid,
-        max_retries: u64,
-        force_version: Option<String>,
-        on_cpu_only: bool,
+        attach_timeout: std::time::Duration,
+        config: &RubySpyConfig,
     ) -> Result<Self, Error> {
-        let mut retries = 0;
+        let started = std::time::Instant::now();
         loop {
-            let err = match Self::new(pid, force_version.clone(), on_cpu_only) {
+            let err = match Self::new(pid, config) {
                 Ok(mut process) => {
                     // verify that we can load a stack trace before returning success
                     match process.get_stack_trace(false) {
@@ -68,9 +108,8 @@ impl RubySpy {
                 Err(err) => err,
             };
 
-            // If we failed, retry a couple times before returning the last error
-            retries += 1;
-            if retries >= max_retries {
+            // If we failed, keep retrying until the timeout before returning the last error
+            if started.elapsed() >= attach_timeout {
                 return Err(err);
             }
             info!(
@@ -81,21 +120,36 @@ impl RubySpy {
         }
     }
 
+    /// Returns the stack trace of the thread that's currently running Ruby code.
+    ///
+    /// When `on_cpu_only` or `idle_samples` is set, there are two checks for whether the process
+    /// is running: first the OS is asked whether any of the process's threads are active, and then
+    /// the stack trace function checks that the Ruby thread's status is `THREAD_RUNNABLE`. If
+    /// either check fails, this returns `None` when only on-CPU samples are wanted, or an `(idle)`
+    /// placeholder trace when idle samples are enabled. Otherwise, both checks are skipped and
+    /// every sample is a wall-clock sample. With `force_on_cpu_samples`, the thread status check
+    /// is skipped, so only the OS's check decides.
     pub fn get_stack_trace(&mut self, lock_process: bool) -> Result<Option<StackTrace>> {
         // First, try OS-specific checks to determine whether the process is on CPU or not.
         // This comes before locking the process because in most operating systems locking
         // will stop the process and interfere with the on-CPU check.
-        if self.on_cpu_only && !self.is_on_cpu()? {
-            return Ok(None);
+        let check_on_cpu = self.on_cpu_only || self.idle_samples;
+        if check_on_cpu && !self.is_on_cpu()? {
+            return Ok(self.idle_trace());
         }
-        match self.get_trace_from_current_thread(lock_process) {
+        let check_status = check_on_cpu && !self.force_on_cpu_samples;
+        match self.get_trace_from_current_thread(lock_process, check_status) {
             Ok(Some(mut trace)) => {
                 return {
                     trace.pid = Some(self.process.pid);
+                    if check_on_cpu {
+                        trace.on_cpu = Some(true);
+                    }
                     Ok(Some(trace))
                 };
             }
-            Ok(None) => Ok(None),
+            // The ruby thread isn't runnable
+            Ok(None) => Ok(self.idle_trace()),
             Err(e) => {
                 if self.process.exe().is_err() {
                     return Err(MemoryCopyError::ProcessEnded.into());
@@ -105,13 +159,239 @@ impl RubySpy {
         }
     }
 
-    fn get_trace_from_current_thread(&self, lock_process: bool) -> Result<Option<StackTrace>> {
+    /// Like `get_stack_trace`, but also returns the local variables of each of the trace's
+    /// frames. This is best-effort: a frame whose locals can't be read doesn't have any. It's
+    /// only supported for Ruby 3.x.
+    pub fn get_stack_trace_with_locals(
+        &mut self,
+        lock_process: bool,
+    ) -> Result<Option<(StackTrace, FrameLocals)>> {
+        let version = &self.vm.ruby_version.semver_version;
+        if version.major < 3 {
+            return Err(format_err!(
+                "Reading local variables is only supported for Ruby 3.x, and this process is running Ruby {}",
+                version
+            ));
+        }
+        let check_on_cpu = self.on_cpu_only || self.idle_samples;
+        if check_on_cpu && !self.is_on_cpu()? {
+            return Ok(self.idle_trace().map(|trace| (trace, vec![Vec::new()])));
+        }
+        let check_status = check_on_cpu && !self.force_on_cpu_samples;
+        let result = {
+            let _lock;
+            if lock_process {
+                _lock = lock_with_retry(
+                    &self.process,
+                    self.lock_retries,
+                    &mut self.lock_retries_used,
+                )
+                .context("locking process during stack trace retrieval")?;
+            }
+
+            (self.vm.ruby_version.get_stack_trace_with_locals_fn)(
+                self.vm.current_thread_addr_location,
+                self.vm.ruby_vm_addr_location,
+                self.vm.global_symbols_addr_location,
+                &self.process,
+                self.process.pid,
+                check_status,
+                &mut self.frame_cache,
+            )
+        };
+        match result {
+            Ok(Some((mut trace, locals))) => {
+                trace.pid = Some(self.process.pid);
+                if check_on_cpu {
+                    trace.on_cpu = Some(true);
+                }
+                Ok(Some((trace, locals)))
+            }
+            // The ruby thread isn't runnable
+            Ok(None) => Ok(self.idle_trace().map(|trace| (trace, vec![Vec::new()]))),
+            Err(e) => {
+                if self.process.exe().is_err() {
+                    return Err(MemoryCopyError::ProcessEnded.into());
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the stack traces of all of the process's Ruby threads, including the ones that are
+    /// waiting. Each trace's `on_cpu` says whether its thread was runnable, and when `on_cpu_only`
+    /// is set, only the runnable threads are returned. `idle_samples` doesn't apply, since the
+    /// waiting threads' traces show where they're waiting.
+    pub fn get_all_stack_traces(&mut self, lock_process: bool) -> Result<Vec<StackTrace>> {
+        let result = {
+            let _lock;
+            if lock_process {
+                _lock = lock_with_retry(
+                    &self.process,
+                    self.lock_retries,
+                    &mut self.lock_retries_used,
+                )
+                .context("locking process during stack trace retrieval")?;
+            }
+
+            (self.vm.ruby_version.get_all_stack_traces_fn)(
+                self.vm.current_thread_addr_location,
+                self.vm.ruby_vm_addr_location,
+                self.vm.global_symbols_addr_location,
+                &self.process,
+                self.process.pid,
+                &mut self.frame_cache,
+            )
+        };
+        match result {
+            Ok(mut traces) => {
+                if self.on_cpu_only {
+                    traces.retain(|trace| trace.on_cpu != Some(false));
+                }
+                Ok(traces)
+            }
+            Err(e) => {
+                if self.process.exe().is_err() {
+                    return Err(MemoryCopyError::ProcessEnded.into());
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Like `get_stack_trace`, but only reads the addresses of the trace's frames, for recording
+    /// with deferred symbolization. Frames that haven't been seen before are named once from the
+    /// process, to capture the memory that naming them reads, which is returned along with the
+    /// trace. The first sample also has the process's Ruby version.
+    pub fn get_deferred_sample(&mut self, lock_process: bool) -> Result<Option<DeferredSample>> {
+        let check_on_cpu = self.on_cpu_only || self.idle_samples;
+        if check_on_cpu && !self.is_on_cpu()? {
+            return Ok(self
+                .idle_trace()
+                .map(|trace| self.deferred_sample(trace.into(), Vec::new())));
+        }
+        let check_status = check_on_cpu && !self.force_on_cpu_samples;
+        let result = {
+            let _lock;
+            if lock_process {
+                _lock = lock_with_retry(
+                    &self.process,
+                    self.lock_retries,
+                    &mut self.lock_retries_used,
+                )
+                .context("locking process during stack trace retrieval")?;
+            }
+
+            (self.vm.ruby_version.get_raw_stack_trace_fn)(
+                self.vm.current_thread_addr_location,
+                self.vm.ruby_vm_addr_location,
+                self.vm.global_symbols_addr_location,
+                &self.process,
+                self.process.pid,
+                check_status,
+            )
+            .map(|trace| {
+                trace.map(|trace| {
+                    // The memory has to be read while the process is still locked
+                    let memory = self.capture_new_frames(&trace);
+                    (trace, memory)
+                })
+            })
+        };
+        match result {
+            Ok(Some((mut trace, memory))) => {
+                if check_on_cpu {
+                    trace.trace.on_cpu = Some(true);
+                }
+                Ok(Some(self.deferred_sample(trace, memory)))
+            }
+            // The ruby thread isn't runnable
+            Ok(None) => Ok(self
+                .idle_trace()
+                .map(|trace| self.deferred_sample(trace.into(), Vec::new()))),
+            Err(e) => {
+                if self.process.exe().is_err() {
+                    return Err(MemoryCopyError::ProcessEnded.into());
+                }
+                Err(e)
+            }
+        }
+    }
+
+    // Names the frames of `trace` that haven't been seen before, returning the memory that was
+    // read. A frame that can't be named is tried again the next time it's seen.
+    fn capture_new_frames(&mut self, trace: &RawStackTrace) -> Vec<(usize, Vec<u8>)> {
+        let mut memory = Vec::new();
+        for frame in &trace.frames {
+            if matches!(frame, RawFrame::Known(_)) || self.captured_frames.contains(frame) {
+                continue;
+            }
+            match (self.vm.ruby_version.capture_frame_fn)(
+                frame,
+                self.vm.global_symbols_addr_location,
+                &self.process,
+            ) {
+                Ok(captured) => {
+                    memory.extend(captured);
+                    self.captured_frames.insert(frame.clone());
+                }
+                Err(e) => debug!("Couldn't capture frame {:?}: {:?}", frame, e),
+            }
+        }
+        memory
+    }
+
+    fn deferred_sample(
+        &mut self,
+        trace: RawStackTrace,
+        memory: Vec<(usize, Vec<u8>)>,
+    ) -> DeferredSample {
+        let ruby = match std::mem::replace(&mut self.sent_ruby, true) {
+            true => None,
+            false => Some((
+                self.vm.ruby_version.semver_version.to_string(),
+                self.vm.global_symbols_addr_location,
+            )),
+        };
+        DeferredSample {
+            trace,
+            ruby,
+            memory,
+        }
+    }
+
+    /// When idle samples are enabled, returns a placeholder trace to account for time where the
+    /// process wasn't running, so that the output covers the whole wall-clock duration.
+    fn idle_trace(&self) -> Option<StackTrace> {
+        if !self.idle_samples {
+            return None;
+        }
+        Some(StackTrace {
+            trace: vec![StackFrame::idle()],
+            pid: Some(self.process.pid),
+            thread_id: None,
+            time: Some(SystemTime::now()),
+            on_cpu: Some(false),
+            holds_gvl: None,
+            thread_name: None,
+            rss: None,
+            monotonic_ns: None,
+        })
+    }
+
+    fn get_trace_from_current_thread(
+        &mut self,
+        lock_process: bool,
+        on_cpu: bool,
+    ) -> Result<Option<StackTrace>> {
         let _lock;
         if lock_process {
-            _lock = self
-                .process
-                .lock()
-                .context("locking process during stack trace retrieval")?;
+            _lock = lock_with_retry(
+                &self.process,
+                self.lock_retries,
+                &mut self.lock_retries_used,
+            )
+            .context("locking process during stack trace retrieval")?;
         }
 
         (&self.vm.ruby_version.get_stack_trace_fn)(
@@ -120,11 +400,18 @@ impl RubySpy {
             self.vm.global_symbols_addr_location,
             &self.process,
             self.process.pid,
-            self.on_cpu_only,
+            on_cpu,
+            &mut self.frame_cache,
         )
     }
 
-    fn is_on_cpu(&self) -> Result<bool> {
+    /// Returns how many times locking the process has been retried since the last call
+    pub fn take_lock_retries(&mut self) -> usize {
+        std::mem::take(&mut self.lock_retries_used)
+    }
+
+    /// Whether any of the process's threads are running, according to the OS
+    pub fn is_on_cpu(&self) -> Result<bool> {
         if self
             .process
             .threads()?
@@ -173,9 +460,10 @@ mod tests {
     use crate::core::process::tests::RubyScript;
     #[cfg(any(unix, windows))]
     use crate::core::process::Pid;
-    use crate::core::ruby_spy::RubySpy;
+    use crate::core::ruby_spy::{RubySpy, RubySpyConfig};
     #[cfg(target_os = "macos")]
     use std::process::Command;
+    use std::time::Duration;
 
     #[test]
     #[cfg(all(windows, target_arch = "x86_64"))]
@@ -203,7 +491,7 @@ mod tests {
 
     #[test]
     fn test_initialize_with_nonexistent_process() {
-        match RubySpy::new(65535, None, false) {
+        match RubySpy::new(65535, &Default::default()) {
             Ok(_) => assert!(
                 false,
                 "Expected error because process probably doesn't exist"
@@ -215,7 +503,7 @@ mod tests {
     #[test]
     #[cfg(target_os = "linux")]
     fn test_initialize_with_disallowed_process() {
-        match RubySpy::new(1, None, false) {
+        match RubySpy::new(1, &Default::default()) {
             Ok(_) => assert!(
                 false,
                 "Expected error because we shouldn't be allowed to profile the init process"
@@ -231,7 +519,7 @@ mod tests {
         let mut process = Command::new("/usr/bin/ruby").spawn().unwrap();
         let pid = process.id() as Pid;
 
-        match RubySpy::new(pid, None, false) {
+        match RubySpy::new(pid, &Default::default()) {
             Ok(_) => assert!(
                 false,
                 "Expected error because we shouldn't be allowed to profile system processes"
@@ -252,7 +540,8 @@ mod tests {
 
         let cmd = RubyScript::new("./ci/ruby-programs/infinite_on_cpu.rb");
         let pid = cmd.id() as Pid;
-        let mut spy = RubySpy::retry_new(pid, 100, None, false).expect("couldn't initialize spy");
+        let mut spy = RubySpy::retry_new(pid, Duration::from_secs(2), &Default::default())
+            .expect("couldn't initialize spy");
         spy.get_stack_trace(false)
             .expect("couldn't get stack trace");
     }
@@ -284,7 +573,15 @@ mod tests {
             std::thread::sleep(std::time::Duration::from_millis(100));
         }
 
-        let mut spy = RubySpy::retry_new(pid, 100, None, true).expect("couldn't initialize spy");
+        let mut spy = RubySpy::retry_new(
+            pid,
+            Duration::from_secs(2),
+            &RubySpyConfig {
+                on_cpu_only: true,
+                ..Default::default()
+            },
+        )
+        .expect("couldn't initialize spy");
         let trace = spy
             .get_stack_trace(false)
             .expect("couldn't get stack trace");
@@ -300,7 +597,8 @@ mod tests {
         }
 
         let mut cmd = RubyScript::new("./ci/ruby-programs/infinite_on_cpu.rb");
-        let mut getter = RubySpy::retry_new(cmd.id(), 100, None, false).unwrap();
+        let mut getter =
+            RubySpy::retry_new(cmd.id(), Duration::from_secs(2), &Default::default()).unwrap();
 
         cmd.kill().expect("couldn't clean up test process");
 
@@ -8,6 +8,73 @@
  * Defines a bunch of submodules, one per Ruby version (`ruby_1_9_3`, `ruby_2_2_0`, etc.)
  */
 
+/// The most locals that `snapshot --with-locals` reads from each frame
+const MAX_LOCALS: usize = 16;
+
+/// Strings that are longer than this many characters are cut short when they're shown as locals
+const MAX_LOCAL_STRING_LEN: usize = 40;
+
+/// Renders a VALUE that's stored inline rather than as a pointer to an object: `nil`, `true`,
+/// `false`, and Integers and Floats that are small enough to be immediates. Returns `None` for
+/// anything else. `nil` and `undef` moved in Ruby 3.2, and neither of their old and new values
+/// mean anything else in the other versions, so both are recognized.
+fn describe_immediate(value: u64) -> Option<String> {
+    match value {
+        0x00 => return Some("false".to_string()),
+        0x04 | 0x08 => return Some("nil".to_string()),
+        0x14 => return Some("true".to_string()),
+        0x24 | 0x34 => return Some("undef".to_string()),
+        _ => {}
+    }
+    if value & 1 == 1 {
+        // Fixnum
+        return Some(((value as i64) >> 1).to_string());
+    }
+    if value & 3 == 2 {
+        // Flonum. See rb_float_flonum_value in ruby's internal/float.h
+        if value == 0x8000_0000_0000_0002 {
+            return Some("0.0".to_string());
+        }
+        let b63 = value >> 63;
+        let bits = ((2 - b63) | (value & !3)).rotate_right(3);
+        return Some(format!("{:?}", f64::from_bits(bits)));
+    }
+    None
+}
+
+/// Renders a string local the way Ruby's `inspect` would, cutting long strings short
+fn describe_string(s: &str) -> String {
+    if s.chars().count() <= MAX_LOCAL_STRING_LEN {
+        return format!("{:?}", s);
+    }
+    let short: String = s.chars().take(MAX_LOCAL_STRING_LEN).collect();
+    format!("{:?}...", short)
+}
+
+/// The name of a class of objects that aren't shown inline, from the type in their flags
+fn ruby_type_name(ruby_type: usize) -> &'static str {
+    // These are the ruby_value_type constants from ruby's include/ruby/internal/value_type.h
+    match ruby_type {
+        0x01 => "Object",
+        0x02 => "Class",
+        0x03 => "Module",
+        0x04 => "Float",
+        0x05 => "String",
+        0x06 => "Regexp",
+        0x07 => "Array",
+        0x08 => "Hash",
+        0x09 => "Struct",
+        0x0a => "Integer",
+        0x0b => "File",
+        0x0c => "Data",
+        0x0d => "MatchData",
+        0x0e => "Complex",
+        0x0f => "Rational",
+        0x14 => "Symbol",
+        _ => "unknown",
+    }
+}
+
 macro_rules! ruby_version_v_1_9_1(
     ($ruby_version:ident) => (
         pub mod $ruby_version {
@@ -18,6 +85,7 @@ macro_rules! ruby_version_v_1_9_1(
 
             get_stack_trace!(rb_thread_struct);
             get_execution_context_from_thread!(rb_thread_struct);
+            get_execution_contexts_unsupported!();
             rstring_as_array_1_9_1!();
             get_ruby_string_1_9_1!();
             get_cfps!();
@@ -27,7 +95,11 @@ macro_rules! ruby_version_v_1_9_1(
             stack_field_1_9_0!();
             get_thread_status_1_9_0!();
             get_thread_id_1_9_0!();
+            get_thread_name_1_9_0!();
             get_cfunc_name_unsupported!();
+            get_locals_unsupported!();
+            is_in_gc_unsupported!();
+            is_jit_frame_unsupported!();
         }
     )
 );
@@ -43,6 +115,7 @@ macro_rules! ruby_version_v_1_9_2_to_3(
 
             get_stack_trace!(rb_thread_struct);
             get_execution_context_from_thread!(rb_thread_struct);
+            get_execution_contexts_unsupported!();
             rstring_as_array_1_9_1!();
             get_ruby_string_1_9_1!();
             get_cfps!();
@@ -52,7 +125,11 @@ macro_rules! ruby_version_v_1_9_2_to_3(
             stack_field_1_9_0!();
             get_thread_status_1_9_0!();
             get_thread_id_1_9_0!();
+            get_thread_name_1_9_0!();
             get_cfunc_name_unsupported!();
+            get_locals_unsupported!();
+            is_in_gc_unsupported!();
+            is_jit_frame_unsupported!();
         }
     )
 );
@@ -79,6 +156,7 @@ macro_rules! ruby_version_v_2_0_to_2_2(
             // on how it's stored
             get_stack_trace!(rb_thread_struct);
             get_execution_context_from_thread!(rb_thread_struct);
+            get_execution_contexts_unsupported!();
             rstring_as_array_1_9_1!();
             get_ruby_string_1_9_1!();
             get_cfps!();
@@ -88,7 +166,11 @@ macro_rules! ruby_version_v_2_0_to_2_2(
             stack_field_1_9_0!();
             get_thread_status_1_9_0!();
             get_thread_id_1_9_0!();
+            get_thread_name_1_9_0!();
             get_cfunc_name_unsupported!();
+            get_locals_unsupported!();
+            is_in_gc_unsupported!();
+            is_jit_frame_unsupported!();
         }
     )
 );
@@ -103,6 +185,7 @@ macro_rules! ruby_version_v_2_3_to_2_4(
 
             get_stack_trace!(rb_thread_struct);
             get_execution_context_from_thread!(rb_thread_struct);
+            get_execution_contexts!(thread);
             rstring_as_array_1_9_1!();
             get_ruby_string_1_9_1!();
             get_cfps!();
@@ -112,7 +195,11 @@ macro_rules! ruby_version_v_2_3_to_2_4(
             stack_field_1_9_0!();
             get_thread_status_1_9_0!();
             get_thread_id_1_9_0!();
+            get_thread_name_1_9_0!();
             get_cfunc_name_unsupported!();
+            get_locals_unsupported!();
+            is_in_gc_unsupported!();
+            is_jit_frame_unsupported!();
         }
     )
 );
@@ -127,6 +214,7 @@ macro_rules! ruby_version_v2_5_x(
 
             get_stack_trace!(rb_execution_context_struct);
             get_execution_context_from_thread!(rb_execution_context_struct);
+            get_execution_contexts!(ec);
             rstring_as_array_1_9_1!();
             get_ruby_string_1_9_1!();
             get_cfps!();
@@ -137,10 +225,14 @@ macro_rules! ruby_version_v2_5_x(
             get_ruby_string_array_2_5_0!();
             get_thread_status_2_5_0!();
             get_thread_id_2_5_0!();
-            #[cfg(any(target_os = "freebsd", target_os = "macos", target_os = "windows"))]
+            get_thread_name_1_9_0!();
+            #[cfg(any(target_os = "freebsd", target_os = "windows"))]
             get_cfunc_name_unsupported!();
-            #[cfg(target_os = "linux")]
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
             get_cfunc_name!();
+            get_locals_unsupported!();
+            is_in_gc_unsupported!();
+            is_jit_frame_unsupported!();
         }
     )
 );
@@ -155,6 +247,7 @@ macro_rules! ruby_version_v2_6_x(
 
             get_stack_trace!(rb_execution_context_struct);
             get_execution_context_from_thread!(rb_execution_context_struct);
+            get_execution_contexts!(ec);
             rstring_as_array_1_9_1!();
             get_ruby_string_1_9_1!();
             get_ruby_string_array_2_5_0!();
@@ -165,10 +258,14 @@ macro_rules! ruby_version_v2_6_x(
             stack_field_2_5_0!();
             get_thread_status_2_6_0!();
             get_thread_id_2_5_0!();
-            #[cfg(any(target_os = "freebsd", target_os = "macos", target_os = "windows"))]
+            get_thread_name_1_9_0!();
+            #[cfg(any(target_os = "freebsd", target_os = "windows"))]
             get_cfunc_name_unsupported!();
-            #[cfg(target_os = "linux")]
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
             get_cfunc_name!();
+            get_locals_unsupported!();
+            is_in_gc_unsupported!();
+            is_jit_frame_unsupported!();
         }
     )
 );
@@ -183,6 +280,7 @@ macro_rules! ruby_version_v2_7_x(
 
             get_stack_trace!(rb_execution_context_struct);
             get_execution_context_from_thread!(rb_execution_context_struct);
+            get_execution_contexts!(ec);
             rstring_as_array_1_9_1!();
             get_ruby_string_1_9_1!();
             get_ruby_string_array_2_5_0!();
@@ -193,7 +291,11 @@ macro_rules! ruby_version_v2_7_x(
             stack_field_2_5_0!();
             get_thread_status_2_6_0!();
             get_thread_id_2_5_0!();
+            get_thread_name_1_9_0!();
             get_cfunc_name!();
+            get_locals_unsupported!();
+            is_in_gc_unsupported!();
+            is_jit_frame_unsupported!();
         }
     )
 );
@@ -208,6 +310,7 @@ macro_rules! ruby_version_v3_0_x(
 
             get_stack_trace!(rb_execution_context_struct);
             get_execution_context_from_vm!();
+            get_execution_contexts!(ec);
             rstring_as_array_1_9_1!();
             get_ruby_string_1_9_1!();
             get_ruby_string_array_2_5_0!();
@@ -218,7 +321,12 @@ macro_rules! ruby_version_v3_0_x(
             stack_field_2_5_0!();
             get_thread_status_2_6_0!();
             get_thread_id_2_5_0!();
+            get_thread_name_3_0_0!();
             get_cfunc_name!();
+            get_locals_3_0_0!();
+            is_in_gc_3_0_0!();
+            is_jit_frame_unsupported!();
+            rstring_heap_len_3_0_0!();
 
             #[allow(non_upper_case_globals)]
             const ruby_fl_type_RUBY_FL_USHIFT: ruby_fl_type = ruby_fl_ushift_RUBY_FL_USHIFT as i32;
@@ -236,6 +344,7 @@ macro_rules! ruby_version_v3_1_x(
 
             get_stack_trace!(rb_execution_context_struct);
             get_execution_context_from_vm!();
+            get_execution_contexts!(ec);
             rstring_as_array_3_1_0!();
             get_ruby_string_1_9_1!();
             get_ruby_string_array_2_5_0!();
@@ -246,7 +355,12 @@ macro_rules! ruby_version_v3_1_x(
             stack_field_2_5_0!();
             get_thread_status_2_6_0!();
             get_thread_id_2_5_0!();
+            get_thread_name_3_0_0!();
             get_cfunc_name!();
+            get_locals_3_0_0!();
+            is_in_gc_3_0_0!();
+            is_jit_frame_unsupported!();
+            rstring_heap_len_3_0_0!();
 
             #[allow(non_upper_case_globals)]
             const ruby_fl_type_RUBY_FL_USHIFT: ruby_fl_type = ruby_fl_ushift_RUBY_FL_USHIFT as i32;
@@ -264,6 +378,7 @@ macro_rules! ruby_version_v3_2_x(
 
             get_stack_trace!(rb_execution_context_struct);
             get_execution_context_from_vm!();
+            get_execution_contexts!(ec);
             get_ruby_string_3_2_0!();
             get_ruby_string_array_3_2_0!();
             get_cfps!();
@@ -273,7 +388,12 @@ macro_rules! ruby_version_v3_2_x(
             stack_field_2_5_0!();
             get_thread_status_2_6_0!();
             get_thread_id_3_2_0!();
+            get_thread_name_3_0_0!();
             get_cfunc_name!();
+            get_locals_3_0_0!();
+            is_in_gc_3_0_0!();
+            is_jit_frame_unsupported!();
+            rstring_heap_len_3_0_0!();
 
             #[allow(non_upper_case_globals)]
             const ruby_fl_type_RUBY_FL_USHIFT: ruby_fl_type = ruby_fl_ushift_RUBY_FL_USHIFT as i32;
@@ -291,6 +411,40 @@ macro_rules! ruby_version_v3_3_x(
 
             get_stack_trace!(rb_execution_context_struct);
             get_execution_context_from_vm!();
+            get_execution_contexts!(ec);
+            get_ruby_string_3_3_0!();
+            get_ruby_string_array_3_2_0!();
+            get_cfps!();
+            get_pos!(rb_iseq_constant_body);
+            get_lineno_2_6_0!();
+            get_stack_frame_2_5_0!();
+            stack_field_2_5_0!();
+            get_thread_status_2_6_0!();
+            get_thread_id_3_2_0!();
+            get_thread_name_3_0_0!();
+            get_cfunc_name!();
+            get_locals_3_0_0!();
+            is_in_gc_3_0_0!();
+            is_jit_frame_3_3_0!();
+            rstring_heap_len_3_3_0!();
+
+            #[allow(non_upper_case_globals)]
+            const ruby_fl_type_RUBY_FL_USHIFT: ruby_fl_type = ruby_fl_ushift_RUBY_FL_USHIFT as i32;
+        }
+    )
+);
+
+macro_rules! ruby_version_v3_4_x(
+    ($ruby_version:ident) => (
+        pub mod $ruby_version {
+            use std;
+            use anyhow::{Context, format_err, Result};
+            use bindings::$ruby_version::*;
+            use crate::core::process::ProcessMemory;
+
+            get_stack_trace!(rb_execution_context_struct);
+            get_execution_context_from_vm!();
+            get_execution_contexts!(ec);
             get_ruby_string_3_3_0!();
             get_ruby_string_array_3_2_0!();
             get_cfps!();
@@ -300,7 +454,12 @@ macro_rules! ruby_version_v3_3_x(
             stack_field_2_5_0!();
             get_thread_status_2_6_0!();
             get_thread_id_3_2_0!();
+            get_thread_name_3_0_0!();
             get_cfunc_name!();
+            get_locals_3_0_0!();
+            is_in_gc_unsupported!();
+            is_jit_frame_3_3_0!();
+            rstring_heap_len_3_3_0!();
 
             #[allow(non_upper_case_globals)]
             const ruby_fl_type_RUBY_FL_USHIFT: ruby_fl_type = ruby_fl_ushift_RUBY_FL_USHIFT as i32;
@@ -372,10 +531,94 @@ macro_rules! get_execution_context_from_vm(
     )
 );
 
+// Every living thread is in a circular linked list that runs through the VM (or, since 3.0, the
+// main ractor). The list node is the first field of the thread struct, so a node's address is
+// also its thread's address. Only the leading fields of the thread struct are used, because
+// they're the same on every platform.
+macro_rules! get_execution_contexts(
+    // Before 2.5, the thread struct is also the execution context
+    (thread) => (
+        fn get_thread_address<T: ProcessMemory>(execution_context_addr: usize, _source: &T) -> Result<usize> {
+            Ok(execution_context_addr)
+        }
+
+        fn get_thread_execution_context(thread_addr: usize, _thread: &rb_thread_struct) -> usize {
+            thread_addr
+        }
+
+        get_execution_contexts!();
+    );
+    (ec) => (
+        fn get_thread_address<T: ProcessMemory>(execution_context_addr: usize, source: &T) -> Result<usize> {
+            let ec: rb_execution_context_struct = source.copy_struct(execution_context_addr)
+                .context("couldn't copy execution context")?;
+            Ok(ec.thread_ptr as usize)
+        }
+
+        fn get_thread_execution_context(_thread_addr: usize, thread: &rb_thread_struct) -> usize {
+            thread.ec as usize
+        }
+
+        get_execution_contexts!();
+    );
+    () => (
+        pub fn get_execution_contexts<T: ProcessMemory>(
+            current_thread_address_ptr: usize,
+            ruby_vm_address_ptr: usize,
+            source: &T
+        ) -> Result<Vec<usize>> {
+            // More threads than this means that the list is being read while it changes, or
+            // that it isn't a list of threads at all
+            const MAX_THREADS: usize = 10_000;
+
+            let current = get_execution_context(current_thread_address_ptr, ruby_vm_address_ptr, source)
+                .context("couldn't get execution context")?;
+            let start = get_thread_address(current, source)?;
+            let vm = source.copy_struct::<rb_thread_struct>(start)
+                .context("couldn't copy thread struct")?
+                .vm as usize;
+
+            let mut contexts = vec![];
+            let mut node = start;
+            for _ in 0..MAX_THREADS {
+                // The list head isn't part of a thread, so it won't point back to the VM
+                match source.copy_struct::<rb_thread_struct>(node) {
+                    Ok(thread) if thread.vm as usize == vm => {
+                        contexts.push(get_thread_execution_context(node, &thread));
+                    },
+                    _ => {},
+                }
+                // `next` is the first field of the node
+                node = source.copy_struct(node).context("couldn't read thread list")?;
+                if node == start {
+                    return Ok(contexts);
+                }
+            }
+            Err(format_err!("thread list has more than {} threads", MAX_THREADS))
+        }
+    );
+);
+
+macro_rules! get_execution_contexts_unsupported(
+    () => (
+        // Threads were kept in a hash table rather than a list before 2.2, and 2.2 is read with
+        // the same code as 2.0 and 2.1
+        pub fn get_execution_contexts<T: ProcessMemory>(
+            _current_thread_address_ptr: usize,
+            _ruby_vm_address_ptr: usize,
+            _source: &T
+        ) -> Result<Vec<usize>> {
+            Err(format_err!("reading all threads isn't supported for Ruby versions before 2.3"))
+        }
+    )
+);
+
 macro_rules! get_stack_trace(
     ($thread_type:ident) => (
         use crate::core::process::Pid;
-        use crate::core::types::{StackFrame, StackTrace};
+        use crate::core::buffered_memory::BufferedMemory;
+        use crate::core::memory_snapshot::CapturingMemory;
+        use crate::core::types::{FrameCache, FrameLocals, LocalVariable, RawFrame, RawStackTrace, StackFrame, StackTrace};
 
         pub fn get_stack_trace<T: ProcessMemory>(
             ruby_current_thread_address_location: usize,
@@ -384,75 +627,232 @@ macro_rules! get_stack_trace(
             source: &T,
             pid: Pid,
             on_cpu: bool,
+            cache: &mut FrameCache,
         ) -> Result<Option<StackTrace>, anyhow::Error> {
+            let source = &BufferedMemory::new(source);
             let current_thread_addr: usize = get_execution_context(ruby_current_thread_address_location, ruby_vm_address_location, source)
                 .context("couldn't get execution context")?;
-            let thread: $thread_type = source.copy_struct(current_thread_addr)
-                .context("couldn't get current thread")?;
+            let mut trace = get_thread_stack_trace(current_thread_addr, ruby_global_symbols_address_location, source, pid, on_cpu, cache, None)?;
+            if let Some(trace) = trace.as_mut() {
+                if in_gc(ruby_vm_address_location, source) {
+                    trace.trace.insert(0, StackFrame::gc());
+                }
+            }
+            Ok(trace)
+        }
 
-            if on_cpu && get_thread_status(&thread, source)? != 0 /* THREAD_RUNNABLE */ {
-                // This is in addition to any OS-specific checks for thread activity, and provides
-                // an extra measure of reliability for targets that don't have them. It also works
-                // for coredump targets.
-                return Ok(None);
+        /// Like `get_stack_trace`, but also reads the local variables of each Ruby frame. The
+        /// locals are in the same order as the trace's frames, and C frames don't have any.
+        pub fn get_stack_trace_with_locals<T: ProcessMemory>(
+            ruby_current_thread_address_location: usize,
+            ruby_vm_address_location: usize,
+            ruby_global_symbols_address_location: Option<usize>,
+            source: &T,
+            pid: Pid,
+            on_cpu: bool,
+            cache: &mut FrameCache,
+        ) -> Result<Option<(StackTrace, FrameLocals)>, anyhow::Error> {
+            let source = &BufferedMemory::new(source);
+            let current_thread_addr: usize = get_execution_context(ruby_current_thread_address_location, ruby_vm_address_location, source)
+                .context("couldn't get execution context")?;
+            let mut locals = Vec::new();
+            let mut trace = get_thread_stack_trace(current_thread_addr, ruby_global_symbols_address_location, source, pid, on_cpu, cache, Some(&mut locals))?;
+            if let Some(trace) = trace.as_mut() {
+                if in_gc(ruby_vm_address_location, source) {
+                    trace.trace.insert(0, StackFrame::gc());
+                    locals.insert(0, Vec::new());
+                }
             }
+            Ok(trace.map(|trace| (trace, locals)))
+        }
 
-            let thread_id = match get_thread_id(&thread, source) {
-                Ok(tid) => Some(tid),
-                Err(e) => {
-                    debug!("Couldn't get thread ID: {}", e);
-                    None
+        /// Reads the stack traces of all of the threads in the main ractor, including the ones
+        /// that are waiting (e.g. for the GVL or on I/O). Each trace's `on_cpu` says whether its
+        /// thread was runnable, and `holds_gvl` whether it was the VM's current thread.
+        pub fn get_all_stack_traces<T: ProcessMemory>(
+            ruby_current_thread_address_location: usize,
+            ruby_vm_address_location: usize,
+            ruby_global_symbols_address_location: Option<usize>,
+            source: &T,
+            pid: Pid,
+            cache: &mut FrameCache,
+        ) -> Result<Vec<StackTrace>, anyhow::Error> {
+            let source = &BufferedMemory::new(source);
+            let contexts = get_execution_contexts(ruby_current_thread_address_location, ruby_vm_address_location, source)?;
+            // The current execution context is switched when a thread takes the GVL
+            let current = get_execution_context(ruby_current_thread_address_location, ruby_vm_address_location, source)
+                .context("couldn't get execution context")?;
+            // The garbage collector runs on the thread that holds the GVL
+            let gc = in_gc(ruby_vm_address_location, source);
+            let mut traces = Vec::with_capacity(contexts.len());
+            let mut error = None;
+            for addr in contexts {
+                let result = source.copy_struct::<$thread_type>(addr)
+                    .context("couldn't get thread")
+                    .and_then(|thread| get_thread_status(&thread, source))
+                    .and_then(|status| {
+                        let trace = get_thread_stack_trace(addr, ruby_global_symbols_address_location, source, pid, false, cache, None)?;
+                        let runnable = status == 0 /* THREAD_RUNNABLE */;
+                        Ok(trace.map(|mut trace| {
+                            if gc && runnable && addr == current {
+                                trace.trace.insert(0, StackFrame::gc());
+                            }
+                            StackTrace {
+                                on_cpu: Some(runnable),
+                                holds_gvl: Some(runnable && addr == current),
+                                ..trace
+                            }
+                        }))
+                    });
+                match result {
+                    Ok(trace) => traces.extend(trace),
+                    // A thread that's starting or exiting can be in an inconsistent state, which
+                    // shouldn't stop the other threads from being read
+                    Err(e) => {
+                        debug!("Couldn't get stack trace for thread at {:#x}: {:?}", addr, e);
+                        error = Some(e);
+                    },
+                }
+            }
+            match error {
+                Some(e) if traces.is_empty() => Err(e),
+                _ => Ok(traces),
+            }
+        }
+
+        /// Like `get_stack_trace`, but only reads the addresses of the trace's frames, for
+        /// recording with deferred symbolization. The frames are named later by `resolve_frame`.
+        pub fn get_raw_stack_trace<T: ProcessMemory>(
+            ruby_current_thread_address_location: usize,
+            ruby_vm_address_location: usize,
+            ruby_global_symbols_address_location: Option<usize>,
+            source: &T,
+            pid: Pid,
+            on_cpu: bool,
+        ) -> Result<Option<RawStackTrace>, anyhow::Error> {
+            let source = &BufferedMemory::new(source);
+            let current_thread_addr: usize = get_execution_context(ruby_current_thread_address_location, ruby_vm_address_location, source)
+                .context("couldn't get execution context")?;
+            let walked = walk_thread_stack(current_thread_addr, ruby_global_symbols_address_location, source, pid, on_cpu)?;
+            Ok(walked.map(|(mut trace, _)| {
+                if in_gc(ruby_vm_address_location, source) {
+                    trace.frames.insert(0, RawFrame::Known(StackFrame::gc()));
+                }
+                trace
+            }))
+        }
+
+        /// Names a frame that was read by `get_raw_stack_trace`. `source` is usually the memory
+        /// that was captured by `capture_frame_memory` when the frame was first seen.
+        pub fn resolve_frame<T: ProcessMemory>(
+            frame: &RawFrame,
+            ruby_global_symbols_address_location: Option<usize>,
+            source: &T,
+        ) -> Result<StackFrame, anyhow::Error> {
+            match *frame {
+                RawFrame::Ruby { iseq, pc, jit } => {
+                    let iseq_struct: rb_iseq_struct = source.copy_struct(iseq)
+                        .context("couldn't copy iseq struct")?;
+                    // Naming a frame only reads the control frame's iseq and pc
+                    let mut cfp: rb_control_frame_t = unsafe { std::mem::zeroed() };
+                    cfp.iseq = iseq as _;
+                    cfp.pc = pc as _;
+                    let mut call = get_stack_frame(&iseq_struct, &cfp, source, &mut FrameCache::new())?;
+                    if jit {
+                        call.name = format!("[JIT] {}", call.name);
+                    }
+                    Ok(call)
                 },
-            };
-            if stack_field(&thread) as usize == 0 {
-                return Ok(Some(StackTrace {
-                    pid: Some(pid),
-                    trace: vec!(StackFrame::unknown_c_function()),
-                    thread_id: thread_id,
-                    time: Some(SystemTime::now()),
-                    on_cpu: None,
-                }));
+                RawFrame::CFunc { method_entry } => {
+                    let global_symbols_addr = ruby_global_symbols_address_location
+                        .ok_or_else(|| format_err!("global symbol table address is unknown"))?;
+                    let name = get_method_entry_name(method_entry, global_symbols_addr, source)?;
+                    Ok(StackFrame{
+                        name: format!("{} [c function]", name),
+                        relative_path: "(unknown)".to_string(),
+                        absolute_path: None,
+                        lineno: None,
+                    })
+                },
+                RawFrame::Known(ref frame) => Ok(frame.clone()),
             }
-            let mut trace = Vec::new();
-            let cfps = get_cfps(thread.cfp as usize, stack_base(&thread) as usize, source)?;
-            for cfp in cfps.iter() {
-                if cfp.iseq as usize == 0 {
-                    let mut frame = StackFrame::unknown_c_function();
-                    if let Some(global_symbols_addr) = ruby_global_symbols_address_location {
-                        match get_cfunc_name(cfp, global_symbols_addr, source, pid) {
-                            Ok(name) => {
-                                frame = StackFrame{
-                                    name: format!("{} [c function]", name),
-                                    relative_path: "(unknown)".to_string(),
-                                    absolute_path: None,
-                                    lineno: None,
-                                };
-                            },
-                            Err(e) => {
-                                debug!("Unknown C function: {:?}", e);
-                            }
+        }
+
+        /// Names a frame from the live process like `resolve_frame`, returning the memory that
+        /// was read to do it
+        pub fn capture_frame_memory<T: ProcessMemory>(
+            frame: &RawFrame,
+            ruby_global_symbols_address_location: Option<usize>,
+            source: &T,
+        ) -> Result<Vec<(usize, Vec<u8>)>, anyhow::Error> {
+            let source = CapturingMemory::new(source);
+            resolve_frame(frame, ruby_global_symbols_address_location, &source)?;
+            Ok(source.into_captured())
+        }
+
+        // Whether the trace of the thread holding the GVL should get a `<GC>` frame. Versions whose
+        // GC state can't be read never do.
+        fn in_gc<T: ProcessMemory>(ruby_vm_address_location: usize, source: &T) -> bool {
+            is_in_gc(ruby_vm_address_location, source).unwrap_or_else(|e| {
+                debug!("Couldn't get GC state: {:?}", e);
+                false
+            })
+        }
+
+        fn get_thread_stack_trace<T: ProcessMemory>(
+            thread_addr: usize,
+            ruby_global_symbols_address_location: Option<usize>,
+            source: &T,
+            pid: Pid,
+            on_cpu: bool,
+            cache: &mut FrameCache,
+            mut locals: Option<&mut FrameLocals>,
+        ) -> Result<Option<StackTrace>, anyhow::Error> {
+            let (raw, cfps) = match walk_thread_stack(thread_addr, ruby_global_symbols_address_location, source, pid, on_cpu)? {
+                Some(walked) => walked,
+                None => return Ok(None),
+            };
+            let mut trace = raw.trace;
+            for (frame, cfp) in raw.frames.into_iter().zip(cfps) {
+                let (cfp, jit) = match (&frame, cfp) {
+                    (RawFrame::Ruby { jit, .. }, Some(cfp)) => (cfp, *jit),
+                    _ => {
+                        let call = resolve_frame(&frame, ruby_global_symbols_address_location, source).unwrap_or_else(|e| {
+                            debug!("Unknown C function: {:?}", e);
+                            StackFrame::unknown_c_function()
+                        });
+                        trace.trace.push(call);
+                        if let Some(locals) = locals.as_mut() {
+                            locals.push(Vec::new());
                         }
+                        continue;
                     }
-                    trace.push(frame);
-                    continue;
-                }
-                if cfp.pc as usize == 0 {
-                    debug!("pc was 0. Not sure what that means, but skipping CFP");
-                    continue;
-                }
+                };
                 let iseq_struct: rb_iseq_struct = source.copy_struct(cfp.iseq as usize)
                     .context("couldn't copy iseq struct")?;
 
-                let label_path  = get_stack_frame(&iseq_struct, &cfp, source);
+                let label_path  = get_stack_frame(&iseq_struct, &cfp, source, cache);
                 match label_path {
-                    Ok(call)  => trace.push(call),
+                    Ok(mut call)  => {
+                        if jit {
+                            call.name = format!("[JIT] {}", call.name);
+                        }
+                        trace.trace.push(call);
+                        if let Some(locals) = locals.as_mut() {
+                            // Locals are best-effort, so a frame whose locals can't be read is
+                            // still part of the trace
+                            locals.push(get_locals(&iseq_struct, &cfp, ruby_global_symbols_address_location, source).unwrap_or_else(|e| {
+                                debug!("Couldn't get locals: {:?}", e);
+                                Vec::new()
+                            }));
+                        }
+                    },
                     Err(x) => {
                         debug!("Error: {:#?}", x);
                         debug!("cfp: {:?}", cfp);
-                        debug!("thread: {:?}", thread);
                         debug!("iseq struct: {:?}", iseq_struct);
                         // this is a heuristic: the intent of this is that it skips function calls into C extensions
-                        if trace.len() > 0 {
+                        if trace.trace.len() > 0 {
                             debug!("Skipping function call, possibly into C extension");
                         } else {
                             return Err(x);
@@ -460,6 +860,30 @@ macro_rules! get_stack_trace(
                     }
                 }
             }
+            Ok(Some(trace))
+        }
+
+        // Reads a thread's trace with only the addresses of its frames, along with the control
+        // frame of each Ruby frame, which naming the frame from the process (and reading its
+        // locals) needs. Live and deferred traces are both read this way, so that they have the
+        // same frames and metadata.
+        fn walk_thread_stack<T: ProcessMemory>(
+            thread_addr: usize,
+            ruby_global_symbols_address_location: Option<usize>,
+            source: &T,
+            pid: Pid,
+            on_cpu: bool,
+        ) -> Result<Option<(RawStackTrace, Vec<Option<rb_control_frame_t>>)>, anyhow::Error> {
+            let thread: $thread_type = source.copy_struct(thread_addr)
+                .context("couldn't get current thread")?;
+
+            if on_cpu && get_thread_status(&thread, source)? != 0 /* THREAD_RUNNABLE */ {
+                // This is in addition to any OS-specific checks for thread activity, and provides
+                // an extra measure of reliability for targets that don't have them. It also works
+                // for coredump targets.
+                return Ok(None);
+            }
+
             let thread_id = match get_thread_id(&thread, source) {
                 Ok(tid) => Some(tid),
                 Err(e) => {
@@ -467,7 +891,54 @@ macro_rules! get_stack_trace(
                     None
                 },
             };
-            Ok(Some(StackTrace{trace, pid: Some(pid), thread_id, time: Some(SystemTime::now()), on_cpu: Some(on_cpu)}))
+            let thread_name = get_thread_name(&thread, source).unwrap_or_else(|e| {
+                debug!("Couldn't get thread name: {}", e);
+                None
+            });
+            let mut trace = StackTrace {
+                trace: Vec::new(),
+                pid: Some(pid),
+                thread_id,
+                time: Some(SystemTime::now()),
+                on_cpu: Some(on_cpu),
+                holds_gvl: None,
+                thread_name,
+                rss: None,
+                monotonic_ns: None,
+            };
+            if stack_field(&thread) as usize == 0 {
+                trace.on_cpu = None;
+                let frames = vec!(RawFrame::Known(StackFrame::unknown_c_function()));
+                return Ok(Some((RawStackTrace { trace, frames }, vec!(None))));
+            }
+            let mut frames = Vec::new();
+            let mut control_frames = Vec::new();
+            let cfps = get_cfps(thread.cfp as usize, stack_base(&thread) as usize, source)?;
+            for cfp in cfps {
+                if cfp.iseq as usize == 0 {
+                    let method_entry = match ruby_global_symbols_address_location {
+                        Some(_) => get_cfunc_method_entry(&cfp, source),
+                        None => Err(format_err!("global symbol table address is unknown")),
+                    };
+                    frames.push(match method_entry {
+                        Ok(method_entry) => RawFrame::CFunc { method_entry },
+                        Err(e) => {
+                            debug!("Unknown C function: {:?}", e);
+                            RawFrame::Known(StackFrame::unknown_c_function())
+                        }
+                    });
+                    control_frames.push(None);
+                    continue;
+                }
+                let jit = is_jit_frame(&cfp);
+                if cfp.pc as usize == 0 && !jit {
+                    debug!("pc was 0. Not sure what that means, but skipping CFP");
+                    continue;
+                }
+                frames.push(RawFrame::Ruby { iseq: cfp.iseq as usize, pc: cfp.pc as usize, jit });
+                control_frames.push(Some(cfp));
+            }
+            Ok(Some((RawStackTrace { trace, frames }, control_frames)))
         }
 
         use proc_maps::{maps_contain_addr, MapRange};
@@ -509,7 +980,7 @@ macro_rules! get_stack_trace(
             }
 
             // finally, try to get an actual stack trace from the source and see if it works
-            get_stack_trace(candidate_thread_addr_ptr, 0, None, source, 0, false).is_ok()
+            get_stack_trace(candidate_thread_addr_ptr, 0, None, source, 0, false, &mut FrameCache::new()).is_ok()
         }
     )
 );
@@ -571,8 +1042,8 @@ macro_rules! get_thread_status_2_6_0(
 
 macro_rules! get_thread_id_1_9_0(
     () => (
-        fn get_thread_id<T>(thread_struct: &rb_thread_struct, _source: &T) -> Result<usize> {
-            Ok(thread_struct.thread_id as usize)
+        fn get_thread_id<T>(thread_struct: &rb_thread_struct, _source: &T) -> Result<u64> {
+            Ok(thread_struct.thread_id as u64)
         }
     )
 );
@@ -580,10 +1051,10 @@ macro_rules! get_thread_id_1_9_0(
 macro_rules! get_thread_id_2_5_0(
     () => (
         fn get_thread_id<T>(thread_struct: &rb_execution_context_struct, source: &T)
-                            -> Result<usize> where T: ProcessMemory {
+                            -> Result<u64> where T: ProcessMemory {
             let thread: rb_thread_struct = source.copy_struct(thread_struct.thread_ptr as usize)
                 .context("couldn't copy thread struct")?;
-            Ok(thread.thread_id as usize)
+            Ok(thread.thread_id as u64)
         }
     )
 );
@@ -591,7 +1062,7 @@ macro_rules! get_thread_id_2_5_0(
 macro_rules! get_thread_id_3_2_0(
     () => (
         fn get_thread_id<T>(thread_struct: &rb_execution_context_struct, source: &T)
-                            -> Result<usize> where T: ProcessMemory {
+                            -> Result<u64> where T: ProcessMemory {
             let thread: rb_thread_struct = source.copy_struct(thread_struct.thread_ptr as usize)
                 .context("couldn't copy thread struct")?;
             if thread.nt.is_null() {
@@ -599,7 +1070,33 @@ macro_rules! get_thread_id_3_2_0(
             }
             let native_thread: rb_native_thread = source.copy_struct(thread.nt as usize)
                 .context("couldn't copy native thread struct")?;
-            Ok(native_thread.thread_id as usize)
+            Ok(native_thread.thread_id as u64)
+        }
+    )
+);
+
+macro_rules! get_thread_name_1_9_0(
+    () => (
+        // Thread names aren't read for these Ruby versions
+        fn get_thread_name<T, S>(_thread_struct: &S, _source: &T) -> Result<Option<String>> {
+            Ok(None)
+        }
+    )
+);
+
+macro_rules! get_thread_name_3_0_0(
+    () => (
+        fn get_thread_name<T>(thread_struct: &rb_execution_context_struct, source: &T)
+                            -> Result<Option<String>> where T: ProcessMemory {
+            let thread: rb_thread_struct = source.copy_struct(thread_struct.thread_ptr as usize)
+                .context("couldn't copy thread struct")?;
+            // Unnamed threads have a name of nil. Special constants like nil are small immediate
+            // values, whereas a string is a pointer to an 8-byte-aligned heap slot.
+            let name = thread.name as usize;
+            if name < 0x100 || name % 8 != 0 {
+                return Ok(None);
+            }
+            get_ruby_string(name, source).map(Some)
         }
     )
 );
@@ -690,6 +1187,22 @@ macro_rules! rstring_as_array_3_1_0(
     )
 );
 
+macro_rules! rstring_heap_len_3_0_0(
+    () => (
+        fn rstring_heap_len(rstring: &RString) -> usize {
+            unsafe { rstring.as_.heap.len as usize }
+        }
+    )
+);
+
+macro_rules! rstring_heap_len_3_3_0(
+    () => (
+        fn rstring_heap_len(rstring: &RString) -> usize {
+            rstring.len as usize
+        }
+    )
+);
+
 macro_rules! get_ruby_string_1_9_1(
     () => (
         fn get_ruby_string<T>(
@@ -796,19 +1309,22 @@ macro_rules! get_stack_frame_1_9_1(
             iseq_struct: &rb_iseq_struct,
             cfp: &rb_control_frame_t,
             source: &T,
+            cache: &mut FrameCache,
             ) -> Result<StackFrame> where T: ProcessMemory {
-            Ok(StackFrame{
+            let lineno = match get_lineno(iseq_struct, cfp, source) {
+                Ok(lineno) => Some(lineno),
+                Err(e) => {
+                    warn!("couldn't get lineno: {}", e);
+                    None
+                },
+            };
+            let key = (cfp.iseq as usize, iseq_struct.name as usize, iseq_struct.filename as usize);
+            cache.get_or_read(key, lineno, || Ok(StackFrame{
                 name: get_ruby_string(iseq_struct.name as usize, source)?,
                 relative_path: get_ruby_string(iseq_struct.filename as usize, source)?,
                 absolute_path: None,
-                lineno: match get_lineno(iseq_struct, cfp, source) {
-                    Ok(lineno) => Some(lineno),
-                    Err(e) => {
-                        warn!("couldn't get lineno: {}", e);
-                        None
-                    },
-                }
-            })
+                lineno: None,
+            }))
         }
     )
 );
@@ -819,19 +1335,22 @@ macro_rules! get_stack_frame_1_9_2(
             iseq_struct: &rb_iseq_struct,
             cfp: &rb_control_frame_t,
             source: &T,
+            cache: &mut FrameCache,
             ) -> Result<StackFrame> where T: ProcessMemory {
-            Ok(StackFrame{
+            let lineno = match get_lineno(iseq_struct, cfp, source) {
+                Ok(lineno) => Some(lineno),
+                Err(e) => {
+                    warn!("couldn't get lineno: {}", e);
+                    None
+                },
+            };
+            let key = (cfp.iseq as usize, iseq_struct.name as usize, iseq_struct.filepath as usize);
+            cache.get_or_read(key, lineno, || Ok(StackFrame{
                 name: get_ruby_string(iseq_struct.name as usize, source)?,
                 relative_path: get_ruby_string(iseq_struct.filename as usize, source)?,
                 absolute_path: Some(get_ruby_string(iseq_struct.filepath as usize, source)?),
-                lineno: match get_lineno(iseq_struct, cfp, source) {
-                    Ok(lineno) => Some(lineno),
-                    Err(e) => {
-                        warn!("couldn't get lineno: {}", e);
-                        None
-                    },
-                }
-            })
+                lineno: None,
+            }))
         }
     )
 );
@@ -842,19 +1361,22 @@ macro_rules! get_stack_frame_2_0_0(
             iseq_struct: &rb_iseq_struct,
             cfp: &rb_control_frame_t,
             source: &T,
+            cache: &mut FrameCache,
         ) -> Result<StackFrame> where T: ProcessMemory {
-            Ok(StackFrame{
+            let lineno = match get_lineno(iseq_struct, cfp, source) {
+                Ok(lineno) => Some(lineno),
+                Err(e) => {
+                    warn!("couldn't get lineno: {}", e);
+                    None
+                },
+            };
+            let key = (cfp.iseq as usize, iseq_struct.location.label as usize, iseq_struct.location.absolute_path as usize);
+            cache.get_or_read(key, lineno, || Ok(StackFrame{
                 name: get_ruby_string(iseq_struct.location.label as usize, source)?,
                 relative_path: get_ruby_string(iseq_struct.location.path as usize, source)?,
                 absolute_path: Some(get_ruby_string(iseq_struct.location.absolute_path as usize, source)?),
-                lineno: match get_lineno(iseq_struct, cfp, source) {
-                    Ok(lineno) => Some(lineno),
-                    Err(e) => {
-                        warn!("couldn't get lineno: {}", e);
-                        None
-                    },
-                }
-            })
+                lineno: None,
+            }))
         }
     )
 );
@@ -865,21 +1387,24 @@ macro_rules! get_stack_frame_2_3_0(
             iseq_struct: &rb_iseq_struct,
             cfp: &rb_control_frame_t,
             source: &T,
+            cache: &mut FrameCache,
         ) -> Result<StackFrame> where T: ProcessMemory {
             let body: rb_iseq_constant_body = source.copy_struct(iseq_struct.body as usize)
                 .context(iseq_struct.body as usize)?;
-            Ok(StackFrame{
+            let lineno = match get_lineno(&body, cfp, source) {
+                Ok(lineno) => Some(lineno),
+                Err(e) => {
+                    warn!("couldn't get lineno: {}", e);
+                    None
+                },
+            };
+            let key = (iseq_struct.body as usize, body.location.label as usize, body.location.absolute_path as usize);
+            cache.get_or_read(key, lineno, || Ok(StackFrame{
                 name: get_ruby_string(body.location.label as usize, source)?,
                 relative_path: get_ruby_string(body.location.path as usize, source)?,
                 absolute_path: Some(get_ruby_string(body.location.absolute_path as usize, source)?),
-                lineno: match get_lineno(&body, cfp, source) {
-                    Ok(lineno) => Some(lineno),
-                    Err(e) => {
-                        warn!("couldn't get lineno: {}", e);
-                        None
-                    },
-                }
-            })
+                lineno: None,
+            }))
         }
     )
 );
@@ -890,30 +1415,40 @@ macro_rules! get_stack_frame_2_5_0(
             iseq_struct: &rb_iseq_struct,
             cfp: &rb_control_frame_t,
             source: &T,
+            cache: &mut FrameCache,
         ) -> Result<StackFrame> where T: ProcessMemory {
             if iseq_struct.body == std::ptr::null_mut() {
                 return Err(format_err!("iseq body is null"));
             }
             let body: rb_iseq_constant_body = source.copy_struct(iseq_struct.body as usize)
                 .context("couldn't copy rb_iseq_constant_body")?;
-            let rstring: RString = source.copy_struct(body.location.label as usize)
-                .context("couldn't copy RString")?;
-            let (path, absolute_path) = get_ruby_string_array(
-                body.location.pathobj as usize,
-                rstring.basic.klass as usize,
-                source
-            ).context("couldn't get ruby string from iseq body")?;
-            Ok(StackFrame{
-                name: get_ruby_string(body.location.label as usize, source)?,
-                relative_path: path,
-                absolute_path: Some(absolute_path),
-                lineno: match get_lineno(&body, cfp, source) {
+            // Frames run by YJIT don't have a pc until it's needed
+            let lineno = if cfp.pc.is_null() {
+                None
+            } else {
+                match get_lineno(&body, cfp, source) {
                     Ok(lineno) => Some(lineno),
                     Err(e) => {
                         warn!("couldn't get lineno: {}", e);
                         None
                     },
                 }
+            };
+            let key = (iseq_struct.body as usize, body.location.label as usize, body.location.pathobj as usize);
+            cache.get_or_read(key, lineno, || {
+                let rstring: RString = source.copy_struct(body.location.label as usize)
+                    .context("couldn't copy RString")?;
+                let (path, absolute_path) = get_ruby_string_array(
+                    body.location.pathobj as usize,
+                    rstring.basic.klass as usize,
+                    source
+                ).context("couldn't get ruby string from iseq body")?;
+                Ok(StackFrame{
+                    name: get_ruby_string(body.location.label as usize, source)?,
+                    relative_path: path,
+                    absolute_path: Some(absolute_path),
+                    lineno: None,
+                })
             })
         }
     )
@@ -1063,23 +1598,52 @@ macro_rules! get_lineno_2_6_0(
     () => (
         fn get_lineno<T>(
             iseq_struct: &rb_iseq_constant_body,
-            _cfp: &rb_control_frame_t,
+            cfp: &rb_control_frame_t,
             source: &T,
         ) -> Result<usize> where T: ProcessMemory {
-            let t_size = iseq_struct.insns_info.size as usize;
+            // `get_pos` is in bytes, but Ruby counts instruction positions in words. Dividing also
+            // keeps the step back to the current instruction that `get_pos` makes (see calc_pos
+            // in vm_backtrace.c).
+            let pos = get_pos(iseq_struct, cfp)? / std::mem::size_of::<VALUE>();
+            let info = &iseq_struct.insns_info;
+            let t_size = info.size as usize;
             if t_size == 0 {
-                Err(format_err!("line number is not available"))
-            } else if t_size == 1 {
-                let table: [iseq_insn_info_entry; 1] = source.copy_struct(iseq_struct.insns_info.body as usize)
-                    .context("couldn't copy instruction table")?;
-                Ok(table[0].line_no as usize)
-            } else {
-                // TODO: To handle this properly, we need to imitate ruby's succinct bit vector lookup.
-                // See https://github.com/rbspy/rbspy/issues/213#issuecomment-826363857
-                let table: Vec<iseq_insn_info_entry> = source.copy_vec(iseq_struct.insns_info.body as usize, t_size as usize)
-                    .context(iseq_struct.insns_info.body as usize)?;
-                Ok(table[t_size-1].line_no as usize)
+                return Err(format_err!("line number is not available"));
             }
+            let lookup = || -> Result<usize> {
+                if t_size == 1 || pos == 0 {
+                    Ok(0)
+                } else if !info.succ_index_table.is_null() {
+                    // This mirrors get_insn_info_succinct_bitvector in iseq.c
+                    crate::core::succ_index_table::lookup(info.succ_index_table as usize, pos, source)?
+                        .checked_sub(1)
+                        .ok_or_else(|| format_err!("invalid instruction info index"))
+                } else if !info.positions.is_null() {
+                    // The succinct index table replaces the positions once the iseq is compiled,
+                    // so only iseqs that are still being compiled have positions
+                    let positions: Vec<u32> = source.copy_vec(info.positions as usize, t_size)
+                        .context("couldn't copy instruction positions")?;
+                    Ok(positions.iter().take_while(|&&p| p as usize <= pos).count().saturating_sub(1))
+                } else {
+                    Err(format_err!("no instruction positions"))
+                }
+            };
+            // The last entry's line is at least in the right method, so it's better than nothing
+            let index = match lookup() {
+                Ok(index) if index < t_size => index,
+                Ok(index) => {
+                    debug!("instruction info index {} is out of range, using the last line", index);
+                    t_size - 1
+                }
+                Err(e) => {
+                    debug!("couldn't look up instruction info ({}), using the last line", e);
+                    t_size - 1
+                }
+            };
+            let entry: iseq_insn_info_entry = source
+                .copy_struct(info.body as usize + index * std::mem::size_of::<iseq_insn_info_entry>())
+                .context("couldn't copy instruction info")?;
+            Ok(entry.line_no as usize)
         }
     )
 );
@@ -1108,7 +1672,11 @@ macro_rules! get_cfps(
 
 macro_rules! get_cfunc_name_unsupported(
     () => (
-        fn get_cfunc_name<T: ProcessMemory>(_cfp: &rb_control_frame_t, _global_symbols_address: usize, _source: &T, _pid: Pid) -> Result<String> {
+        fn get_cfunc_method_entry<T: ProcessMemory>(_cfp: &rb_control_frame_t, _source: &T) -> Result<usize> {
+            return Err(format_err!("C function resolution is not supported for this version of Ruby").into());
+        }
+
+        fn get_method_entry_name<T: ProcessMemory>(_method_entry: usize, _global_symbols_address: usize, _source: &T) -> Result<String> {
             return Err(format_err!("C function resolution is not supported for this version of Ruby").into());
         }
     )
@@ -1134,14 +1702,13 @@ macro_rules! get_cfunc_name(
             }
         }
 
-        fn get_cfunc_name<T: ProcessMemory>(
+        /// Finds the address of the method entry of a C function's control frame
+        fn get_cfunc_method_entry<T: ProcessMemory>(
             cfp: &rb_control_frame_t,
-            global_symbols_address: usize,
             source: &T,
-            _pid: Pid
-        ) -> Result<String> {
-            // The logic in this function is adapted from the .gdbinit script in
-            // github.com/ruby/ruby, in particular the print_id function.
+        ) -> Result<usize> {
+            // The logic in this function and `get_method_entry_name` is adapted from the
+            // .gdbinit script in github.com/ruby/ruby, in particular the print_id function.
 
             let mut ep = cfp.ep as *mut usize;
             let frame_flag: usize = unsafe {
@@ -1171,8 +1738,16 @@ macro_rules! get_cfunc_name(
                     env_me_cref = source.copy_struct(ep.offset(-2) as usize).context(ep.offset(-2) as usize)?;
                 }
             }
+            Ok(env_me_cref)
+        }
 
-            let imemo: rb_method_entry_struct = source.copy_struct(env_me_cref).context(env_me_cref)?;
+        /// Looks up the name of the method that a method entry is for
+        fn get_method_entry_name<T: ProcessMemory>(
+            method_entry: usize,
+            global_symbols_address: usize,
+            source: &T,
+        ) -> Result<String> {
+            let imemo: rb_method_entry_struct = source.copy_struct(method_entry).context(method_entry)?;
             if imemo.def.is_null() {
                 return Err(format_err!("No method definition").into());
             }
@@ -1182,6 +1757,16 @@ macro_rules! get_cfunc_name(
                 return Err(format_err!("Not a method entry").into());
             }
 
+            let def: rb_method_definition_struct = source.copy_struct(imemo.def as usize).context(imemo.def as usize)?;
+            get_id_name(def.original_id as usize, global_symbols_address, source)
+        }
+
+        /// Looks up the name of a method or variable from its ID in the global symbol table
+        fn get_id_name<T: ProcessMemory>(
+            id: usize,
+            global_symbols_address: usize,
+            source: &T,
+        ) -> Result<String> {
             #[allow(non_camel_case_types)]
             type rb_id_serial_t = u32;
 
@@ -1197,17 +1782,15 @@ macro_rules! get_cfunc_name(
             }
 
             let global_symbols: rb_symbols_t = source.copy_struct(global_symbols_address as usize).context(global_symbols_address as usize)?;
-            let def: rb_method_definition_struct = source.copy_struct(imemo.def as usize).context(imemo.def as usize)?;
-            let method_id = def.original_id as usize;
 
             // rb_id_to_serial
-            let mut serial = method_id;
-            if method_id > ruby_method_ids_tLAST_OP_ID as usize {
-                serial = method_id >> ruby_id_types_RUBY_ID_SCOPE_SHIFT;
+            let mut serial = id;
+            if id > ruby_method_ids_tLAST_OP_ID as usize {
+                serial = id >> ruby_id_types_RUBY_ID_SCOPE_SHIFT;
             }
 
             if serial > global_symbols.last_id as usize {
-                return Err(format_err!("Invalid method ID").into());
+                return Err(format_err!("Invalid ID").into());
             }
 
             // ID_ENTRY_UNIT is defined in symbol.c, so not accessible by bindgen
@@ -1249,6 +1832,174 @@ macro_rules! get_cfunc_name(
     )
 );
 
+macro_rules! is_in_gc_unsupported(
+    () => (
+        pub fn is_in_gc<T: ProcessMemory>(_ruby_vm_address_ptr: usize, _source: &T) -> Result<bool> {
+            Ok(false)
+        }
+    )
+);
+
+macro_rules! is_in_gc_3_0_0(
+    () => (
+        /// Whether the garbage collector is running. `rb_objspace` is private to gc.c, so this reads
+        /// its `flags` bitfield by offset: it comes right after `malloc_params` (two `size_t`s), and
+        /// `during_gc` is its sixth bit. `rb_vm_struct` has native lock fields before `objspace`,
+        /// whose sizes vary by platform, so `vm.objspace` is only where the bindings say it is on
+        /// x86_64 Linux.
+        pub fn is_in_gc<T: ProcessMemory>(ruby_vm_address_ptr: usize, source: &T) -> Result<bool> {
+            if !cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+                return Ok(false);
+            }
+            let vm_addr: usize = source.copy_struct(ruby_vm_address_ptr)
+                .context("couldn't read Ruby VM pointer")?;
+            let vm: rb_vm_struct = source.copy_struct(vm_addr)
+                .context("couldn't read Ruby VM struct")?;
+            let [malloc_limit, _malloc_increase, flags]: [usize; 3] = source.copy_struct(vm.objspace as usize)
+                .context("couldn't read GC state")?;
+            // The malloc limit is never 0, which catches most reads from the wrong place
+            if malloc_limit == 0 {
+                return Err(format_err!("unexpected GC state"));
+            }
+            Ok((flags as u32) & (1 << 5) != 0)
+        }
+    )
+);
+
+macro_rules! is_jit_frame_unsupported(
+    () => (
+        pub fn is_jit_frame(_cfp: &rb_control_frame_t) -> bool {
+            false
+        }
+    )
+);
+
+macro_rules! is_jit_frame_3_3_0(
+    () => (
+        /// Whether YJIT pushed the frame, in which case it's running compiled code rather than
+        /// being interpreted. YJIT sets `jit_return` to where the frame returns to in its caller's
+        /// compiled code, and only saves the frame's `pc` when something might read it, so its
+        /// line number can be missing or out of date.
+        pub fn is_jit_frame(cfp: &rb_control_frame_t) -> bool {
+            !cfp.jit_return.is_null()
+        }
+    )
+);
+
+macro_rules! get_locals_unsupported(
+    () => (
+        fn get_locals<T: ProcessMemory>(
+            _iseq_struct: &rb_iseq_struct,
+            _cfp: &rb_control_frame_t,
+            _global_symbols_address: Option<usize>,
+            _source: &T,
+        ) -> Result<Vec<LocalVariable>> {
+            Err(format_err!("Reading local variables is not supported for this version of Ruby"))
+        }
+    )
+);
+
+macro_rules! get_locals_3_0_0(
+    () => (
+        fn get_locals<T: ProcessMemory>(
+            iseq_struct: &rb_iseq_struct,
+            cfp: &rb_control_frame_t,
+            global_symbols_address: Option<usize>,
+            source: &T,
+        ) -> Result<Vec<LocalVariable>> {
+            let global_symbols_address = global_symbols_address
+                .ok_or_else(|| format_err!("the global symbol table wasn't found"))?;
+            if iseq_struct.body.is_null() {
+                return Err(format_err!("iseq body is null"));
+            }
+            let body: rb_iseq_constant_body = source.copy_struct(iseq_struct.body as usize)
+                .context("couldn't copy rb_iseq_constant_body")?;
+            let size = body.local_table_size as usize;
+            if size == 0 || body.local_table.is_null() {
+                return Ok(Vec::new());
+            }
+            if size > 10_000 {
+                return Err(format_err!("invalid local table size: {}", size));
+            }
+            let ids: Vec<ID> = source.copy_vec(body.local_table as usize, size)
+                .context("couldn't copy local table")?;
+
+            // The locals are stored just below the environment data that cfp.ep points to (the
+            // method entry at ep[-2], the specval at ep[-1] and the frame flags at ep[0]), with
+            // local i at ep[-(size - i + 2)]. See the getlocal instruction in ruby's insns.def.
+            let first_local = (cfp.ep as usize)
+                .checked_sub((size + 2) * std::mem::size_of::<VALUE>())
+                .ok_or_else(|| format_err!("invalid environment pointer: {:?}", cfp.ep))?;
+            let values: Vec<VALUE> = source.copy_vec(first_local, size)
+                .context("couldn't copy locals")?;
+
+            let mut locals = Vec::new();
+            for (id, value) in ids.iter().zip(values.iter()) {
+                if locals.len() == super::MAX_LOCALS {
+                    break;
+                }
+                // Hidden locals, like the ones for anonymous arguments, don't have names
+                let name = match get_id_name(*id as usize, global_symbols_address, source) {
+                    Ok(name) => name,
+                    Err(e) => {
+                        debug!("Couldn't get local variable name: {:?}", e);
+                        continue;
+                    }
+                };
+                let value = describe_value(*value as usize, global_symbols_address, source);
+                locals.push(LocalVariable { name, value });
+            }
+            Ok(locals)
+        }
+
+        fn describe_value<T: ProcessMemory>(
+            value: usize,
+            global_symbols_address: usize,
+            source: &T,
+        ) -> String {
+            if let Some(description) = super::describe_immediate(value as u64) {
+                return description;
+            }
+            // Static symbols hold their ID above RUBY_SPECIAL_SHIFT
+            if value & 0xff == 0x0c {
+                return match get_id_name(value >> 8, global_symbols_address, source) {
+                    Ok(name) => format!(":{}", name),
+                    Err(_) => "#<Symbol>".to_string(),
+                };
+            }
+            if value & 0x07 != 0 {
+                return "#<unknown>".to_string();
+            }
+            let basic: RBasic = match source.copy_struct(value) {
+                Ok(basic) => basic,
+                Err(_) => return "#<unknown>".to_string(),
+            };
+            let ruby_type = basic.flags as usize & 0x1f;
+            if ruby_type == 0x05 /* T_STRING */ {
+                match get_short_string(value, source) {
+                    Ok(s) => return super::describe_string(&s),
+                    Err(e) => debug!("Couldn't get string local: {:?}", e),
+                }
+            }
+            format!("#<{}>", super::ruby_type_name(ruby_type))
+        }
+
+        fn get_short_string<T: ProcessMemory>(addr: usize, source: &T) -> Result<String> {
+            let rstring: RString = source.copy_struct(addr).context("couldn't copy rstring")?;
+            // See RSTRING_NOEMBED and RUBY_FL_USER1
+            let is_embedded_string = rstring.basic.flags & 1 << 13 == 0;
+            let max_bytes = super::MAX_LOCAL_STRING_LEN * 4;
+            if is_embedded_string || rstring_heap_len(&rstring) <= max_bytes {
+                return get_ruby_string(addr, source);
+            }
+            // Only the start of a long string is shown, so there's no need to copy all of it
+            let addr = unsafe { rstring.as_.heap.ptr as usize };
+            let bytes = source.copy(addr, max_bytes).context("couldn't copy ruby string from heap")?;
+            Ok(String::from_utf8_lossy(&bytes).into_owned())
+        }
+    )
+);
+
 ruby_version_v_1_9_1!(ruby_1_9_1_0);
 ruby_version_v_1_9_2_to_3!(ruby_1_9_2_0);
 ruby_version_v_1_9_2_to_3!(ruby_1_9_3_0);
@@ -1355,16 +2106,17 @@ ruby_version_v3_3_x!(ruby_3_3_4);
 ruby_version_v3_3_x!(ruby_3_3_5);
 ruby_version_v3_3_x!(ruby_3_3_6);
 ruby_version_v3_3_x!(ruby_3_3_7);
-ruby_version_v3_3_x!(ruby_3_4_0);
-ruby_version_v3_3_x!(ruby_3_4_1);
+ruby_version_v3_4_x!(ruby_3_4_0);
+ruby_version_v3_4_x!(ruby_3_4_1);
 
 #[cfg(not(debug_assertions))]
 #[cfg(test)]
 mod tests {
     use rbspy_testdata::*;
 
+    use crate::core::memory_snapshot::MemorySnapshot;
     use crate::core::ruby_version;
-    use crate::core::types::StackFrame;
+    use crate::core::types::{FrameCache, StackFrame, StackTrace};
 
     fn real_stack_trace_1_9_3() -> Vec<StackFrame> {
         vec![
@@ -1427,25 +2179,25 @@ mod tests {
                 name: "aaa".to_string(),
                 relative_path: "ci/ruby-programs/infinite.rb".to_string(),
                 absolute_path: Some("/vagrant/ci/ruby-programs/infinite.rb".to_string()),
-                lineno: Some(3),
+                lineno: Some(2),
             },
             StackFrame {
                 name: "bbb".to_string(),
                 relative_path: "ci/ruby-programs/infinite.rb".to_string(),
                 absolute_path: Some("/vagrant/ci/ruby-programs/infinite.rb".to_string()),
-                lineno: Some(7),
+                lineno: Some(6),
             },
             StackFrame {
                 name: "ccc".to_string(),
                 relative_path: "ci/ruby-programs/infinite.rb".to_string(),
                 absolute_path: Some("/vagrant/ci/ruby-programs/infinite.rb".to_string()),
-                lineno: Some(11),
+                lineno: Some(10),
             },
             StackFrame {
                 name: "block in <main>".to_string(),
                 relative_path: "ci/ruby-programs/infinite.rb".to_string(),
                 absolute_path: Some("/vagrant/ci/ruby-programs/infinite.rb".to_string()),
-                lineno: Some(15),
+                lineno: Some(14),
             },
             StackFrame {
                 name: "loop [c function]".to_string(),
@@ -1470,7 +2222,7 @@ mod tests {
                 absolute_path: Some(
                     "/home/acj/workspace/rbspy/ci/ruby-programs/infinite.rb".to_string(),
                 ),
-                lineno: Some(3),
+                lineno: Some(2),
             },
             StackFrame {
                 name: "bbb".to_string(),
@@ -1478,7 +2230,7 @@ mod tests {
                 absolute_path: Some(
                     "/home/acj/workspace/rbspy/ci/ruby-programs/infinite.rb".to_string(),
                 ),
-                lineno: Some(7),
+                lineno: Some(6),
             },
             StackFrame {
                 name: "ccc".to_string(),
@@ -1486,7 +2238,7 @@ mod tests {
                 absolute_path: Some(
                     "/home/acj/workspace/rbspy/ci/ruby-programs/infinite.rb".to_string(),
                 ),
-                lineno: Some(11),
+                lineno: Some(10),
             },
             StackFrame {
                 name: "block in <main>".to_string(),
@@ -1494,7 +2246,7 @@ mod tests {
                 absolute_path: Some(
                     "/home/acj/workspace/rbspy/ci/ruby-programs/infinite.rb".to_string(),
                 ),
-                lineno: Some(15),
+                lineno: Some(14),
             },
             StackFrame {
                 name: "loop [c function]".to_string(),
@@ -1519,7 +2271,7 @@ mod tests {
                 absolute_path: Some(
                     "/home/parallels/rbspy/ci/ruby-programs/infinite.rb".to_string(),
                 ),
-                lineno: Some(3),
+                lineno: Some(2),
             },
             StackFrame {
                 name: "bbb".to_string(),
@@ -1527,7 +2279,7 @@ mod tests {
                 absolute_path: Some(
                     "/home/parallels/rbspy/ci/ruby-programs/infinite.rb".to_string(),
                 ),
-                lineno: Some(7),
+                lineno: Some(6),
             },
             StackFrame {
                 name: "ccc".to_string(),
@@ -1535,7 +2287,7 @@ mod tests {
                 absolute_path: Some(
                     "/home/parallels/rbspy/ci/ruby-programs/infinite.rb".to_string(),
                 ),
-                lineno: Some(11),
+                lineno: Some(10),
             },
             StackFrame {
                 name: "block in <main>".to_string(),
@@ -1543,7 +2295,7 @@ mod tests {
                 absolute_path: Some(
                     "/home/parallels/rbspy/ci/ruby-programs/infinite.rb".to_string(),
                 ),
-                lineno: Some(15),
+                lineno: Some(14),
             },
             StackFrame {
                 name: "loop [c function]".to_string(),
@@ -1576,7 +2328,7 @@ mod tests {
                 absolute_path: Some(
                     "/home/runner/work/rbspy/rbspy/ci/ruby-programs/infinite.rb".to_string(),
                 ),
-                lineno: Some(3),
+                lineno: Some(2),
             },
             StackFrame {
                 name: "bbb".to_string(),
@@ -1584,7 +2336,7 @@ mod tests {
                 absolute_path: Some(
                     "/home/runner/work/rbspy/rbspy/ci/ruby-programs/infinite.rb".to_string(),
                 ),
-                lineno: Some(7),
+                lineno: Some(6),
             },
             StackFrame {
                 name: "ccc".to_string(),
@@ -1592,7 +2344,7 @@ mod tests {
                 absolute_path: Some(
                     "/home/runner/work/rbspy/rbspy/ci/ruby-programs/infinite.rb".to_string(),
                 ),
-                lineno: Some(11),
+                lineno: Some(10),
             },
             StackFrame {
                 name: "block in <main>".to_string(),
@@ -1600,13 +2352,13 @@ mod tests {
                 absolute_path: Some(
                     "/home/runner/work/rbspy/rbspy/ci/ruby-programs/infinite.rb".to_string(),
                 ),
-                lineno: Some(15),
+                lineno: Some(14),
             },
             StackFrame {
                 name: "loop".to_string(),
                 relative_path: "<internal:kernel>".to_string(),
                 absolute_path: Some("unknown".to_string()),
-                lineno: Some(192),
+                lineno: Some(187),
             },
         ]
     }
@@ -1711,6 +2463,7 @@ mod tests {
             &coredump_1_9_3(),
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -1728,6 +2481,7 @@ mod tests {
             &coredump_2_1_6(),
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -1746,6 +2500,7 @@ mod tests {
             &coredump_2_1_6_c_function(),
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -1763,6 +2518,7 @@ mod tests {
             &coredump_2_4_0(),
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -1780,6 +2536,7 @@ mod tests {
             &coredump_2_5_0(),
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -1798,6 +2555,7 @@ mod tests {
             &coredump_2_7_2(),
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -1816,6 +2574,7 @@ mod tests {
             &coredump_2_7_2(),
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -1834,6 +2593,7 @@ mod tests {
             &coredump_2_7_2(),
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -1852,6 +2612,7 @@ mod tests {
             &coredump_2_7_2(),
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -1870,6 +2631,7 @@ mod tests {
             &coredump_2_7_2(),
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -1888,6 +2650,7 @@ mod tests {
             &coredump_2_7_2(),
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -1906,6 +2669,7 @@ mod tests {
             &coredump_2_7_2(),
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -1925,6 +2689,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -1944,6 +2709,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -1963,6 +2729,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -1982,6 +2749,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2001,6 +2769,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2020,6 +2789,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2039,6 +2809,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2058,6 +2829,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2077,6 +2849,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2096,6 +2869,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2115,6 +2889,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2134,6 +2909,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2153,6 +2929,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2172,6 +2949,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2191,6 +2969,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2210,6 +2989,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2229,6 +3009,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2248,6 +3029,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2267,6 +3049,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2286,6 +3069,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2305,6 +3089,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap();
         assert_eq!(real_stack_trace_3_2_0(), stack_trace.unwrap().trace);
@@ -2323,11 +3108,24 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap();
         assert_eq!(real_stack_trace_3_2_0(), stack_trace.unwrap().trace);
     }
 
+    #[test]
+    fn test_is_jit_frame() {
+        // Safety: the control frame is plain data, so all zeroes is a valid (interpreted) frame
+        let mut cfp: bindings::ruby_3_3_0::rb_control_frame_t = unsafe { std::mem::zeroed() };
+        assert!(!ruby_version::ruby_3_3_0::is_jit_frame(&cfp));
+        cfp.jit_return = 0x1000 as *mut std::os::raw::c_void;
+        assert!(ruby_version::ruby_3_3_0::is_jit_frame(&cfp));
+
+        let cfp: bindings::ruby_3_2_0::rb_control_frame_t = unsafe { std::mem::zeroed() };
+        assert!(!ruby_version::ruby_3_2_0::is_jit_frame(&cfp));
+    }
+
     #[cfg(target_pointer_width = "64")]
     #[test]
     fn test_get_ruby_stack_trace_3_3_0() {
@@ -2341,12 +3139,72 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
         assert_eq!(real_stack_trace_3_3_0(), stack_trace.trace);
     }
 
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_get_deferred_stack_trace_3_3_0() {
+        // Naming the frames of a deferred trace from the memory that was captured while recording
+        // gives the same trace, with the same metadata, as reading it live
+        let source = coredump_3_3_0();
+        let vm_addr = 0x7f7ff21f1868;
+        let global_symbols_addr = Some(0x7f7ff21e0c60);
+        let live = ruby_version::ruby_3_3_0::get_stack_trace::<CoreDump>(
+            0,
+            vm_addr,
+            global_symbols_addr,
+            &source,
+            0,
+            false,
+            &mut FrameCache::new(),
+        )
+        .unwrap()
+        .unwrap();
+        let raw = ruby_version::ruby_3_3_0::get_raw_stack_trace::<CoreDump>(
+            0,
+            vm_addr,
+            global_symbols_addr,
+            &source,
+            0,
+            false,
+        )
+        .unwrap()
+        .unwrap();
+
+        // Like when recording, frames that can't be named from the process are skipped, and
+        // so are frames whose memory wasn't captured when they're resolved
+        let mut snapshot = MemorySnapshot::new();
+        for frame in &raw.frames {
+            let captured = ruby_version::ruby_3_3_0::capture_frame_memory::<CoreDump>(
+                frame,
+                global_symbols_addr,
+                &source,
+            );
+            for (addr, bytes) in captured.unwrap_or_default() {
+                snapshot.add(addr, bytes);
+            }
+        }
+        let frames: Vec<StackFrame> = raw
+            .frames
+            .iter()
+            .filter_map(|frame| {
+                ruby_version::ruby_3_3_0::resolve_frame(frame, global_symbols_addr, &snapshot).ok()
+            })
+            .collect();
+        assert!(!frames.is_empty());
+        let deferred = StackTrace {
+            trace: frames,
+            time: live.time,
+            ..raw.trace
+        };
+        assert_eq!(live, deferred);
+    }
+
     #[cfg(target_pointer_width = "64")]
     #[test]
     fn test_get_ruby_stack_trace_3_3_1() {
@@ -2360,6 +3218,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2379,6 +3238,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap()
         .unwrap();
@@ -2398,6 +3258,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap();
         assert_eq!(real_stack_trace_3_3_0(), stack_trace.unwrap().trace);
@@ -2416,6 +3277,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap();
         assert_eq!(real_stack_trace_3_3_0(), stack_trace.unwrap().trace);
@@ -2434,6 +3296,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap();
         assert_eq!(real_stack_trace_3_3_0(), stack_trace.unwrap().trace);
@@ -2452,6 +3315,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap();
         assert_eq!(real_stack_trace_3_3_0(), stack_trace.unwrap().trace);
@@ -2470,6 +3334,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap();
         assert_eq!(real_stack_trace_3_3_0(), stack_trace.unwrap().trace);
@@ -2488,6 +3353,7 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap();
         assert_eq!(real_stack_trace_3_3_0(), stack_trace.unwrap().trace);
@@ -2506,8 +3372,274 @@ mod tests {
             &source,
             0,
             false,
+            &mut FrameCache::new(),
         )
         .unwrap();
         assert_eq!(real_stack_trace_3_3_0(), stack_trace.unwrap().trace);
     }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_get_all_ruby_stack_traces_2_4_0() {
+        // The programs that were dumped only have a main thread
+        let stack_traces = ruby_version::ruby_2_4_0::get_all_stack_traces::<CoreDump>(
+            0x55df44959920,
+            0,
+            None,
+            &coredump_2_4_0(),
+            0,
+            &mut FrameCache::new(),
+        )
+        .unwrap();
+        assert_eq!(stack_traces.len(), 1);
+        assert_eq!(real_stack_trace(), stack_traces[0].trace);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_get_all_ruby_stack_traces_2_5_0() {
+        let stack_traces = ruby_version::ruby_2_5_0::get_all_stack_traces::<CoreDump>(
+            0x55dd8c3b7758,
+            0,
+            None,
+            &coredump_2_5_0(),
+            0,
+            &mut FrameCache::new(),
+        )
+        .unwrap();
+        assert_eq!(stack_traces.len(), 1);
+        assert_eq!(real_stack_trace(), stack_traces[0].trace);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_get_all_ruby_stack_traces_2_7_2() {
+        let stack_traces = ruby_version::ruby_2_7_2::get_all_stack_traces::<CoreDump>(
+            0x7fdd8d626070,
+            0,
+            Some(0x7fdd8d60eb80),
+            &coredump_2_7_2(),
+            0,
+            &mut FrameCache::new(),
+        )
+        .unwrap();
+        assert_eq!(stack_traces.len(), 1);
+        assert_eq!(real_stack_trace_2_7_2(), stack_traces[0].trace);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_get_all_ruby_stack_traces_3_0_0() {
+        let stack_traces = ruby_version::ruby_3_0_0::get_all_stack_traces::<CoreDump>(
+            0,
+            0x7fdacdab7470,
+            Some(0x7fdacdaa9d80),
+            &coredump_3_0_0(),
+            0,
+            &mut FrameCache::new(),
+        )
+        .unwrap();
+        assert_eq!(stack_traces.len(), 1);
+        assert_eq!(real_stack_trace_2_7_2(), stack_traces[0].trace);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_get_all_ruby_stack_traces_3_1_0() {
+        let stack_traces = ruby_version::ruby_3_1_0::get_all_stack_traces::<CoreDump>(
+            0,
+            0x7f0dc0c83c58,
+            Some(0x7f0dc0c75e80),
+            &coredump_3_1_0(),
+            0,
+            &mut FrameCache::new(),
+        )
+        .unwrap();
+        assert_eq!(stack_traces.len(), 1);
+        assert_eq!(real_stack_trace_3_1_0(), stack_traces[0].trace);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_get_all_ruby_stack_traces_3_2_0() {
+        let stack_traces = ruby_version::ruby_3_2_0::get_all_stack_traces::<CoreDump>(
+            0,
+            0xffffb8034578,
+            Some(0xffffb8025340),
+            &coredump_3_2_0(),
+            0,
+            &mut FrameCache::new(),
+        )
+        .unwrap();
+        assert_eq!(stack_traces.len(), 1);
+        assert_eq!(real_stack_trace_3_2_0(), stack_traces[0].trace);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_get_all_ruby_stack_traces_3_3_0() {
+        let stack_traces = ruby_version::ruby_3_3_0::get_all_stack_traces::<CoreDump>(
+            0,
+            0x7f7ff21f1868,
+            Some(0x7f7ff21e0c60),
+            &coredump_3_3_0(),
+            0,
+            &mut FrameCache::new(),
+        )
+        .unwrap();
+        assert_eq!(stack_traces.len(), 1);
+        assert_eq!(real_stack_trace_3_3_0(), stack_traces[0].trace);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_get_all_ruby_stack_traces_unsupported() {
+        assert!(ruby_version::ruby_2_1_6::get_all_stack_traces::<CoreDump>(
+            0x562658abd7f0,
+            0,
+            None,
+            &coredump_2_1_6(),
+            0,
+            &mut FrameCache::new(),
+        )
+        .is_err());
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_get_ruby_stack_trace_with_locals_3_1_0() {
+        let source = coredump_3_1_0();
+        let vm_addr = 0x7f0dc0c83c58;
+        let global_symbols_addr = Some(0x7f0dc0c75e80);
+        let (stack_trace, locals) =
+            ruby_version::ruby_3_1_0::get_stack_trace_with_locals::<CoreDump>(
+                0,
+                vm_addr,
+                global_symbols_addr,
+                &source,
+                0,
+                false,
+                &mut FrameCache::new(),
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(stack_trace.trace.len(), locals.len());
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_get_ruby_stack_trace_with_locals_unsupported() {
+        let (stack_trace, locals) =
+            ruby_version::ruby_2_1_6::get_stack_trace_with_locals::<CoreDump>(
+                0x562658abd7f0,
+                0,
+                None,
+                &coredump_2_1_6(),
+                0,
+                false,
+                &mut FrameCache::new(),
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(stack_trace.trace.len(), locals.len());
+        assert!(locals.iter().all(|locals| locals.is_empty()));
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[test]
+    fn test_is_in_gc() {
+        use crate::core::process::ProcessMemory;
+
+        // Memory made of a few regions, by start address
+        struct Regions(Vec<(usize, Vec<u8>)>);
+
+        impl ProcessMemory for Regions {
+            fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), remoteprocess::Error> {
+                for (start, bytes) in &self.0 {
+                    if addr >= *start && addr + buf.len() <= start + bytes.len() {
+                        buf.copy_from_slice(&bytes[addr - start..addr - start + buf.len()]);
+                        return Ok(());
+                    }
+                }
+                Err(remoteprocess::Error::Other(format!(
+                    "bad address {:#x}",
+                    addr
+                )))
+            }
+        }
+
+        let (vm_ptr_addr, vm_addr, objspace_addr) = (0x1000usize, 0x2000usize, 0x100000usize);
+        let mut vm: bindings::ruby_3_3_0::rb_vm_struct = unsafe { std::mem::zeroed() };
+        vm.objspace = objspace_addr as *mut _;
+        let vm = unsafe {
+            std::slice::from_raw_parts(&vm as *const _ as *const u8, std::mem::size_of_val(&vm))
+        };
+        let memory = |malloc_limit: usize, flags: usize| {
+            let objspace = [malloc_limit, 0, flags]
+                .iter()
+                .flat_map(|word| word.to_ne_bytes())
+                .collect();
+            Regions(vec![
+                (vm_ptr_addr, vm_addr.to_ne_bytes().to_vec()),
+                (vm_addr, vm.to_vec()),
+                (objspace_addr, objspace),
+            ])
+        };
+        let is_in_gc = |memory: &Regions| ruby_version::ruby_3_3_0::is_in_gc(vm_ptr_addr, memory);
+
+        assert!(!is_in_gc(&memory(16 << 20, 0)).unwrap());
+        // `during_gc`
+        assert!(is_in_gc(&memory(16 << 20, 1 << 5)).unwrap());
+        // Marking, but between the steps of an incremental GC
+        assert!(!is_in_gc(&memory(16 << 20, 1)).unwrap());
+        assert!(is_in_gc(&memory(0, 1 << 5)).is_err());
+        // Ruby 3.4's GC has a different layout
+        assert!(
+            !ruby_version::ruby_3_4_0::is_in_gc(vm_ptr_addr, &memory(16 << 20, 1 << 5)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_describe_immediate() {
+        assert_eq!(
+            ruby_version::describe_immediate(0x00),
+            Some("false".to_string())
+        );
+        assert_eq!(
+            ruby_version::describe_immediate(0x08),
+            Some("nil".to_string())
+        );
+        assert_eq!(
+            ruby_version::describe_immediate(0x04),
+            Some("nil".to_string())
+        );
+        assert_eq!(
+            ruby_version::describe_immediate(0x14),
+            Some("true".to_string())
+        );
+        // Fixnums are 2n + 1
+        assert_eq!(ruby_version::describe_immediate(85), Some("42".to_string()));
+        assert_eq!(
+            ruby_version::describe_immediate(-5i64 as u64),
+            Some("-3".to_string())
+        );
+        // Flonums are the double's bits rotated left by 3, with the low bits set to 0b10
+        let flonum = |f: f64| (f.to_bits().rotate_left(3) & !3) | 2;
+        assert_eq!(
+            ruby_version::describe_immediate(flonum(1.5)),
+            Some("1.5".to_string())
+        );
+        assert_eq!(
+            ruby_version::describe_immediate(0x8000_0000_0000_0002),
+            Some("0.0".to_string())
+        );
+        // Pointers to objects aren't immediates
+        assert_eq!(ruby_version::describe_immediate(0x7f0dc0c83c58), None);
+
+        assert_eq!(ruby_version::describe_string("abc"), "\"abc\"");
+        assert_eq!(
+            ruby_version::describe_string(&"a".repeat(50)),
+            format!("{:?}...", "a".repeat(40))
+        );
+    }
 }
@@ -0,0 +1,153 @@
+/// Reading Ruby's succinct bit vector (`struct succ_index_table` in iseq.c), which Ruby 2.6+ uses
+/// to find the instruction info entry, and so the line number, for an instruction position.
+///
+/// The bit vector has a bit set for each position where a new instruction info entry starts. A
+/// lookup returns the number of set bits at or before the position (its rank), and the entry for
+/// the position is at `rank - 1`. The ranks of the first `IMMEDIATE_TABLE_SIZE` positions are
+/// stored directly, 7 bits each. After that, the bits are split into blocks of 512, each of which
+/// stores the rank at its start and the ranks at the start of its 64-bit words.
+use anyhow::{Context, Result};
+
+use crate::core::process::ProcessMemory;
+
+const IMMEDIATE_TABLE_SIZE: usize = 54;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SuccDictBlock {
+    rank: u32,
+    small_block_ranks: u64,
+    bits: [u64; 8],
+}
+
+/// Returns the rank of instruction position `pos` in the table at `table_addr`.
+pub fn lookup<T: ProcessMemory>(table_addr: usize, pos: usize, source: &T) -> Result<usize> {
+    if pos < IMMEDIATE_TABLE_SIZE {
+        let imm_part: [u64; IMMEDIATE_TABLE_SIZE / 9] = source
+            .copy_struct(table_addr)
+            .context("couldn't copy succinct index table")?;
+        return Ok(((imm_part[pos / 9] >> ((pos % 9) * 7)) & 0x7f) as usize);
+    }
+    let block_index = (pos - IMMEDIATE_TABLE_SIZE) / 512;
+    let block_addr = table_addr
+        + std::mem::size_of::<[u64; IMMEDIATE_TABLE_SIZE / 9]>()
+        + block_index * std::mem::size_of::<SuccDictBlock>();
+    let block: SuccDictBlock = source
+        .copy_struct(block_addr)
+        .context("couldn't copy succinct index table block")?;
+    let bit = (pos - IMMEDIATE_TABLE_SIZE) % 512;
+    let small_block = bit / 64;
+    let small_block_rank = match small_block {
+        0 => 0,
+        i => (block.small_block_ranks >> ((i - 1) * 9)) & 0x1ff,
+    };
+    // Count the set bits up to and including this position's bit
+    let popcount = (block.bits[small_block] << (63 - bit % 64)).count_ones();
+    Ok(block.rank as usize + small_block_rank as usize + popcount as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::succ_index_table::*;
+
+    const BASE: usize = 0x1000;
+
+    struct Memory(Vec<u8>);
+
+    impl ProcessMemory for Memory {
+        fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), remoteprocess::Error> {
+            let start = addr - BASE;
+            buf.copy_from_slice(&self.0[start..start + buf.len()]);
+            Ok(())
+        }
+    }
+
+    // Builds a table the same way as `succ_index_table_create` in Ruby's iseq.c
+    fn create(max_pos: usize, positions: &[usize]) -> Memory {
+        let imm_size = (if max_pos < IMMEDIATE_TABLE_SIZE {
+            max_pos + 8
+        } else {
+            IMMEDIATE_TABLE_SIZE
+        }) / 9;
+        let succ_size = (if max_pos < IMMEDIATE_TABLE_SIZE {
+            0
+        } else {
+            max_pos - IMMEDIATE_TABLE_SIZE + 511
+        }) / 512;
+        let mut imm_part = [0u64; IMMEDIATE_TABLE_SIZE / 9];
+        let mut r = 0;
+        for (j, imm) in imm_part.iter_mut().enumerate().take(imm_size) {
+            for i in 0..9 {
+                if r < positions.len() && positions[r] == j * 9 + i {
+                    r += 1;
+                }
+                *imm |= (r as u64) << (7 * i);
+            }
+        }
+        let mut data: Vec<u8> = imm_part.iter().flat_map(|v| v.to_ne_bytes()).collect();
+        for k in 0..succ_size {
+            let mut block = SuccDictBlock {
+                rank: r as u32,
+                small_block_ranks: 0,
+                bits: [0; 8],
+            };
+            let mut small_rank = 0;
+            for j in 0..8 {
+                if j > 0 {
+                    block.small_block_ranks |= (small_rank as u64) << (9 * (j - 1));
+                }
+                for i in 0..64 {
+                    if r < positions.len()
+                        && positions[r] == k * 512 + j * 64 + i + IMMEDIATE_TABLE_SIZE
+                    {
+                        block.bits[j] |= 1 << i;
+                        r += 1;
+                    }
+                }
+                small_rank += block.bits[j].count_ones();
+            }
+            // The rank is followed by padding, and then the 64-bit fields
+            let mut bytes = vec![0; std::mem::size_of::<SuccDictBlock>()];
+            bytes[..4].copy_from_slice(&block.rank.to_ne_bytes());
+            let words = std::iter::once(block.small_block_ranks).chain(block.bits);
+            let start = bytes.len() - 9 * 8;
+            for (i, word) in words.enumerate() {
+                bytes[start + i * 8..start + (i + 1) * 8].copy_from_slice(&word.to_ne_bytes());
+            }
+            data.extend_from_slice(&bytes);
+        }
+        Memory(data)
+    }
+
+    #[test]
+    fn test_lookup() {
+        // The instruction positions where each line of a method starts
+        let positions = [0, 3, 10, 60, 100, 130, 700];
+        let lines = [1, 2, 3, 4, 5, 6, 7];
+        let memory = create(800, &positions);
+        let line = |pos: usize| lines[lookup(BASE, pos, &memory).unwrap() - 1];
+
+        let cases = [
+            (0, 1),
+            (2, 1),
+            (3, 2),
+            (9, 2),
+            (10, 3),
+            (53, 3),
+            (54, 3),
+            (60, 4),
+            (99, 4),
+            (100, 5),
+            (129, 5),
+            (130, 6),
+            (565, 6),
+            (566, 6),
+            (699, 6),
+            (700, 7),
+            (800, 7),
+        ];
+        for (pos, expected) in cases {
+            assert_eq!(line(pos), expected, "position {}", pos);
+        }
+    }
+}
@@ -6,658 +6,1512 @@ pub struct RubyVersion {
     pub get_execution_context_fn: crate::core::types::GetExecutionContextFn,
     pub is_maybe_thread_fn: crate::core::types::IsMaybeThreadFn,
     pub get_stack_trace_fn: crate::core::types::StackTraceFn,
+    pub get_all_stack_traces_fn: crate::core::types::AllStackTracesFn,
+    pub get_stack_trace_with_locals_fn: crate::core::types::StackTraceWithLocalsFn,
+    pub get_core_dump_stack_trace_fn: crate::core::types::CoreDumpStackTraceFn,
+    pub get_raw_stack_trace_fn: crate::core::types::RawStackTraceFn,
+    pub resolve_frame_fn: crate::core::types::ResolveFrameFn,
+    pub capture_frame_fn: crate::core::types::CaptureFrameFn,
 }
 
-pub fn get(v: &str) -> Result<RubyVersion> {
-    match v {
-        "1.9.1" => Ok(RubyVersion {
+/// Finds the functions for reading the memory of a process running Ruby `v`. Versions are matched
+/// by their numbers, so e.g. 2.10.0 can't be mistaken for a version before 2.9.0.
+///
+/// Patch releases almost never change the structs that rbspy reads, so a version that's newer
+/// than any supported release in its minor series (e.g. 3.3.10 when 3.3.9 is the newest) falls
+/// back to the newest supported release in that series, with a warning. `--force-version` can
+/// still be used to pick a version explicitly.
+pub fn get(v: &Version) -> Result<RubyVersion> {
+    if let Some(ruby_version) = get_exact(v.major, v.minor, v.patch) {
+        return Ok(ruby_version);
+    }
+    let fallback = (0..v.patch)
+        .rev()
+        .find_map(|patch| get_exact(v.major, v.minor, patch));
+    match fallback {
+        Some(ruby_version) => {
+            warn!(
+                "rbspy doesn't support Ruby {} yet, so assuming that it's laid out like Ruby {}",
+                v, ruby_version.semver_version
+            );
+            Ok(RubyVersion {
+                semver_version: v.clone(),
+                ..ruby_version
+            })
+        }
+        None => Err(format_err!("rbspy doesn't support Ruby {} yet. If this is a new version of Ruby, you can try using `--force-version` with the closest supported version number.", v)),
+    }
+}
+
+fn get_exact(major: u64, minor: u64, patch: u64) -> Option<RubyVersion> {
+    match (major, minor, patch) {
+        (1, 9, 1) => Some(RubyVersion {
             semver_version: Version::new(1, 9, 1),
             get_execution_context_fn: super::ruby_version::ruby_1_9_1_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_1_9_1_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_1_9_1_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_1_9_1_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_1_9_1_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_1_9_1_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_1_9_1_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_1_9_1_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_1_9_1_0::capture_frame_memory,
         }),
-        "1.9.2" => Ok(RubyVersion {
+        (1, 9, 2) => Some(RubyVersion {
             semver_version: Version::new(1, 9, 2),
             get_execution_context_fn: super::ruby_version::ruby_1_9_2_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_1_9_2_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_1_9_2_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_1_9_2_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_1_9_2_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_1_9_2_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_1_9_2_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_1_9_2_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_1_9_2_0::capture_frame_memory,
         }),
-        "1.9.3" => Ok(RubyVersion {
+        (1, 9, 3) => Some(RubyVersion {
             semver_version: Version::new(1, 9, 3),
             get_execution_context_fn: super::ruby_version::ruby_1_9_3_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_1_9_3_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_1_9_3_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_1_9_3_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_1_9_3_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_1_9_3_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_1_9_3_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_1_9_3_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_1_9_3_0::capture_frame_memory,
         }),
-        "2.0.0" => Ok(RubyVersion {
+        (2, 0, 0) => Some(RubyVersion {
             semver_version: Version::new(2, 0, 0),
             get_execution_context_fn: super::ruby_version::ruby_2_0_0_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_0_0_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_0_0_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_0_0_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_0_0_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_0_0_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_0_0_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_0_0_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_0_0_0::capture_frame_memory,
         }),
-        "2.1.0" => Ok(RubyVersion {
+        (2, 1, 0) => Some(RubyVersion {
             semver_version: Version::new(2, 1, 0),
             get_execution_context_fn: super::ruby_version::ruby_2_1_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_1_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_1_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_1_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_1_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_1_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_1_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_1_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_1_0::capture_frame_memory,
         }),
-        "2.1.1" => Ok(RubyVersion {
+        (2, 1, 1) => Some(RubyVersion {
             semver_version: Version::new(2, 1, 1),
             get_execution_context_fn: super::ruby_version::ruby_2_1_1::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_1_1::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_1_1::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_1_1::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_1_1::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_1_1::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_1_1::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_1_1::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_1_1::capture_frame_memory,
         }),
-        "2.1.2" => Ok(RubyVersion {
+        (2, 1, 2) => Some(RubyVersion {
             semver_version: Version::new(2, 1, 2),
             get_execution_context_fn: super::ruby_version::ruby_2_1_2::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_1_2::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_1_2::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_1_2::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_1_2::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_1_2::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_1_2::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_1_2::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_1_2::capture_frame_memory,
         }),
-        "2.1.3" => Ok(RubyVersion {
+        (2, 1, 3) => Some(RubyVersion {
             semver_version: Version::new(2, 1, 3),
             get_execution_context_fn: super::ruby_version::ruby_2_1_3::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_1_3::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_1_3::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_1_3::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_1_3::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_1_3::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_1_3::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_1_3::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_1_3::capture_frame_memory,
         }),
-        "2.1.4" => Ok(RubyVersion {
+        (2, 1, 4) => Some(RubyVersion {
             semver_version: Version::new(2, 1, 4),
             get_execution_context_fn: super::ruby_version::ruby_2_1_4::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_1_4::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_1_4::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_1_4::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_1_4::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_1_4::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_1_4::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_1_4::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_1_4::capture_frame_memory,
         }),
-        "2.1.5" => Ok(RubyVersion {
+        (2, 1, 5) => Some(RubyVersion {
             semver_version: Version::new(2, 1, 5),
             get_execution_context_fn: super::ruby_version::ruby_2_1_5::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_1_5::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_1_5::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_1_5::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_1_5::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_1_5::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_1_5::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_1_5::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_1_5::capture_frame_memory,
         }),
-        "2.1.6" => Ok(RubyVersion {
+        (2, 1, 6) => Some(RubyVersion {
             semver_version: Version::new(2, 1, 6),
             get_execution_context_fn: super::ruby_version::ruby_2_1_6::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_1_6::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_1_6::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_1_6::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_1_6::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_1_6::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_1_6::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_1_6::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_1_6::capture_frame_memory,
         }),
-        "2.1.7" => Ok(RubyVersion {
+        (2, 1, 7) => Some(RubyVersion {
             semver_version: Version::new(2, 1, 7),
             get_execution_context_fn: super::ruby_version::ruby_2_1_7::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_1_7::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_1_7::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_1_7::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_1_7::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_1_7::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_1_7::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_1_7::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_1_7::capture_frame_memory,
         }),
-        "2.1.8" => Ok(RubyVersion {
+        (2, 1, 8) => Some(RubyVersion {
             semver_version: Version::new(2, 1, 8),
             get_execution_context_fn: super::ruby_version::ruby_2_1_8::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_1_8::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_1_8::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_1_8::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_1_8::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_1_8::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_1_8::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_1_8::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_1_8::capture_frame_memory,
         }),
-        "2.1.9" => Ok(RubyVersion {
+        (2, 1, 9) => Some(RubyVersion {
             semver_version: Version::new(2, 1, 9),
             get_execution_context_fn: super::ruby_version::ruby_2_1_9::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_1_9::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_1_9::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_1_9::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_1_9::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_1_9::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_1_9::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_1_9::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_1_9::capture_frame_memory,
         }),
-        "2.1.10" => Ok(RubyVersion {
+        (2, 1, 10) => Some(RubyVersion {
             semver_version: Version::new(2, 1, 10),
             get_execution_context_fn: super::ruby_version::ruby_2_1_10::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_1_10::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_1_10::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_1_10::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_1_10::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_1_10::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_1_10::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_1_10::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_1_10::capture_frame_memory,
         }),
-        "2.2.0" => Ok(RubyVersion {
+        (2, 2, 0) => Some(RubyVersion {
             semver_version: Version::new(2, 2, 0),
             get_execution_context_fn: super::ruby_version::ruby_2_2_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_2_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_2_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_2_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_2_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_2_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_2_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_2_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_2_0::capture_frame_memory,
         }),
-        "2.2.1" => Ok(RubyVersion {
+        (2, 2, 1) => Some(RubyVersion {
             semver_version: Version::new(2, 2, 1),
             get_execution_context_fn: super::ruby_version::ruby_2_2_1::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_2_1::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_2_1::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_2_1::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_2_1::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_2_1::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_2_1::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_2_1::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_2_1::capture_frame_memory,
         }),
-        "2.2.2" => Ok(RubyVersion {
+        (2, 2, 2) => Some(RubyVersion {
             semver_version: Version::new(2, 2, 2),
             get_execution_context_fn: super::ruby_version::ruby_2_2_2::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_2_2::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_2_2::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_2_2::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_2_2::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_2_2::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_2_2::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_2_2::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_2_2::capture_frame_memory,
         }),
-        "2.2.3" => Ok(RubyVersion {
+        (2, 2, 3) => Some(RubyVersion {
             semver_version: Version::new(2, 2, 3),
             get_execution_context_fn: super::ruby_version::ruby_2_2_3::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_2_3::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_2_3::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_2_3::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_2_3::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_2_3::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_2_3::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_2_3::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_2_3::capture_frame_memory,
         }),
-        "2.2.4" => Ok(RubyVersion {
+        (2, 2, 4) => Some(RubyVersion {
             semver_version: Version::new(2, 2, 4),
             get_execution_context_fn: super::ruby_version::ruby_2_2_4::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_2_4::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_2_4::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_2_4::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_2_4::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_2_4::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_2_4::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_2_4::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_2_4::capture_frame_memory,
         }),
-        "2.2.5" => Ok(RubyVersion {
+        (2, 2, 5) => Some(RubyVersion {
             semver_version: Version::new(2, 2, 5),
             get_execution_context_fn: super::ruby_version::ruby_2_2_5::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_2_5::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_2_5::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_2_5::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_2_5::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_2_5::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_2_5::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_2_5::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_2_5::capture_frame_memory,
         }),
-        "2.2.6" => Ok(RubyVersion {
+        (2, 2, 6) => Some(RubyVersion {
             semver_version: Version::new(2, 2, 6),
             get_execution_context_fn: super::ruby_version::ruby_2_2_6::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_2_6::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_2_6::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_2_6::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_2_6::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_2_6::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_2_6::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_2_6::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_2_6::capture_frame_memory,
         }),
-        "2.2.7" => Ok(RubyVersion {
+        (2, 2, 7) => Some(RubyVersion {
             semver_version: Version::new(2, 2, 7),
             get_execution_context_fn: super::ruby_version::ruby_2_2_7::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_2_7::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_2_7::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_2_7::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_2_7::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_2_7::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_2_7::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_2_7::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_2_7::capture_frame_memory,
         }),
-        "2.2.8" => Ok(RubyVersion {
+        (2, 2, 8) => Some(RubyVersion {
             semver_version: Version::new(2, 2, 8),
             get_execution_context_fn: super::ruby_version::ruby_2_2_8::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_2_8::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_2_8::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_2_8::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_2_8::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_2_8::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_2_8::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_2_8::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_2_8::capture_frame_memory,
         }),
-        "2.2.9" => Ok(RubyVersion {
+        (2, 2, 9) => Some(RubyVersion {
             semver_version: Version::new(2, 2, 9),
             get_execution_context_fn: super::ruby_version::ruby_2_2_9::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_2_9::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_2_9::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_2_9::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_2_9::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_2_9::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_2_9::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_2_9::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_2_9::capture_frame_memory,
         }),
-        "2.2.10" => Ok(RubyVersion {
+        (2, 2, 10) => Some(RubyVersion {
             semver_version: Version::new(2, 2, 10),
             get_execution_context_fn: super::ruby_version::ruby_2_2_10::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_2_10::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_2_10::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_2_10::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_2_10::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_2_10::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_2_10::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_2_10::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_2_10::capture_frame_memory,
         }),
-        "2.3.0" => Ok(RubyVersion {
+        (2, 3, 0) => Some(RubyVersion {
             semver_version: Version::new(2, 3, 0),
             get_execution_context_fn: super::ruby_version::ruby_2_3_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_3_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_3_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_3_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_3_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_3_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_3_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_3_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_3_0::capture_frame_memory,
         }),
-        "2.3.1" => Ok(RubyVersion {
+        (2, 3, 1) => Some(RubyVersion {
             semver_version: Version::new(2, 3, 1),
             get_execution_context_fn: super::ruby_version::ruby_2_3_1::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_3_1::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_3_1::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_3_1::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_3_1::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_3_1::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_3_1::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_3_1::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_3_1::capture_frame_memory,
         }),
-        "2.3.2" => Ok(RubyVersion {
+        (2, 3, 2) => Some(RubyVersion {
             semver_version: Version::new(2, 3, 2),
             get_execution_context_fn: super::ruby_version::ruby_2_3_2::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_3_2::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_3_2::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_3_2::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_3_2::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_3_2::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_3_2::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_3_2::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_3_2::capture_frame_memory,
         }),
-        "2.3.3" => Ok(RubyVersion {
+        (2, 3, 3) => Some(RubyVersion {
             semver_version: Version::new(2, 3, 3),
             get_execution_context_fn: super::ruby_version::ruby_2_3_3::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_3_3::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_3_3::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_3_3::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_3_3::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_3_3::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_3_3::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_3_3::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_3_3::capture_frame_memory,
         }),
-        "2.3.4" => Ok(RubyVersion {
+        (2, 3, 4) => Some(RubyVersion {
             semver_version: Version::new(2, 3, 4),
             get_execution_context_fn: super::ruby_version::ruby_2_3_4::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_3_4::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_3_4::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_3_4::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_3_4::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_3_4::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_3_4::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_3_4::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_3_4::capture_frame_memory,
         }),
-        "2.3.5" => Ok(RubyVersion {
+        (2, 3, 5) => Some(RubyVersion {
             semver_version: Version::new(2, 3, 5),
             get_execution_context_fn: super::ruby_version::ruby_2_3_5::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_3_5::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_3_5::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_3_5::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_3_5::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_3_5::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_3_5::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_3_5::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_3_5::capture_frame_memory,
         }),
-        "2.3.6" => Ok(RubyVersion {
+        (2, 3, 6) => Some(RubyVersion {
             semver_version: Version::new(2, 3, 6),
             get_execution_context_fn: super::ruby_version::ruby_2_3_6::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_3_6::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_3_6::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_3_6::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_3_6::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_3_6::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_3_6::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_3_6::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_3_6::capture_frame_memory,
         }),
-        "2.3.7" => Ok(RubyVersion {
+        (2, 3, 7) => Some(RubyVersion {
             semver_version: Version::new(2, 3, 7),
             get_execution_context_fn: super::ruby_version::ruby_2_3_7::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_3_7::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_3_7::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_3_7::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_3_7::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_3_7::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_3_7::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_3_7::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_3_7::capture_frame_memory,
         }),
-        "2.3.8" => Ok(RubyVersion {
+        (2, 3, 8) => Some(RubyVersion {
             semver_version: Version::new(2, 3, 8),
             get_execution_context_fn: super::ruby_version::ruby_2_3_8::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_3_8::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_3_8::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_3_8::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_3_8::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_3_8::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_3_8::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_3_8::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_3_8::capture_frame_memory,
         }),
-        "2.4.0" => Ok(RubyVersion {
+        (2, 4, 0) => Some(RubyVersion {
             semver_version: Version::new(2, 4, 0),
             get_execution_context_fn: super::ruby_version::ruby_2_4_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_4_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_4_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_4_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_4_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_4_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_4_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_4_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_4_0::capture_frame_memory,
         }),
-        "2.4.1" => Ok(RubyVersion {
+        (2, 4, 1) => Some(RubyVersion {
             semver_version: Version::new(2, 4, 1),
             get_execution_context_fn: super::ruby_version::ruby_2_4_1::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_4_1::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_4_1::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_4_1::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_4_1::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_4_1::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_4_1::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_4_1::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_4_1::capture_frame_memory,
         }),
-        "2.4.2" => Ok(RubyVersion {
+        (2, 4, 2) => Some(RubyVersion {
             semver_version: Version::new(2, 4, 2),
             get_execution_context_fn: super::ruby_version::ruby_2_4_2::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_4_2::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_4_2::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_4_2::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_4_2::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_4_2::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_4_2::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_4_2::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_4_2::capture_frame_memory,
         }),
-        "2.4.3" => Ok(RubyVersion {
+        (2, 4, 3) => Some(RubyVersion {
             semver_version: Version::new(2, 4, 3),
             get_execution_context_fn: super::ruby_version::ruby_2_4_3::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_4_3::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_4_3::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_4_3::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_4_3::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_4_3::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_4_3::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_4_3::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_4_3::capture_frame_memory,
         }),
-        "2.4.4" => Ok(RubyVersion {
+        (2, 4, 4) => Some(RubyVersion {
             semver_version: Version::new(2, 4, 4),
             get_execution_context_fn: super::ruby_version::ruby_2_4_4::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_4_4::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_4_4::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_4_4::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_4_4::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_4_4::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_4_4::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_4_4::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_4_4::capture_frame_memory,
         }),
-        "2.4.5" => Ok(RubyVersion {
+        (2, 4, 5) => Some(RubyVersion {
             semver_version: Version::new(2, 4, 5),
             get_execution_context_fn: super::ruby_version::ruby_2_4_5::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_4_5::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_4_5::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_4_5::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_4_5::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_4_5::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_4_5::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_4_5::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_4_5::capture_frame_memory,
         }),
-        "2.4.6" => Ok(RubyVersion {
+        (2, 4, 6) => Some(RubyVersion {
             semver_version: Version::new(2, 4, 6),
             get_execution_context_fn: super::ruby_version::ruby_2_4_6::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_4_6::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_4_6::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_4_6::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_4_6::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_4_6::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_4_6::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_4_6::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_4_6::capture_frame_memory,
         }),
-        "2.4.7" => Ok(RubyVersion {
+        (2, 4, 7) => Some(RubyVersion {
             semver_version: Version::new(2, 4, 7),
             get_execution_context_fn: super::ruby_version::ruby_2_4_7::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_4_7::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_4_7::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_4_7::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_4_7::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_4_7::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_4_7::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_4_7::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_4_7::capture_frame_memory,
         }),
-        "2.4.8" => Ok(RubyVersion {
+        (2, 4, 8) => Some(RubyVersion {
             semver_version: Version::new(2, 4, 8),
             get_execution_context_fn: super::ruby_version::ruby_2_4_8::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_4_8::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_4_8::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_4_8::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_4_8::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_4_8::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_4_8::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_4_8::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_4_8::capture_frame_memory,
         }),
-        "2.4.9" => Ok(RubyVersion {
+        (2, 4, 9) => Some(RubyVersion {
             semver_version: Version::new(2, 4, 9),
             get_execution_context_fn: super::ruby_version::ruby_2_4_9::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_4_9::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_4_9::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_4_9::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_4_9::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_4_9::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_4_9::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_4_9::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_4_9::capture_frame_memory,
         }),
-        "2.4.10" => Ok(RubyVersion {
+        (2, 4, 10) => Some(RubyVersion {
             semver_version: Version::new(2, 4, 10),
             get_execution_context_fn: super::ruby_version::ruby_2_4_10::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_4_10::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_4_10::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_4_10::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_4_10::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_4_10::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_4_10::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_4_10::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_4_10::capture_frame_memory,
         }),
-        "2.5.0" => Ok(RubyVersion {
+        (2, 5, 0) => Some(RubyVersion {
             semver_version: Version::new(2, 5, 0),
             get_execution_context_fn: super::ruby_version::ruby_2_5_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_5_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_5_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_5_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_5_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_5_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_5_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_5_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_5_0::capture_frame_memory,
         }),
-        "2.5.1" => Ok(RubyVersion {
+        (2, 5, 1) => Some(RubyVersion {
             semver_version: Version::new(2, 5, 1),
             get_execution_context_fn: super::ruby_version::ruby_2_5_1::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_5_1::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_5_1::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_5_1::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_5_1::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_5_1::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_5_1::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_5_1::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_5_1::capture_frame_memory,
         }),
-        "2.5.2" => Ok(RubyVersion {
+        (2, 5, 2) => Some(RubyVersion {
             semver_version: Version::new(2, 5, 2),
             get_execution_context_fn: super::ruby_version::ruby_2_5_2::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_5_2::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_5_2::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_5_2::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_5_2::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_5_2::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_5_2::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_5_2::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_5_2::capture_frame_memory,
         }),
-        "2.5.3" => Ok(RubyVersion {
+        (2, 5, 3) => Some(RubyVersion {
             semver_version: Version::new(2, 5, 3),
             get_execution_context_fn: super::ruby_version::ruby_2_5_3::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_5_3::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_5_3::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_5_3::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_5_3::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_5_3::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_5_3::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_5_3::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_5_3::capture_frame_memory,
         }),
-        "2.5.4" => Ok(RubyVersion {
+        (2, 5, 4) => Some(RubyVersion {
             semver_version: Version::new(2, 5, 4),
             get_execution_context_fn: super::ruby_version::ruby_2_5_4::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_5_4::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_5_4::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_5_4::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_5_4::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_5_4::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_5_4::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_5_4::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_5_4::capture_frame_memory,
         }),
-        "2.5.5" => Ok(RubyVersion {
+        (2, 5, 5) => Some(RubyVersion {
             semver_version: Version::new(2, 5, 5),
             get_execution_context_fn: super::ruby_version::ruby_2_5_5::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_5_5::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_5_5::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_5_5::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_5_5::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_5_5::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_5_5::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_5_5::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_5_5::capture_frame_memory,
         }),
-        "2.5.6" => Ok(RubyVersion {
+        (2, 5, 6) => Some(RubyVersion {
             semver_version: Version::new(2, 5, 6),
             get_execution_context_fn: super::ruby_version::ruby_2_5_6::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_5_6::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_5_6::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_5_6::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_5_6::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_5_6::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_5_6::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_5_6::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_5_6::capture_frame_memory,
         }),
-        "2.5.7" => Ok(RubyVersion {
+        (2, 5, 7) => Some(RubyVersion {
             semver_version: Version::new(2, 5, 7),
             get_execution_context_fn: super::ruby_version::ruby_2_5_7::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_5_7::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_5_7::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_5_7::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_5_7::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_5_7::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_5_7::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_5_7::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_5_7::capture_frame_memory,
         }),
-        "2.5.8" => Ok(RubyVersion {
+        (2, 5, 8) => Some(RubyVersion {
             semver_version: Version::new(2, 5, 8),
             get_execution_context_fn: super::ruby_version::ruby_2_5_8::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_5_8::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_5_8::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_5_8::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_5_8::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_5_8::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_5_8::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_5_8::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_5_8::capture_frame_memory,
         }),
-        "2.5.9" => Ok(RubyVersion {
+        (2, 5, 9) => Some(RubyVersion {
             semver_version: Version::new(2, 5, 9),
             get_execution_context_fn: super::ruby_version::ruby_2_5_9::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_5_9::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_5_9::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_5_9::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_5_9::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_5_9::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_5_9::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_5_9::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_5_9::capture_frame_memory,
         }),
-        "2.6.0" => Ok(RubyVersion {
+        (2, 6, 0) => Some(RubyVersion {
             semver_version: Version::new(2, 6, 0),
             get_execution_context_fn: super::ruby_version::ruby_2_6_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_6_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_6_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_6_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_6_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_6_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_6_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_6_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_6_0::capture_frame_memory,
         }),
-        "2.6.1" => Ok(RubyVersion {
+        (2, 6, 1) => Some(RubyVersion {
             semver_version: Version::new(2, 6, 1),
             get_execution_context_fn: super::ruby_version::ruby_2_6_1::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_6_1::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_6_1::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_6_1::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_6_1::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_6_1::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_6_1::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_6_1::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_6_1::capture_frame_memory,
         }),
-        "2.6.2" => Ok(RubyVersion {
+        (2, 6, 2) => Some(RubyVersion {
             semver_version: Version::new(2, 6, 2),
             get_execution_context_fn: super::ruby_version::ruby_2_6_2::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_6_2::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_6_2::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_6_2::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_6_2::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_6_2::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_6_2::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_6_2::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_6_2::capture_frame_memory,
         }),
-        "2.6.3" => Ok(RubyVersion {
+        (2, 6, 3) => Some(RubyVersion {
             semver_version: Version::new(2, 6, 3),
             get_execution_context_fn: super::ruby_version::ruby_2_6_3::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_6_3::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_6_3::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_6_3::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_6_3::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_6_3::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_6_3::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_6_3::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_6_3::capture_frame_memory,
         }),
-        "2.6.4" => Ok(RubyVersion {
+        (2, 6, 4) => Some(RubyVersion {
             semver_version: Version::new(2, 6, 4),
             get_execution_context_fn: super::ruby_version::ruby_2_6_4::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_6_4::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_6_4::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_6_4::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_6_4::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_6_4::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_6_4::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_6_4::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_6_4::capture_frame_memory,
         }),
-        "2.6.5" => Ok(RubyVersion {
+        (2, 6, 5) => Some(RubyVersion {
             semver_version: Version::new(2, 6, 5),
             get_execution_context_fn: super::ruby_version::ruby_2_6_5::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_6_5::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_6_5::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_6_5::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_6_5::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_6_5::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_6_5::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_6_5::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_6_5::capture_frame_memory,
         }),
-        "2.6.6" => Ok(RubyVersion {
+        (2, 6, 6) => Some(RubyVersion {
             semver_version: Version::new(2, 6, 6),
             get_execution_context_fn: super::ruby_version::ruby_2_6_6::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_6_6::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_6_6::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_6_6::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_6_6::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_6_6::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_6_6::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_6_6::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_6_6::capture_frame_memory,
         }),
-        "2.6.7" => Ok(RubyVersion {
+        (2, 6, 7) => Some(RubyVersion {
             semver_version: Version::new(2, 6, 7),
             get_execution_context_fn: super::ruby_version::ruby_2_6_7::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_6_7::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_6_7::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_6_7::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_6_7::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_6_7::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_6_7::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_6_7::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_6_7::capture_frame_memory,
         }),
-        "2.6.8" => Ok(RubyVersion {
+        (2, 6, 8) => Some(RubyVersion {
             semver_version: Version::new(2, 6, 8),
             get_execution_context_fn: super::ruby_version::ruby_2_6_8::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_6_8::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_6_8::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_6_8::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_6_8::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_6_8::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_6_8::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_6_8::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_6_8::capture_frame_memory,
         }),
-        "2.6.9" => Ok(RubyVersion {
+        (2, 6, 9) => Some(RubyVersion {
             semver_version: Version::new(2, 6, 9),
             get_execution_context_fn: super::ruby_version::ruby_2_6_9::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_6_9::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_6_9::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_6_9::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_6_9::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_6_9::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_6_9::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_6_9::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_6_9::capture_frame_memory,
         }),
-        "2.6.10" => Ok(RubyVersion {
+        (2, 6, 10) => Some(RubyVersion {
             semver_version: Version::new(2, 6, 10),
             get_execution_context_fn: super::ruby_version::ruby_2_6_10::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_6_10::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_6_10::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_6_10::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_6_10::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_6_10::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_6_10::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_6_10::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_6_10::capture_frame_memory,
         }),
-        "2.7.0" => Ok(RubyVersion {
+        (2, 7, 0) => Some(RubyVersion {
             semver_version: Version::new(2, 7, 0),
             get_execution_context_fn: super::ruby_version::ruby_2_7_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_7_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_7_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_7_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_7_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_7_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_7_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_7_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_7_0::capture_frame_memory,
         }),
-        "2.7.1" => Ok(RubyVersion {
+        (2, 7, 1) => Some(RubyVersion {
             semver_version: Version::new(2, 7, 1),
             get_execution_context_fn: super::ruby_version::ruby_2_7_1::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_7_1::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_7_1::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_7_1::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_7_1::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_7_1::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_7_1::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_7_1::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_7_1::capture_frame_memory,
         }),
-        "2.7.2" => Ok(RubyVersion {
+        (2, 7, 2) => Some(RubyVersion {
             semver_version: Version::new(2, 7, 2),
             get_execution_context_fn: super::ruby_version::ruby_2_7_2::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_7_2::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_7_2::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_7_2::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_7_2::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_7_2::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_7_2::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_7_2::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_7_2::capture_frame_memory,
         }),
-        "2.7.3" => Ok(RubyVersion {
+        (2, 7, 3) => Some(RubyVersion {
             semver_version: Version::new(2, 7, 3),
             get_execution_context_fn: super::ruby_version::ruby_2_7_3::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_7_3::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_7_3::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_7_3::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_7_3::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_7_3::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_7_3::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_7_3::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_7_3::capture_frame_memory,
         }),
-        "2.7.4" => Ok(RubyVersion {
+        (2, 7, 4) => Some(RubyVersion {
             semver_version: Version::new(2, 7, 4),
             get_execution_context_fn: super::ruby_version::ruby_2_7_4::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_7_4::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_7_4::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_7_4::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_7_4::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_7_4::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_7_4::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_7_4::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_7_4::capture_frame_memory,
         }),
-        "2.7.5" => Ok(RubyVersion {
+        (2, 7, 5) => Some(RubyVersion {
             semver_version: Version::new(2, 7, 5),
             get_execution_context_fn: super::ruby_version::ruby_2_7_5::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_7_5::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_7_5::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_7_5::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_7_5::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_7_5::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_7_5::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_7_5::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_7_5::capture_frame_memory,
         }),
-        "2.7.6" => Ok(RubyVersion {
+        (2, 7, 6) => Some(RubyVersion {
             semver_version: Version::new(2, 7, 6),
             get_execution_context_fn: super::ruby_version::ruby_2_7_6::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_7_6::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_7_6::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_7_6::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_7_6::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_7_6::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_7_6::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_7_6::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_7_6::capture_frame_memory,
         }),
-        "2.7.7" => Ok(RubyVersion {
+        (2, 7, 7) => Some(RubyVersion {
             semver_version: Version::new(2, 7, 7),
             get_execution_context_fn: super::ruby_version::ruby_2_7_7::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_7_7::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_7_7::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_7_7::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_7_7::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_7_7::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_7_7::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_7_7::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_7_7::capture_frame_memory,
         }),
-        "2.7.8" => Ok(RubyVersion {
+        (2, 7, 8) => Some(RubyVersion {
             semver_version: Version::new(2, 7, 8),
             get_execution_context_fn: super::ruby_version::ruby_2_7_8::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_2_7_8::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_2_7_8::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_2_7_8::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_2_7_8::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_2_7_8::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_2_7_8::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_2_7_8::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_2_7_8::capture_frame_memory,
         }),
-        "3.0.0" => Ok(RubyVersion {
+        (3, 0, 0) => Some(RubyVersion {
             semver_version: Version::new(3, 0, 0),
             get_execution_context_fn: super::ruby_version::ruby_3_0_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_0_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_0_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_0_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_0_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_0_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_0_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_0_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_0_0::capture_frame_memory,
         }),
-        "3.0.1" => Ok(RubyVersion {
+        (3, 0, 1) => Some(RubyVersion {
             semver_version: Version::new(3, 0, 1),
             get_execution_context_fn: super::ruby_version::ruby_3_0_1::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_0_1::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_0_1::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_0_1::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_0_1::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_0_1::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_0_1::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_0_1::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_0_1::capture_frame_memory,
         }),
-        "3.0.2" => Ok(RubyVersion {
+        (3, 0, 2) => Some(RubyVersion {
             semver_version: Version::new(3, 0, 2),
             get_execution_context_fn: super::ruby_version::ruby_3_0_2::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_0_2::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_0_2::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_0_2::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_0_2::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_0_2::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_0_2::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_0_2::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_0_2::capture_frame_memory,
         }),
-        "3.0.3" => Ok(RubyVersion {
+        (3, 0, 3) => Some(RubyVersion {
             semver_version: Version::new(3, 0, 3),
             get_execution_context_fn: super::ruby_version::ruby_3_0_3::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_0_3::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_0_3::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_0_3::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_0_3::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_0_3::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_0_3::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_0_3::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_0_3::capture_frame_memory,
         }),
-        "3.0.4" => Ok(RubyVersion {
+        (3, 0, 4) => Some(RubyVersion {
             semver_version: Version::new(3, 0, 4),
             get_execution_context_fn: super::ruby_version::ruby_3_0_4::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_0_4::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_0_4::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_0_4::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_0_4::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_0_4::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_0_4::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_0_4::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_0_4::capture_frame_memory,
         }),
-        "3.0.5" => Ok(RubyVersion {
+        (3, 0, 5) => Some(RubyVersion {
             semver_version: Version::new(3, 0, 5),
             get_execution_context_fn: super::ruby_version::ruby_3_0_5::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_0_5::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_0_5::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_0_5::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_0_5::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_0_5::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_0_5::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_0_5::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_0_5::capture_frame_memory,
         }),
-        "3.0.6" => Ok(RubyVersion {
+        (3, 0, 6) => Some(RubyVersion {
             semver_version: Version::new(3, 0, 6),
             get_execution_context_fn: super::ruby_version::ruby_3_0_6::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_0_6::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_0_6::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_0_6::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_0_6::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_0_6::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_0_6::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_0_6::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_0_6::capture_frame_memory,
         }),
-        "3.0.7" => Ok(RubyVersion {
+        (3, 0, 7) => Some(RubyVersion {
             semver_version: Version::new(3, 0, 7),
             get_execution_context_fn: super::ruby_version::ruby_3_0_7::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_0_7::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_0_7::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_0_7::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_0_7::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_0_7::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_0_7::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_0_7::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_0_7::capture_frame_memory,
         }),
-        "3.1.0" => Ok(RubyVersion {
+        (3, 1, 0) => Some(RubyVersion {
             semver_version: Version::new(3, 1, 0),
             get_execution_context_fn: super::ruby_version::ruby_3_1_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_1_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_1_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_1_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_1_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_1_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_1_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_1_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_1_0::capture_frame_memory,
         }),
-        "3.1.1" => Ok(RubyVersion {
+        (3, 1, 1) => Some(RubyVersion {
             semver_version: Version::new(3, 1, 1),
             get_execution_context_fn: super::ruby_version::ruby_3_1_1::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_1_1::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_1_1::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_1_1::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_1_1::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_1_1::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_1_1::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_1_1::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_1_1::capture_frame_memory,
         }),
-        "3.1.2" => Ok(RubyVersion {
+        (3, 1, 2) => Some(RubyVersion {
             semver_version: Version::new(3, 1, 2),
             get_execution_context_fn: super::ruby_version::ruby_3_1_2::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_1_2::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_1_2::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_1_2::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_1_2::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_1_2::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_1_2::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_1_2::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_1_2::capture_frame_memory,
         }),
-        "3.1.3" => Ok(RubyVersion {
+        (3, 1, 3) => Some(RubyVersion {
             semver_version: Version::new(3, 1, 3),
             get_execution_context_fn: super::ruby_version::ruby_3_1_3::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_1_3::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_1_3::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_1_3::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_1_3::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_1_3::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_1_3::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_1_3::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_1_3::capture_frame_memory,
         }),
-        "3.1.4" => Ok(RubyVersion {
+        (3, 1, 4) => Some(RubyVersion {
             semver_version: Version::new(3, 1, 4),
             get_execution_context_fn: super::ruby_version::ruby_3_1_4::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_1_4::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_1_4::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_1_4::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_1_4::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_1_4::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_1_4::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_1_4::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_1_4::capture_frame_memory,
         }),
-        "3.1.5" => Ok(RubyVersion {
+        (3, 1, 5) => Some(RubyVersion {
             semver_version: Version::new(3, 1, 5),
             get_execution_context_fn: super::ruby_version::ruby_3_1_5::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_1_5::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_1_5::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_1_5::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_1_5::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_1_5::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_1_5::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_1_5::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_1_5::capture_frame_memory,
         }),
-        "3.1.6" => Ok(RubyVersion {
+        (3, 1, 6) => Some(RubyVersion {
             semver_version: Version::new(3, 1, 6),
             get_execution_context_fn: super::ruby_version::ruby_3_1_6::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_1_6::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_1_6::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_1_6::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_1_6::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_1_6::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_1_6::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_1_6::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_1_6::capture_frame_memory,
         }),
-        "3.2.0" => Ok(RubyVersion {
+        (3, 2, 0) => Some(RubyVersion {
             semver_version: Version::new(3, 2, 0),
             get_execution_context_fn: super::ruby_version::ruby_3_2_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_2_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_2_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_2_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_2_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_2_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_2_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_2_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_2_0::capture_frame_memory,
         }),
-        "3.2.1" => Ok(RubyVersion {
+        (3, 2, 1) => Some(RubyVersion {
             semver_version: Version::new(3, 2, 1),
             get_execution_context_fn: super::ruby_version::ruby_3_2_1::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_2_1::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_2_1::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_2_1::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_2_1::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_2_1::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_2_1::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_2_1::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_2_1::capture_frame_memory,
         }),
-        "3.2.2" => Ok(RubyVersion {
+        (3, 2, 2) => Some(RubyVersion {
             semver_version: Version::new(3, 2, 2),
             get_execution_context_fn: super::ruby_version::ruby_3_2_2::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_2_2::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_2_2::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_2_2::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_2_2::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_2_2::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_2_2::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_2_2::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_2_2::capture_frame_memory,
         }),
-        "3.2.3" => Ok(RubyVersion {
+        (3, 2, 3) => Some(RubyVersion {
             semver_version: Version::new(3, 2, 3),
             get_execution_context_fn: super::ruby_version::ruby_3_2_3::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_2_3::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_2_3::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_2_3::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_2_3::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_2_3::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_2_3::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_2_3::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_2_3::capture_frame_memory,
         }),
-        "3.2.4" => Ok(RubyVersion {
+        (3, 2, 4) => Some(RubyVersion {
             semver_version: Version::new(3, 2, 4),
             get_execution_context_fn: super::ruby_version::ruby_3_2_4::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_2_4::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_2_4::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_2_4::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_2_4::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_2_4::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_2_4::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_2_4::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_2_4::capture_frame_memory,
         }),
-        "3.2.5" => Ok(RubyVersion {
+        (3, 2, 5) => Some(RubyVersion {
             semver_version: Version::new(3, 2, 5),
             get_execution_context_fn: super::ruby_version::ruby_3_2_5::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_2_5::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_2_5::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_2_5::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_2_5::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_2_5::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_2_5::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_2_5::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_2_5::capture_frame_memory,
         }),
-        "3.2.6" => Ok(RubyVersion {
+        (3, 2, 6) => Some(RubyVersion {
             semver_version: Version::new(3, 2, 6),
             get_execution_context_fn: super::ruby_version::ruby_3_2_6::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_2_6::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_2_6::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_2_6::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_2_6::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_2_6::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_2_6::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_2_6::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_2_6::capture_frame_memory,
         }),
-        "3.3.0" => Ok(RubyVersion {
+        (3, 3, 0) => Some(RubyVersion {
             semver_version: Version::new(3, 3, 0),
             get_execution_context_fn: super::ruby_version::ruby_3_3_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_3_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_3_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_3_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_3_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_3_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_3_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_3_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_3_0::capture_frame_memory,
         }),
-        "3.3.1" => Ok(RubyVersion {
+        (3, 3, 1) => Some(RubyVersion {
             semver_version: Version::new(3, 3, 1),
             get_execution_context_fn: super::ruby_version::ruby_3_3_1::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_3_1::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_3_1::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_3_1::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_3_1::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_3_1::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_3_1::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_3_1::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_3_1::capture_frame_memory,
         }),
-        "3.3.2" => Ok(RubyVersion {
+        (3, 3, 2) => Some(RubyVersion {
             semver_version: Version::new(3, 3, 2),
             get_execution_context_fn: super::ruby_version::ruby_3_3_2::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_3_2::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_3_2::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_3_2::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_3_2::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_3_2::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_3_2::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_3_2::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_3_2::capture_frame_memory,
         }),
-        "3.3.3" => Ok(RubyVersion {
+        (3, 3, 3) => Some(RubyVersion {
             semver_version: Version::new(3, 3, 3),
             get_execution_context_fn: super::ruby_version::ruby_3_3_3::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_3_3::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_3_3::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_3_3::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_3_3::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_3_3::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_3_3::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_3_3::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_3_3::capture_frame_memory,
         }),
-        "3.3.4" => Ok(RubyVersion {
+        (3, 3, 4) => Some(RubyVersion {
             semver_version: Version::new(3, 3, 4),
             get_execution_context_fn: super::ruby_version::ruby_3_3_4::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_3_4::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_3_4::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_3_4::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_3_4::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_3_4::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_3_4::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_3_4::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_3_4::capture_frame_memory,
         }),
-        "3.3.5" => Ok(RubyVersion {
+        (3, 3, 5) => Some(RubyVersion {
             semver_version: Version::new(3, 3, 5),
             get_execution_context_fn: super::ruby_version::ruby_3_3_5::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_3_5::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_3_5::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_3_5::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_3_5::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_3_5::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_3_5::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_3_5::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_3_5::capture_frame_memory,
         }),
-        "3.3.6" => Ok(RubyVersion {
+        (3, 3, 6) => Some(RubyVersion {
             semver_version: Version::new(3, 3, 6),
             get_execution_context_fn: super::ruby_version::ruby_3_3_6::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_3_6::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_3_6::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_3_6::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_3_6::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_3_6::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_3_6::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_3_6::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_3_6::capture_frame_memory,
         }),
-        "3.3.7" => Ok(RubyVersion {
+        (3, 3, 7) => Some(RubyVersion {
             semver_version: Version::new(3, 3, 7),
             get_execution_context_fn: super::ruby_version::ruby_3_3_7::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_3_7::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_3_7::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_3_7::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_3_7::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_3_7::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_3_7::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_3_7::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_3_7::capture_frame_memory,
         }),
-        "3.4.0" => Ok(RubyVersion {
+        (3, 4, 0) => Some(RubyVersion {
             semver_version: Version::new(3, 4, 0),
             get_execution_context_fn: super::ruby_version::ruby_3_4_0::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_4_0::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_4_0::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_4_0::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_4_0::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_4_0::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_4_0::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_4_0::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_4_0::capture_frame_memory,
         }),
-        "3.4.1" => Ok(RubyVersion {
+        (3, 4, 1) => Some(RubyVersion {
             semver_version: Version::new(3, 4, 1),
             get_execution_context_fn: super::ruby_version::ruby_3_4_1::get_execution_context,
             get_stack_trace_fn: super::ruby_version::ruby_3_4_1::get_stack_trace,
+            get_all_stack_traces_fn: super::ruby_version::ruby_3_4_1::get_all_stack_traces,
+            get_stack_trace_with_locals_fn:
+                super::ruby_version::ruby_3_4_1::get_stack_trace_with_locals,
+            get_core_dump_stack_trace_fn: super::ruby_version::ruby_3_4_1::get_stack_trace,
             is_maybe_thread_fn: super::ruby_version::ruby_3_4_1::is_maybe_thread,
+            get_raw_stack_trace_fn: super::ruby_version::ruby_3_4_1::get_raw_stack_trace,
+            resolve_frame_fn: super::ruby_version::ruby_3_4_1::resolve_frame,
+            capture_frame_fn: super::ruby_version::ruby_3_4_1::capture_frame_memory,
         }),
-        _ => Err(format_err!("rbspy doesn't support Ruby {} yet. If this is a new patch-level version of Ruby, you can try using `--force-version` with the previous version number.", v)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::supported_ruby_versions::*;
+
+    #[test]
+    fn test_every_binding_is_supported() {
+        // Each module in ruby-structs holds the bindings for one Ruby version, e.g. `ruby_3_3_0`
+        // or `ruby_1_9_3_0`, and should have a matching version here
+        let bindings = include_str!("../../ruby-structs/src/lib.rs");
+        let mut count = 0;
+        for module in bindings
+            .lines()
+            .filter_map(|line| line.strip_prefix("pub mod ruby_"))
+        {
+            let parts: Vec<&str> = module.trim_end_matches(';').split('_').take(3).collect();
+            let version = parts.join(".");
+            match get(&Version::parse(&version).unwrap()) {
+                Ok(ruby_version) => assert_eq!(ruby_version.semver_version.to_string(), version),
+                Err(e) => panic!("{}", e),
+            }
+            count += 1;
+        }
+        assert!(count > 100);
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        assert!(get(&Version::new(1, 8, 7)).is_err());
+        assert!(get(&Version::new(3, 5, 0)).is_err());
+        assert!(get(&Version::new(2, 10, 0)).is_err());
+    }
+
+    #[test]
+    fn test_unknown_patch_version_falls_back() {
+        let ruby_version = get(&Version::new(3, 3, 10)).unwrap();
+        assert_eq!(ruby_version.semver_version, Version::new(3, 3, 10));
+        assert_eq!(
+            ruby_version.get_stack_trace_fn as usize,
+            get(&Version::new(3, 3, 7)).unwrap().get_stack_trace_fn as usize
+        );
+        // Only newer patch releases fall back
+        assert!(get(&Version::new(1, 9, 0)).is_err());
+    }
+
+    #[test]
+    fn test_version_ordering() {
+        let (old, new) = (
+            Version::parse("2.9.0").unwrap(),
+            Version::parse("2.10.0").unwrap(),
+        );
+        assert!(old < new);
+        assert!(Version::parse("3.3.10").unwrap() > Version::parse("3.3.9").unwrap());
+        let err = get(&Version::parse("2.10.0").unwrap()).err().unwrap();
+        assert!(err.to_string().contains("Ruby 2.10.0"));
+        assert_eq!(
+            get(&Version::parse("2.7.1").unwrap())
+                .unwrap()
+                .semver_version,
+            Version::new(2, 7, 1)
+        );
     }
 }
@@ -1,7 +1,8 @@
 /// Core types used throughout rbspy: StackFrame and StackTrace
 use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 use std::{self, convert::From};
 
 use anyhow::{Error, Result};
@@ -9,14 +10,43 @@ use clap::ValueEnum;
 use remoteprocess::Pid;
 use thiserror::Error;
 
+use crate::core::coredump::CoreDump;
+use crate::core::filter::TraceFilter;
+use crate::core::memory_snapshot::MemorySnapshot;
 use crate::core::process::Process;
 use crate::ui::*;
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
-pub(crate) struct Header {
+/// Metadata at the start of a raw data file. Files written by older versions of rbspy don't have
+/// all of it.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub struct Header {
     pub sample_rate: Option<u32>,
     pub rbspy_version: Option<String>,
     pub start_time: Option<SystemTime>,
+    /// The command line of each profiled process, by PID
+    #[serde(default)]
+    pub command_lines: BTreeMap<Pid, Vec<String>>,
+    /// The names that threads were given with `Thread#name=`, by thread ID
+    #[serde(default)]
+    pub thread_names: BTreeMap<u64, String>,
+}
+
+impl Header {
+    /// How long after the recording started `trace` was sampled, where `index` is the trace's
+    /// position in the file, counting from 0. The trace's timestamp is used when the file has a
+    /// start time. Otherwise, e.g. for files whose traces don't have timestamps, samples are
+    /// assumed to be evenly spaced at the sample rate. Returns `None` if neither is known.
+    pub fn elapsed(&self, trace: &StackTrace, index: usize) -> Option<Duration> {
+        if let (Some(start), Some(time)) = (self.start_time, trace.time) {
+            // Traces sampled just before the header was written, or after the clock was set
+            // back, count as the start of the recording
+            return Some(time.duration_since(start).unwrap_or_default());
+        }
+        match self.sample_rate {
+            Some(rate) if rate > 0 => Some(Duration::from_secs_f64(index as f64 / rate as f64)),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
@@ -31,13 +61,142 @@ pub struct StackFrame {
 pub struct StackTrace {
     pub trace: Vec<StackFrame>,
     pub pid: Option<Pid>,
-    pub thread_id: Option<usize>,
+    pub thread_id: Option<u64>,
     pub time: Option<SystemTime>,
     pub on_cpu: Option<bool>,
+    /// Whether the thread held the GVL when it was sampled. Only known for traces of every thread
+    /// (see `RecordConfig::all_threads`), where a thread that's runnable but doesn't hold the GVL
+    /// is waiting for it, or for I/O. This is the thread that the VM considers current rather
+    /// than the lock's owner, so a thread that has released the GVL for I/O still holds it
+    /// until another thread takes it.
+    pub holds_gvl: Option<bool>,
+    /// The name of the thread, if it was given one with `Thread#name=`
+    pub thread_name: Option<String>,
+    /// The process's resident set size (RSS) in bytes when the trace was sampled, if it was
+    /// recorded (see `RecordConfig::with_rss`)
+    pub rss: Option<u64>,
+    /// When the trace was sampled, in nanoseconds on the system's monotonic clock
+    /// (`CLOCK_MONOTONIC`), for correlating samples with other tools' timestamps. Unlike `time`,
+    /// it doesn't jump when the wall clock is changed. Not recorded on Windows.
+    pub monotonic_ns: Option<u64>,
+}
+
+/// A stack frame as it was found on the Ruby VM's stack while recording with deferred
+/// symbolization, before any of the memory that's needed to name it has been read. Its name and
+/// path are read when the raw data is reported on, from the memory that was captured the first
+/// time the frame was seen (see `DeferredSample`).
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RawFrame {
+    /// A Ruby frame, by the addresses of its iseq and program counter. `jit` is set for frames
+    /// run by YJIT, which get a `[JIT]` prefix once they're named.
+    Ruby { iseq: usize, pc: usize, jit: bool },
+    /// A C function, by the address of its method entry
+    CFunc { method_entry: usize },
+    /// A frame that's named without reading any memory, like `<GC>`
+    Known(StackFrame),
+}
+
+/// A stack trace whose frames haven't been named yet, from a recording with deferred
+/// symbolization. `trace` has everything but the frames, so its own `trace` is empty. Thread
+/// names aren't read while recording this way.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub struct RawStackTrace {
+    pub trace: StackTrace,
+    pub frames: Vec<RawFrame>,
+}
+
+/// What's recorded for each sample with deferred symbolization: the trace, along with anything
+/// that's needed to name its frames later that hasn't been recorded already.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DeferredSample {
+    pub trace: RawStackTrace,
+    /// The Ruby version of the process and the address of its global symbol table, which are
+    /// only sent with the first sample after rbspy attaches to the process
+    pub ruby: Option<(String, Option<usize>)>,
+    /// The process memory that naming the trace's new frames reads, as (address, bytes) pairs
+    pub memory: Vec<(usize, Vec<u8>)>,
+}
+
+impl From<StackTrace> for RawStackTrace {
+    /// A raw trace whose frames are already named, like the `(idle)` placeholder trace
+    fn from(trace: StackTrace) -> RawStackTrace {
+        let frames = trace.trace.iter().cloned().map(RawFrame::Known).collect();
+        RawStackTrace {
+            trace: StackTrace {
+                trace: Vec::new(),
+                ..trace
+            },
+            frames,
+        }
+    }
+}
+
+impl DeferredSample {
+    /// Adds what was sent with `earlier`, a sample that won't be recorded itself (e.g. because
+    /// it's from a thread that isn't being profiled), to this sample. Naming the frames of later
+    /// samples can need memory that was only captured for `earlier`.
+    pub fn absorb(&mut self, earlier: DeferredSample) {
+        if self.ruby.is_none() {
+            self.ruby = earlier.ruby;
+        }
+        let memory = std::mem::replace(&mut self.memory, earlier.memory);
+        self.memory.extend(memory);
+    }
+}
+
+/// A local variable (or method argument) of a Ruby frame, read by `snapshot --with-locals`. The
+/// value is a short rendering of what was in memory, e.g. `42`, `:sym`, `"str"`, or `#<Array>`
+/// for objects that aren't shown inline.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub struct LocalVariable {
+    pub name: String,
+    pub value: String,
 }
 
-pub type StackTraceFn =
-    fn(usize, usize, Option<usize>, &Process, Pid, bool) -> Result<Option<StackTrace>>;
+pub type StackTraceFn = fn(
+    usize,
+    usize,
+    Option<usize>,
+    &Process,
+    Pid,
+    bool,
+    &mut FrameCache,
+) -> Result<Option<StackTrace>>;
+
+pub type CoreDumpStackTraceFn = fn(
+    usize,
+    usize,
+    Option<usize>,
+    &CoreDump,
+    Pid,
+    bool,
+    &mut FrameCache,
+) -> Result<Option<StackTrace>>;
+
+pub type AllStackTracesFn =
+    fn(usize, usize, Option<usize>, &Process, Pid, &mut FrameCache) -> Result<Vec<StackTrace>>;
+
+/// The locals of each frame of a stack trace, in the same order as its frames
+pub type FrameLocals = Vec<Vec<LocalVariable>>;
+
+pub type StackTraceWithLocalsFn = fn(
+    usize,
+    usize,
+    Option<usize>,
+    &Process,
+    Pid,
+    bool,
+    &mut FrameCache,
+) -> Result<Option<(StackTrace, FrameLocals)>>;
+
+pub type RawStackTraceFn =
+    fn(usize, usize, Option<usize>, &Process, Pid, bool) -> Result<Option<RawStackTrace>>;
+
+pub type ResolveFrameFn = fn(&RawFrame, Option<usize>, &MemorySnapshot) -> Result<StackFrame>;
+
+/// Names a raw frame from the live process, returning the (address, bytes) pairs that were read
+pub type CaptureFrameFn = fn(&RawFrame, Option<usize>, &Process) -> Result<Vec<(usize, Vec<u8>)>>;
 
 pub type IsMaybeThreadFn = fn(usize, usize, &Process, &[proc_maps::MapRange]) -> bool;
 
@@ -59,6 +218,54 @@ pub enum MemoryCopyError {
     InvalidAddressError(usize),
 }
 
+/// Stack frames that have already been read from a process, so that each sample doesn't have to
+/// read the method name and path strings again for every frame. Frames are keyed by the address
+/// of their iseq along with the addresses of its label and path, so that an iseq address that's
+/// reused after garbage collection doesn't return a stale frame. Line numbers change between
+/// samples, so they're not cached.
+#[derive(Default)]
+pub struct FrameCache {
+    frames: HashMap<(usize, usize, usize), StackFrame>,
+}
+
+impl FrameCache {
+    // Programs that keep generating code could otherwise make the cache grow without bound
+    const MAX_ENTRIES: usize = 10_000;
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Returns the cached frame for `key` with its line number set to `lineno`, calling
+    /// `read_frame` to read the frame if it isn't cached yet.
+    pub fn get_or_read<F>(
+        &mut self,
+        key: (usize, usize, usize),
+        lineno: Option<usize>,
+        read_frame: F,
+    ) -> Result<StackFrame>
+    where
+        F: FnOnce() -> Result<StackFrame>,
+    {
+        let frame = match self.frames.get(&key) {
+            Some(frame) => frame.clone(),
+            None => {
+                let frame = read_frame()?;
+                if self.frames.len() >= Self::MAX_ENTRIES {
+                    self.clear();
+                }
+                self.frames.insert(key, frame.clone());
+                frame
+            }
+        };
+        Ok(StackFrame { lineno, ..frame })
+    }
+}
+
 impl StackFrame {
     pub fn path(&self) -> &str {
         match self.absolute_path {
@@ -67,6 +274,55 @@ impl StackFrame {
         }
     }
 
+    /// Whether this frame is a function implemented in C, rather than Ruby code
+    pub fn is_c_function(&self) -> bool {
+        self.name.ends_with("[c function]")
+    }
+
+    // we use this stack frame for samples taken while the process wasn't running, when idle
+    // samples are enabled
+    pub fn idle() -> StackFrame {
+        StackFrame {
+            name: "(idle)".to_string(),
+            relative_path: "(idle)".to_string(),
+            absolute_path: None,
+            lineno: None,
+        }
+    }
+
+    // we use this stack frame for traces whose frames were all removed by a `TraceFilter`, so
+    // that the sample is still counted
+    pub fn filtered() -> StackFrame {
+        StackFrame {
+            name: "(filtered)".to_string(),
+            relative_path: "(filtered)".to_string(),
+            absolute_path: None,
+            lineno: None,
+        }
+    }
+
+    // we use this stack frame in place of each run of frames that a `TraceFilter`'s focus patterns
+    // don't match
+    pub fn other() -> StackFrame {
+        StackFrame {
+            name: "<other>".to_string(),
+            relative_path: "<other>".to_string(),
+            absolute_path: None,
+            lineno: None,
+        }
+    }
+
+    // we put this stack frame on top of traces that were sampled while the garbage collector was
+    // running
+    pub fn gc() -> StackFrame {
+        StackFrame {
+            name: "<GC>".to_string(),
+            relative_path: "(gc)".to_string(),
+            absolute_path: None,
+            lineno: None,
+        }
+    }
+
     // we use this stack frame when there's a C function that we don't recognize in the stack. This
     // would be a constant but it has strings in it so it can't be.
     pub fn unknown_c_function() -> StackFrame {
@@ -112,12 +368,64 @@ impl StackTrace {
             thread_id: None,
             time: None,
             on_cpu: None,
+            holds_gvl: None,
+            thread_name: None,
+            rss: None,
+            monotonic_ns: None,
         }
     }
 
     pub fn iter(&self) -> std::slice::Iter<StackFrame> {
         self.trace.iter()
     }
+
+    /// A human-readable name for the thread: its name if it has one, or else its ID
+    pub fn thread_label(&self) -> String {
+        match (&self.thread_name, self.thread_id) {
+            (Some(name), _) => name.clone(),
+            (None, Some(thread_id)) => thread_id.to_string(),
+            (None, None) => "unknown".to_string(),
+        }
+    }
+
+    /// Whether the trace was taken from one of `thread_ids`. Any trace matches if `thread_ids` is
+    /// empty, and traces without a thread ID never match otherwise.
+    pub fn is_from_threads(&self, thread_ids: &[u64]) -> bool {
+        thread_ids.is_empty()
+            || self
+                .thread_id
+                .map_or(false, |thread_id| thread_ids.contains(&thread_id))
+    }
+
+    /// The trace as a line of collapsed ("folded") output without its count: its frames from the
+    /// root down, separated by semicolons, e.g. `<main> - /app/app.rb:10;foo - /app/app.rb:2`.
+    /// This is how collapsed output and flamegraphs tell stacks apart.
+    pub fn fold(&self) -> String {
+        fold_frames(&self.trace)
+    }
+}
+
+/// Counts how many times each stack appears in `traces`, keyed by `StackTrace::fold`. This is the
+/// aggregation that collapsed output and flamegraphs are made from, so the counts are the same as
+/// the ones in `rbspy report --format collapsed` for the same traces.
+pub fn fold_traces<'a, I: IntoIterator<Item = &'a StackTrace>>(
+    traces: I,
+) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for trace in traces {
+        *counts.entry(trace.fold()).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Joins frames, innermost first as in `StackTrace::trace`, into a line of collapsed output
+pub(crate) fn fold_frames(frames: &[StackFrame]) -> String {
+    frames
+        .iter()
+        .rev()
+        .map(|frame| frame.to_string())
+        .collect::<Vec<String>>()
+        .join(";")
 }
 
 impl fmt::Display for StackTrace {
@@ -160,18 +468,64 @@ pub enum OutputFormat {
     pprof,
     summary,
     summary_by_line,
+    summary_by_gem,
+    tree_json,
+    chrometrace,
+    csv,
+    jsonl,
+    rss,
+    d3flamegraph,
 }
 
 impl OutputFormat {
-    pub fn outputter(self, flame_min_width: f64) -> Box<dyn output::Outputter> {
+    /// Creates an outputter for this format. `sample_rate` is the rate the traces were recorded
+    /// at, if it's known. The outputter doesn't apply `options.filter`; the traces it's given
+    /// should already be filtered.
+    pub fn outputter(
+        self,
+        flame_min_width: f64,
+        options: &ReportOptions,
+        sample_rate: Option<u32>,
+    ) -> Box<dyn output::Outputter> {
+        let (summary_format, summary_top) = (options.summary_format, options.top);
         match self {
-            OutputFormat::flamegraph => Box::new(output::Flamegraph::new(flame_min_width)),
+            OutputFormat::flamegraph => Box::new(output::Flamegraph::new(
+                flame_min_width,
+                options.title.clone(),
+                options.subtitle.clone(),
+                options.reverse,
+                options.color_by,
+                sample_rate,
+            )),
             OutputFormat::collapsed => Box::new(output::Collapsed::default()),
             OutputFormat::callgrind => Box::new(output::Callgrind(callgrind::Stats::new())),
             OutputFormat::speedscope => Box::new(output::Speedscope(speedscope::Stats::new())),
             OutputFormat::pprof => Box::new(output::Pprof(pprof::Stats::new())),
-            OutputFormat::summary => Box::new(output::Summary(summary::Stats::new())),
-            OutputFormat::summary_by_line => Box::new(output::SummaryLine(summary::Stats::new())),
+            OutputFormat::summary => Box::new(output::Summary(
+                summary::Stats::new(),
+                summary_format,
+                summary_top,
+            )),
+            OutputFormat::summary_by_line => Box::new(output::SummaryLine(
+                summary::Stats::new(),
+                summary_format,
+                summary_top,
+            )),
+            OutputFormat::summary_by_gem => Box::new(output::SummaryGem(
+                summary::Stats::new(),
+                summary_format,
+                summary_top,
+            )),
+            OutputFormat::tree_json => Box::new(output::TreeJson(tree::Stats::new())),
+            OutputFormat::chrometrace => {
+                let interval =
+                    std::time::Duration::from_secs(1) / sample_rate.unwrap_or(100).max(1);
+                Box::new(output::Chrometrace(chrometrace::Stats::new(interval)))
+            }
+            OutputFormat::csv => Box::new(output::Csv(summary::Stats::new())),
+            OutputFormat::jsonl => Box::new(output::Jsonl::default()),
+            OutputFormat::rss => Box::new(output::Rss::default()),
+            OutputFormat::d3flamegraph => Box::new(output::D3Flamegraph(tree::Stats::new())),
         }
     }
 
@@ -184,6 +538,13 @@ impl OutputFormat {
             OutputFormat::pprof => "profile.pb.gz",
             OutputFormat::summary => "summary.txt",
             OutputFormat::summary_by_line => "summary_by_line.txt",
+            OutputFormat::summary_by_gem => "summary_by_gem.txt",
+            OutputFormat::tree_json => "tree.json",
+            OutputFormat::chrometrace => "chrometrace.json",
+            OutputFormat::csv => "summary.csv",
+            OutputFormat::jsonl => "traces.jsonl",
+            OutputFormat::rss => "rss.csv",
+            OutputFormat::d3flamegraph => "d3flamegraph.json",
         }
         .to_string()
     }
@@ -207,7 +568,206 @@ impl std::str::FromStr for OutputFormat {
             "pprof" => Ok(OutputFormat::pprof),
             "summary" => Ok(OutputFormat::summary),
             "summary-by-line" => Ok(OutputFormat::summary_by_line),
+            "summary-by-gem" => Ok(OutputFormat::summary_by_gem),
+            "tree-json" => Ok(OutputFormat::tree_json),
+            "chrometrace" => Ok(OutputFormat::chrometrace),
+            "csv" => Ok(OutputFormat::csv),
+            "jsonl" => Ok(OutputFormat::jsonl),
+            "rss" => Ok(OutputFormat::rss),
+            "d3flamegraph" => Ok(OutputFormat::d3flamegraph),
             _ => Err(anyhow::format_err!("Unknown output format: {}", s)),
         }
     }
 }
+
+/// Formats for comparing two recordings with `rbspy diff`
+#[derive(ValueEnum, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[allow(non_camel_case_types)]
+pub enum DiffFormat {
+    /// A differential flamegraph, colored by how much each frame changed
+    flamegraph,
+    /// Folded stacks with a count for each recording, for use with other flamegraph tools
+    collapsed,
+    /// A table of the functions whose share of samples changed the most
+    summary,
+}
+
+/// How raw data files are compressed
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum RawCompression {
+    /// Written as plain text, which takes more space but less CPU time
+    None,
+    /// Gzipped at the given level, from 0 (fastest) to 9 (smallest)
+    Gzip(u32),
+}
+
+impl Default for RawCompression {
+    fn default() -> Self {
+        RawCompression::Gzip(6)
+    }
+}
+
+impl RawCompression {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RawCompression::None => "raw",
+            RawCompression::Gzip(_) => "raw.gz",
+        }
+    }
+}
+
+/// Table formats for the `summary` output formats
+#[derive(ValueEnum, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[allow(non_camel_case_types)]
+pub enum SummaryFormat {
+    /// Aligned columns, as shown while recording
+    #[default]
+    text,
+    /// A Markdown table, e.g. for pasting into a GitHub issue
+    #[value(alias = "md")]
+    markdown,
+    /// Comma-separated values with sample counts, e.g. for importing into a spreadsheet
+    csv,
+}
+
+/// How `rbspy record` reports its progress while it's recording
+#[derive(ValueEnum, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[allow(non_camel_case_types)]
+pub enum ProgressFormat {
+    /// A summary table that's redrawn every second, clearing the terminal
+    #[default]
+    text,
+    /// One line of JSON every second (see `Recorder::progress`), for scripts and CI logs
+    json,
+}
+
+/// How to color the frames of flamegraphs
+#[derive(ValueEnum, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[allow(non_camel_case_types)]
+pub enum ColorBy {
+    /// Inferno's default colors, which vary with each function's name
+    #[default]
+    none,
+    /// Like path, but with a different color for each gem
+    gem,
+    /// One color for each kind of code: application code (red), gems (blue), Ruby's standard
+    /// library (green), and C functions (yellow)
+    path,
+}
+
+/// How to format traces, for `rbspy report` and the formatted outputs written while recording.
+#[derive(Clone, Debug, Default)]
+pub struct ReportOptions {
+    /// The title of flamegraphs. Default: none (Inferno's default title).
+    pub title: Option<String>,
+    /// The subtitle of flamegraphs. Default: none (describe the recording, e.g. the PID, sample
+    /// rate, and duration).
+    pub subtitle: Option<String>,
+    /// Merges the stacks in flamegraphs from the function that was running, instead of from the
+    /// root. Default: `false`.
+    pub reverse: bool,
+    /// How to color the frames of flamegraphs. Default: `none` (inferno's default colors).
+    pub color_by: ColorBy,
+    /// The table format of the summary formats. Default: `text`.
+    pub summary_format: SummaryFormat,
+    /// Limits the summary formats to this many of the most expensive names. Default: none (show
+    /// all of them).
+    pub top: Option<usize>,
+    /// Changes which frames appear in the output, e.g. by collapsing gem frames. Default: show
+    /// every frame.
+    pub filter: TraceFilter,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::types::*;
+
+    #[test]
+    fn test_frame_cache() {
+        let mut cache = FrameCache::new();
+        let mut reads = 0;
+        let mut read = |cache: &mut FrameCache, key, lineno| {
+            cache
+                .get_or_read(key, lineno, || {
+                    reads += 1;
+                    Ok(StackFrame {
+                        name: "foo".to_string(),
+                        relative_path: "foo.rb".to_string(),
+                        absolute_path: None,
+                        lineno: None,
+                    })
+                })
+                .unwrap()
+        };
+        assert_eq!(read(&mut cache, (1, 2, 3), Some(10)).lineno, Some(10));
+        assert_eq!(read(&mut cache, (1, 2, 3), Some(20)).lineno, Some(20));
+        // A different label or path at the same iseq address is a different frame
+        read(&mut cache, (1, 4, 3), Some(10));
+        cache.clear();
+        read(&mut cache, (1, 2, 3), None);
+        assert_eq!(reads, 3);
+    }
+
+    #[test]
+    fn test_fold_traces() {
+        let frame = |name: &str, lineno: usize| StackFrame {
+            name: name.to_string(),
+            relative_path: "app.rb".to_string(),
+            absolute_path: Some("/app/app.rb".to_string()),
+            lineno: Some(lineno),
+        };
+        let trace = |frames: Vec<StackFrame>| StackTrace {
+            trace: frames,
+            ..StackTrace::new_empty()
+        };
+        let in_foo = trace(vec![frame("foo", 2), frame("<main>", 10)]);
+        let in_bar = trace(vec![frame("bar", 6), frame("<main>", 11)]);
+        assert_eq!(in_foo.fold(), "<main> - /app/app.rb:10;foo - /app/app.rb:2");
+
+        // Traces from different threads and times have the same stack
+        let mut in_foo_later = in_foo.clone();
+        in_foo_later.thread_id = Some(2);
+        in_foo_later.time = Some(SystemTime::now());
+
+        let counts = fold_traces(&[in_foo.clone(), in_bar, in_foo_later]);
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[&in_foo.fold()], 2);
+        assert_eq!(counts["<main> - /app/app.rb:11;bar - /app/app.rb:6"], 1);
+        assert!(fold_traces(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_header_elapsed() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let mut header = Header {
+            sample_rate: Some(100),
+            start_time: Some(start),
+            ..Default::default()
+        };
+        let mut trace = StackTrace::new_empty();
+        trace.time = Some(start + Duration::from_millis(1500));
+        assert_eq!(header.elapsed(&trace, 3), Some(Duration::from_millis(1500)));
+        trace.time = Some(start - Duration::from_millis(1));
+        assert_eq!(header.elapsed(&trace, 3), Some(Duration::ZERO));
+
+        // Without a start time, fall back to the trace's position
+        header.start_time = None;
+        assert_eq!(
+            header.elapsed(&trace, 150),
+            Some(Duration::from_millis(1500))
+        );
+        header.sample_rate = None;
+        assert_eq!(header.elapsed(&trace, 150), None);
+    }
+
+    #[test]
+    fn test_is_from_threads() {
+        let mut trace = StackTrace::new_empty();
+        assert!(trace.is_from_threads(&[]));
+        assert!(!trace.is_from_threads(&[1]));
+        trace.thread_id = Some(2);
+        assert!(trace.is_from_threads(&[]));
+        assert!(trace.is_from_threads(&[1, 2]));
+        assert!(!trace.is_from_threads(&[1]));
+    }
+}
@@ -28,49 +28,567 @@ extern crate terminal_size;
 #[cfg(windows)]
 extern crate winapi;
 
-use core::ruby_spy::RubySpy;
+use core::ruby_spy::{RubySpy, RubySpyConfig};
 
-use anyhow::Result;
+use anyhow::{format_err, Result};
 
+#[doc(hidden)]
+pub mod bench;
 mod core;
 pub mod recorder;
 pub mod sampler;
 mod storage;
 pub mod ui;
 
+pub use crate::core::filter::TraceFilter;
 pub use crate::core::process::Pid;
+pub use crate::core::process_list::{
+    find_process_in_namespace, find_ruby_process_by_name, list_ruby_processes,
+    wait_for_ruby_process, RubyProcess,
+};
+pub use crate::core::types::fold_traces;
+pub use crate::core::types::ColorBy;
+pub use crate::core::types::DiffFormat;
+pub use crate::core::types::Header;
 pub use crate::core::types::OutputFormat;
+pub use crate::core::types::ProgressFormat;
+pub use crate::core::types::RawCompression;
+pub use crate::core::types::ReportOptions;
 pub use crate::core::types::StackFrame;
 pub use crate::core::types::StackTrace;
+pub use crate::core::types::SummaryFormat;
+pub use crate::storage::stream_from_reader;
+pub use crate::storage::TraceStream;
+pub use crate::ui::output::Outputter;
 
-/// Generate visualization (e.g. a flamegraph) from raw data that was previously recorded by rbspy
+/// Generate visualization (e.g. a flamegraph) from raw data that was previously recorded by rbspy.
+/// The traces from all of the `inputs` are combined into one visualization, e.g. to merge the
+/// raw data from several runs.
 pub fn report(
     format: OutputFormat,
-    input: &mut dyn std::io::Read,
+    options: &ReportOptions,
+    inputs: &mut [&mut dyn std::io::Read],
     output: &mut dyn std::io::Write,
 ) -> Result<()> {
-    let traces = storage::from_reader(input)?.traces;
-    let mut outputter = format.outputter(0.1);
-    for trace in traces {
-        outputter.record(&trace)?;
+    if inputs.is_empty() {
+        return Err(format_err!("No raw data to report on"));
+    }
+    let streams = inputs
+        .iter_mut()
+        .map(|input| storage::stream_from_reader(&mut **input))
+        .collect::<Result<Vec<TraceStream<_>>>>()?;
+    // Each sample counts the same no matter which recording it came from, so recordings made at
+    // different rates won't be weighted by how long they ran
+    let sample_rate = streams[0].header.sample_rate;
+    if streams.iter().any(|s| s.header.sample_rate != sample_rate) {
+        let rates: Vec<String> = streams
+            .iter()
+            .map(|s| {
+                s.header
+                    .sample_rate
+                    .map_or("unknown".to_string(), |r| r.to_string())
+            })
+            .collect();
+        warn!(
+            "The inputs were recorded at different sample rates ({}), so their samples won't be \
+            weighted consistently",
+            rates.join(", ")
+        );
+    }
+    let mut outputter = format.outputter(0.1, options, sample_rate);
+    for stream in streams {
+        for trace in stream {
+            let trace = trace?;
+            if !options.filter.hides(&trace) {
+                outputter.record(&options.filter.apply(&trace))?;
+            }
+        }
     }
     outputter.complete(output)?;
     Ok(())
 }
 
-pub fn inspect(pid: Pid, force_version: Option<String>) -> Result<()> {
-    let ruby_spy = RubySpy::new(pid, force_version, false)?;
+/// Compare two raw data files recorded by rbspy, e.g. to find out what got slower. The sample
+/// counts are normalized so that recordings of different lengths can be compared.
+pub fn diff(
+    format: DiffFormat,
+    before: &mut dyn std::io::Read,
+    after: &mut dyn std::io::Read,
+    output: &mut dyn std::io::Write,
+) -> Result<()> {
+    let mut stats = ui::diff::Stats::new();
+    for trace in storage::stream_from_reader(before)? {
+        stats.record_before(&trace?.trace)?;
+    }
+    for trace in storage::stream_from_reader(after)? {
+        stats.record_after(&trace?.trace)?;
+    }
+    stats.write(output, format, 0.1)
+}
+
+/// Combines raw data files recorded by rbspy into one raw data file at `output`, e.g. to keep the
+/// recordings of several runs together. The traces are written in the order of `inputs`. The
+/// merged file starts when the earliest input started, and only has a sample rate if all of the
+/// inputs were recorded at the same rate.
+pub fn merge(
+    inputs: &mut [&mut dyn std::io::Read],
+    output: &std::path::Path,
+    compression: RawCompression,
+) -> Result<()> {
+    if inputs.is_empty() {
+        return Err(format_err!("No raw data to merge"));
+    }
+    let streams = inputs
+        .iter_mut()
+        .map(|input| storage::stream_from_reader(&mut **input))
+        .collect::<Result<Vec<TraceStream<_>>>>()?;
+    let mut sample_rate = streams[0].header.sample_rate;
+    if streams.iter().any(|s| s.header.sample_rate != sample_rate) {
+        warn!(
+            "The inputs were recorded at different sample rates, so the merged file won't have a \
+            sample rate"
+        );
+        sample_rate = None;
+    }
+    let header = Header {
+        sample_rate,
+        rbspy_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        start_time: streams.iter().filter_map(|s| s.header.start_time).min(),
+        ..Default::default()
+    };
+    let mut store = storage::Store::with_header(output, &header, compression)?;
+    for mut stream in streams {
+        for trace in stream.by_ref() {
+            store.write(&trace?)?;
+        }
+        // Command lines are only known once the whole input has been read
+        for (pid, command_line) in &stream.header.command_lines {
+            store.add_process(*pid, command_line.clone())?;
+        }
+    }
+    store.complete()?;
+    Ok(())
+}
+
+/// Checks that `input` is an rbspy raw data file and prints its version and header, without
+/// reading any of the recorded traces
+pub fn check(input: &mut dyn std::io::Read, output: &mut dyn std::io::Write) -> Result<()> {
+    let stream = storage::stream_from_reader(input)?;
+    let header = &stream.header;
+    let unknown = || "unknown".to_string();
+    writeln!(output, "Format version: {}", stream.version())?;
+    writeln!(
+        output,
+        "Sample rate: {}",
+        header.sample_rate.map_or_else(unknown, |r| r.to_string())
+    )?;
+    writeln!(
+        output,
+        "rbspy version: {}",
+        header.rbspy_version.clone().unwrap_or_else(unknown)
+    )?;
+    writeln!(
+        output,
+        "Start time: {}",
+        header.start_time.map_or_else(unknown, |t| {
+            chrono::DateTime::<chrono::Utc>::from(t).to_rfc3339()
+        })
+    )?;
+    Ok(())
+}
+
+/// Key memory addresses of a Ruby process, as found by `inspect`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Inspection {
+    pub ruby_version: String,
+    pub ruby_vm_addr_location: usize,
+    pub current_thread_addr_location: usize,
+    pub global_symbols_addr_location: Option<usize>,
+    /// Whether the process seems to have JIT-compiled code, e.g. from YJIT
+    pub jit_code_detected: bool,
+}
+
+impl std::fmt::Display for Inspection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Ruby version: {}", self.ruby_version)?;
+        writeln!(f, "Ruby VM address: {:#x}", self.ruby_vm_addr_location)?;
+        writeln!(
+            f,
+            "Current thread address: {:#x}",
+            self.current_thread_addr_location
+        )?;
+        writeln!(
+            f,
+            "Global symbols address: {:#x}",
+            self.global_symbols_addr_location.unwrap_or(0)
+        )?;
+        write!(
+            f,
+            "JIT code detected: {}",
+            if self.jit_code_detected { "yes" } else { "no" }
+        )
+    }
+}
+
+/// Reads the stack trace of the thread that was running Ruby code when `core` was dumped. The
+/// Ruby version can't always be found in a core dump, so it must be given.
+pub fn analyze_coredump(
+    core: std::path::PathBuf,
+    ruby_version: String,
+    ruby_binary: Option<std::path::PathBuf>,
+) -> Result<Option<StackTrace>> {
+    core::coredump::get_stack_trace(&core, &ruby_version, ruby_binary.as_deref())
+}
+
+pub fn inspect(
+    pid: Pid,
+    force_version: Option<String>,
+    ruby_binary: Option<std::path::PathBuf>,
+) -> Result<Inspection> {
+    let ruby_spy = RubySpy::new(
+        pid,
+        &RubySpyConfig {
+            force_version,
+            ruby_binary,
+            ..Default::default()
+        },
+    )?;
     let vm = ruby_spy.inspect();
-    println!("Ruby version: {}", vm.ruby_version.semver_version);
-    println!("Ruby VM address: {:#x}", vm.ruby_vm_addr_location);
-    println!(
-        "Current thread address: {:#x}",
-        vm.current_thread_addr_location
-    );
-    println!(
-        "Global symbols address: {:#x}",
-        vm.global_symbols_addr_location.unwrap_or(0)
+    Ok(Inspection {
+        ruby_version: vm.ruby_version.semver_version.to_string(),
+        ruby_vm_addr_location: vm.ruby_vm_addr_location,
+        current_thread_addr_location: vm.current_thread_addr_location,
+        global_symbols_addr_location: vm.global_symbols_addr_location,
+        jit_code_detected: vm.jit_code_detected,
+    })
+}
+
+/// Options for `stream_traces`
+#[derive(Clone, Debug)]
+pub struct StreamConfig {
+    /// The number of traces that should be collected each second. Default: `100`.
+    pub sample_rate: u32,
+    /// Whether to pause the process while each trace is read, which gives more accurate traces at
+    /// the cost of slowing the process down. Default: `true`.
+    pub lock_process: bool,
+    /// How many more times to try locking the process when it fails, e.g. because threads are
+    /// starting or exiting. Default: `0`.
+    pub lock_retries: u32,
+    /// How long to sample for. Default: none (until the process exits or `stop` is called).
+    pub time_limit: Option<std::time::Duration>,
+    /// The number of traces after which sampling stops. Default: none.
+    pub max_samples: Option<usize>,
+    /// Whether to sample the process's child processes too. Default: `false`.
+    pub with_subprocesses: bool,
+    /// How often to look for new child processes. Default: 1 second.
+    pub subprocess_poll_interval: std::time::Duration,
+    /// Assume that the process runs this Ruby version instead of detecting it. Default: none.
+    pub force_version: Option<String>,
+    /// Read symbols only from this ruby binary or libruby. Default: none.
+    pub ruby_binary: Option<std::path::PathBuf>,
+    /// Only sample the process while it's using the CPU. Default: `false`.
+    pub on_cpu_only: bool,
+    /// Only keep traces from these OS thread IDs. Default: empty (all threads).
+    pub thread_ids: Vec<u64>,
+    /// Read every Ruby thread on each sample, not just the one that's running Ruby code. Requires
+    /// Ruby 2.3 or newer. Default: `false`.
+    pub all_threads: bool,
+    /// Read the process's resident set size on each sample, into each trace's `rss`. Only
+    /// supported on Linux and macOS. Default: `false`.
+    pub with_rss: bool,
+    /// How many stack traces can fail to be read before sampling a process stops with an error.
+    /// Default: the same as `RecordConfig::error_tolerance`.
+    pub error_tolerance: sampler::ErrorTolerance,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        StreamConfig {
+            sample_rate: 100,
+            lock_process: true,
+            lock_retries: 0,
+            time_limit: None,
+            max_samples: None,
+            with_subprocesses: false,
+            subprocess_poll_interval: std::time::Duration::from_secs(1),
+            force_version: None,
+            ruby_binary: None,
+            on_cpu_only: false,
+            thread_ids: vec![],
+            all_threads: false,
+            with_rss: false,
+            error_tolerance: sampler::ErrorTolerance::default(),
+        }
+    }
+}
+
+/// Stack traces being sampled from a running process, as returned by `stream_traces`. Iterating
+/// blocks until the next trace is sampled, and ends once sampling has stopped and every trace
+/// has been read.
+pub struct LiveTraces {
+    sampler: sampler::Sampler,
+    traces: std::sync::mpsc::Receiver<StackTrace>,
+    results: std::sync::mpsc::Receiver<Result<()>>,
+}
+
+impl LiveTraces {
+    /// Stops sampling. Traces that were sampled before this can still be read.
+    pub fn stop(&self) {
+        self.sampler.stop();
+    }
+
+    /// Stops sampling and returns why sampling ended. This is an error only if sampling failed
+    /// for every process, e.g. because the process isn't running Ruby.
+    pub fn finish(self) -> Result<()> {
+        self.sampler.stop();
+        // A sampling thread could be waiting for room in the channel, so make room until they've
+        // all ended
+        self.traces.iter().for_each(drop);
+        let mut num_ok = 0;
+        let mut last_result = Ok(());
+        for result in self.results.iter() {
+            if result.is_ok() {
+                num_ok += 1;
+            }
+            last_result = result;
+        }
+        match num_ok {
+            0 => last_result,
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for LiveTraces {
+    fn drop(&mut self) {
+        self.sampler.stop();
+    }
+}
+
+impl Iterator for LiveTraces {
+    type Item = StackTrace;
+
+    fn next(&mut self) -> Option<StackTrace> {
+        self.traces.recv().ok()
+    }
+}
+
+/// Samples stack traces from process `pid` on background threads and returns them as they're
+/// sampled, for programs that want to aggregate them themselves. Sampling continues until the
+/// process exits, a limit in `config` is reached, or `LiveTraces::stop` is called. If the
+/// returned `LiveTraces` is dropped, sampling stops too.
+pub fn stream_traces(pid: Pid, config: StreamConfig) -> Result<LiveTraces> {
+    let sampler = sampler::Sampler::new(
+        pid,
+        sampler::SamplerConfig {
+            sample_rate: config.sample_rate,
+            lock_process: config.lock_process,
+            lock_retries: config.lock_retries,
+            time_limit: config.time_limit,
+            max_samples: config.max_samples,
+            with_subprocesses: config.with_subprocesses,
+            subprocess_poll_interval: config.subprocess_poll_interval,
+            force_version: config.force_version,
+            ruby_binary: config.ruby_binary,
+            on_cpu_only: config.on_cpu_only,
+            thread_ids: config.thread_ids,
+            all_threads: config.all_threads,
+            with_rss: config.with_rss,
+            error_tolerance: config.error_tolerance,
+            ..Default::default()
+        },
     );
+    // Like the recorder, leave some room in case the consumer falls a little behind
+    let (trace_sender, traces) = std::sync::mpsc::sync_channel(100);
+    let (result_sender, results) = std::sync::mpsc::channel();
+    sampler.start(trace_sender, result_sender)?;
+    Ok(LiveTraces {
+        sampler,
+        traces,
+        results,
+    })
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use crate::storage::Store;
+    use crate::*;
+
+    fn raw(names: &[&str]) -> Vec<u8> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rbspy.raw.gz");
+        let mut store = Store::new(&path, 99, RawCompression::default()).unwrap();
+        for name in names {
+            store
+                .write(&StackTrace {
+                    trace: vec![StackFrame {
+                        name: name.to_string(),
+                        relative_path: "a.rb".to_string(),
+                        absolute_path: None,
+                        lineno: Some(1),
+                    }],
+                    pid: Some(1),
+                    thread_id: Some(2),
+                    time: None,
+                    on_cpu: None,
+                    holds_gvl: None,
+                    thread_name: None,
+                    rss: None,
+                    monotonic_ns: None,
+                })
+                .unwrap();
+        }
+        store.complete().unwrap();
+        std::fs::read(&path).unwrap()
+    }
+
+    #[test]
+    fn test_stream_traces() {
+        #[cfg(target_os = "macos")]
+        if !nix::unistd::Uid::effective().is_root() {
+            println!("Skipping test because we're not running as root");
+            return;
+        }
+
+        let mut process =
+            crate::core::process::tests::RubyScript::new("ci/ruby-programs/infinite_on_cpu.rb");
+        let pid = process.id() as Pid;
+        let config = StreamConfig {
+            max_samples: Some(5),
+            ..Default::default()
+        };
+        let mut traces = stream_traces(pid, config).unwrap();
+        let sampled: Vec<StackTrace> = traces.by_ref().collect();
+        assert_eq!(sampled.len(), 5);
+        assert!(sampled.iter().all(|trace| trace.pid == Some(pid)));
+        traces.finish().unwrap();
+        process.kill().unwrap();
+    }
+
+    #[test]
+    fn test_report_merges_inputs() {
+        let (first, second) = (raw(&["a", "b"]), raw(&["b"]));
+        let mut output = vec![];
+        report(
+            OutputFormat::collapsed,
+            &ReportOptions::default(),
+            &mut [&mut first.as_slice(), &mut second.as_slice()],
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a - a.rb:1 1\nb - a.rb:1 2\n"
+        );
+
+        assert!(report(
+            OutputFormat::collapsed,
+            &ReportOptions::default(),
+            &mut [],
+            &mut vec![],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_report_summary_top() {
+        let raw = raw(&["a", "b", "b"]);
+        let summary = |summary_format: SummaryFormat, summary_top: Option<usize>| {
+            let mut output = vec![];
+            report(
+                OutputFormat::summary,
+                &ReportOptions {
+                    summary_format,
+                    top: summary_top,
+                    ..Default::default()
+                },
+                &mut [&mut raw.as_slice()],
+                &mut output,
+            )
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(
+            summary(SummaryFormat::text, Some(1)),
+            "% self  % total  name\n 66.67    66.67  b - a.rb:1\n"
+        );
+        assert_eq!(summary(SummaryFormat::text, None).lines().count(), 3);
+        assert_eq!(
+            summary(SummaryFormat::csv, Some(1)),
+            "self_samples,total_samples,self_percent,total_percent,name\n\
+            2,2,66.67,66.67,b - a.rb:1\n"
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let (first, second) = (raw(&["a", "b"]), raw(&["c"]));
+        let dir = tempfile::tempdir().unwrap();
+        let other_rate = dir.path().join("other.raw.gz");
+        let mut store = Store::new(&other_rate, 10, RawCompression::None).unwrap();
+        store.add_process(3, vec!["ruby".to_string()]).unwrap();
+        store.complete().unwrap();
+        let other_rate = std::fs::read(&other_rate).unwrap();
+
+        let path = dir.path().join("merged.raw.gz");
+        merge(
+            &mut [&mut first.as_slice(), &mut second.as_slice()],
+            &path,
+            RawCompression::default(),
+        )
+        .unwrap();
+        let mut stream = storage::stream_from_reader(std::fs::File::open(&path).unwrap()).unwrap();
+        let first_header = storage::stream_from_reader(first.as_slice())
+            .unwrap()
+            .header;
+        assert_eq!(stream.header.sample_rate, Some(99));
+        assert_eq!(stream.header.start_time, first_header.start_time);
+        let names: Vec<String> = stream
+            .by_ref()
+            .map(|t| t.unwrap().trace[0].name.clone())
+            .collect();
+        assert_eq!(names, vec!["a", "b", "c"]);
+
+        merge(
+            &mut [&mut first.as_slice(), &mut other_rate.as_slice()],
+            &path,
+            RawCompression::None,
+        )
+        .unwrap();
+        let mut stream = storage::stream_from_reader(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(stream.header.sample_rate, None);
+        assert_eq!(stream.by_ref().count(), 2);
+        assert_eq!(stream.header.command_lines[&3], vec!["ruby"]);
+
+        assert!(merge(&mut [], &path, RawCompression::None).is_err());
+    }
+
+    #[test]
+    fn test_report_raw_file_from_another_arch() {
+        // Recorded from the aarch64 Ruby 3.2.0 core dump's process. Its thread ID is a 64-bit
+        // pthread address, so reading it mustn't depend on the width of this host's pointers.
+        let raw = include_bytes!("../ci/testdata/aarch64-ruby-3.2.0.raw");
+        let mut stream = storage::stream_from_reader(&raw[..]).unwrap();
+        let traces: Vec<StackTrace> = stream.by_ref().map(|t| t.unwrap()).collect();
+        assert_eq!(traces.len(), 3);
+        assert_eq!(traces[0].thread_id, Some(0xffffa4c2f020));
+        assert_eq!(stream.header.thread_names[&0xffffa4c2f020], "worker");
+
+        let mut output = vec![];
+        report(
+            OutputFormat::collapsed,
+            &ReportOptions::default(),
+            &mut [&mut &raw[..]],
+            &mut output,
+        )
+        .unwrap();
+        let stack = "block in <main> - /home/parallels/rbspy/ci/ruby-programs/infinite.rb:15;\
+            ccc - /home/parallels/rbspy/ci/ruby-programs/infinite.rb:11;\
+            bbb - /home/parallels/rbspy/ci/ruby-programs/infinite.rb:7;\
+            aaa - /home/parallels/rbspy/ci/ruby-programs/infinite.rb:3";
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("{} 1\n{};sleep [c function] - (unknown) 2\n", stack, stack)
+        );
+    }
 }
@@ -6,12 +6,17 @@ use rand::distributions::Alphanumeric;
 use rand::Rng;
 use rbspy::recorder;
 use rbspy::report;
-use rbspy::{OutputFormat, Pid};
+use rbspy::sampler::ErrorTolerance;
+use rbspy::{
+    ColorBy, DiffFormat, OutputFormat, Pid, ProgressFormat, RawCompression, ReportOptions,
+    SummaryFormat, TraceFilter,
+};
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::fs::DirBuilder;
 #[cfg(unix)]
 use std::os::unix::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -19,49 +24,140 @@ use std::time::{Duration, Instant};
 /// The kinds of things we can call `rbspy record` on.
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 enum Target {
-    Pid { pid: Pid },
-    Subprocess { prog: String, args: Vec<String> },
+    Pid {
+        pid: Pid,
+    },
+    Subprocess {
+        prog: String,
+        args: Vec<String>,
+    },
+    /// The only running Ruby process with this name, e.g. `puma`.
+    ProcessName {
+        name: String,
+    },
+    /// The process with PID `pid` inside the container whose root filesystem is at `root`.
+    NamespacePid {
+        pid: Pid,
+        root: PathBuf,
+    },
 }
 
 /// Subcommand.
-#[derive(Clone, PartialEq, PartialOrd, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 enum SubCmd {
     /// Record `target`, writing output `output`.
     Record {
         target: Target,
         out_path: PathBuf,
+        /// Formatted output for the second and later `--format`s
+        extra_outputs: Vec<(OutputFormat, PathBuf)>,
         raw_path: PathBuf,
+        raw_compression: RawCompression,
         sample_rate: u32,
         maybe_duration: Option<std::time::Duration>,
+        max_samples: Option<usize>,
         format: OutputFormat,
+        per_thread: bool,
+        raw_only: bool,
         no_drop_root: bool,
+        drop_privileges: bool,
         with_subprocesses: bool,
+        subprocess_poll_interval: Duration,
         silent: bool,
+        progress: ProgressFormat,
+        diagnostics: bool,
         flame_min_width: f64,
+        flame_title: Option<String>,
+        flame_subtitle: Option<String>,
+        reverse: bool,
+        color_by: ColorBy,
+        summary_format: SummaryFormat,
         lock_process: bool,
+        lock_retries: u32,
         force_version: Option<String>,
+        ruby_binary: Option<PathBuf>,
         on_cpu_only: bool,
+        idle_samples: bool,
+        force_on_cpu_samples: bool,
+        attach_timeout: Duration,
+        burst_rate: Option<u32>,
+        burst_duration: std::time::Duration,
+        adaptive: bool,
+        thread_ids: Vec<u64>,
+        all_threads: bool,
+        with_rss: bool,
+        error_tolerance: ErrorTolerance,
+        stop_signal: Option<i32>,
+        snapshot_signal: Option<i32>,
+        export: Option<String>,
+        serve: Option<String>,
+        rotate_interval: Option<Duration>,
+        rotate_output: bool,
+        defer_symbolization: bool,
+        /// Validate everything and print the resolved settings instead of recording
+        check: bool,
+        filter: TraceFilter,
     },
-    /// Capture and print a stacktrace snapshot of process `pid`.
+    /// Capture and print `count` stacktrace snapshots of process `target`, `interval` apart.
     Snapshot {
-        pid: Pid,
+        target: Target,
         lock_process: bool,
         force_version: Option<String>,
+        ruby_binary: Option<PathBuf>,
         on_cpu_only: bool,
+        idle_samples: bool,
+        thread_ids: Vec<u64>,
+        count: usize,
+        interval: Duration,
+        json: bool,
+        wait_for_file: Option<PathBuf>,
+        with_locals: bool,
     },
     Report {
         format: OutputFormat,
-        input: PathBuf,
+        summary_format: SummaryFormat,
+        summary_top: Option<usize>,
+        flame_title: Option<String>,
+        flame_subtitle: Option<String>,
+        reverse: bool,
+        color_by: ColorBy,
+        inputs: Vec<PathBuf>,
         output: PathBuf,
+        filter: TraceFilter,
+    },
+    /// Compare raw data files `before` and `after`.
+    Diff {
+        format: DiffFormat,
+        before: PathBuf,
+        after: PathBuf,
+        output: PathBuf,
+    },
+    /// Combine raw data files `inputs` into one raw data file at `output`.
+    Merge {
+        inputs: Vec<PathBuf>,
+        output: PathBuf,
+        raw_compression: RawCompression,
+    },
+    /// Validate raw data file `input` and print its header.
+    Check { input: PathBuf },
+    /// Print the stack trace that was running when core dump `core` was taken.
+    AnalyzeCoredump {
+        core: PathBuf,
+        ruby_version: String,
+        ruby_binary: Option<PathBuf>,
     },
     Inspect {
         target: Target,
         force_version: Option<String>,
+        ruby_binary: Option<PathBuf>,
+        json: bool,
     },
+    /// List the Ruby processes that are running.
+    List { json: bool },
 }
 
 /// Top level args type.
-#[derive(Clone, PartialEq, PartialOrd, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 struct Args {
     cmd: SubCmd,
 }
@@ -88,6 +184,7 @@ fn do_main() -> Result<(), Error> {
         let root_cmd = match args.cmd {
             SubCmd::Snapshot { .. } => Some("snapshot"),
             SubCmd::Record { .. } => Some("record"),
+            SubCmd::List { .. } => Some("list"),
             _ => None,
         };
         if let Some(root_cmd) = root_cmd {
@@ -107,52 +204,210 @@ fn do_main() -> Result<(), Error> {
 
     match args.cmd {
         SubCmd::Snapshot {
-            pid,
+            target,
             lock_process,
             force_version,
+            ruby_binary,
             on_cpu_only,
+            idle_samples,
+            thread_ids,
+            count,
+            interval,
+            json,
+            wait_for_file,
+            with_locals,
         } => {
-            match recorder::snapshot(pid, lock_process, force_version, on_cpu_only)? {
-                Some(snap) => println!("{}", snap),
-                None => println!("No stack trace was captured"),
+            let pid = target_pid(target, true)?;
+            let mut snapshotter = recorder::Snapshotter::new(
+                pid,
+                force_version,
+                ruby_binary,
+                on_cpu_only,
+                idle_samples,
+                thread_ids,
+            )?;
+            // Attaching is the slow part, so it's done before waiting and the snapshot is taken as
+            // soon as the file appears
+            if let Some(path) = wait_for_file {
+                eprintln!("Waiting for {} to exist...", path.display());
+                while !path.exists() {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+            }
+            let captured = if with_locals {
+                let snaps = snapshotter.snapshots_with_locals(lock_process, count, interval)?;
+                print_snapshots(&snaps, json, |snap| &snap.trace)?;
+                snaps.len()
+            } else {
+                let snaps = snapshotter.snapshots(lock_process, count, interval)?;
+                print_snapshots(&snaps, json, |trace| trace)?;
+                snaps.len()
             };
+            if captured < count {
+                eprintln!(
+                    "Process {} exited after {} of {} snapshots",
+                    pid, captured, count
+                );
+            }
             Ok(())
         }
         SubCmd::Record {
             target,
             out_path,
+            extra_outputs,
             raw_path,
+            raw_compression,
             sample_rate,
             maybe_duration,
+            max_samples,
             format,
+            per_thread,
+            raw_only,
             no_drop_root,
+            drop_privileges,
             with_subprocesses,
+            subprocess_poll_interval,
             silent,
+            progress,
+            diagnostics,
             flame_min_width,
+            flame_title,
+            flame_subtitle,
+            reverse,
+            color_by,
+            summary_format,
             lock_process,
+            lock_retries,
             force_version,
+            ruby_binary,
             on_cpu_only,
+            idle_samples,
+            force_on_cpu_samples,
+            attach_timeout,
+            burst_rate,
+            burst_duration,
+            adaptive,
+            thread_ids,
+            all_threads,
+            with_rss,
+            error_tolerance,
+            stop_signal,
+            snapshot_signal,
+            export,
+            serve,
+            rotate_interval,
+            rotate_output,
+            defer_symbolization,
+            check,
+            filter,
         } => {
-            let pid = match target {
-                Target::Pid { pid } => pid,
-                Target::Subprocess { prog, args } => spawn_subprocess(prog, args, no_drop_root)?,
+            #[cfg(feature = "export")]
+            let mut exporter = match export {
+                Some(url) => Some(rbspy::ui::export::from_url(&url)?),
+                None => None,
+            };
+            #[cfg(not(feature = "export"))]
+            if export.is_some() {
+                return Err(format_err!(
+                    "rbspy was built without the `export` feature, so --export isn't available"
+                ));
+            }
+
+            let spawned = matches!(target, Target::Subprocess { .. });
+            let mut pid = target_pid(target, no_drop_root)?;
+            // Follow a wrapper like a shell script to the Ruby process that it starts. With
+            // --subprocesses, the wrapper stays the root so that all of its children are sampled.
+            if spawned && !with_subprocesses {
+                pid = rbspy::wait_for_ruby_process(pid, WRAPPER_TIMEOUT);
+            }
+            let raw_path = fill_in_pid(raw_path, pid);
+            let out_path = fill_in_pid(out_path, pid);
+            let extra_outputs: Vec<(OutputFormat, PathBuf)> = extra_outputs
+                .into_iter()
+                .map(|(format, path)| (format, fill_in_pid(path, pid)))
+                .collect();
+            // This has to happen before any threads are started, so that they all run as the user
+            if drop_privileges {
+                drop_privileges_keeping_ptrace()?;
+            }
+            // Attaching is the slow part of a snapshot, so it's done up front to take snapshots as
+            // soon as they're requested
+            #[cfg(unix)]
+            let snapshotter = match snapshot_signal {
+                Some(_) => Some(recorder::Snapshotter::new(
+                    pid,
+                    force_version.clone(),
+                    ruby_binary.clone(),
+                    on_cpu_only,
+                    idle_samples,
+                    thread_ids.clone(),
+                )?),
+                None => None,
             };
 
             let config = recorder::RecordConfig {
-                format,
+                format: format.clone(),
                 raw_path: Some(raw_path.clone()),
-                out_path: Some(out_path.clone()),
+                raw_compression,
+                out_path: match raw_only {
+                    true => None,
+                    false => Some(out_path.clone()),
+                },
+                extra_outputs: extra_outputs.clone(),
+                per_thread,
                 pid,
                 with_subprocesses,
+                subprocess_poll_interval,
                 sample_rate,
                 maybe_duration,
+                max_samples,
                 flame_min_width,
+                flame_title,
+                flame_subtitle,
+                flame_reverse: reverse,
+                flame_color_by: color_by,
+                summary_format,
                 lock_process,
+                lock_retries,
                 force_version,
+                ruby_binary,
                 on_cpu_only,
+                idle_samples,
+                force_on_cpu_samples,
+                attach_timeout,
+                burst_rate,
+                burst_duration,
+                adaptive,
+                thread_ids,
+                all_threads,
+                with_rss,
+                error_tolerance,
+                filter,
+                live_flamegraph: serve.is_some(),
+                outputter: None,
+                rotate_interval,
+                rotate_output,
+                defer_symbolization,
             };
 
+            if check {
+                let inspection = rbspy::inspect(
+                    pid,
+                    config.force_version.clone(),
+                    config.ruby_binary.clone(),
+                )?;
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&checked_config(&config, &inspection))?
+                );
+                return Ok(());
+            }
+
             let recorder = Arc::<recorder::Recorder>::new(recorder::Recorder::new(config));
+            if let Some(address) = serve {
+                recorder::serve(recorder.clone(), &address)?;
+                eprintln!("Serving the flamegraph so far at http://{}/", address);
+            }
             let recorder_handler = recorder.clone();
             let recorder_summary = recorder.clone();
             let interrupted = Arc::<AtomicBool>::new(AtomicBool::new(false));
@@ -169,14 +424,111 @@ fn do_main() -> Result<(), Error> {
             })
             .expect("Error setting Ctrl-C handler");
 
+            #[cfg(unix)]
+            if let Some(signal) = stop_signal {
+                let handler = request_stop as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+                    return Err(format_err!(
+                        "Can't handle signal {} with --stop-signal",
+                        signal
+                    ));
+                }
+                let recorder_stop = recorder.clone();
+                let interrupted_stop = interrupted.clone();
+                std::thread::spawn(move || {
+                    while !interrupted_stop.load(Ordering::Relaxed) {
+                        if STOP_REQUESTED.swap(false, Ordering::Relaxed) {
+                            eprintln!("Received stop signal.");
+                            interrupted_stop.store(true, Ordering::Relaxed);
+                            recorder_stop.stop();
+                        }
+                        std::thread::sleep(Duration::from_millis(100));
+                    }
+                });
+            }
+            #[cfg(windows)]
+            let _ = stop_signal;
+
+            #[cfg(unix)]
+            if let (Some(signal), Some(mut snapshotter)) = (snapshot_signal, snapshotter) {
+                let handler = request_snapshot as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+                    return Err(format_err!(
+                        "Can't handle signal {} with --snapshot-on",
+                        signal
+                    ));
+                }
+                let snapshots_path = snapshots_path(&raw_path, raw_compression.extension());
+                let interrupted_snapshot = interrupted.clone();
+                std::thread::spawn(move || {
+                    while !interrupted_snapshot.load(Ordering::Relaxed) {
+                        if SNAPSHOT_REQUESTED.swap(false, Ordering::Relaxed) {
+                            match write_snapshot(&mut snapshotter, lock_process, &snapshots_path) {
+                                Ok(()) => {
+                                    eprintln!("Wrote a snapshot to {}", snapshots_path.display())
+                                }
+                                Err(e) => eprintln!("Failed to write snapshot: {:#}", e),
+                            }
+                        }
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                });
+            }
+            #[cfg(windows)]
+            let _ = snapshot_signal;
+
+            if burst_rate.is_some() {
+                #[cfg(unix)]
+                {
+                    unsafe {
+                        libc::signal(
+                            libc::SIGUSR1,
+                            request_burst as extern "C" fn(libc::c_int) as libc::sighandler_t,
+                        );
+                    }
+                    let recorder_burst = recorder.clone();
+                    let interrupted_burst = interrupted.clone();
+                    std::thread::spawn(move || {
+                        while !interrupted_burst.load(Ordering::Relaxed) {
+                            if BURST_REQUESTED.swap(false, Ordering::Relaxed) {
+                                recorder_burst.burst();
+                            }
+                            std::thread::sleep(Duration::from_millis(100));
+                        }
+                    });
+                    eprintln!(
+                        "Send SIGUSR1 to rbspy (pid {}) to sample at the burst rate.",
+                        std::process::id()
+                    );
+                }
+                #[cfg(windows)]
+                eprintln!("Bursts can't be triggered on Windows, so --burst-rate will be ignored.");
+            }
+
+            let max_rate = rbspy::sampler::max_reliable_rate();
+            for rate in std::iter::once(sample_rate).chain(burst_rate) {
+                if rate > max_rate {
+                    eprintln!(
+                        "Warning: the system timer can't reliably sample faster than {} times per second, so sampling at {} will fall behind and most samples will be counted as late. The effective rate will be at most about {}.",
+                        max_rate, rate, max_rate
+                    );
+                }
+            }
+
             eprintln!("rbspy is recording traces. Press Ctrl+C to stop.");
 
             let summary_thread = std::thread::spawn(move || {
-                if silent {
+                #[cfg(feature = "export")]
+                let exporting = exporter.is_some();
+                #[cfg(not(feature = "export"))]
+                let exporting = false;
+                if silent && !exporting {
                     return;
                 }
 
                 let mut summary_time = Instant::now() + Duration::from_secs(1);
+                #[cfg(feature = "export")]
+                let mut export_failed = false;
                 loop {
                     if interrupted_summary.load(Ordering::Relaxed) {
                         break;
@@ -184,15 +536,42 @@ fn do_main() -> Result<(), Error> {
 
                     // Print a summary every second
                     if std::time::Instant::now() > summary_time {
-                        println!("{}[2J", 27 as char); // clear screen
-                        println!("{}[0;0H", 27 as char); // go to 0,0
-                        match recorder_summary.write_summary(&mut std::io::stderr()) {
-                            Ok(()) => {}
-                            Err(e) => {
-                                eprintln!("Failed to print summary: {}", e);
-                                break;
+                        #[cfg(feature = "export")]
+                        if let Some(exporter) = exporter.as_mut() {
+                            // Metrics systems can be down for a while, so keep trying, but only
+                            // complain once
+                            if let Err(e) = exporter.export(&recorder_summary.top_functions(20)) {
+                                if !export_failed {
+                                    eprintln!("Failed to export function stats: {:#}", e);
+                                    export_failed = true;
+                                }
                             }
-                        };
+                        }
+                        // The summary goes to stderr, so that stdout only has the output when it's
+                        // written with `--file -`
+                        match (silent, progress) {
+                            (true, _) => {}
+                            (false, ProgressFormat::text) => {
+                                eprintln!("{}[2J", 27 as char); // clear screen
+                                eprintln!("{}[0;0H", 27 as char); // go to 0,0
+                                match recorder_summary.write_summary(&mut std::io::stderr()) {
+                                    Ok(()) => {}
+                                    Err(e) => {
+                                        eprintln!("Failed to print summary: {}", e);
+                                        break;
+                                    }
+                                };
+                            }
+                            (false, ProgressFormat::json) => {
+                                match serde_json::to_string(&recorder_summary.progress()) {
+                                    Ok(line) => eprintln!("{}", line),
+                                    Err(e) => {
+                                        eprintln!("Failed to print progress: {}", e);
+                                        break;
+                                    }
+                                }
+                            }
+                        }
                         summary_time = Instant::now() + Duration::from_secs(1);
                     }
 
@@ -204,42 +583,297 @@ fn do_main() -> Result<(), Error> {
 
             interrupted.store(true, Ordering::Relaxed);
             summary_thread.join().expect("couldn't join summary thread");
-            eprintln!(
-                "{}",
-                format!(
-                    "Wrote raw data to {}\nWrote formatted output to {}",
-                    raw_path.display(),
-                    out_path.display()
-                )
-            );
+            if !silent {
+                if let Err(e) = recorder.write_final_summary(&mut std::io::stderr()) {
+                    eprintln!("Failed to print summary: {}", e);
+                }
+            }
+            if diagnostics {
+                if let Err(e) = recorder.write_diagnostics(&mut std::io::stderr()) {
+                    eprintln!("Failed to print diagnostics: {}", e);
+                }
+            }
+            let raw_path = match rotate_interval {
+                Some(_) => recorder::rotated_path(&raw_path, "*", raw_compression.extension()),
+                None => raw_path,
+            };
+            eprintln!("Wrote raw data to {}", raw_path.display());
+            if defer_symbolization {
+                eprintln!(
+                    "Its frames are named when it's read, e.g. with `rbspy report --input {}`",
+                    raw_path.display()
+                );
+            }
+            let outputs = std::iter::once((format, out_path)).chain(extra_outputs);
+            for (format, out_path) in outputs {
+                let out_path = match rotate_output {
+                    true => recorder::rotated_path(&out_path, "*", &format.extension()),
+                    false => out_path,
+                };
+                let out_path = match per_thread {
+                    true => recorder::thread_output_path(&out_path, "*"),
+                    false => out_path,
+                };
+                if !raw_only && out_path != PathBuf::from("-") {
+                    eprintln!("Wrote formatted output to {}", out_path.display());
+                }
+            }
 
             recording_result
         }
         SubCmd::Report {
             format,
-            input,
+            summary_format,
+            summary_top,
+            flame_title,
+            flame_subtitle,
+            reverse,
+            color_by,
+            inputs,
+            output,
+            filter,
+        } => {
+            let mut files = inputs
+                .iter()
+                .map(|input| {
+                    std::fs::File::open(input)
+                        .context(format!("Failed to open {}", input.display()))
+                })
+                .collect::<Result<Vec<std::fs::File>>>()?;
+            let mut inputs: Vec<&mut dyn std::io::Read> = files
+                .iter_mut()
+                .map(|file| file as &mut dyn std::io::Read)
+                .collect();
+            let options = ReportOptions {
+                title: flame_title,
+                subtitle: flame_subtitle,
+                reverse,
+                color_by,
+                summary_format,
+                top: summary_top,
+                filter,
+            };
+            if output.display().to_string() == "-" {
+                report(format, &options, &mut inputs, &mut std::io::stdout())
+            } else {
+                report(
+                    format,
+                    &options,
+                    &mut inputs,
+                    &mut std::fs::File::create(output)?,
+                )
+            }
+        }
+        SubCmd::Diff {
+            format,
+            before,
+            after,
             output,
         } => {
-            let mut input = std::fs::File::open(input)?;
+            let mut before = std::fs::File::open(&before)
+                .context(format!("Failed to open {}", before.display()))?;
+            let mut after = std::fs::File::open(&after)
+                .context(format!("Failed to open {}", after.display()))?;
             if output.display().to_string() == "-" {
-                report(format, &mut input, &mut std::io::stdout())
+                rbspy::diff(format, &mut before, &mut after, &mut std::io::stdout())
             } else {
-                report(format, &mut input, &mut std::fs::File::create(output)?)
+                rbspy::diff(
+                    format,
+                    &mut before,
+                    &mut after,
+                    &mut std::fs::File::create(output)?,
+                )
             }
         }
+        SubCmd::Merge {
+            inputs,
+            output,
+            raw_compression,
+        } => {
+            let mut files = inputs
+                .iter()
+                .map(|input| {
+                    std::fs::File::open(input)
+                        .context(format!("Failed to open {}", input.display()))
+                })
+                .collect::<Result<Vec<std::fs::File>>>()?;
+            let mut inputs: Vec<&mut dyn std::io::Read> = files
+                .iter_mut()
+                .map(|file| file as &mut dyn std::io::Read)
+                .collect();
+            rbspy::merge(&mut inputs, &output, raw_compression)
+                .context(format!("Failed to write {}", output.display()))
+        }
+        SubCmd::Check { input } => {
+            let mut file = std::fs::File::open(&input)
+                .context(format!("Failed to open {}", input.display()))?;
+            rbspy::check(&mut file, &mut std::io::stdout()).context(format!(
+                "{} isn't a valid rbspy raw data file",
+                input.display()
+            ))
+        }
+        SubCmd::AnalyzeCoredump {
+            core,
+            ruby_version,
+            ruby_binary,
+        } => {
+            match rbspy::analyze_coredump(core, ruby_version, ruby_binary)? {
+                Some(trace) => println!("{}", trace),
+                None => println!("No stack trace was found"),
+            };
+            Ok(())
+        }
         SubCmd::Inspect {
             target,
             force_version,
+            ruby_binary,
+            json,
         } => {
-            let pid = match target {
-                Target::Pid { pid } => pid,
-                Target::Subprocess { prog, args } => spawn_subprocess(prog, args, true)?,
-            };
-            rbspy::inspect(pid, force_version)
+            let pid = target_pid(target, true)?;
+            let inspection = rbspy::inspect(pid, force_version, ruby_binary)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&inspection)?);
+            } else {
+                println!("{}", inspection);
+            }
+            Ok(())
+        }
+        SubCmd::List { json } => {
+            let processes = rbspy::list_ruby_processes()?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&processes)?);
+            } else {
+                println!("{:>8}  {:<8}  {:<12}  EXECUTABLE", "PID", "RUBY", "NAME");
+                for process in processes {
+                    println!(
+                        "{:>8}  {:<8}  {:<12}  {}",
+                        process.pid,
+                        process.ruby_version.as_deref().unwrap_or("?"),
+                        process.name.as_deref().unwrap_or("?"),
+                        process.executable.as_deref().unwrap_or("?")
+                    );
+                }
+            }
+            Ok(())
         }
     }
 }
 
+/// The settings that `record --check` prints, once the target process has been found and
+/// inspected
+fn checked_config(
+    config: &recorder::RecordConfig,
+    inspection: &rbspy::Inspection,
+) -> serde_json::Value {
+    let outputs: Vec<serde_json::Value> = config
+        .out_path
+        .iter()
+        .map(|path| (&config.format, path))
+        .chain(
+            config
+                .extra_outputs
+                .iter()
+                .map(|(format, path)| (format, path)),
+        )
+        .map(|(format, path)| serde_json::json!({ "format": format_name(format), "path": path }))
+        .collect();
+    serde_json::json!({
+        "pid": config.pid,
+        "ruby_version": inspection.ruby_version,
+        "outputs": outputs,
+        "raw_path": config.raw_path,
+        "raw_compression": match config.raw_compression {
+            RawCompression::None => "none".to_string(),
+            RawCompression::Gzip(level) => level.to_string(),
+        },
+        "rate": config.sample_rate,
+        "duration": config.maybe_duration.map(|d| d.as_secs()),
+        "max_samples": config.max_samples,
+        "subprocesses": config.with_subprocesses,
+        "nonblocking": !config.lock_process,
+        "on_cpu": config.on_cpu_only,
+        "idle": config.idle_samples,
+        "all_threads": config.all_threads,
+        "thread_ids": config.thread_ids,
+        "with_rss": config.with_rss,
+        "rotate_interval": config.rotate_interval.map(|d| d.as_secs_f64()),
+        "defer_symbolization": config.defer_symbolization,
+        "attach_timeout": config.attach_timeout.as_secs_f64(),
+    })
+}
+
+/// The highest `--rate` we accept. Sampling any faster leaves less than 10µs per sample, which is
+/// less than it takes to read a single stack trace.
+const MAX_SAMPLE_RATE: i64 = 100_000;
+
+/// How long to wait for a command given to `record` to start Ruby, either itself or in a child
+/// process
+const WRAPPER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Set by the SIGUSR1 handler when a sampling burst is requested during `rbspy record`.
+#[cfg(unix)]
+static BURST_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn request_burst(_: libc::c_int) {
+    BURST_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Set by the `--stop-signal` handler to stop `rbspy record`.
+#[cfg(unix)]
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn request_stop(_: libc::c_int) {
+    STOP_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Set by the `--snapshot-on` handler to take a snapshot during `rbspy record`.
+#[cfg(unix)]
+static SNAPSHOT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn request_snapshot(_: libc::c_int) {
+    SNAPSHOT_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Where `record --snapshot-on` writes its snapshots, next to the raw data, e.g.
+/// `out.snapshots.txt` for `out.raw.gz`
+#[cfg(unix)]
+fn snapshots_path(raw_path: &Path, raw_extension: &str) -> PathBuf {
+    let name = raw_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let base = name
+        .strip_suffix(&format!(".{}", raw_extension))
+        .unwrap_or(&name);
+    raw_path.with_file_name(format!("{}.snapshots.txt", base))
+}
+
+/// Appends a snapshot to the `record --snapshot-on` file, after a line saying when it was taken
+#[cfg(unix)]
+fn write_snapshot(
+    snapshotter: &mut recorder::Snapshotter,
+    lock_process: bool,
+    path: &Path,
+) -> Result<()> {
+    use std::io::Write;
+
+    let trace = snapshotter.snapshot(lock_process)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(format!("open {}", path.display()))?;
+    writeln!(file, "Snapshot at {}:", Local::now().to_rfc3339())?;
+    match trace {
+        Some(trace) => writeln!(file, "{}\n{}\n", recorder::thread_header(&trace), trace)?,
+        None => writeln!(file, "No stack trace was captured\n")?,
+    }
+    Ok(())
+}
+
 fn arg_parser() -> clap::Command {
     clap::Command::new("rbspy")
         .version(env!("CARGO_PKG_VERSION"))
@@ -247,12 +881,15 @@ fn arg_parser() -> clap::Command {
         .subcommand_required(true)
         .subcommand(
             clap::Command::new("snapshot")
-                .about("Capture a single stack trace from a running Ruby program")
+                .about("Capture a stack trace (or several, with --count) from a running Ruby program")
                 .arg(
                     arg!(-p --pid <PID> "PID of the Ruby process you want to profile")
                         .value_parser(validate_pid)
-                        .required(true)
+                        .required_unless_present_any(["process-name", "namespace-pid"])
+                        .conflicts_with_all(["process-name", "namespace-pid"]),
                 )
+                .arg(process_name_arg().conflicts_with("namespace-pid"))
+                .args(namespace_args())
                 .arg(
                     arg!(--nonblocking "Don't pause the ruby process when taking the snapshot. Setting this option will reduce \
                                                     the performance impact of sampling but may produce inaccurate results")
@@ -264,6 +901,17 @@ fn arg_parser() -> clap::Command {
                             version is not yet supported by rbspy, e.g. a release candidate")
                         .long("force-version")
                         .value_name("VERSION")
+                        .value_parser(validate_ruby_version)
+                        .required(false)
+                )
+                .arg(
+                    clap::Arg::new("ruby-binary")
+                        .help("Path of the ruby binary or libruby that the process is running. Use this if \
+                            rbspy has trouble finding the Ruby version or VM addresses, e.g. because \
+                            more than one Ruby is loaded")
+                        .long("ruby-binary")
+                        .value_name("PATH")
+                        .value_parser(clap::value_parser!(PathBuf))
                         .required(false)
                 )
                 .arg(
@@ -274,6 +922,55 @@ fn arg_parser() -> clap::Command {
                         .long("on-cpu")
                         .required(false),
                 )
+                .arg(
+                    clap::Arg::new("mode")
+                        .help("wall: sample whatever the process is doing (the default). cpu: check whether \
+                            the process is using the CPU, and record an (idle) stack trace if it isn't")
+                        .long("mode")
+                        .value_name("MODE")
+                        .value_parser(["wall", "cpu"])
+                        .conflicts_with("on-cpu")
+                        .required(false),
+                )
+                .arg(
+                    clap::Arg::new("count")
+                        .help("Number of snapshots to capture")
+                        .long("count")
+                        .short('n')
+                        .value_name("COUNT")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .default_value("1"),
+                )
+                .arg(
+                    clap::Arg::new("interval")
+                        .help("Time to wait between snapshots, e.g. 200ms or 2s. Plain numbers are milliseconds")
+                        .long("interval")
+                        .value_name("INTERVAL")
+                        .value_parser(validate_interval)
+                        .default_value("100ms"),
+                )
+                .arg(thread_id_arg())
+                .arg(
+                    clap::Arg::new("json")
+                        .help("Print the snapshots as a JSON array")
+                        .long("json")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    clap::Arg::new("wait-for-file")
+                        .help("Attach to the process, then wait until <FILE> exists before capturing, \
+                            e.g. to snapshot a process when a script or another process notices a problem")
+                        .long("wait-for-file")
+                        .value_name("FILE")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg(
+                    clap::Arg::new("with-locals")
+                        .help("Also show the local variables of each Ruby frame, when they can be read. \
+                            Only supported for Ruby 3.x (EXPERIMENTAL)")
+                        .long("with-locals")
+                        .action(clap::ArgAction::SetTrue),
+                )
         )
         .subcommand(
             clap::Command::new("record")
@@ -282,26 +979,134 @@ fn arg_parser() -> clap::Command {
                     arg!(-p --pid <PID> "PID of the Ruby process you want to profile")
                     .value_parser(validate_pid)
                     // It's a bit confusing but this is how to get exactly-one-of behaviour
-                    // for `--pid` and `cmd`.
-                    .required_unless_present("cmd")
-                    .conflicts_with("cmd"),
+                    // for `--pid`, `--process-name`, `--namespace-pid` and `cmd`.
+                    .required_unless_present_any(["cmd", "process-name", "namespace-pid"])
+                    .conflicts_with_all(["cmd", "process-name", "namespace-pid"]),
                 )
+                .arg(process_name_arg().conflicts_with_all(["cmd", "namespace-pid"]))
+                .args(namespace_args().into_iter().map(|arg| arg.conflicts_with("cmd")))
                 .arg(
                     clap::Arg::new("raw-file")
-                        .help("File to write raw data to (will be gzipped)")
+                        .help("File to write raw data to (will be gzipped unless --raw-compression is none)")
                         .long("raw-file")
                         .required(false),
                 )
+                .arg(
+                    clap::Arg::new("raw-compression")
+                        .help("Gzip compression level for the raw data file, from 0 (fastest) to 9 \
+                            (smallest), or `none` to write it uncompressed")
+                        .long("raw-compression")
+                        .value_name("LEVEL")
+                        .value_parser(validate_raw_compression)
+                        .default_value("6"),
+                )
                 .arg(
                     arg!(-f --file <FILE> "File to write formatted output to")
                         .required(false),
                 )
+                .arg(
+                    clap::Arg::new("output-dir")
+                        .help("Directory to write the raw data and formatted output to when \
+                            --raw-file or --file isn't given. Defaults to rbspy's cache directory")
+                        .long("output-dir")
+                        .value_name("DIR")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(false),
+                )
+                .arg(
+                    clap::Arg::new("filename-template")
+                        .help("Name of the raw data and formatted output files when --raw-file or \
+                            --file isn't given, without the extension. {pid}, {date}, {format} and \
+                            {rand} are replaced with the PID, today's date, the output format (raw \
+                            for the raw data) and 10 random characters")
+                        .long("filename-template")
+                        .value_name("TEMPLATE")
+                        .value_parser(validate_filename_template)
+                        .default_value(DEFAULT_FILENAME_TEMPLATE),
+                )
                 .arg(
                     arg!(-r --rate <RATE> "Samples per second collected")
-                        .value_parser(clap::value_parser!(u32))
+                        .value_parser(clap::value_parser!(u32).range(1..=MAX_SAMPLE_RATE))
                         .required(false)
                         .default_value("99"),
                 )
+                .arg(
+                    clap::Arg::new("burst-rate")
+                        .help("Samples per second collected during a burst. A burst is started by sending \
+                            SIGUSR1 to rbspy, and lasts for --burst-duration seconds")
+                        .long("burst-rate")
+                        .value_name("RATE")
+                        .value_parser(clap::value_parser!(u32).range(1..=MAX_SAMPLE_RATE))
+                        .required(false),
+                )
+                .arg(
+                    clap::Arg::new("burst-duration")
+                        .help("Number of seconds that a burst lasts before returning to --rate")
+                        .long("burst-duration")
+                        .value_name("DURATION")
+                        .value_parser(clap::value_parser!(u64))
+                        .requires("burst-rate")
+                        .required(false)
+                        .default_value("10"),
+                )
+                .arg(thread_id_arg())
+                .arg(
+                    arg!(--adaptive "Sample less often while the process is idle, and return to --rate \
+                        as soon as it's active again. Idle time will be underrepresented in the output")
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false)
+                )
+                .arg(
+                    arg!(--"all-threads" "Sample every Ruby thread, including threads that are waiting \
+                        (e.g. for I/O or the GVL), instead of only the one that's running Ruby code. \
+                        Requires Ruby 2.3+")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("adaptive")
+                        .required(false)
+                )
+                .arg(
+                    arg!(--"with-rss" "Record the process's resident memory (RSS) with each sample, \
+                        e.g. to see what it was doing while its memory grew. Use `--format rss` to \
+                        report it over time. Linux and macOS only")
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false)
+                )
+                .arg(
+                    clap::Arg::new("stop-signal")
+                        .help("Also stop recording and write output when rbspy receives <SIGNAL>, e.g. USR2. \
+                            SIGINT, SIGTERM and SIGHUP always stop recording")
+                        .long("stop-signal")
+                        .value_name("SIGNAL")
+                        .value_parser(validate_signal)
+                        .required(false),
+                )
+                .arg(
+                    clap::Arg::new("snapshot-on")
+                        .help("Write a snapshot of the current stack trace when rbspy receives <SIGNAL>, \
+                            e.g. USR2, and keep recording. Snapshots are added to a file next to the raw \
+                            data, e.g. out.snapshots.txt")
+                        .long("snapshot-on")
+                        .value_name("SIGNAL")
+                        .value_parser(validate_signal)
+                        .required(false),
+                )
+                .arg(
+                    clap::Arg::new("per-thread")
+                        .help("Write a separate output file for each thread, with the thread ID added to the \
+                            file name, e.g. out.tid-12345.svg")
+                        .action(clap::ArgAction::SetTrue)
+                        .long("per-thread")
+                        .required(false),
+                )
+                .arg(
+                    clap::Arg::new("raw-only")
+                        .help("Only write the raw data file, and skip the formatted output. The raw \
+                            data can be formatted later with `rbspy report`")
+                        .action(clap::ArgAction::SetTrue)
+                        .long("raw-only")
+                        .conflicts_with_all(["file", "format", "per-thread"])
+                        .required(false),
+                )
                 .arg(
                     clap::Arg::new("no-drop-root")
                         .action(clap::ArgAction::SetTrue)
@@ -311,8 +1116,17 @@ fn arg_parser() -> clap::Command {
                         .required(false),
                 )
                 .arg(
-                    arg!(-o --format <FORMAT> "Output format to write")
+                    arg!(--"drop-privileges" "Run as the user that ran sudo (SUDO_UID) while sampling, \
+                        keeping only the ptrace capability that's needed to read the process's memory. \
+                        Output files are written as that user too. Linux only")
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .arg(
+                    arg!(-o --format <FORMAT> "Output format to write. Give it more than once to \
+                        write several formats from the same recording, each to its own file")
                         .value_parser(clap::value_parser!(OutputFormat))
+                        .action(clap::ArgAction::Append)
                         .ignore_case(true)
                         .required(false)
                         .default_value("flamegraph"),
@@ -323,16 +1137,124 @@ fn arg_parser() -> clap::Command {
                         .conflicts_with("cmd")
                         .required(false),
                 )
+                .arg(
+                    clap::Arg::new("max-samples")
+                        .help("Number of samples to record before stopping. If --duration is also \
+                            given, recording stops at whichever limit is reached first")
+                        .long("max-samples")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .required(false),
+                )
                 .arg(
                     arg!(-s --subprocesses "Record all subprocesses of the given PID or command")
                         .action(clap::ArgAction::SetTrue)
                         .required(false)
                 )
+                .arg(
+                    clap::Arg::new("subprocess-poll-interval")
+                        .help("How often to look for new subprocesses, e.g. 100ms or 2s. Plain numbers are \
+                            milliseconds. Lower this to catch short-lived subprocesses")
+                        .long("subprocess-poll-interval")
+                        .value_name("INTERVAL")
+                        .value_parser(validate_interval)
+                        .requires("subprocesses")
+                        .default_value("1s"),
+                )
+                .arg(
+                    arg!(--check "Check the arguments and that the process is running Ruby that rbspy \
+                        can read, print the settings that would be used as JSON, and exit without \
+                        recording. The process has to already be running, so this can't be used with \
+                        a command")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("cmd")
+                        .required(false),
+                )
                 .arg(
                     arg!(--silent "Don't print the summary profiling data every second")
                         .action(clap::ArgAction::SetTrue)
                         .required(false)
                 )
+                .arg(
+                    clap::Arg::new("progress")
+                        .help("How to show progress while recording: a summary table that's redrawn \
+                            every second, or one line of JSON a second (elapsed time, sample counts and \
+                            the top function) for scripts and CI logs. Both are written to stderr")
+                        .long("progress")
+                        .value_name("FORMAT")
+                        .value_parser(clap::value_parser!(ProgressFormat))
+                        .ignore_case(true)
+                        .conflicts_with("silent")
+                        .default_value("text"),
+                )
+                .arg(
+                    arg!(--diagnostics "Print how well sampling went when recording stops, e.g. dropped stack traces by error and the cost of each sample. Useful in bug reports")
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false)
+                )
+                .arg(
+                    clap::Arg::new("rotate-interval")
+                        .help("Start a new raw data file this often, e.g. 15m or 1h, so that long \
+                            recordings are split into numbered files (out.0001.raw.gz, \
+                            out.0002.raw.gz, ...) that are each complete on their own")
+                        .long("rotate-interval")
+                        .value_name("INTERVAL")
+                        .value_parser(validate_rotate_interval)
+                        .required(false),
+                )
+                .arg(
+                    arg!(--"rotate-output" "Also write the formatted output at each rotation, \
+                        numbered like the raw data files, instead of one output file for the whole \
+                        recording")
+                        .requires("rotate-interval")
+                        .conflicts_with("raw-only")
+                        .required(false),
+                )
+                .arg(
+                    arg!(--"defer-symbolization" "Experimental: only record the addresses of each \
+                        stack frame, along with the memory that's needed to name it, and name the \
+                        frames later with `rbspy report`. Sampling is much cheaper, so higher rates \
+                        are possible, but only the raw data file is written. Linux only")
+                        .conflicts_with_all([
+                            "file",
+                            "format",
+                            "per-thread",
+                            "all-threads",
+                            "adaptive",
+                            "rotate-interval",
+                            "serve",
+                            "export",
+                        ])
+                        .required(false),
+                )
+                .arg(
+                    clap::Arg::new("serve")
+                        .help("Serve a flamegraph of the samples so far over HTTP while recording, \
+                            e.g. 127.0.0.1:8080. Reload the page to see the latest samples")
+                        .long("serve")
+                        .value_name("ADDRESS")
+                        .required(false),
+                )
+                .arg(
+                    clap::Arg::new("export")
+                        .help("Push the top functions to a metrics system every second while \
+                            recording, e.g. statsd://localhost:8125 or \
+                            prometheus-pushgateway://localhost:9091/metrics/job/rbspy. Requires \
+                            rbspy to be built with the `export` feature")
+                        .long("export")
+                        .value_name("URL")
+                        .required(false),
+                )
+                .arg(
+                    clap::Arg::new("summary-format")
+                        .help("Table format for the summary output formats")
+                        .long("summary-format")
+                        .value_name("FORMAT")
+                        .value_parser(clap::value_parser!(SummaryFormat))
+                        .ignore_case(true)
+                        .required(false)
+                        .default_value("text"),
+                )
                 .arg(
                     clap::Arg::new("flame-min-width")
                         .value_parser(clap::value_parser!(f64))
@@ -341,18 +1263,67 @@ fn arg_parser() -> clap::Command {
                         .required(false)
                         .default_value("0.1"),
                 )
+                .args(flamegraph_args())
                 .arg(
                     arg!(--nonblocking "Don't pause the ruby process when collecting stack samples. Setting this option will reduce \
                                                    the performance impact of sampling but may produce inaccurate results")
                         .action(clap::ArgAction::SetTrue)
                         .required(false),
                 )
+                .arg(
+                    clap::Arg::new("attach-timeout")
+                        .help("How long to keep trying to attach to the ruby process before giving up, \
+                            e.g. 10s for an app that takes a while to boot. Whole milliseconds (ms), \
+                            seconds (s) or minutes (m)")
+                        .long("attach-timeout")
+                        .value_name("DURATION")
+                        .value_parser(validate_interval)
+                        .default_value("200ms"),
+                )
+                .arg(
+                    clap::Arg::new("lock-retries")
+                        .help("Number of times to retry pausing the ruby process when it fails, e.g. because \
+                            threads are starting or exiting, before dropping the sample")
+                        .long("lock-retries")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with("nonblocking")
+                        .default_value("0"),
+                )
+                .arg(
+                    clap::Arg::new("max-error-rate")
+                        .help("Stop recording a process when more than <RATE> (from 0 to 1) of its stack \
+                            traces can't be read, once --min-errors-before-abort have failed. 1 never stops")
+                        .long("max-error-rate")
+                        .value_name("RATE")
+                        .value_parser(validate_error_rate)
+                        .default_value("0.5"),
+                )
+                .arg(
+                    clap::Arg::new("min-errors-before-abort")
+                        .help("How many stack traces have to fail to be read before --max-error-rate applies")
+                        .long("min-errors-before-abort")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("20"),
+                )
                 .arg(
                     clap::Arg::new("force-version")
                         .help("Assume that the Ruby version is <VERSION>. This is useful when the Ruby \
                             version is not yet supported by rbspy, e.g. a release candidate")
                         .long("force-version")
                         .value_name("VERSION")
+                        .value_parser(validate_ruby_version)
+                        .required(false)
+                )
+                .arg(
+                    clap::Arg::new("ruby-binary")
+                        .help("Path of the ruby binary or libruby that the process is running. Use this if \
+                            rbspy has trouble finding the Ruby version or VM addresses, e.g. because \
+                            more than one Ruby is loaded")
+                        .long("ruby-binary")
+                        .value_name("PATH")
+                        .value_parser(clap::value_parser!(PathBuf))
                         .required(false)
                 )
                 .arg(
@@ -363,14 +1334,46 @@ fn arg_parser() -> clap::Command {
                         .long("on-cpu")
                         .required(false),
                 )
+                .arg(
+                    arg!(--idle "Record an (idle) stack trace whenever the process isn't using the CPU, so that \
+                                 the output accounts for all of the elapsed time")
+                        .action(clap::ArgAction::SetTrue)
+                        .conflicts_with("on-cpu")
+                        .required(false),
+                )
+                .arg(
+                    clap::Arg::new("mode")
+                        .help("wall: sample whatever the process is doing (the default). cpu: check whether \
+                            the process is using the CPU, and record an (idle) stack trace if it isn't")
+                        .long("mode")
+                        .value_name("MODE")
+                        .value_parser(["wall", "cpu"])
+                        .conflicts_with_all(["on-cpu", "idle"])
+                        .required(false),
+                )
+                .arg(
+                    arg!(--"force-on-cpu-samples" "With --on-cpu, --idle or --mode cpu, decide whether \
+                        the process is using the CPU only by asking the OS whether any of its threads \
+                        are running, and skip checking the Ruby thread's status. Try this if samples \
+                        seem to be missing: the Ruby status is sometimes wrong about threads that are \
+                        running. It's less accurate in the other direction, since the OS doesn't know \
+                        which thread runs Ruby code, so a Ruby thread that's waiting can be recorded \
+                        while another thread (e.g. in a native extension) is running")
+                        .action(clap::ArgAction::SetTrue)
+                        .required(false),
+                )
+                .args(filter_args())
                 .arg(arg!(<cmd> ... "command to run").required(false)),
         )
         .subcommand(
             clap::Command::new("report")
                 .about("Generate visualization from raw data recorded by `rbspy record`")
                 .arg(
-                    arg!(-i --input <FILE> "Input raw data to use")
+                    arg!(-i --input <FILE> "Input raw data to use. Give more than one file, or --input \
+                        more than once, to combine several recordings")
                         .required(true)
+                        .num_args(1..)
+                        .action(clap::ArgAction::Append)
                         .value_parser(clap::value_parser!(PathBuf))
                     )
                 .arg(
@@ -386,7 +1389,118 @@ fn arg_parser() -> clap::Command {
                         .required(false)
                         .default_value("flamegraph"),
                 )
-        )
+                .arg(
+                    clap::Arg::new("summary-format")
+                        .help("Table format for the summary output formats")
+                        .long("summary-format")
+                        .value_name("FORMAT")
+                        .value_parser(clap::value_parser!(SummaryFormat))
+                        .ignore_case(true)
+                        .required(false)
+                        .default_value("text"),
+                )
+                .arg(
+                    clap::Arg::new("top")
+                        .help("Only show the N most expensive functions (or lines, or gems) in the \
+                            summary output formats, like the summary shown while recording")
+                        .long("top")
+                        .short('n')
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .required(false),
+                )
+                .args(flamegraph_args())
+                .args(filter_args())
+        )
+        .subcommand(
+            clap::Command::new("diff")
+                .about("Compare two recordings made by `rbspy record`, e.g. to find out what got slower")
+                .arg(
+                    arg!(--before <FILE> "Raw data from the first recording")
+                        .required(true)
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(--after <FILE> "Raw data from the second recording")
+                        .required(true)
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(-o --output <FILE> "Output file")
+                        .required(false)
+                        .default_value("-")
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(-f --format <FORMAT> "Output format to write")
+                        .value_parser(clap::value_parser!(DiffFormat))
+                        .ignore_case(true)
+                        .required(false)
+                        .default_value("flamegraph"),
+                )
+        )
+        .subcommand(
+            clap::Command::new("merge")
+                .about("Combine raw data files recorded by `rbspy record` into one raw data file")
+                .arg(
+                    arg!(-i --input <FILE> "Input raw data to merge. Give more than one file, or \
+                        --input more than once")
+                        .required(true)
+                        .num_args(1..)
+                        .action(clap::ArgAction::Append)
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    arg!(-o --output <FILE> "Raw data file to write")
+                        .required(true)
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    clap::Arg::new("raw-compression")
+                        .help("Gzip compression level for the merged file, from 0 (fastest) to 9 \
+                            (smallest), or `none` to write it uncompressed")
+                        .long("raw-compression")
+                        .value_name("LEVEL")
+                        .value_parser(validate_raw_compression)
+                        .default_value("6"),
+                )
+        )
+        .subcommand(
+            clap::Command::new("check")
+                .about("Check that a file contains raw data recorded by `rbspy record`, and print its header")
+                .arg(
+                    arg!(-i --input <FILE> "Input raw data to check")
+                        .required(true)
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+        )
+        .subcommand(
+            clap::Command::new("analyze-coredump")
+                .about("Print the Ruby stack trace from a core dump of a crashed or hung Ruby process")
+                .arg(
+                    arg!(--core <FILE> "Core dump to analyze")
+                        .required(true)
+                        .value_parser(clap::value_parser!(PathBuf))
+                )
+                .arg(
+                    clap::Arg::new("ruby-version")
+                        .help("Version of Ruby that the process was running, e.g. 3.3.0")
+                        .long("ruby-version")
+                        .value_name("VERSION")
+                        .value_parser(validate_ruby_version)
+                        .required(true)
+                )
+                .arg(
+                    clap::Arg::new("ruby-binary")
+                        .help("Path of the ruby binary or libruby that the process was running. By default, \
+                            the path recorded in the core dump is used, so this is needed when analyzing a \
+                            core dump on a different machine")
+                        .long("ruby-binary")
+                        .value_name("PATH")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(false)
+                )
+        )
         .subcommand(
             clap::Command::new("inspect")
                 .about("Inspect a Ruby process, finding key memory addresses that are needed for profiling")
@@ -395,21 +1509,286 @@ fn arg_parser() -> clap::Command {
                     arg!(-p --pid <PID> "PID of the Ruby process you want to inspect")
                     .value_parser(validate_pid)
                     // It's a bit confusing but this is how to get exactly-one-of behaviour
-                    // for `--pid` and `cmd`.
-                    .required_unless_present("cmd")
-                    .conflicts_with("cmd")
+                    // for `--pid`, `--process-name`, `--namespace-pid` and `cmd`.
+                    .required_unless_present_any(["cmd", "process-name", "namespace-pid"])
+                    .conflicts_with_all(["cmd", "process-name", "namespace-pid"])
                     .required(false),
                 )
+                .arg(process_name_arg().conflicts_with_all(["cmd", "namespace-pid"]))
+                .args(namespace_args().into_iter().map(|arg| arg.conflicts_with("cmd")))
                 .arg(
                     clap::Arg::new("force-version")
                         .help("Assume that the Ruby version is <VERSION>. This is useful when the Ruby \
                             version is not yet supported by rbspy, e.g. a release candidate")
                         .long("force-version")
                         .value_name("VERSION")
+                        .value_parser(validate_ruby_version)
+                        .required(false)
+                )
+                .arg(
+                    clap::Arg::new("ruby-binary")
+                        .help("Path of the ruby binary or libruby that the process is running. Use this if \
+                            rbspy has trouble finding the Ruby version or VM addresses, e.g. because \
+                            more than one Ruby is loaded")
+                        .long("ruby-binary")
+                        .value_name("PATH")
+                        .value_parser(clap::value_parser!(PathBuf))
                         .required(false)
                 )
+                .arg(
+                    clap::Arg::new("json")
+                        .help("Print the addresses as a JSON object")
+                        .long("json")
+                        .action(clap::ArgAction::SetTrue),
+                )
                 .arg(arg!(<cmd> ... "command to run").required(false)),
         )
+        .subcommand(
+            clap::Command::new("list")
+                .about("List the Ruby processes that are running, with their PIDs and names")
+                .arg(
+                    clap::Arg::new("json")
+                        .help("Print the processes as a JSON array")
+                        .long("json")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
+}
+
+/// Args that set the flamegraph's title and layout. Shared by `record` and `report`.
+fn flamegraph_args() -> Vec<clap::Arg> {
+    vec![
+        clap::Arg::new("flame-title")
+            .help("Title of the flamegraph")
+            .long("flame-title")
+            .value_name("TITLE")
+            .required(false),
+        clap::Arg::new("flame-subtitle")
+            .help(
+                "Subtitle of the flamegraph. By default, it describes the recording: the PID, \
+                sample rate, and duration",
+            )
+            .long("flame-subtitle")
+            .value_name("SUBTITLE")
+            .required(false),
+        clap::Arg::new("reverse")
+            .help(
+                "Merge the flamegraph's stacks from the function that was running instead of from \
+                the root, to find the functions that use the most time no matter where they're \
+                called from",
+            )
+            .action(clap::ArgAction::SetTrue)
+            .long("reverse")
+            .required(false),
+        clap::Arg::new("color-by")
+            .help("How to color the flamegraph's frames")
+            .long("color-by")
+            .value_name("COLORS")
+            .value_parser(clap::value_parser!(ColorBy))
+            .default_value("none"),
+    ]
+}
+
+/// Args that control which frames appear in formatted output. Shared by `record` and `report`.
+fn filter_args() -> Vec<clap::Arg> {
+    vec![
+        clap::Arg::new("collapse-gems")
+            .help("Replace the frames of each gem with a single frame named after the gem")
+            .action(clap::ArgAction::SetTrue)
+            .long("collapse-gems")
+            .required(false),
+        clap::Arg::new("collapse-stdlib")
+            .help(
+                "Replace the frames of Ruby's standard library (including <internal:...> frames) \
+                with a single frame",
+            )
+            .action(clap::ArgAction::SetTrue)
+            .long("collapse-stdlib")
+            .required(false),
+        clap::Arg::new("stdlib-path")
+            .help(
+                "Treat files under <PREFIX> as part of the standard library. Can be given more \
+                than once",
+            )
+            .long("stdlib-path")
+            .value_name("PREFIX")
+            .action(clap::ArgAction::Append)
+            .requires("collapse-stdlib")
+            .required(false),
+        clap::Arg::new("collapse-c-functions")
+            .help(
+                "Hide C function frames, attributing their time to the Ruby code that called them",
+            )
+            .action(clap::ArgAction::SetTrue)
+            .long("collapse-c-functions")
+            .required(false),
+        clap::Arg::new("only-ruby")
+            .help(
+                "Hide C function frames without attributing their time to anything. Stack traces \
+                that were in a C function are left out, and C functions called further up the stack \
+                are cut out, so that the Ruby code they called appears under the nearest Ruby frame",
+            )
+            .action(clap::ArgAction::SetTrue)
+            .long("only-ruby")
+            .conflicts_with("collapse-c-functions")
+            .required(false),
+        clap::Arg::new("include-path")
+            .help(
+                "Only show frames from files that match <GLOB>, or are in a directory that \
+                matches it. `*` doesn't match `/`, but `**` does. Can be given more than once",
+            )
+            .long("include-path")
+            .value_name("GLOB")
+            .action(clap::ArgAction::Append)
+            .required(false),
+        clap::Arg::new("exclude-path")
+            .help(
+                "Hide frames from files that match <GLOB> (see --include-path), attributing the \
+                functions they call to their caller instead. Can be given more than once",
+            )
+            .long("exclude-path")
+            .value_name("GLOB")
+            .action(clap::ArgAction::Append)
+            .required(false),
+        clap::Arg::new("focus-file")
+            .help(
+                "Only show frames that match one of the patterns in <PATH>, replacing each run of \
+                frames that don't with a single <other> frame. The file has one pattern per line, \
+                which is a glob that's matched against the method name (e.g. UsersController#*) \
+                or the path (see --include-path) of each frame. Blank lines and lines starting \
+                with # are ignored",
+            )
+            .long("focus-file")
+            .value_name("PATH")
+            .value_parser(clap::value_parser!(PathBuf))
+            .required(false),
+        clap::Arg::new("hide-idle")
+            .help(
+                "Leave out stack traces that were waiting rather than working: (idle) samples, and \
+                traces whose innermost frame is a C function that waits, i.e. sleep, select, poll, \
+                epoll_wait, wait, wait_readable, wait_writable, wait_priority, accept, join or \
+                waitpid",
+            )
+            .action(clap::ArgAction::SetTrue)
+            .long("hide-idle")
+            .required(false),
+        clap::Arg::new("idle-function")
+            .help(
+                "Also treat the C function named <NAME> as waiting with --hide-idle, e.g. pop for \
+                Queue#pop. Can be given more than once",
+            )
+            .long("idle-function")
+            .value_name("NAME")
+            .action(clap::ArgAction::Append)
+            .requires("hide-idle")
+            .required(false),
+        clap::Arg::new("normalize-frames")
+            .help(
+                "Show blocks and rescue/ensure clauses (e.g. `block in foo`) as part of the method \
+                they're in, merging them into its frame when it called them directly",
+            )
+            .action(clap::ArgAction::SetTrue)
+            .long("normalize-frames")
+            .required(false),
+    ]
+}
+
+fn filter_from_matches(matches: &ArgMatches) -> Result<TraceFilter> {
+    let focus_patterns = match matches.get_one::<PathBuf>("focus-file") {
+        Some(path) => TraceFilter::read_focus_file(path)?,
+        None => vec![],
+    };
+    Ok(TraceFilter {
+        collapse_gems: *matches.get_one::<bool>("collapse-gems").unwrap(),
+        collapse_stdlib: *matches.get_one::<bool>("collapse-stdlib").unwrap(),
+        stdlib_paths: matches
+            .get_many::<String>("stdlib-path")
+            .map(|paths| paths.cloned().collect())
+            .unwrap_or_default(),
+        collapse_c_functions: *matches.get_one::<bool>("collapse-c-functions").unwrap(),
+        only_ruby: *matches.get_one::<bool>("only-ruby").unwrap(),
+        include_paths: matches
+            .get_many::<String>("include-path")
+            .map(|paths| paths.cloned().collect())
+            .unwrap_or_default(),
+        exclude_paths: matches
+            .get_many::<String>("exclude-path")
+            .map(|paths| paths.cloned().collect())
+            .unwrap_or_default(),
+        focus_patterns,
+        hide_idle: *matches.get_one::<bool>("hide-idle").unwrap(),
+        idle_functions: matches
+            .get_many::<String>("idle-function")
+            .map(|names| names.cloned().collect())
+            .unwrap_or_default(),
+        normalize_frames: *matches.get_one::<bool>("normalize-frames").unwrap(),
+    })
+}
+
+fn process_name_arg() -> clap::Arg {
+    clap::Arg::new("process-name")
+        .help(
+            "Name of the Ruby process you want to profile, e.g. `ruby` or `puma`. This is the \
+            program in its command line (or its process title), or its executable. Run `rbspy \
+            list` to see the names of the Ruby processes that are running",
+        )
+        .long("process-name")
+        .value_name("NAME")
+        .required(false)
+}
+
+/// `--namespace-pid` and `--root`, for profiling a process in a container from the host
+fn namespace_args() -> Vec<clap::Arg> {
+    vec![
+        clap::Arg::new("namespace-pid")
+            .help(
+                "PID of the Ruby process you want to profile, as seen inside its container (Linux \
+                only). Requires --root to say which container it's in",
+            )
+            .long("namespace-pid")
+            .value_name("PID")
+            .value_parser(validate_pid)
+            .requires("root")
+            .required(false),
+        clap::Arg::new("root")
+            .help(
+                "The container's root filesystem as seen from here, e.g. \
+                /var/lib/docker/overlay2/<ID>/merged for Docker",
+            )
+            .long("root")
+            .value_name("HOST_PATH_TO_ROOTFS")
+            .value_parser(clap::value_parser!(PathBuf))
+            .requires("namespace-pid")
+            .required(false),
+    ]
+}
+
+fn thread_id_arg() -> clap::Arg {
+    clap::Arg::new("thread-id")
+        .help(
+            "Only keep stack traces from the thread with this OS thread ID. Can be given more \
+            than once. Unless `record --all-threads` is used, rbspy samples whichever thread is \
+            running Ruby code, so traces from other threads are dropped rather than sampled \
+            separately",
+        )
+        .long("thread-id")
+        .value_name("TID")
+        .value_parser(clap::value_parser!(u64))
+        .action(clap::ArgAction::Append)
+        .required(false)
+}
+
+fn thread_ids_from_matches(matches: &ArgMatches) -> Vec<u64> {
+    matches
+        .get_many::<u64>("thread-id")
+        .map(|ids| ids.cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Whether `--mode cpu` was given. CPU mode is the same as `--idle`: off-CPU samples are recorded
+/// as idle instead of being dropped (like `--on-cpu` does) or sampled normally.
+fn cpu_mode(matches: &ArgMatches) -> bool {
+    matches.get_one::<String>("mode").map(String::as_str) == Some("cpu")
 }
 
 /// Check `s` is a positive integer.
@@ -424,38 +1803,333 @@ fn validate_pid(s: &str) -> Result<Pid, String> {
     Ok(pid)
 }
 
+/// Parses a duration with an optional `ms` or `s` unit, defaulting to milliseconds.
+fn validate_interval(s: &str) -> Result<Duration, String> {
+    let (number, millis_per_unit) = if let Some(number) = s.strip_suffix("ms") {
+        (number, 1)
+    } else if let Some(number) = s.strip_suffix('s') {
+        (number, 1000)
+    } else if let Some(number) = s.strip_suffix('m') {
+        (number, 60 * 1000)
+    } else if let Some(number) = s.strip_suffix('h') {
+        (number, 60 * 60 * 1000)
+    } else {
+        (s, 1)
+    };
+    let error =
+        "Interval must be a whole number of milliseconds (ms), seconds (s), minutes (m) or hours (h)";
+    let number: u64 = number.trim().parse().map_err(|_| error)?;
+    let millis = number.checked_mul(millis_per_unit).ok_or(error)?;
+    Ok(Duration::from_millis(millis))
+}
+
+// Rotating more often than this would mostly produce files with a handful of traces in them
+fn validate_rotate_interval(s: &str) -> Result<Duration, String> {
+    let interval = validate_interval(s)?;
+    if interval < Duration::from_secs(1) {
+        return Err("Rotation interval must be at least 1s".to_string());
+    }
+    Ok(interval)
+}
+
+// Checks that a Ruby version is well-formed, so that a typo is caught before attaching to the
+// process. Whether rbspy supports the version is checked later.
+fn validate_ruby_version(s: &str) -> Result<String, String> {
+    match semver::Version::parse(s) {
+        Ok(_) => Ok(s.to_string()),
+        Err(_) => Err("Ruby version must be a full version number, e.g. 3.3.0".to_string()),
+    }
+}
+
+/// Parses the fraction of failed stack traces that stops recording, from 0 to 1.
+fn validate_error_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err("Error rate must be a number from 0 to 1, e.g. 0.5".to_string()),
+    }
+}
+
+/// Parses a gzip compression level, or `none` for no compression.
+fn validate_raw_compression(s: &str) -> Result<RawCompression, String> {
+    if s.eq_ignore_ascii_case("none") {
+        return Ok(RawCompression::None);
+    }
+    match s.parse::<u32>() {
+        Ok(level) if level <= 9 => Ok(RawCompression::Gzip(level)),
+        _ => Err("Compression level must be a number from 0 to 9, or `none`".to_string()),
+    }
+}
+
+/// Parses a signal name (with or without the `SIG` prefix) or number.
+fn validate_signal(s: &str) -> Result<i32, String> {
+    #[cfg(unix)]
+    {
+        if let Ok(signal) = s.parse::<i32>() {
+            if signal <= 0 {
+                return Err("Signal numbers must be positive".to_string());
+            }
+            return Ok(signal);
+        }
+        let name = s.to_ascii_uppercase();
+        match name.strip_prefix("SIG").unwrap_or(&name) {
+            "HUP" => Ok(libc::SIGHUP),
+            "INT" => Ok(libc::SIGINT),
+            "QUIT" => Ok(libc::SIGQUIT),
+            "TERM" => Ok(libc::SIGTERM),
+            "USR1" => Ok(libc::SIGUSR1),
+            "USR2" => Ok(libc::SIGUSR2),
+            _ => Err(format!(
+                "Unsupported signal '{}'. Try one of HUP, INT, QUIT, TERM, USR1 or USR2",
+                s
+            )),
+        }
+    }
+    #[cfg(windows)]
+    {
+        let _ = s;
+        Err("Signals aren't supported on Windows".to_string())
+    }
+}
+
+/// Options that say what to profile or which file to report on, rather than how, so they can
+/// only be given on the command line
+const UNCONFIGURABLE_OPTIONS: &[&str] = &[
+    "pid",
+    "process-name",
+    "namespace-pid",
+    "root",
+    "input",
+    "help",
+    "version",
+];
+
+/// Defaults for `record` and `report` options, from a `rbspy.toml` or `.rbspy.toml` file. Each
+/// table maps the options' long names to their values, e.g.
+///
+/// ```toml
+/// [record]
+/// rate = 50
+/// format = "speedscope"
+/// exclude-path = ["**/vendor/**"]
+/// hide-idle = true
+/// ```
+#[derive(Clone, Debug, Default, serde_derive::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(default)]
+    record: BTreeMap<String, toml::Value>,
+    #[serde(default)]
+    report: BTreeMap<String, toml::Value>,
+}
+
+impl ConfigFile {
+    fn read(path: &Path) -> Result<ConfigFile> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        ConfigFile::parse(path, &contents)
+    }
+
+    fn parse(path: &Path, contents: &str) -> Result<ConfigFile> {
+        let mut config: ConfigFile = toml::from_str(contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+        config.path = path.to_path_buf();
+        Ok(config)
+    }
+
+    fn options(&self, subcommand: &str) -> &BTreeMap<String, toml::Value> {
+        match subcommand {
+            "record" => &self.record,
+            _ => &self.report,
+        }
+    }
+}
+
+/// The config files to read, from lowest to highest precedence: `rbspy.toml` in the user's config
+/// directory (e.g. `~/.config/rbspy` on Linux), then the nearest `.rbspy.toml` or `rbspy.toml` in
+/// the current directory or one of its parents.
+fn config_paths() -> Vec<PathBuf> {
+    let mut paths = vec![];
+    if let Some(dirs) = directories::ProjectDirs::from("", "", "rbspy") {
+        paths.push(dirs.config_dir().join("rbspy.toml"));
+    }
+    if let Ok(cwd) = env::current_dir() {
+        paths.extend(
+            cwd.ancestors()
+                .flat_map(|dir| [dir.join(".rbspy.toml"), dir.join("rbspy.toml")])
+                .find(|path| path.is_file()),
+        );
+    }
+    paths.retain(|path| path.is_file());
+    paths
+}
+
+/// Makes the options in `configs` the defaults for `record` and `report`, with later configs
+/// overriding earlier ones. Each of these options can also be set with an environment variable,
+/// e.g. `RBSPY_RATE` for `--rate`, which overrides the config files but not the command line.
+fn apply_config(mut parser: clap::Command, configs: &[ConfigFile]) -> Result<clap::Command> {
+    for name in ["record", "report"] {
+        let subcommand = parser
+            .find_subcommand(name)
+            .expect("record and report are subcommands");
+        // The IDs and long names of the options that can be configured
+        let options: Vec<(String, String)> = subcommand
+            .get_arguments()
+            .filter_map(|arg| Some((arg.get_id().to_string(), arg.get_long()?.to_string())))
+            .filter(|(_, long)| !UNCONFIGURABLE_OPTIONS.contains(&long.as_str()))
+            .collect();
+
+        let mut defaults: HashMap<String, Vec<String>> = HashMap::new();
+        for config in configs {
+            for (option, value) in config.options(name) {
+                if !options.iter().any(|(_, long)| long == option) {
+                    return Err(format_err!(
+                        "Config file {} sets {} in [{}], but `rbspy {}` doesn't have a --{} option that can be configured",
+                        config.path.display(),
+                        option,
+                        name,
+                        name,
+                        option
+                    ));
+                }
+                let values = config_values(value).ok_or_else(|| {
+                    format_err!(
+                        "Config file {} has an invalid value for {} in [{}]: {}",
+                        config.path.display(),
+                        option,
+                        name,
+                        value
+                    )
+                })?;
+                defaults.insert(option.clone(), values);
+            }
+        }
+
+        parser = parser.mut_subcommand(name, |mut subcommand| {
+            for (id, long) in &options {
+                let env_var = format!("RBSPY_{}", long.to_uppercase().replace('-', "_"));
+                subcommand = subcommand.mut_arg(id, |arg| {
+                    let arg = arg.env(env_var);
+                    match defaults.get(long) {
+                        Some(values) => arg.default_values(values),
+                        None => arg,
+                    }
+                });
+            }
+            subcommand
+        });
+    }
+    Ok(parser)
+}
+
+// The command line values for an option's value in a config file, e.g. `true` for a flag or one
+// value per element of an array
+fn config_values(value: &toml::Value) -> Option<Vec<String>> {
+    match value {
+        toml::Value::String(s) => Some(vec![s.clone()]),
+        toml::Value::Integer(i) => Some(vec![i.to_string()]),
+        toml::Value::Float(f) => Some(vec![f.to_string()]),
+        toml::Value::Boolean(b) => Some(vec![b.to_string()]),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(|value| match value {
+                toml::Value::Array(_) => None,
+                value => config_values(value)?.pop(),
+            })
+            .collect(),
+        toml::Value::Datetime(_) | toml::Value::Table(_) => None,
+    }
+}
+
 impl Args {
+    /// Parses `args` with the built-in defaults, ignoring any config files.
+    #[cfg(test)]
+    fn from<'a, I: IntoIterator<Item = String> + 'a>(args: I) -> Result<Args, Error> {
+        Args::from_parser(arg_parser(), args)
+    }
+
     /// Converts from clap's matches.
     // TODO(TryFrom): Replace with TryFrom whenever that stabilizes.
     // TODO(maybe): Consider replacing with one of the derive-based arg thingies.
-    fn from<'a, I: IntoIterator<Item = String> + 'a>(args: I) -> Result<Args, Error> {
-        let matches: ArgMatches = arg_parser().get_matches_from(args);
+    fn from_parser<'a, I: IntoIterator<Item = String> + 'a>(
+        parser: clap::Command,
+        args: I,
+    ) -> Result<Args, Error> {
+        let matches: ArgMatches = parser.get_matches_from(args);
         let cmd = match matches.subcommand() {
             Some(("snapshot", submatches)) => SubCmd::Snapshot {
-                pid: *submatches
-                    .get_one::<Pid>("pid")
-                    .expect("this shouldn't happen because clap requires a pid"),
+                target: target_from_matches(submatches),
                 lock_process: !*submatches.get_one::<bool>("nonblocking").unwrap(),
                 force_version: match submatches.get_one::<String>("force-version") {
                     Some(version) => Some(version.to_string()),
                     None => None,
                 },
+                ruby_binary: submatches.get_one::<PathBuf>("ruby-binary").cloned(),
                 on_cpu_only: *submatches.get_one::<bool>("on-cpu").unwrap(),
+                idle_samples: cpu_mode(submatches),
+                thread_ids: thread_ids_from_matches(submatches),
+                count: *submatches.get_one::<u64>("count").unwrap() as usize,
+                interval: *submatches.get_one::<Duration>("interval").unwrap(),
+                json: submatches.get_flag("json"),
+                wait_for_file: submatches.get_one::<PathBuf>("wait-for-file").cloned(),
+                with_locals: submatches.get_flag("with-locals"),
             },
             Some(("record", submatches)) => {
-                let format: OutputFormat =
-                    ArgMatches::get_one::<OutputFormat>(submatches, "format")
-                        .unwrap()
-                        .clone();
+                let formats: Vec<OutputFormat> = submatches
+                    .get_many::<OutputFormat>("format")
+                    .unwrap()
+                    .cloned()
+                    .collect();
+                for (i, format) in formats.iter().enumerate() {
+                    if formats[..i].contains(format) {
+                        return Err(format_err!(
+                            "--format {} was given more than once",
+                            format_name(format)
+                        ));
+                    }
+                }
+                let format = formats[0].clone();
 
+                let raw_compression =
+                    *ArgMatches::get_one::<RawCompression>(submatches, "raw-compression").unwrap();
+                let output_dir = submatches.get_one::<PathBuf>("output-dir");
+                let template = submatches.get_one::<String>("filename-template").unwrap();
                 let raw_path = output_filename(
                     submatches.get_one::<String>("raw-file").map(|x| x.as_str()),
-                    "raw.gz",
+                    output_dir.map(|x| x.as_path()),
+                    template,
+                    "raw",
+                    raw_compression.extension(),
                 )?;
+                let file = submatches.get_one::<String>("file").map(|x| x.as_str());
                 let out_path = output_filename(
-                    submatches.get_one::<String>("file").map(|x| x.as_str()),
+                    file,
+                    output_dir.map(|x| x.as_path()),
+                    template,
+                    &format_name(&format),
                     &format.extension(),
                 )?;
+                // The other formats' files are named after --file if it's given, or like the first
+                // format's file if it isn't
+                let extra_outputs = formats[1..]
+                    .iter()
+                    .map(|extra| {
+                        let path = match file {
+                            Some(file) if file != "-" => {
+                                extra_output_path(Path::new(file), &format, extra)
+                            }
+                            _ => output_filename(
+                                None,
+                                output_dir.map(|x| x.as_path()),
+                                template,
+                                &format_name(extra),
+                                &extra.extension(),
+                            )?,
+                        };
+                        Ok((extra.clone(), path))
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
                 let maybe_duration = match ArgMatches::get_one::<u64>(submatches, "duration") {
                     Some(integer_duration) => {
                         Some(std::time::Duration::from_secs(*integer_duration))
@@ -463,77 +2137,205 @@ impl Args {
                     None => None,
                 };
 
+                let per_thread = *submatches.get_one::<bool>("per-thread").unwrap();
+                if per_thread && out_path == PathBuf::from("-") {
+                    return Err(format_err!(
+                        "--per-thread writes one file per thread, so it can't be used with `--file -`"
+                    ));
+                }
+                let rotate_output = *submatches.get_one::<bool>("rotate-output").unwrap();
+                if rotate_output && out_path == PathBuf::from("-") {
+                    return Err(format_err!(
+                        "--rotate-output writes one file per rotation, so it can't be used with `--file -`"
+                    ));
+                }
+                let with_rss = *submatches.get_one::<bool>("with-rss").unwrap();
+                if formats.contains(&OutputFormat::rss) && !with_rss {
+                    return Err(format_err!(
+                        "The rss format reports the memory usage that's recorded with --with-rss, so it needs --with-rss"
+                    ));
+                }
                 let no_drop_root = *submatches.get_one::<bool>("no-drop-root").unwrap();
                 let silent = *submatches.get_one::<bool>("silent").unwrap();
                 let with_subprocesses = *submatches.get_one::<bool>("subprocesses").unwrap();
                 let nonblocking = *submatches.get_one::<bool>("nonblocking").unwrap();
                 let on_cpu_only = *submatches.get_one::<bool>("on-cpu").unwrap();
+                let idle_samples =
+                    *submatches.get_one::<bool>("idle").unwrap() || cpu_mode(submatches);
+                let force_on_cpu_samples = submatches.get_flag("force-on-cpu-samples");
+                if force_on_cpu_samples && !on_cpu_only && !idle_samples {
+                    return Err(format_err!(
+                        "--force-on-cpu-samples changes how --on-cpu, --idle and --mode cpu decide whether the process is using the CPU, so it needs one of them"
+                    ));
+                }
 
                 let sample_rate = *ArgMatches::get_one::<u32>(submatches, "rate").unwrap();
+                let burst_rate = ArgMatches::get_one::<u32>(submatches, "burst-rate").cloned();
+                let burst_duration = std::time::Duration::from_secs(
+                    *ArgMatches::get_one::<u64>(submatches, "burst-duration").unwrap(),
+                );
+                let stop_signal = ArgMatches::get_one::<i32>(submatches, "stop-signal").cloned();
+                #[cfg(unix)]
+                if burst_rate.is_some() && stop_signal == Some(libc::SIGUSR1) {
+                    return Err(format_err!(
+                        "--stop-signal can't be USR1 when --burst-rate is given, because USR1 starts a burst"
+                    ));
+                }
+                let snapshot_signal =
+                    ArgMatches::get_one::<i32>(submatches, "snapshot-on").cloned();
+                #[cfg(unix)]
+                if let Some(signal) = snapshot_signal {
+                    if [libc::SIGINT, libc::SIGTERM, libc::SIGHUP].contains(&signal) {
+                        return Err(format_err!(
+                            "--snapshot-on can't be INT, TERM or HUP, because they stop recording"
+                        ));
+                    }
+                    if stop_signal == Some(signal) {
+                        return Err(format_err!(
+                            "--snapshot-on and --stop-signal can't be the same signal"
+                        ));
+                    }
+                    if burst_rate.is_some() && signal == libc::SIGUSR1 {
+                        return Err(format_err!(
+                            "--snapshot-on can't be USR1 when --burst-rate is given, because USR1 starts a burst"
+                        ));
+                    }
+                }
                 let flame_min_width =
                     *ArgMatches::get_one::<f64>(submatches, "flame-min-width").unwrap();
+                let flame_title = submatches.get_one::<String>("flame-title").cloned();
+                let flame_subtitle = submatches.get_one::<String>("flame-subtitle").cloned();
+                let summary_format =
+                    *ArgMatches::get_one::<SummaryFormat>(submatches, "summary-format").unwrap();
                 let force_version =
                     ArgMatches::get_one::<String>(submatches, "force-version").cloned();
-                let target = if let Some(pid) = submatches.get_one::<Pid>("pid") {
-                    Target::Pid { pid: *pid }
-                } else {
-                    let mut cmd = submatches
-                        .get_many::<String>("cmd")
-                        .expect("shouldn't happen");
-                    let prog = cmd.next().expect("nope");
-                    let args = cmd;
-                    Target::Subprocess {
-                        prog: prog.to_string(),
-                        args: args.map(String::from).collect(),
-                    }
-                };
+                let target = target_from_matches(submatches);
                 SubCmd::Record {
                     target,
                     out_path,
+                    extra_outputs,
                     raw_path,
+                    raw_compression,
                     sample_rate,
                     maybe_duration,
+                    max_samples: submatches.get_one::<usize>("max-samples").cloned(),
                     format,
+                    per_thread,
+                    // There's nothing to format until the frames are named
+                    raw_only: submatches.get_flag("raw-only")
+                        || submatches.get_flag("defer-symbolization"),
                     no_drop_root,
+                    drop_privileges: submatches.get_flag("drop-privileges"),
                     with_subprocesses,
+                    subprocess_poll_interval: *submatches
+                        .get_one::<Duration>("subprocess-poll-interval")
+                        .unwrap(),
                     silent,
+                    progress: *submatches.get_one::<ProgressFormat>("progress").unwrap(),
+                    diagnostics: submatches.get_flag("diagnostics"),
                     flame_min_width,
+                    flame_title,
+                    flame_subtitle,
+                    reverse: submatches.get_flag("reverse"),
+                    color_by: *submatches.get_one::<ColorBy>("color-by").unwrap(),
+                    summary_format,
                     lock_process: !nonblocking,
+                    lock_retries: *submatches.get_one::<u32>("lock-retries").unwrap(),
                     force_version,
+                    ruby_binary: submatches.get_one::<PathBuf>("ruby-binary").cloned(),
                     on_cpu_only: on_cpu_only,
+                    idle_samples,
+                    force_on_cpu_samples,
+                    attach_timeout: *submatches.get_one::<Duration>("attach-timeout").unwrap(),
+                    burst_rate,
+                    burst_duration,
+                    adaptive: *submatches.get_one::<bool>("adaptive").unwrap(),
+                    thread_ids: thread_ids_from_matches(submatches),
+                    all_threads: *submatches.get_one::<bool>("all-threads").unwrap(),
+                    with_rss,
+                    error_tolerance: ErrorTolerance {
+                        min_errors: *submatches
+                            .get_one::<usize>("min-errors-before-abort")
+                            .unwrap(),
+                        max_error_rate: *submatches.get_one::<f64>("max-error-rate").unwrap(),
+                    },
+                    stop_signal,
+                    snapshot_signal,
+                    export: submatches.get_one::<String>("export").cloned(),
+                    serve: submatches.get_one::<String>("serve").cloned(),
+                    rotate_interval: submatches.get_one::<Duration>("rotate-interval").cloned(),
+                    rotate_output,
+                    defer_symbolization: submatches.get_flag("defer-symbolization"),
+                    check: submatches.get_flag("check"),
+                    filter: filter_from_matches(submatches)?,
                 }
             }
             Some(("report", submatches)) => {
                 let format = ArgMatches::get_one::<OutputFormat>(submatches, "format").cloned();
-                let input = ArgMatches::get_one::<PathBuf>(submatches, "input").cloned();
+                let inputs = ArgMatches::get_many::<PathBuf>(submatches, "input")
+                    .unwrap()
+                    .cloned()
+                    .collect();
                 let output = ArgMatches::get_one::<PathBuf>(submatches, "output").cloned();
                 SubCmd::Report {
                     format: format.unwrap(),
-                    input: input.unwrap(),
+                    summary_format: *ArgMatches::get_one::<SummaryFormat>(
+                        submatches,
+                        "summary-format",
+                    )
+                    .unwrap(),
+                    summary_top: submatches.get_one::<usize>("top").cloned(),
+                    flame_title: submatches.get_one::<String>("flame-title").cloned(),
+                    flame_subtitle: submatches.get_one::<String>("flame-subtitle").cloned(),
+                    reverse: submatches.get_flag("reverse"),
+                    color_by: *submatches.get_one::<ColorBy>("color-by").unwrap(),
+                    inputs,
                     output: output.unwrap(),
+                    filter: filter_from_matches(submatches)?,
                 }
             }
+            Some(("diff", submatches)) => SubCmd::Diff {
+                format: *submatches.get_one::<DiffFormat>("format").unwrap(),
+                before: submatches.get_one::<PathBuf>("before").cloned().unwrap(),
+                after: submatches.get_one::<PathBuf>("after").cloned().unwrap(),
+                output: submatches.get_one::<PathBuf>("output").cloned().unwrap(),
+            },
+            Some(("merge", submatches)) => SubCmd::Merge {
+                inputs: submatches
+                    .get_many::<PathBuf>("input")
+                    .unwrap()
+                    .cloned()
+                    .collect(),
+                output: submatches.get_one::<PathBuf>("output").cloned().unwrap(),
+                raw_compression: *submatches
+                    .get_one::<RawCompression>("raw-compression")
+                    .unwrap(),
+            },
+            Some(("check", submatches)) => SubCmd::Check {
+                input: submatches.get_one::<PathBuf>("input").cloned().unwrap(),
+            },
+            Some(("analyze-coredump", submatches)) => SubCmd::AnalyzeCoredump {
+                core: submatches.get_one::<PathBuf>("core").cloned().unwrap(),
+                ruby_version: submatches
+                    .get_one::<String>("ruby-version")
+                    .cloned()
+                    .unwrap(),
+                ruby_binary: submatches.get_one::<PathBuf>("ruby-binary").cloned(),
+            },
             Some(("inspect", submatches)) => {
                 let force_version =
                     ArgMatches::get_one::<String>(submatches, "force-version").cloned();
-                let target = if let Some(pid) = submatches.get_one::<Pid>("pid") {
-                    Target::Pid { pid: *pid }
-                } else {
-                    let mut cmd = submatches
-                        .get_many::<String>("cmd")
-                        .expect("shouldn't happen");
-                    let prog = cmd.next().expect("nope");
-                    let args = cmd;
-                    Target::Subprocess {
-                        prog: prog.to_string(),
-                        args: args.map(String::from).collect(),
-                    }
-                };
+                let target = target_from_matches(submatches);
                 SubCmd::Inspect {
                     target,
                     force_version,
+                    ruby_binary: submatches.get_one::<PathBuf>("ruby-binary").cloned(),
+                    json: submatches.get_flag("json"),
                 }
             }
+            Some(("list", submatches)) => SubCmd::List {
+                json: submatches.get_flag("json"),
+            },
             _ => panic!("this shouldn't happen, please report the command you ran!"),
         };
 
@@ -541,36 +2343,256 @@ impl Args {
     }
 
     fn from_args() -> Result<Args, Error> {
-        Args::from(env::args())
+        let mut configs = vec![];
+        for path in config_paths() {
+            configs.push(ConfigFile::read(&path)?);
+        }
+        Args::from_parser(apply_config(arg_parser(), &configs)?, env::args())
     }
 }
 
-fn output_filename(maybe_filename: Option<&str>, extension: &str) -> Result<PathBuf, Error> {
+/// The name of a recording's raw data and formatted output files, without their extensions,
+/// when `--raw-file` or `--file` isn't given
+const DEFAULT_FILENAME_TEMPLATE: &str = "{date}-{rand}";
+
+fn output_filename(
+    maybe_filename: Option<&str>,
+    output_dir: Option<&Path>,
+    template: &str,
+    format: &str,
+    extension: &str,
+) -> Result<PathBuf, Error> {
     match maybe_filename {
         Some(filename) => Ok(filename.into()),
         None => {
-            let s: String = rand::thread_rng()
-                .sample_iter(&Alphanumeric)
-                .take(10)
-                .map(char::from)
-                .collect();
-            let filename = format!("{}-{}.{}", Utc::now().format("%Y-%m-%d"), s, extension);
-            let dirs = match directories::ProjectDirs::from("", "", "rbspy") {
-                Some(dirs) => dirs,
-                None => {
-                    return Err(format_err!(
-                        "Couldn't find a home directory. You might need to set $HOME."
-                    ))
-                }
+            let filename = format!(
+                "{}.{}",
+                expand_filename_template(template, format)?,
+                extension
+            );
+            let dir = match output_dir {
+                Some(dir) => dir.to_path_buf(),
+                None => match directories::ProjectDirs::from("", "", "rbspy") {
+                    Some(dirs) => dirs.cache_dir().to_path_buf(),
+                    None => {
+                        return Err(format_err!(
+                            "Couldn't find a home directory. You might need to set $HOME."
+                        ))
+                    }
+                },
             };
+            let path = dir.join(&filename);
+            // The template can put the files in subdirectories
             DirBuilder::new()
                 .recursive(true)
-                .create(&dirs.cache_dir())?;
-            Ok(dirs.cache_dir().join(&filename))
+                .create(path.parent().unwrap_or(&dir))?;
+            Ok(path)
         }
     }
 }
 
+/// The name of `format` on the command line, e.g. `summary_by_line`
+fn format_name(format: &OutputFormat) -> String {
+    clap::ValueEnum::to_possible_value(format)
+        .expect("output formats aren't skipped")
+        .get_name()
+        .to_string()
+}
+
+/// The path to write output in `format` to when `--file path` is given for output in `primary`,
+/// e.g. `out.speedscope.json` for `out.svg` or `out.flamegraph.svg`
+fn extra_output_path(path: &Path, primary: &OutputFormat, format: &OutputFormat) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stem = match name.strip_suffix(&format!(".{}", primary.extension())) {
+        Some(stem) => stem.to_string(),
+        None => path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or(name),
+    };
+    path.with_file_name(format!("{}.{}", stem, format.extension()))
+}
+
+/// Replaces the placeholders in a `--filename-template`, except for `{pid}`, which isn't known
+/// until the process has been found or started. See `fill_in_pid`.
+fn expand_filename_template(template: &str, format: &str) -> Result<String> {
+    let mut filename = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filename.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format_err!("Unclosed {{ in filename template {}", template)),
+        };
+        match &rest[start + 1..end] {
+            "pid" => filename.push_str("{pid}"),
+            "date" => filename.push_str(&Utc::now().format("%Y-%m-%d").to_string()),
+            "format" => filename.push_str(format),
+            "rand" => filename.extend(
+                rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(10)
+                    .map(char::from),
+            ),
+            placeholder => {
+                return Err(format_err!(
+                    "Unknown placeholder {{{}}} in filename template. Try {{pid}}, {{date}}, {{format}} or {{rand}}",
+                    placeholder
+                ))
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    filename.push_str(rest);
+    Ok(filename)
+}
+
+fn validate_filename_template(s: &str) -> Result<String, String> {
+    expand_filename_template(s, "format")
+        .map(|_| s.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Replaces `{pid}` in a generated output path now that the PID is known.
+fn fill_in_pid(path: PathBuf, pid: Pid) -> PathBuf {
+    match path.to_str() {
+        Some(s) if s.contains("{pid}") => s.replace("{pid}", &pid.to_string()).into(),
+        _ => path,
+    }
+}
+
+/// Switches to the user that ran sudo, keeping the `CAP_SYS_PTRACE` capability so that the
+/// process can still be attached to and its memory read. Capabilities belong to threads, so this
+/// must be called before any other threads are started; threads started later inherit them.
+#[cfg(target_os = "linux")]
+fn drop_privileges_keeping_ptrace() -> Result<()> {
+    use nix::unistd::{setgid, setgroups, setuid, Gid, Uid};
+
+    if !Uid::effective().is_root() {
+        return Err(format_err!(
+            "--drop-privileges needs rbspy to be started as root with sudo"
+        ));
+    }
+    let id = |var: &str| -> Result<u32> {
+        std::env::var(var)
+            .context(format!(
+                "--drop-privileges needs {} to be set, which sudo does",
+                var
+            ))?
+            .parse::<u32>()
+            .context(format!("Failed to parse {}", var))
+    };
+    let uid = Uid::from_raw(id("SUDO_UID")?);
+    let gid = Gid::from_raw(id("SUDO_GID")?);
+
+    // Keep the permitted capabilities when switching away from root, so that CAP_SYS_PTRACE can
+    // be made effective again afterwards
+    if unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to keep capabilities");
+    }
+    setgroups(&[]).context("Failed to drop supplementary groups")?;
+    setgid(gid).context("Failed to set group ID")?;
+    setuid(uid).context("Failed to set user ID")?;
+
+    #[repr(C)]
+    struct CapHeader {
+        version: u32,
+        pid: libc::c_int,
+    }
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct CapData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+    const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+    const CAP_SYS_PTRACE: u32 = 19;
+
+    let header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapData::default(); 2];
+    data[0].effective = 1 << CAP_SYS_PTRACE;
+    data[0].permitted = 1 << CAP_SYS_PTRACE;
+    if unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to keep CAP_SYS_PTRACE");
+    }
+    eprintln!(
+        "Dropping permissions: sampling as user {}",
+        std::env::var("SUDO_USER").unwrap_or_else(|_| uid.to_string())
+    );
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_privileges_keeping_ptrace() -> Result<()> {
+    Err(format_err!("--drop-privileges is only supported on Linux"))
+}
+
+/// Reads the target of `record`, `snapshot` or `inspect`, which clap has checked is given exactly
+/// once.
+fn target_from_matches(submatches: &ArgMatches) -> Target {
+    if let Some(pid) = submatches.get_one::<Pid>("pid") {
+        Target::Pid { pid: *pid }
+    } else if let Some(name) = submatches.get_one::<String>("process-name") {
+        Target::ProcessName { name: name.clone() }
+    } else if let Some(pid) = submatches.get_one::<Pid>("namespace-pid") {
+        Target::NamespacePid {
+            pid: *pid,
+            root: submatches.get_one::<PathBuf>("root").cloned().unwrap(),
+        }
+    } else {
+        let mut cmd = submatches
+            .get_many::<String>("cmd")
+            .expect("shouldn't happen");
+        let prog = cmd.next().expect("nope");
+        let args = cmd;
+        Target::Subprocess {
+            prog: prog.to_string(),
+            args: args.map(String::from).collect(),
+        }
+    }
+}
+
+/// Prints the snapshots taken by `rbspy snapshot` as text, each under a line saying which thread
+/// it's from, or as a JSON array.
+/// `trace` gets the stack trace out of a snapshot.
+fn print_snapshots<S: std::fmt::Display + serde::Serialize>(
+    snaps: &[Option<S>],
+    json: bool,
+    trace: impl Fn(&S) -> &rbspy::StackTrace,
+) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(snaps)?);
+        return Ok(());
+    }
+    for (i, snap) in snaps.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        match snap {
+            Some(snap) => println!("{}\n{}", recorder::thread_header(trace(snap)), snap),
+            None => println!("No stack trace was captured"),
+        };
+    }
+    Ok(())
+}
+
+/// Finds the PID of `target`, starting it first if it's a subprocess.
+fn target_pid(target: Target, no_drop_root: bool) -> Result<Pid> {
+    match target {
+        Target::Pid { pid } => Ok(pid),
+        Target::Subprocess { prog, args } => spawn_subprocess(prog, args, no_drop_root),
+        Target::ProcessName { name } => rbspy::find_ruby_process_by_name(&name),
+        Target::NamespacePid { pid, root } => rbspy::find_process_in_namespace(pid, &root),
+    }
+}
+
 fn spawn_subprocess(prog: String, args: Vec<String>, no_drop_root: bool) -> Result<Pid> {
     if cfg!(target_os = "macos") {
         // sleep to prevent freezes (because of High Sierra kernel bug)
@@ -649,10 +2671,18 @@ mod tests {
             args,
             Args {
                 cmd: SubCmd::Snapshot {
-                    pid: 1234,
+                    target: Target::Pid { pid: 1234 },
                     lock_process: true,
                     force_version: None,
+                    ruby_binary: None,
                     on_cpu_only: false,
+                    idle_samples: false,
+                    thread_ids: vec![],
+                    count: 1,
+                    interval: Duration::from_millis(100),
+                    json: false,
+                    wait_for_file: None,
+                    with_locals: false,
                 },
             }
         );
@@ -682,17 +2712,52 @@ mod tests {
                 cmd: SubCmd::Record {
                     target: Target::Pid { pid: 1234 },
                     out_path: "foo.txt".into(),
+                    extra_outputs: vec![],
                     raw_path: "raw.gz".into(),
+                    raw_compression: RawCompression::default(),
                     sample_rate: 99,
                     maybe_duration: None,
+                    max_samples: None,
                     format: OutputFormat::flamegraph,
+                    per_thread: false,
+                    raw_only: false,
                     no_drop_root: false,
+                    drop_privileges: false,
                     with_subprocesses: false,
+                    subprocess_poll_interval: Duration::from_secs(1),
                     silent: false,
+                    progress: ProgressFormat::text,
+                    diagnostics: false,
                     flame_min_width: 0.1,
+                    flame_title: None,
+                    flame_subtitle: None,
+                    reverse: false,
+                    color_by: ColorBy::none,
+                    summary_format: SummaryFormat::text,
                     lock_process: true,
+                    lock_retries: 0,
                     force_version: None,
+                    ruby_binary: None,
                     on_cpu_only: true,
+                    idle_samples: false,
+                    force_on_cpu_samples: false,
+                    attach_timeout: Duration::from_millis(200),
+                    burst_rate: None,
+                    burst_duration: std::time::Duration::from_secs(10),
+                    adaptive: false,
+                    thread_ids: vec![],
+                    all_threads: false,
+                    with_rss: false,
+                    error_tolerance: ErrorTolerance::default(),
+                    stop_signal: None,
+                    snapshot_signal: None,
+                    export: None,
+                    serve: None,
+                    rotate_interval: None,
+                    rotate_output: false,
+                    defer_symbolization: false,
+                    check: false,
+                    filter: TraceFilter::default(),
                 },
             }
         );
@@ -707,17 +2772,52 @@ mod tests {
                 cmd: SubCmd::Record {
                     target: Target::Pid { pid: 1234 },
                     out_path: "foo.txt".into(),
+                    extra_outputs: vec![],
                     raw_path: "raw.gz".into(),
+                    raw_compression: RawCompression::default(),
                     sample_rate: 25,
                     maybe_duration: None,
+                    max_samples: None,
                     format: OutputFormat::flamegraph,
+                    per_thread: false,
+                    raw_only: false,
                     no_drop_root: false,
+                    drop_privileges: false,
                     with_subprocesses: false,
+                    subprocess_poll_interval: Duration::from_secs(1),
                     silent: false,
+                    progress: ProgressFormat::text,
+                    diagnostics: false,
                     flame_min_width: 0.1,
+                    flame_title: None,
+                    flame_subtitle: None,
+                    reverse: false,
+                    color_by: ColorBy::none,
+                    summary_format: SummaryFormat::text,
                     lock_process: true,
+                    lock_retries: 0,
                     force_version: None,
+                    ruby_binary: None,
                     on_cpu_only: false,
+                    idle_samples: false,
+                    force_on_cpu_samples: false,
+                    attach_timeout: Duration::from_millis(200),
+                    burst_rate: None,
+                    burst_duration: std::time::Duration::from_secs(10),
+                    adaptive: false,
+                    thread_ids: vec![],
+                    all_threads: false,
+                    with_rss: false,
+                    error_tolerance: ErrorTolerance::default(),
+                    stop_signal: None,
+                    snapshot_signal: None,
+                    export: None,
+                    serve: None,
+                    rotate_interval: None,
+                    rotate_output: false,
+                    defer_symbolization: false,
+                    check: false,
+                    filter: TraceFilter::default(),
                 },
             }
         );
@@ -732,17 +2832,52 @@ mod tests {
                 cmd: SubCmd::Record {
                     target: Target::Pid { pid: 1234 },
                     out_path: "foo.txt".into(),
+                    extra_outputs: vec![],
                     raw_path: "raw.gz".into(),
+                    raw_compression: RawCompression::default(),
                     sample_rate: 99,
                     maybe_duration: Some(std::time::Duration::from_secs(60)),
+                    max_samples: None,
                     format: OutputFormat::flamegraph,
+                    per_thread: false,
+                    raw_only: false,
                     no_drop_root: false,
+                    drop_privileges: false,
                     with_subprocesses: false,
+                    subprocess_poll_interval: Duration::from_secs(1),
                     silent: false,
+                    progress: ProgressFormat::text,
+                    diagnostics: false,
                     flame_min_width: 0.1,
+                    flame_title: None,
+                    flame_subtitle: None,
+                    reverse: false,
+                    color_by: ColorBy::none,
+                    summary_format: SummaryFormat::text,
                     lock_process: true,
+                    lock_retries: 0,
                     force_version: None,
+                    ruby_binary: None,
                     on_cpu_only: false,
+                    idle_samples: false,
+                    force_on_cpu_samples: false,
+                    attach_timeout: Duration::from_millis(200),
+                    burst_rate: None,
+                    burst_duration: std::time::Duration::from_secs(10),
+                    adaptive: false,
+                    thread_ids: vec![],
+                    all_threads: false,
+                    with_rss: false,
+                    error_tolerance: ErrorTolerance::default(),
+                    stop_signal: None,
+                    snapshot_signal: None,
+                    export: None,
+                    serve: None,
+                    rotate_interval: None,
+                    rotate_output: false,
+                    defer_symbolization: false,
+                    check: false,
+                    filter: TraceFilter::default(),
                 },
             }
         );
@@ -756,17 +2891,52 @@ mod tests {
                 cmd: SubCmd::Record {
                     target: Target::Pid { pid: 1234 },
                     out_path: "foo.txt".into(),
+                    extra_outputs: vec![],
                     raw_path: "raw.gz".into(),
+                    raw_compression: RawCompression::default(),
                     sample_rate: 99,
                     maybe_duration: Some(std::time::Duration::from_secs(60)),
+                    max_samples: None,
                     format: OutputFormat::callgrind,
+                    per_thread: false,
+                    raw_only: false,
                     no_drop_root: false,
+                    drop_privileges: false,
                     with_subprocesses: false,
+                    subprocess_poll_interval: Duration::from_secs(1),
                     silent: false,
+                    progress: ProgressFormat::text,
+                    diagnostics: false,
                     flame_min_width: 0.1,
+                    flame_title: None,
+                    flame_subtitle: None,
+                    reverse: false,
+                    color_by: ColorBy::none,
+                    summary_format: SummaryFormat::text,
                     lock_process: true,
+                    lock_retries: 0,
                     force_version: None,
+                    ruby_binary: None,
                     on_cpu_only: false,
+                    idle_samples: false,
+                    force_on_cpu_samples: false,
+                    attach_timeout: Duration::from_millis(200),
+                    burst_rate: None,
+                    burst_duration: std::time::Duration::from_secs(10),
+                    adaptive: false,
+                    thread_ids: vec![],
+                    all_threads: false,
+                    with_rss: false,
+                    error_tolerance: ErrorTolerance::default(),
+                    stop_signal: None,
+                    snapshot_signal: None,
+                    export: None,
+                    serve: None,
+                    rotate_interval: None,
+                    rotate_output: false,
+                    defer_symbolization: false,
+                    check: false,
+                    filter: TraceFilter::default(),
                 },
             }
         );
@@ -781,17 +2951,52 @@ mod tests {
                 cmd: SubCmd::Record {
                     target: Target::Pid { pid: 1234 },
                     out_path: "foo.txt".into(),
+                    extra_outputs: vec![],
                     raw_path: "raw.gz".into(),
+                    raw_compression: RawCompression::default(),
                     sample_rate: 99,
                     maybe_duration: None,
+                    max_samples: None,
                     format: OutputFormat::flamegraph,
+                    per_thread: false,
+                    raw_only: false,
                     no_drop_root: true,
+                    drop_privileges: false,
                     with_subprocesses: false,
+                    subprocess_poll_interval: Duration::from_secs(1),
                     silent: false,
+                    progress: ProgressFormat::text,
+                    diagnostics: false,
                     flame_min_width: 0.1,
+                    flame_title: None,
+                    flame_subtitle: None,
+                    reverse: false,
+                    color_by: ColorBy::none,
+                    summary_format: SummaryFormat::text,
                     lock_process: true,
+                    lock_retries: 0,
                     force_version: None,
+                    ruby_binary: None,
                     on_cpu_only: false,
+                    idle_samples: false,
+                    force_on_cpu_samples: false,
+                    attach_timeout: Duration::from_millis(200),
+                    burst_rate: None,
+                    burst_duration: std::time::Duration::from_secs(10),
+                    adaptive: false,
+                    thread_ids: vec![],
+                    all_threads: false,
+                    with_rss: false,
+                    error_tolerance: ErrorTolerance::default(),
+                    stop_signal: None,
+                    snapshot_signal: None,
+                    export: None,
+                    serve: None,
+                    rotate_interval: None,
+                    rotate_output: false,
+                    defer_symbolization: false,
+                    check: false,
+                    filter: TraceFilter::default(),
                 },
             }
         );
@@ -806,17 +3011,52 @@ mod tests {
                 cmd: SubCmd::Record {
                     target: Target::Pid { pid: 1234 },
                     out_path: "foo.txt".into(),
+                    extra_outputs: vec![],
                     raw_path: "raw.gz".into(),
+                    raw_compression: RawCompression::default(),
                     sample_rate: 99,
                     maybe_duration: None,
+                    max_samples: None,
                     format: OutputFormat::flamegraph,
+                    per_thread: false,
+                    raw_only: false,
                     no_drop_root: false,
+                    drop_privileges: false,
                     with_subprocesses: true,
+                    subprocess_poll_interval: Duration::from_secs(1),
                     silent: false,
+                    progress: ProgressFormat::text,
+                    diagnostics: false,
                     flame_min_width: 0.1,
+                    flame_title: None,
+                    flame_subtitle: None,
+                    reverse: false,
+                    color_by: ColorBy::none,
+                    summary_format: SummaryFormat::text,
                     lock_process: true,
+                    lock_retries: 0,
                     force_version: None,
+                    ruby_binary: None,
                     on_cpu_only: false,
+                    idle_samples: false,
+                    force_on_cpu_samples: false,
+                    attach_timeout: Duration::from_millis(200),
+                    burst_rate: None,
+                    burst_duration: std::time::Duration::from_secs(10),
+                    adaptive: false,
+                    thread_ids: vec![],
+                    all_threads: false,
+                    with_rss: false,
+                    error_tolerance: ErrorTolerance::default(),
+                    stop_signal: None,
+                    snapshot_signal: None,
+                    export: None,
+                    serve: None,
+                    rotate_interval: None,
+                    rotate_output: false,
+                    defer_symbolization: false,
+                    check: false,
+                    filter: TraceFilter::default(),
                 },
             }
         );
@@ -831,17 +3071,52 @@ mod tests {
                 cmd: SubCmd::Record {
                     target: Target::Pid { pid: 1234 },
                     out_path: "foo.txt".into(),
+                    extra_outputs: vec![],
                     raw_path: "raw.gz".into(),
+                    raw_compression: RawCompression::default(),
                     sample_rate: 99,
                     maybe_duration: None,
+                    max_samples: None,
                     format: OutputFormat::flamegraph,
+                    per_thread: false,
+                    raw_only: false,
                     no_drop_root: false,
+                    drop_privileges: false,
                     with_subprocesses: false,
+                    subprocess_poll_interval: Duration::from_secs(1),
                     silent: false,
+                    progress: ProgressFormat::text,
+                    diagnostics: false,
                     flame_min_width: 0.02,
+                    flame_title: None,
+                    flame_subtitle: None,
+                    reverse: false,
+                    color_by: ColorBy::none,
+                    summary_format: SummaryFormat::text,
                     lock_process: true,
+                    lock_retries: 0,
                     force_version: None,
+                    ruby_binary: None,
                     on_cpu_only: false,
+                    idle_samples: false,
+                    force_on_cpu_samples: false,
+                    attach_timeout: Duration::from_millis(200),
+                    burst_rate: None,
+                    burst_duration: std::time::Duration::from_secs(10),
+                    adaptive: false,
+                    thread_ids: vec![],
+                    all_threads: false,
+                    with_rss: false,
+                    error_tolerance: ErrorTolerance::default(),
+                    stop_signal: None,
+                    snapshot_signal: None,
+                    export: None,
+                    serve: None,
+                    rotate_interval: None,
+                    rotate_output: false,
+                    defer_symbolization: false,
+                    check: false,
+                    filter: TraceFilter::default(),
                 },
             }
         );
@@ -856,48 +3131,1307 @@ mod tests {
                 cmd: SubCmd::Record {
                     target: Target::Pid { pid: 1234 },
                     out_path: "foo.txt".into(),
+                    extra_outputs: vec![],
                     raw_path: "raw.gz".into(),
+                    raw_compression: RawCompression::default(),
                     sample_rate: 99,
                     maybe_duration: None,
+                    max_samples: None,
                     format: OutputFormat::flamegraph,
+                    per_thread: false,
+                    raw_only: false,
                     no_drop_root: false,
+                    drop_privileges: false,
                     with_subprocesses: false,
+                    subprocess_poll_interval: Duration::from_secs(1),
                     silent: false,
+                    progress: ProgressFormat::text,
+                    diagnostics: false,
                     flame_min_width: 0.1,
+                    flame_title: None,
+                    flame_subtitle: None,
+                    reverse: false,
+                    color_by: ColorBy::none,
+                    summary_format: SummaryFormat::text,
                     lock_process: false,
+                    lock_retries: 0,
                     force_version: None,
+                    ruby_binary: None,
                     on_cpu_only: false,
+                    idle_samples: false,
+                    force_on_cpu_samples: false,
+                    attach_timeout: Duration::from_millis(200),
+                    burst_rate: None,
+                    burst_duration: std::time::Duration::from_secs(10),
+                    adaptive: false,
+                    thread_ids: vec![],
+                    all_threads: false,
+                    with_rss: false,
+                    error_tolerance: ErrorTolerance::default(),
+                    stop_signal: None,
+                    snapshot_signal: None,
+                    export: None,
+                    serve: None,
+                    rotate_interval: None,
+                    rotate_output: false,
+                    defer_symbolization: false,
+                    check: false,
+                    filter: TraceFilter::default(),
                 },
             }
         );
-    }
 
-    #[test]
-    fn test_snapshot_arg_parsing() {
-        let args = Args::from(make_args("rbspy snapshot --pid 1234")).unwrap();
+        let args = Args::from(make_args(
+            "rbspy record --pid 1234 --raw-file raw.gz --file foo.txt --burst-rate 500 --burst-duration 30",
+        ))
+        .unwrap();
         assert_eq!(
             args,
             Args {
-                cmd: SubCmd::Snapshot {
-                    pid: 1234,
+                cmd: SubCmd::Record {
+                    target: Target::Pid { pid: 1234 },
+                    out_path: "foo.txt".into(),
+                    extra_outputs: vec![],
+                    raw_path: "raw.gz".into(),
+                    raw_compression: RawCompression::default(),
+                    sample_rate: 99,
+                    maybe_duration: None,
+                    max_samples: None,
+                    format: OutputFormat::flamegraph,
+                    per_thread: false,
+                    raw_only: false,
+                    no_drop_root: false,
+                    drop_privileges: false,
+                    with_subprocesses: false,
+                    subprocess_poll_interval: Duration::from_secs(1),
+                    silent: false,
+                    progress: ProgressFormat::text,
+                    diagnostics: false,
+                    flame_min_width: 0.1,
+                    flame_title: None,
+                    flame_subtitle: None,
+                    reverse: false,
+                    color_by: ColorBy::none,
+                    summary_format: SummaryFormat::text,
                     lock_process: true,
+                    lock_retries: 0,
                     force_version: None,
+                    ruby_binary: None,
                     on_cpu_only: false,
+                    idle_samples: false,
+                    force_on_cpu_samples: false,
+                    attach_timeout: Duration::from_millis(200),
+                    burst_rate: Some(500),
+                    burst_duration: std::time::Duration::from_secs(30),
+                    adaptive: false,
+                    thread_ids: vec![],
+                    all_threads: false,
+                    with_rss: false,
+                    error_tolerance: ErrorTolerance::default(),
+                    stop_signal: None,
+                    snapshot_signal: None,
+                    export: None,
+                    serve: None,
+                    rotate_interval: None,
+                    rotate_output: false,
+                    defer_symbolization: false,
+                    check: false,
+                    filter: TraceFilter::default(),
                 },
             }
         );
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_report_arg_parsing() {
+    fn test_raw_compression_arg_parsing() {
+        let d = tempfile::tempdir().unwrap();
+        std::env::set_var("HOME", d.path().to_str().unwrap());
+
+        for (arg, expected) in [
+            ("9", RawCompression::Gzip(9)),
+            ("none", RawCompression::None),
+        ] {
+            match Args::from(make_args(&format!(
+                "rbspy record --pid 1234 --raw-compression {}",
+                arg
+            )))
+            .unwrap()
+            {
+                Args {
+                    cmd:
+                        SubCmd::Record {
+                            raw_compression,
+                            raw_path,
+                            ..
+                        },
+                } => {
+                    assert_eq!(raw_compression, expected);
+                    assert!(raw_path
+                        .to_str()
+                        .unwrap()
+                        .ends_with(&format!(".{}", expected.extension())));
+                }
+                x => panic!("Unexpected: {:?}", x),
+            }
+        }
+
+        assert!(validate_raw_compression("10").is_err());
+        assert!(validate_raw_compression("fast").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stop_signal_arg_parsing() {
+        let d = tempfile::tempdir().unwrap();
+        std::env::set_var("HOME", d.path().to_str().unwrap());
+
+        for (arg, expected) in [
+            ("TERM", libc::SIGTERM),
+            ("sigusr2", libc::SIGUSR2),
+            ("1", 1),
+        ] {
+            match Args::from(make_args(&format!(
+                "rbspy record --pid 1234 --stop-signal {}",
+                arg
+            )))
+            .unwrap()
+            {
+                Args {
+                    cmd: SubCmd::Record { stop_signal, .. },
+                } => assert_eq!(stop_signal, Some(expected)),
+                x => panic!("Unexpected: {:?}", x),
+            }
+        }
+
+        assert!(validate_signal("BOGUS").is_err());
+        assert!(validate_signal("-1").is_err());
+        assert!(Args::from(make_args(
+            "rbspy record --pid 1234 --burst-rate 500 --stop-signal USR1"
+        ))
+        .is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_snapshot_on_arg_parsing() {
+        let d = tempfile::tempdir().unwrap();
+        std::env::set_var("HOME", d.path().to_str().unwrap());
+
+        match Args::from(make_args("rbspy record --pid 1234 --snapshot-on USR2")).unwrap() {
+            Args {
+                cmd: SubCmd::Record {
+                    snapshot_signal, ..
+                },
+            } => assert_eq!(snapshot_signal, Some(libc::SIGUSR2)),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        for args in [
+            "rbspy record --pid 1234 --snapshot-on TERM",
+            "rbspy record --pid 1234 --snapshot-on USR2 --stop-signal USR2",
+            "rbspy record --pid 1234 --burst-rate 500 --snapshot-on USR1",
+        ] {
+            assert!(Args::from(make_args(args)).is_err(), "{}", args);
+        }
+
+        assert_eq!(
+            snapshots_path(Path::new("/tmp/out.raw.gz"), "raw.gz"),
+            PathBuf::from("/tmp/out.snapshots.txt")
+        );
+        assert_eq!(
+            snapshots_path(Path::new("out"), "raw"),
+            PathBuf::from("out.snapshots.txt")
+        );
+    }
+
+    #[test]
+    fn test_progress_arg_parsing() {
+        let d = tempfile::tempdir().unwrap();
+        std::env::set_var("HOME", d.path().to_str().unwrap());
+
+        match Args::from(make_args("rbspy record --pid 1234 --progress JSON")).unwrap() {
+            Args {
+                cmd: SubCmd::Record { progress, .. },
+            } => assert_eq!(progress, ProgressFormat::json),
+            x => panic!("Unexpected: {:?}", x),
+        }
+        let result = arg_parser().try_get_matches_from(make_args(
+            "rbspy record --pid 1234 --progress json --silent",
+        ));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_idle_arg_parsing() {
+        let d = tempfile::tempdir().unwrap();
+        std::env::set_var("HOME", d.path().to_str().unwrap());
+
+        match Args::from(make_args("rbspy record --pid 1234 --idle")).unwrap() {
+            Args {
+                cmd:
+                    SubCmd::Record {
+                        idle_samples,
+                        on_cpu_only,
+                        ..
+                    },
+            } => {
+                assert!(idle_samples);
+                assert!(!on_cpu_only);
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let result =
+            arg_parser().try_get_matches_from(make_args("rbspy record --pid 1234 --idle --on-cpu"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_snapshot_arg_parsing() {
+        let args = Args::from(make_args("rbspy snapshot --pid 1234")).unwrap();
+        assert_eq!(
+            args,
+            Args {
+                cmd: SubCmd::Snapshot {
+                    target: Target::Pid { pid: 1234 },
+                    lock_process: true,
+                    force_version: None,
+                    ruby_binary: None,
+                    on_cpu_only: false,
+                    idle_samples: false,
+                    thread_ids: vec![],
+                    count: 1,
+                    interval: Duration::from_millis(100),
+                    json: false,
+                    wait_for_file: None,
+                    with_locals: false,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_snapshot_count_arg_parsing() {
+        let args = Args::from(make_args(
+            "rbspy snapshot --pid 1234 --count 10 --interval 200ms --json",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Snapshot {
+                count,
+                interval,
+                json,
+                ..
+            } => {
+                assert_eq!(count, 10);
+                assert_eq!(interval, Duration::from_millis(200));
+                assert!(json);
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let args = Args::from(make_args(
+            "rbspy snapshot --pid 1234 --wait-for-file /tmp/trigger",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Snapshot { wait_for_file, .. } => {
+                assert_eq!(wait_for_file, Some(PathBuf::from("/tmp/trigger")))
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let args = Args::from(make_args("rbspy snapshot --pid 1234 --with-locals")).unwrap();
+        match args.cmd {
+            SubCmd::Snapshot { with_locals, .. } => assert!(with_locals),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let args = Args::from(make_args(
+            "rbspy snapshot --pid 1234 --thread-id 5 --thread-id 6",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Snapshot { thread_ids, .. } => assert_eq!(thread_ids, vec![5, 6]),
+            x => panic!("Unexpected: {:?}", x),
+        }
+        let args = Args::from(make_args("rbspy record --pid 1234 --thread-id 7")).unwrap();
+        match args.cmd {
+            SubCmd::Record { thread_ids, .. } => assert_eq!(thread_ids, vec![7]),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        assert_eq!(validate_interval("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(validate_interval("50"), Ok(Duration::from_millis(50)));
+        assert_eq!(validate_interval("15m"), Ok(Duration::from_secs(15 * 60)));
+        assert_eq!(validate_interval("1h"), Ok(Duration::from_secs(60 * 60)));
+        assert!(validate_interval("1.5s").is_err());
+        assert!(validate_interval("fast").is_err());
+        assert!(validate_interval("9999999999999999h").is_err());
+        assert!(validate_rotate_interval("9999999999999999h").is_err());
+        assert!(arg_parser()
+            .try_get_matches_from(make_args("rbspy snapshot --pid 1234 --count 0"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_ruby_binary_arg_parsing() {
+        let args = Args::from(make_args(
+            "rbspy snapshot --pid 1234 --ruby-binary /usr/lib/libruby.so.3.3",
+        ))
+        .unwrap();
+        assert_eq!(
+            args,
+            Args {
+                cmd: SubCmd::Snapshot {
+                    target: Target::Pid { pid: 1234 },
+                    lock_process: true,
+                    force_version: None,
+                    ruby_binary: Some(PathBuf::from("/usr/lib/libruby.so.3.3")),
+                    on_cpu_only: false,
+                    idle_samples: false,
+                    thread_ids: vec![],
+                    count: 1,
+                    interval: Duration::from_millis(100),
+                    json: false,
+                    wait_for_file: None,
+                    with_locals: false,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_report_arg_parsing() {
         let args = Args::from(make_args("rbspy report --input xyz.raw.gz --output xyz")).unwrap();
         assert_eq!(
             args,
             Args {
                 cmd: SubCmd::Report {
                     format: OutputFormat::flamegraph,
-                    input: PathBuf::from("xyz.raw.gz"),
+                    summary_format: SummaryFormat::text,
+                    summary_top: None,
+                    flame_title: None,
+                    flame_subtitle: None,
+                    reverse: false,
+                    color_by: ColorBy::none,
+                    inputs: vec![PathBuf::from("xyz.raw.gz")],
                     output: PathBuf::from("xyz"),
+                    filter: TraceFilter::default(),
+                },
+            }
+        );
+
+        let args = Args::from(make_args(
+            "rbspy report -i a.raw.gz b.raw.gz --input c.raw.gz --output xyz",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Report { inputs, .. } => assert_eq!(
+                inputs,
+                vec![
+                    PathBuf::from("a.raw.gz"),
+                    PathBuf::from("b.raw.gz"),
+                    PathBuf::from("c.raw.gz"),
+                ]
+            ),
+            _ => panic!("wrong subcommand"),
+        }
+    }
+
+    #[test]
+    fn test_focus_file_arg_parsing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("focus.txt");
+        std::fs::write(&path, "/app/**\n# Controllers\n*Controller#*\n").unwrap();
+        let args = Args::from(make_args(&format!(
+            "rbspy record --pid 1234 --focus-file {}",
+            path.display()
+        )))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Record { filter, .. } => {
+                assert_eq!(filter.focus_patterns, vec!["/app/**", "*Controller#*"])
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let missing = dir.path().join("missing.txt");
+        assert!(Args::from(make_args(&format!(
+            "rbspy report -i xyz.raw.gz --focus-file {}",
+            missing.display()
+        )))
+        .is_err());
+    }
+
+    #[test]
+    fn test_filter_arg_parsing() {
+        let args = Args::from(make_args(
+            "rbspy report -i xyz.raw.gz --collapse-gems --collapse-stdlib --stdlib-path /opt/ruby/lib --stdlib-path /opt/other",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Report { filter, .. } => assert_eq!(
+                filter,
+                TraceFilter {
+                    collapse_gems: true,
+                    collapse_stdlib: true,
+                    stdlib_paths: vec!["/opt/ruby/lib".to_string(), "/opt/other".to_string()],
+                    ..Default::default()
+                }
+            ),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let args = Args::from(make_args(
+            "rbspy record --pid 1234 --collapse-gems --collapse-c-functions --include-path /app --exclude-path **/vendor --exclude-path /tmp",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Record { filter, .. } => {
+                assert!(filter.collapse_gems);
+                assert!(!filter.collapse_stdlib);
+                assert!(filter.collapse_c_functions);
+                assert_eq!(filter.include_paths, vec!["/app"]);
+                assert_eq!(filter.exclude_paths, vec!["**/vendor", "/tmp"]);
+                assert!(!filter.normalize_frames);
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let result = arg_parser()
+            .try_get_matches_from(make_args("rbspy report -i xyz.raw.gz --stdlib-path /opt"));
+        assert!(result.is_err());
+
+        let args = Args::from(make_args(
+            "rbspy report -i xyz.raw.gz --hide-idle --idle-function pop --idle-function gets",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Report { filter, .. } => {
+                assert!(filter.hide_idle);
+                assert_eq!(filter.idle_functions, vec!["pop", "gets"]);
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let result = arg_parser()
+            .try_get_matches_from(make_args("rbspy record --pid 1234 --idle-function pop"));
+        assert!(result.is_err());
+
+        match Args::from(make_args("rbspy report -i xyz.raw.gz --only-ruby"))
+            .unwrap()
+            .cmd
+        {
+            SubCmd::Report { filter, .. } => assert!(filter.only_ruby),
+            x => panic!("Unexpected: {:?}", x),
+        }
+        let result = arg_parser().try_get_matches_from(make_args(
+            "rbspy report -i xyz.raw.gz --only-ruby --collapse-c-functions",
+        ));
+        assert!(result.is_err());
+
+        match Args::from(make_args("rbspy report -i xyz.raw.gz --normalize-frames"))
+            .unwrap()
+            .cmd
+        {
+            SubCmd::Report { filter, .. } => assert!(filter.normalize_frames),
+            x => panic!("Unexpected: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_summary_format_arg_parsing() {
+        let args = Args::from(make_args(
+            "rbspy report -i xyz.raw.gz -f summary --summary-format md -n 30",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Report {
+                format,
+                summary_format,
+                summary_top,
+                ..
+            } => {
+                assert_eq!(format, OutputFormat::summary);
+                assert_eq!(summary_format, SummaryFormat::markdown);
+                assert_eq!(summary_top, Some(30));
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let args = Args::from(make_args("rbspy record --pid 1234 --summary-format CSV")).unwrap();
+        match args.cmd {
+            SubCmd::Record { summary_format, .. } => {
+                assert_eq!(summary_format, SummaryFormat::csv)
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_per_thread_arg_parsing() {
+        let args =
+            Args::from(make_args("rbspy record --pid 1234 --per-thread -f out.svg")).unwrap();
+        match args.cmd {
+            SubCmd::Record {
+                per_thread,
+                out_path,
+                ..
+            } => {
+                assert!(per_thread);
+                assert_eq!(out_path, PathBuf::from("out.svg"));
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        assert!(Args::from(make_args("rbspy record --pid 1234 --per-thread -f -")).is_err());
+    }
+
+    #[test]
+    fn test_raw_only_arg_parsing() {
+        let args = Args::from(make_args("rbspy record --pid 1234 --raw-only")).unwrap();
+        match args.cmd {
+            SubCmd::Record { raw_only, .. } => assert!(raw_only),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        assert!(arg_parser()
+            .try_get_matches_from(make_args("rbspy record --pid 1234 --raw-only -f out.svg"))
+            .is_err());
+        assert!(arg_parser()
+            .try_get_matches_from(make_args(
+                "rbspy record --pid 1234 --raw-only --format speedscope"
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn test_drop_privileges_arg_parsing() {
+        let args = Args::from(make_args("rbspy record --pid 1234 --drop-privileges")).unwrap();
+        match args.cmd {
+            SubCmd::Record {
+                drop_privileges, ..
+            } => assert!(drop_privileges),
+            x => panic!("Unexpected: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_subprocess_poll_interval_arg_parsing() {
+        let args = Args::from(make_args(
+            "rbspy record --pid 1234 --subprocesses --subprocess-poll-interval 100ms",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Record {
+                subprocess_poll_interval,
+                ..
+            } => assert_eq!(subprocess_poll_interval, Duration::from_millis(100)),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        assert!(arg_parser()
+            .try_get_matches_from(make_args(
+                "rbspy record --pid 1234 --subprocess-poll-interval 100ms"
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn test_flame_title_arg_parsing() {
+        let args = Args::from(make_args(
+            "rbspy record --pid 1234 --flame-title Checkout --flame-subtitle production",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Record {
+                flame_title,
+                flame_subtitle,
+                ..
+            } => {
+                assert_eq!(flame_title, Some("Checkout".to_string()));
+                assert_eq!(flame_subtitle, Some("production".to_string()));
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let args = Args::from(make_args(
+            "rbspy report --input xyz.raw.gz --flame-title Checkout",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Report {
+                flame_title,
+                flame_subtitle,
+                ..
+            } => {
+                assert_eq!(flame_title, Some("Checkout".to_string()));
+                assert_eq!(flame_subtitle, None);
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_reverse_arg_parsing() {
+        let args = Args::from(make_args("rbspy record --pid 1234 --reverse")).unwrap();
+        match args.cmd {
+            SubCmd::Record { reverse, .. } => assert!(reverse),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let args = Args::from(make_args("rbspy report --input xyz.raw.gz --reverse")).unwrap();
+        match args.cmd {
+            SubCmd::Report { reverse, .. } => assert!(reverse),
+            x => panic!("Unexpected: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_color_by_arg_parsing() {
+        let args = Args::from(make_args("rbspy record --pid 1234 --color-by gem")).unwrap();
+        match args.cmd {
+            SubCmd::Record { color_by, .. } => assert_eq!(color_by, ColorBy::gem),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let args =
+            Args::from(make_args("rbspy report --input xyz.raw.gz --color-by path")).unwrap();
+        match args.cmd {
+            SubCmd::Report { color_by, .. } => assert_eq!(color_by, ColorBy::path),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        assert!(arg_parser()
+            .try_get_matches_from(make_args("rbspy record --pid 1234 --color-by rainbow"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_lock_retries_arg_parsing() {
+        let args = Args::from(make_args("rbspy record --pid 1234 --lock-retries 5")).unwrap();
+        match args.cmd {
+            SubCmd::Record { lock_retries, .. } => assert_eq!(lock_retries, 5),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        assert!(arg_parser()
+            .try_get_matches_from(make_args(
+                "rbspy record --pid 1234 --lock-retries 5 --nonblocking"
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn test_process_name_arg_parsing() {
+        let process_name = Target::ProcessName {
+            name: "puma".to_string(),
+        };
+        for cmd in ["record", "snapshot", "inspect"] {
+            let args =
+                Args::from(make_args(&format!("rbspy {} --process-name puma", cmd))).unwrap();
+            match args.cmd {
+                SubCmd::Record { target, .. }
+                | SubCmd::Snapshot { target, .. }
+                | SubCmd::Inspect { target, .. } => assert_eq!(target, process_name),
+                x => panic!("Unexpected: {:?}", x),
+            }
+
+            assert!(arg_parser()
+                .try_get_matches_from(make_args(&format!(
+                    "rbspy {} --process-name puma --pid 1234",
+                    cmd
+                )))
+                .is_err());
+        }
+        assert!(arg_parser()
+            .try_get_matches_from(make_args("rbspy record --process-name puma -- ruby app.rb"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_namespace_pid_arg_parsing() {
+        let namespace_pid = Target::NamespacePid {
+            pid: 1,
+            root: PathBuf::from("/var/lib/docker/overlay2/abc/merged"),
+        };
+        for cmd in ["record", "snapshot", "inspect"] {
+            let args = Args::from(make_args(&format!(
+                "rbspy {} --namespace-pid 1 --root /var/lib/docker/overlay2/abc/merged",
+                cmd
+            )))
+            .unwrap();
+            match args.cmd {
+                SubCmd::Record { target, .. }
+                | SubCmd::Snapshot { target, .. }
+                | SubCmd::Inspect { target, .. } => assert_eq!(target, namespace_pid),
+                x => panic!("Unexpected: {:?}", x),
+            }
+
+            for invalid in [
+                "--namespace-pid 1",
+                "--root /containers/abc",
+                "--namespace-pid 1 --root /containers/abc --pid 1234",
+                "--namespace-pid 1 --root /containers/abc --process-name puma",
+            ] {
+                assert!(arg_parser()
+                    .try_get_matches_from(make_args(&format!("rbspy {} {}", cmd, invalid)))
+                    .is_err());
+            }
+        }
+        assert!(arg_parser()
+            .try_get_matches_from(make_args(
+                "rbspy record --namespace-pid 1 --root /containers/abc -- ruby app.rb"
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn test_list_arg_parsing() {
+        let args = Args::from(make_args("rbspy list")).unwrap();
+        assert_eq!(args.cmd, SubCmd::List { json: false });
+        let args = Args::from(make_args("rbspy list --json")).unwrap();
+        assert_eq!(args.cmd, SubCmd::List { json: true });
+    }
+
+    #[test]
+    fn test_rate_arg_parsing() {
+        let args = Args::from(make_args("rbspy record --pid 1234 --rate 1000")).unwrap();
+        match args.cmd {
+            SubCmd::Record { sample_rate, .. } => assert_eq!(sample_rate, 1000),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        for rate in ["0", "1000000", "-5"] {
+            assert!(arg_parser()
+                .try_get_matches_from(make_args(&format!(
+                    "rbspy record --pid 1234 --rate {}",
+                    rate
+                )))
+                .is_err());
+            assert!(arg_parser()
+                .try_get_matches_from(make_args(&format!(
+                    "rbspy record --pid 1234 --burst-rate {}",
+                    rate
+                )))
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_config_files() {
+        let config = |path: &str, contents: &str| ConfigFile::parse(Path::new(path), contents);
+        let user = config(
+            "user.toml",
+            "[record]\nrate = 7\nformat = \"summary\"\n[report]\nformat = \"callgrind\"\n",
+        )
+        .unwrap();
+        let project = config(
+            "project.toml",
+            "[record]\nrate = 50\nhide-idle = true\nexclude-path = [\"**/vendor/**\", \"**/gems/**\"]\n",
+        )
+        .unwrap();
+        let parser = || apply_config(arg_parser(), &[user.clone(), project.clone()]).unwrap();
+
+        let args = Args::from_parser(
+            parser(),
+            make_args("rbspy record --pid 1234 --format speedscope"),
+        )
+        .unwrap();
+        match args.cmd {
+            SubCmd::Record {
+                sample_rate,
+                format,
+                filter,
+                ..
+            } => {
+                assert_eq!(sample_rate, 50);
+                assert_eq!(format, OutputFormat::speedscope);
+                assert!(filter.hide_idle);
+                assert_eq!(filter.exclude_paths.len(), 2);
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let args = Args::from_parser(
+            parser(),
+            make_args("rbspy report --input rbspy.raw.gz --output out.txt"),
+        )
+        .unwrap();
+        match args.cmd {
+            SubCmd::Report { format, .. } => assert_eq!(format, OutputFormat::callgrind),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        // Environment variables override config files, and command line options override both
+        env::set_var("RBSPY_BURST_RATE", "200");
+        let burst_config = config("burst.toml", "[record]\nburst-rate = 100\n").unwrap();
+        let burst_rate = |command: &str| {
+            let parser = apply_config(arg_parser(), &[burst_config.clone()]).unwrap();
+            match Args::from_parser(parser, make_args(command)).unwrap().cmd {
+                SubCmd::Record { burst_rate, .. } => burst_rate,
+                x => panic!("Unexpected: {:?}", x),
+            }
+        };
+        assert_eq!(burst_rate("rbspy record --pid 1234"), Some(200));
+        assert_eq!(
+            burst_rate("rbspy record --pid 1234 --burst-rate 300"),
+            Some(300)
+        );
+        env::remove_var("RBSPY_BURST_RATE");
+
+        for contents in [
+            "[record]\npid = 1234\n",
+            "[record]\nno-such-option = 1\n",
+            "[record]\nrate = { value = 5 }\n",
+        ] {
+            let config = config("bad.toml", contents).unwrap();
+            assert!(apply_config(arg_parser(), &[config]).is_err());
+        }
+        assert!(config("bad.toml", "[snapshot]\npid = 1234\n").is_err());
+    }
+
+    #[test]
+    fn test_filename_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = Args::from(make_args(&format!(
+            "rbspy record --pid 1234 --output-dir {} --filename-template app/{{pid}}-{{format}}",
+            dir.path().display()
+        )))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Record {
+                out_path, raw_path, ..
+            } => {
+                assert_eq!(
+                    fill_in_pid(out_path, 1234),
+                    dir.path().join("app/1234-flamegraph.flamegraph.svg")
+                );
+                assert_eq!(
+                    fill_in_pid(raw_path, 1234),
+                    dir.path().join("app/1234-raw.raw.gz")
+                );
+                assert!(dir.path().join("app").is_dir());
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let filename = expand_filename_template("{date}-{rand}", "flamegraph").unwrap();
+        assert_eq!(filename.len(), "2024-01-01-".len() + 10);
+        assert_eq!(
+            expand_filename_template("profile", "flamegraph").unwrap(),
+            "profile"
+        );
+        assert!(expand_filename_template("{time}", "flamegraph").is_err());
+        assert!(expand_filename_template("{pid", "flamegraph").is_err());
+        assert!(arg_parser()
+            .try_get_matches_from(make_args(
+                "rbspy record --pid 1234 --filename-template {host}"
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn test_rotate_arg_parsing() {
+        let args = Args::from(make_args(
+            "rbspy record --pid 1234 --file out.svg --rotate-interval 1h --rotate-output",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Record {
+                rotate_interval,
+                rotate_output,
+                ..
+            } => {
+                assert_eq!(rotate_interval, Some(Duration::from_secs(60 * 60)));
+                assert!(rotate_output);
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        for command in [
+            "rbspy record --pid 1234 --rotate-interval 500ms",
+            "rbspy record --pid 1234 --rotate-output",
+            "rbspy record --pid 1234 --rotate-interval 1h --rotate-output --raw-only",
+        ] {
+            assert!(arg_parser()
+                .try_get_matches_from(make_args(command))
+                .is_err());
+        }
+        assert!(Args::from(make_args(
+            "rbspy record --pid 1234 --file - --rotate-interval 1h --rotate-output"
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_defer_symbolization_arg_parsing() {
+        let args = Args::from(make_args("rbspy record --pid 1234 --defer-symbolization")).unwrap();
+        match args.cmd {
+            SubCmd::Record {
+                defer_symbolization,
+                raw_only,
+                ..
+            } => {
+                assert!(defer_symbolization);
+                assert!(raw_only);
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        for command in [
+            "rbspy record --pid 1234 --defer-symbolization --file out.svg",
+            "rbspy record --pid 1234 --defer-symbolization --format speedscope",
+            "rbspy record --pid 1234 --defer-symbolization --all-threads",
+            "rbspy record --pid 1234 --defer-symbolization --rotate-interval 1h",
+        ] {
+            assert!(arg_parser()
+                .try_get_matches_from(make_args(command))
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_force_version_arg_parsing() {
+        let args = Args::from(make_args("rbspy record --pid 1234 --force-version 3.3.10")).unwrap();
+        match args.cmd {
+            SubCmd::Record { force_version, .. } => {
+                assert_eq!(force_version, Some("3.3.10".to_string()))
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        assert!(arg_parser()
+            .try_get_matches_from(make_args("rbspy record --pid 1234 --force-version 3.3"))
+            .is_err());
+        assert!(arg_parser()
+            .try_get_matches_from(make_args(
+                "rbspy snapshot --pid 1234 --force-version latest"
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn test_all_threads_arg_parsing() {
+        let args = Args::from(make_args("rbspy record --pid 1234 --all-threads")).unwrap();
+        match args.cmd {
+            SubCmd::Record { all_threads, .. } => assert!(all_threads),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        assert!(arg_parser()
+            .try_get_matches_from(make_args(
+                "rbspy record --pid 1234 --all-threads --adaptive"
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn test_error_tolerance_arg_parsing() {
+        let args = Args::from(make_args(
+            "rbspy record --pid 1234 --max-error-rate 0.9 --min-errors-before-abort 100",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Record {
+                error_tolerance, ..
+            } => assert_eq!(
+                error_tolerance,
+                ErrorTolerance {
+                    min_errors: 100,
+                    max_error_rate: 0.9,
+                }
+            ),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        for rate in ["1.5", "-0.1", "half"] {
+            assert!(arg_parser()
+                .try_get_matches_from(make_args(&format!(
+                    "rbspy record --pid 1234 --max-error-rate {}",
+                    rate
+                )))
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_with_rss_arg_parsing() {
+        let args =
+            Args::from(make_args("rbspy record --pid 1234 --with-rss --format rss")).unwrap();
+        match args.cmd {
+            SubCmd::Record { with_rss, .. } => assert!(with_rss),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        assert!(Args::from(make_args("rbspy record --pid 1234 --format rss")).is_err());
+    }
+
+    #[test]
+    fn test_multiple_formats_arg_parsing() {
+        let args = Args::from(make_args(
+            "rbspy record --pid 1234 --file out.svg --format flamegraph --format speedscope -o collapsed",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Record {
+                format,
+                out_path,
+                extra_outputs,
+                ..
+            } => {
+                assert_eq!(format, OutputFormat::flamegraph);
+                assert_eq!(out_path, PathBuf::from("out.svg"));
+                assert_eq!(
+                    extra_outputs,
+                    vec![
+                        (
+                            OutputFormat::speedscope,
+                            PathBuf::from("out.speedscope.json")
+                        ),
+                        (OutputFormat::collapsed, PathBuf::from("out.collapsed.txt")),
+                    ]
+                );
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        assert!(Args::from(make_args(
+            "rbspy record --pid 1234 --format speedscope --format speedscope"
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_extra_output_path() {
+        let extra = |path: &str| {
+            extra_output_path(
+                Path::new(path),
+                &OutputFormat::flamegraph,
+                &OutputFormat::speedscope,
+            )
+        };
+        assert_eq!(extra("out.svg"), PathBuf::from("out.speedscope.json"));
+        assert_eq!(
+            extra("dir/out.flamegraph.svg"),
+            PathBuf::from("dir/out.speedscope.json")
+        );
+        assert_eq!(extra("out"), PathBuf::from("out.speedscope.json"));
+    }
+
+    #[test]
+    fn test_record_check_arg_parsing() {
+        let args = Args::from(make_args("rbspy record --pid 1234 --check")).unwrap();
+        match args.cmd {
+            SubCmd::Record { check, .. } => assert!(check),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        // A command would have to be started to check it
+        let matches =
+            arg_parser().try_get_matches_from(make_args("rbspy record --check -- ruby foo.rb"));
+        assert!(matches.is_err());
+    }
+
+    #[test]
+    fn test_attach_timeout_arg_parsing() {
+        let args = Args::from(make_args("rbspy record --pid 1234 --attach-timeout 10s")).unwrap();
+        match args.cmd {
+            SubCmd::Record { attach_timeout, .. } => {
+                assert_eq!(attach_timeout, Duration::from_secs(10))
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let args = Args::from(make_args("rbspy record --pid 1234")).unwrap();
+        match args.cmd {
+            SubCmd::Record { attach_timeout, .. } => {
+                assert_eq!(attach_timeout, rbspy::sampler::DEFAULT_ATTACH_TIMEOUT)
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        assert!(arg_parser()
+            .try_get_matches_from(make_args("rbspy record --pid 1234 --attach-timeout soon"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_force_on_cpu_samples_arg_parsing() {
+        let args = Args::from(make_args(
+            "rbspy record --pid 1234 --on-cpu --force-on-cpu-samples",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Record {
+                on_cpu_only,
+                force_on_cpu_samples,
+                ..
+            } => {
+                assert!(on_cpu_only);
+                assert!(force_on_cpu_samples);
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let args = Args::from(make_args(
+            "rbspy record --pid 1234 --mode cpu --force-on-cpu-samples",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Record {
+                force_on_cpu_samples,
+                ..
+            } => assert!(force_on_cpu_samples),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        // It only changes the on-CPU check, so it's an error without one
+        assert!(Args::from(make_args("rbspy record --pid 1234 --force-on-cpu-samples")).is_err());
+    }
+
+    #[test]
+    fn test_diff_arg_parsing() {
+        let args = Args::from(make_args(
+            "rbspy diff --before a.raw.gz --after b.raw.gz -f summary",
+        ))
+        .unwrap();
+        assert_eq!(
+            args,
+            Args {
+                cmd: SubCmd::Diff {
+                    format: DiffFormat::summary,
+                    before: PathBuf::from("a.raw.gz"),
+                    after: PathBuf::from("b.raw.gz"),
+                    output: PathBuf::from("-"),
+                },
+            }
+        );
+
+        let result = arg_parser().try_get_matches_from(make_args("rbspy diff --before a.raw.gz"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_mode_arg_parsing() {
+        let args = Args::from(make_args("rbspy record --pid 1234 --mode cpu")).unwrap();
+        match args.cmd {
+            SubCmd::Record {
+                idle_samples,
+                on_cpu_only,
+                ..
+            } => {
+                assert!(idle_samples);
+                assert!(!on_cpu_only);
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let args = Args::from(make_args("rbspy snapshot --pid 1234 --mode cpu")).unwrap();
+        match args.cmd {
+            SubCmd::Snapshot { idle_samples, .. } => assert!(idle_samples),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let args = Args::from(make_args("rbspy record --pid 1234 --mode wall")).unwrap();
+        match args.cmd {
+            SubCmd::Record { idle_samples, .. } => assert!(!idle_samples),
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        for cmd in [
+            "rbspy record --pid 1234 --mode cpu --on-cpu",
+            "rbspy record --pid 1234 --mode cpu --idle",
+            "rbspy record --pid 1234 --mode idle",
+        ] {
+            assert!(arg_parser().try_get_matches_from(make_args(cmd)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_merge_arg_parsing() {
+        let args = Args::from(make_args(
+            "rbspy merge -i a.raw.gz -i b.raw.gz -o merged.raw.gz",
+        ))
+        .unwrap();
+        assert_eq!(
+            args,
+            Args {
+                cmd: SubCmd::Merge {
+                    inputs: vec![PathBuf::from("a.raw.gz"), PathBuf::from("b.raw.gz")],
+                    output: PathBuf::from("merged.raw.gz"),
+                    raw_compression: RawCompression::Gzip(6),
+                },
+            }
+        );
+
+        let args = Args::from(make_args(
+            "rbspy merge -i a.raw b.raw -o merged.raw --raw-compression none",
+        ))
+        .unwrap();
+        match args.cmd {
+            SubCmd::Merge {
+                inputs,
+                raw_compression,
+                ..
+            } => {
+                assert_eq!(inputs.len(), 2);
+                assert_eq!(raw_compression, RawCompression::None);
+            }
+            x => panic!("Unexpected: {:?}", x),
+        }
+
+        let result = arg_parser().try_get_matches_from(make_args("rbspy merge -i a.raw.gz"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_check_arg_parsing() {
+        let args = Args::from(make_args("rbspy check -i xyz.raw.gz")).unwrap();
+        assert_eq!(
+            args,
+            Args {
+                cmd: SubCmd::Check {
+                    input: PathBuf::from("xyz.raw.gz"),
+                },
+            }
+        );
+    }
+    #[test]
+    fn test_analyze_coredump_arg_parsing() {
+        let args = Args::from(make_args(
+            "rbspy analyze-coredump --core ruby.core --ruby-version 3.3.0",
+        ))
+        .unwrap();
+        assert_eq!(
+            args,
+            Args {
+                cmd: SubCmd::AnalyzeCoredump {
+                    core: PathBuf::from("ruby.core"),
+                    ruby_version: "3.3.0".to_string(),
+                    ruby_binary: None,
+                },
+            }
+        );
+
+        assert!(arg_parser()
+            .try_get_matches_from(make_args("rbspy analyze-coredump --core ruby.core"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_inspect_arg_parsing() {
+        let args = Args::from(make_args("rbspy inspect --pid 1234 --json")).unwrap();
+        assert_eq!(
+            args,
+            Args {
+                cmd: SubCmd::Inspect {
+                    target: Target::Pid { pid: 1234 },
+                    force_version: None,
+                    ruby_binary: None,
+                    json: true,
                 },
             }
         );
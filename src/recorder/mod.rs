@@ -1,6 +1,10 @@
 mod record;
+mod serve;
 mod snapshot;
 
 pub use record::Config as RecordConfig;
-pub use record::Recorder;
-pub use snapshot::snapshot;
+pub use record::{rotated_path, thread_output_path};
+pub use record::{Progress, Recorder};
+pub use serve::serve;
+pub use snapshot::{snapshot, thread_header};
+pub use snapshot::{SnapshotWithLocals, Snapshotter};
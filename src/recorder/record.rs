@@ -1,9 +1,16 @@
-use anyhow::{Context, Error, Result};
+use anyhow::{format_err, Context, Error, Result};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use crate::storage::Store;
+use crate::ui::flamegraph;
+use crate::ui::output::{Outputter, OutputterFactory};
 use crate::ui::summary;
 
 /// A configuration bundle for the recorder
@@ -12,8 +19,23 @@ pub struct Config {
     pub format: crate::core::types::OutputFormat,
     /// Where to write rbspy's raw trace output, which can be used for later processing.
     pub raw_path: Option<PathBuf>,
-    /// Where to write rbspy's output. If `-` is given, output is written to standard output.
+    /// How to compress the raw trace output. Default: gzip at level 6.
+    pub raw_compression: crate::core::types::RawCompression,
+    /// Where to write rbspy's output. If `-` is given, output is written to standard output. If
+    /// not given, traces aren't formatted at all, and only the raw output is written.
+    /// Collapsed and JSON lines output are written to standard output as the traces arrive, one
+    /// line per trace, instead of when recording stops.
     pub out_path: Option<PathBuf>,
+    /// More formats to write the traces in, each to its own path, e.g. a speedscope profile as
+    /// well as the flamegraph at `out_path`. The traces are only received once, however many
+    /// outputs there are. No two outputs (or the raw output) can have the same path. Default:
+    /// empty.
+    pub extra_outputs: Vec<(crate::core::types::OutputFormat, PathBuf)>,
+    /// Writes a separate output file for each thread instead of combining all threads into one.
+    /// The files are named after `out_path`, with the thread ID inserted before the extension
+    /// (see `thread_output_path`). Traces without a thread ID go into an `unknown` file.
+    /// Default: `false`.
+    pub per_thread: bool,
     /// The process ID (PID) of the process to profile. This is usually a ruby process, but rbspy
     /// will locate and profile any ruby subprocesses of the target process if `with_subprocesses`
     /// is enabled.
@@ -21,61 +43,259 @@ pub struct Config {
     /// Whether to profile the target process (given by `pid`) as well as its child processes, and
     /// their child processes, and so on. Default: `false`.
     pub with_subprocesses: bool,
+    /// How often to look for new child processes when `with_subprocesses` is enabled. Children
+    /// that start and exit between two checks aren't profiled. Default: 1 second.
+    pub subprocess_poll_interval: std::time::Duration,
     /// The number of traces that should be collected each second. Default: `100`.
     pub sample_rate: u32,
     /// The length of time that the recorder should run before stopping. Default: none (run until
     /// interrupted).
     pub maybe_duration: Option<std::time::Duration>,
+    /// The number of traces after which the recorder should stop. When combined with
+    /// `maybe_duration`, whichever limit is reached first stops the recorder. Default: none.
+    pub max_samples: Option<usize>,
     /// Minimum flame width. Applies to flamegraph output only. If your sample has many small
     /// functions in it and is difficult to read, then consider increasing this value.
     /// Default: 0.1.
     pub flame_min_width: f64,
+    /// The title of flamegraph output. Default: none (Inferno's default title).
+    pub flame_title: Option<String>,
+    /// The subtitle of flamegraph output. Default: none (describe the recording, e.g. the PID,
+    /// sample rate, and duration).
+    pub flame_subtitle: Option<String>,
+    /// Merges the stacks in flamegraph output from the function that was running, instead of
+    /// from the root, to find the functions that use the most time no matter where they're
+    /// called from. Default: `false`.
+    pub flame_reverse: bool,
+    /// How to color the frames of flamegraph output, including the live flamegraph. Default:
+    /// `none` (inferno's default colors).
+    pub flame_color_by: crate::core::types::ColorBy,
+    /// The table format for summary output. Applies to the summary formats only. Default: `text`.
+    pub summary_format: crate::core::types::SummaryFormat,
     /// Locks the process when a sample is being taken.
     ///
     /// You should enable this option for the most accurate samples. However, it briefly
     /// stops the process from executing and can affect performance. The performance impact
     /// is most noticeable in CPU-bound ruby programs or when a high sampling rate is used.
+    /// Default: `true`.
     pub lock_process: bool,
+    /// How many more times to try locking the process when locking fails, waiting a little longer
+    /// before each retry. Locking can fail while the process is starting or ending threads, and
+    /// the sample is dropped if every attempt fails. Applies only with `lock_process`. Default: `0`.
+    pub lock_retries: u32,
     /// Forces the recorder to use the given Ruby version. If not given, rbspy will attempt to
     /// determine the Ruby version from the running process.
     ///
     /// This option shouldn't be needed unless you're testing a pre-release Ruby version.
     pub force_version: Option<String>,
+    /// The path of the ruby binary (or libruby) that the target process is running. If given,
+    /// rbspy only looks for the Ruby version and VM addresses in that file. This is useful when
+    /// rbspy picks the wrong file, e.g. if the process has loaded more than one libruby. Default:
+    /// none (detect it automatically).
+    pub ruby_binary: Option<PathBuf>,
     /// Includes stack traces only when the program is using the CPU. Default: `false` (always
     /// includes stack traces, even when the program is waiting).
     pub on_cpu_only: bool,
+    /// Records a placeholder `(idle)` trace for each sample taken while the program isn't using
+    /// the CPU, so that the output accounts for all of the recording's wall-clock time. Can't be
+    /// combined with `on_cpu_only`. Default: `false`.
+    pub idle_samples: bool,
+    /// Skips the check of the Ruby thread's status when deciding whether the process is on the
+    /// CPU (with `on_cpu_only` or `idle_samples`), and relies only on the OS's view of whether
+    /// any of its threads are running. The Ruby status is sometimes wrong, which drops samples
+    /// of threads that were running, but the OS only knows that some thread is running, not that
+    /// it's the one running Ruby code, so this can keep samples of a thread that's waiting while
+    /// another (e.g. a native extension's) runs. Default: `false`.
+    pub force_on_cpu_samples: bool,
+    /// How long to keep trying to attach to the process before giving up, e.g. while a large app
+    /// is still booting and its Ruby VM can't be read yet. Default: 200 milliseconds.
+    pub attach_timeout: std::time::Duration,
+    /// The number of traces that should be collected each second while a burst is in progress.
+    /// Bursts are started with `Recorder::burst`. Default: none (bursts are disabled).
+    ///
+    /// Traces collected during a burst aren't weighted differently from other traces, so bursts
+    /// will be overrepresented in the output.
+    pub burst_rate: Option<u32>,
+    /// How long a burst lasts before sampling returns to `sample_rate`. Default: 10 seconds.
+    pub burst_duration: std::time::Duration,
+    /// Lowers the sample rate while the process is idle, i.e. while consecutive samples show the
+    /// same stack and the process isn't using the CPU, and returns to `sample_rate` as soon as it's
+    /// active again. This reduces rbspy's overhead on mostly idle processes, like web workers
+    /// waiting for requests. Default: `false`.
+    ///
+    /// Idle periods get fewer samples than they would at a fixed rate, so they will be
+    /// underrepresented in the output.
+    pub adaptive: bool,
+    /// Keeps only the traces from these threads, identified by their OS thread IDs, and drops
+    /// traces that don't have a thread ID. Default: empty (keeps traces from all threads).
+    pub thread_ids: Vec<u64>,
+    /// Reads the stack traces of all of the Ruby threads on every sample, including the ones that
+    /// are waiting, instead of only the thread that's running Ruby code. Each trace's `on_cpu`
+    /// says whether its thread was runnable. Requires Ruby 2.3 or newer. Default: `false`.
+    pub all_threads: bool,
+    /// Reads the process's resident set size (RSS) on every sample and stores it on the traces,
+    /// to correlate what the program was doing with its memory growth. The `rss` format reports
+    /// it over time. Only supported on Linux and macOS. Default: `false`.
+    pub with_rss: bool,
+    /// How many stack traces can fail to be read before recording gives up on a process. Default:
+    /// stop once more than 20 have failed and they're more than half of the samples.
+    pub error_tolerance: crate::sampler::ErrorTolerance,
+    /// Changes which frames appear in the formatted output and the live summary, e.g. by
+    /// collapsing gem frames. The raw output always contains the unfiltered traces.
+    pub filter: crate::core::filter::TraceFilter,
+    /// Keeps a flamegraph of the traces collected so far, which can be written while recording
+    /// with `Recorder::write_flamegraph`. Default: `false`.
+    pub live_flamegraph: bool,
+    /// Creates the outputter for the formatted output at `out_path`, for formats that rbspy
+    /// doesn't support itself. It's called once, or once per thread with `per_thread`, and
+    /// `format` is ignored. `extra_outputs` still use their formats. Default: none (use `format`).
+    pub outputter: Option<OutputterFactory>,
+    /// Closes the raw output file and starts a new one this often, so that a long recording is
+    /// split into files that can be reported on (and deleted) while it continues. The files are
+    /// numbered from 1, e.g. `out.0001.raw.gz` for `out.raw.gz` (see `rotated_path`), and each
+    /// one is complete on its own. Default: none (write a single raw file).
+    pub rotate_interval: Option<std::time::Duration>,
+    /// Also writes the formatted output at each rotation, numbered the same way as the raw files,
+    /// and starts the next file's output afresh. Without it, the formatted output covers the
+    /// whole recording. Applies only with `rotate_interval`. Default: `false`.
+    pub rotate_output: bool,
+    /// Records only the addresses of each trace's frames, along with the memory that's needed to
+    /// name them later, and leaves naming the frames to whatever reads the raw output (e.g.
+    /// `rbspy report`). Sampling is much cheaper this way, so higher sample rates are possible.
+    /// Experimental and only supported on Linux. Requires `raw_path`, and can't be combined with
+    /// formatted outputs, `live_flamegraph`, `rotate_interval`, `all_threads`, or `adaptive`. The
+    /// live summary only has threads, not functions. Default: `false`.
+    pub defer_symbolization: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            format: crate::core::types::OutputFormat::flamegraph,
+            raw_path: None,
+            raw_compression: Default::default(),
+            out_path: None,
+            extra_outputs: vec![],
+            per_thread: false,
+            pid: 0,
+            with_subprocesses: false,
+            subprocess_poll_interval: std::time::Duration::from_secs(1),
+            sample_rate: 100,
+            maybe_duration: None,
+            max_samples: None,
+            flame_min_width: 0.1,
+            flame_title: None,
+            flame_subtitle: None,
+            flame_reverse: false,
+            flame_color_by: Default::default(),
+            summary_format: Default::default(),
+            lock_process: true,
+            lock_retries: 0,
+            force_version: None,
+            ruby_binary: None,
+            on_cpu_only: false,
+            idle_samples: false,
+            force_on_cpu_samples: false,
+            attach_timeout: crate::sampler::DEFAULT_ATTACH_TIMEOUT,
+            burst_rate: None,
+            burst_duration: std::time::Duration::from_secs(10),
+            adaptive: false,
+            thread_ids: vec![],
+            all_threads: false,
+            with_rss: false,
+            error_tolerance: Default::default(),
+            filter: Default::default(),
+            live_flamegraph: false,
+            outputter: None,
+            rotate_interval: None,
+            rotate_output: false,
+            defer_symbolization: false,
+        }
+    }
 }
 
 pub struct Recorder {
-    format: crate::core::types::OutputFormat,
+    // The formatted outputs, starting with `format` at `out_path` if it was given
+    outputs: Vec<(crate::core::types::OutputFormat, PathBuf)>,
     flame_min_width: f64,
-    out_path: Option<PathBuf>,
+    options: crate::core::types::ReportOptions,
+    per_thread: bool,
     raw_path: Option<PathBuf>,
+    raw_compression: crate::core::types::RawCompression,
     sample_rate: u32,
     sampler: crate::sampler::Sampler,
     summary: Arc<Mutex<summary::Stats>>,
+    live_flamegraph: Option<Mutex<flamegraph::Stats>>,
+    outputter: Option<OutputterFactory>,
+    rotate_interval: Option<std::time::Duration>,
+    rotate_output: bool,
+    defer_symbolization: bool,
 }
 
 impl Recorder {
     pub fn new(config: Config) -> Self {
         let sampler = crate::sampler::Sampler::new(
             config.pid,
-            config.sample_rate,
-            config.lock_process,
-            config.maybe_duration,
-            config.with_subprocesses,
-            config.force_version,
-            config.on_cpu_only,
+            crate::sampler::SamplerConfig {
+                sample_rate: config.sample_rate,
+                lock_process: config.lock_process,
+                lock_retries: config.lock_retries,
+                time_limit: config.maybe_duration,
+                max_samples: config.max_samples,
+                with_subprocesses: config.with_subprocesses,
+                subprocess_poll_interval: config.subprocess_poll_interval,
+                force_version: config.force_version,
+                ruby_binary: config.ruby_binary,
+                on_cpu_only: config.on_cpu_only,
+                idle_samples: config.idle_samples,
+                force_on_cpu_samples: config.force_on_cpu_samples,
+                attach_timeout: config.attach_timeout,
+                burst_rate: config.burst_rate,
+                burst_duration: config.burst_duration,
+                adaptive: config.adaptive,
+                thread_ids: config.thread_ids,
+                all_threads: config.all_threads,
+                with_rss: config.with_rss,
+                error_tolerance: config.error_tolerance,
+            },
         );
 
+        let outputter = config.out_path.as_ref().and(config.outputter);
+        let mut outputs: Vec<_> = config
+            .out_path
+            .map(|out_path| (config.format, out_path))
+            .into_iter()
+            .collect();
+        outputs.extend(config.extra_outputs);
+
         Recorder {
-            format: config.format,
+            outputs,
             flame_min_width: config.flame_min_width,
-            out_path: config.out_path,
+            options: crate::core::types::ReportOptions {
+                title: config.flame_title,
+                subtitle: config.flame_subtitle,
+                reverse: config.flame_reverse,
+                color_by: config.flame_color_by,
+                summary_format: config.summary_format,
+                top: None,
+                filter: config.filter,
+            },
+            per_thread: config.per_thread,
             raw_path: config.raw_path,
+            raw_compression: config.raw_compression,
             sample_rate: config.sample_rate,
             sampler,
             summary: Arc::new(Mutex::new(summary::Stats::new())),
+            live_flamegraph: match config.live_flamegraph {
+                true => Some(Mutex::new(flamegraph::Stats::with_colors(
+                    config.flame_color_by,
+                ))),
+                false => None,
+            },
+            outputter,
+            rotate_interval: config.rotate_interval,
+            rotate_output: config.rotate_output,
+            defer_symbolization: config.defer_symbolization,
         }
     }
 
@@ -85,6 +305,25 @@ impl Recorder {
         // from each target process.
         // Give the child threads a buffer in case we fall a little behind with aggregating the stack
         // traces, but not an unbounded buffer.
+        let mut paths = HashSet::new();
+        for (_, path) in &self.outputs {
+            if self.per_thread && path == Path::new("-") {
+                return Err(format_err!(
+                    "Per-thread output can't be written to standard output"
+                ));
+            }
+            if !paths.insert(path) || self.raw_path.as_ref() == Some(path) {
+                return Err(format_err!(
+                    "More than one output would be written to {}",
+                    path.display()
+                ));
+            }
+        }
+
+        if self.defer_symbolization {
+            return self.record_deferred();
+        }
+
         let (trace_sender, trace_receiver) = std::sync::mpsc::sync_channel(100);
         let (result_sender, result_receiver) = std::sync::mpsc::channel();
         self.sampler.start(trace_sender, result_sender)?;
@@ -92,59 +331,283 @@ impl Recorder {
         // Aggregate stack traces as we receive them from the threads that are collecting them
         // Aggregate to 3 places: the raw output (`.raw.gz`), some summary statistics we display live,
         // and the formatted output (a flamegraph or something)
-        let mut out = None;
-        if self.out_path.is_some() {
-            out = Some(self.format.clone().outputter(self.flame_min_width));
-        }
-        let mut raw_store = None;
-        if let Some(raw_path) = &self.raw_path {
-            raw_store = Some(Store::new(&raw_path, self.sample_rate)?);
-        }
+        let mut rotation = 1;
+        let mut outs = self.new_outputs();
+        let mut raw_store = self.new_raw_store(rotation)?;
 
-        for trace in trace_receiver {
-            if let Some(out) = &mut out {
-                out.record(&trace)?;
-            }
-            if let Some(raw_store) = &mut raw_store {
-                raw_store.write(&trace)?;
-            }
+        // If aggregation fails or panics, still write out whatever was collected up to that point
+        // so that a long recording isn't lost entirely
+        let aggregated = panic::catch_unwind(AssertUnwindSafe(|| -> Result<(), Error> {
+            let mut pids = HashSet::new();
+            let mut next_rotation = self
+                .rotate_interval
+                .map(|interval| Instant::now() + interval);
+            loop {
+                let received = match next_rotation {
+                    Some(next_rotation) => trace_receiver
+                        .recv_timeout(next_rotation.saturating_duration_since(Instant::now())),
+                    None => trace_receiver
+                        .recv()
+                        .map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                let trace = match received {
+                    Ok(trace) => Some(trace),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                if let (Some(interval), Some(at)) = (self.rotate_interval, next_rotation) {
+                    if Instant::now() >= at {
+                        let finished_outs = match self.rotate_output {
+                            true => std::mem::replace(&mut outs, self.new_outputs()),
+                            false => vec![],
+                        };
+                        self.write_output(finished_outs, raw_store.take(), rotation)?;
+                        rotation += 1;
+                        raw_store = self.new_raw_store(rotation)?;
+                        // Each file gets the command lines of the processes in it
+                        pids.clear();
+                        // If aggregating fell behind, skip the intervals that were missed rather
+                        // than writing an empty file for each of them
+                        let now = Instant::now();
+                        next_rotation = match at + interval {
+                            next if next > now => Some(next),
+                            _ => Some(now + interval),
+                        };
+                    }
+                }
+                let trace = match trace {
+                    Some(trace) => trace,
+                    None => continue,
+                };
+
+                if let Some(raw_store) = &mut raw_store {
+                    // Save each process's command line the first time we see it, so that
+                    // recordings of several processes can tell them apart
+                    if let Some(pid) = trace.pid.filter(|pid| pids.insert(*pid)) {
+                        match command_line(pid) {
+                            Ok(command_line) => raw_store.add_process(pid, command_line)?,
+                            Err(e) => debug!("Couldn't get command line of process {}: {}", pid, e),
+                        }
+                    }
+                    raw_store.write(&trace)?;
+                }
 
-            let mut summary = self.summary.lock().unwrap();
-            summary.add_function_name(&trace.trace);
+                if self.options.filter.hides(&trace) {
+                    continue;
+                }
+                let trace = self.options.filter.apply(&trace);
+                for (i, out) in outs.iter_mut().enumerate() {
+                    match out {
+                        Output::Combined(out) => out.record(&trace)?,
+                        Output::PerThread(outs) => {
+                            let thread =
+                                outs.entry(trace.thread_id).or_insert_with(|| ThreadOutput {
+                                    name: None,
+                                    out: self.outputter(i),
+                                });
+                            if trace.thread_name.is_some() {
+                                thread.name = trace.thread_name.clone();
+                            }
+                            thread.out.record(&trace)?
+                        }
+                        Output::Streamed(stdout) => match self.outputs[i].0 {
+                            crate::core::types::OutputFormat::jsonl => {
+                                writeln!(stdout, "{}", serde_json::to_string(&trace)?)?
+                            }
+                            _ => writeln!(stdout, "{} 1", flamegraph::collapse(&trace.trace))?,
+                        },
+                    }
+                }
+
+                if let Some(live) = &self.live_flamegraph {
+                    live.lock().unwrap().record(&trace.trace)?;
+                }
+
+                let mut summary = self.summary.lock().unwrap();
+                summary.add_function_name(&trace.trace);
+                summary.add_thread(trace.thread_label());
+            }
+            Ok(())
+        }));
+        if !matches!(aggregated, Ok(Ok(()))) {
+            self.stop();
         }
 
         // Finish writing all data to disk
-        if let (Some(out), Some(out_path)) = (&mut out, self.out_path.as_ref()) {
-            if out_path.display().to_string() == "-" {
-                out.complete(&mut std::io::stdout())?;
-            } else {
-                let mut out_file = File::create(&out_path).context(format!(
-                    "Failed to create output file {}",
-                    &out_path.display()
-                ))?;
-                out.complete(&mut out_file)?;
+        let written = self.write_output(outs, raw_store, rotation);
+        match aggregated {
+            Ok(result) => result?,
+            Err(e) => {
+                if let Err(write_error) = written {
+                    eprintln!("Failed to write partial output: {:?}", write_error);
+                }
+                panic::resume_unwind(e);
             }
         }
-        if let Some(raw_store) = raw_store {
-            raw_store.complete();
+        written?;
+
+        thread_results(result_receiver)
+    }
+
+    // Records with deferred symbolization (see `Config::defer_symbolization`). The frames can't
+    // be named here, so the samples only go to the raw output.
+    fn record_deferred(&self) -> Result<(), Error> {
+        if !self.outputs.is_empty() || self.live_flamegraph.is_some() {
+            return Err(format_err!(
+                "Deferred symbolization can only write raw output; use `rbspy report` to format it"
+            ));
         }
+        if self.rotate_interval.is_some() {
+            return Err(format_err!(
+                "Deferred symbolization can't be combined with rotating the raw output"
+            ));
+        }
+        let raw_path = self
+            .raw_path
+            .as_ref()
+            .ok_or_else(|| format_err!("Deferred symbolization needs a raw output path"))?;
 
-        // Check for errors from the child threads. Ignore errors unless every single thread
-        // returned an error. If that happens, return the last error. This lets rbspy successfully
-        // record processes even if the parent thread isn't a Ruby process.
-        let mut num_ok = 0;
-        let mut last_result = Ok(());
-        for result in result_receiver {
-            if result.is_ok() {
-                num_ok += 1;
+        let (sample_sender, sample_receiver) = std::sync::mpsc::sync_channel(100);
+        let (result_sender, result_receiver) = std::sync::mpsc::channel();
+        self.sampler.start_deferred(sample_sender, result_sender)?;
+
+        let mut raw_store = Store::new_deferred(raw_path, self.sample_rate, self.raw_compression)?;
+        let mut pids = HashSet::new();
+        let written = (|| -> Result<(), Error> {
+            for sample in sample_receiver {
+                let trace = &sample.trace.trace;
+                if let Some(pid) = trace.pid.filter(|pid| pids.insert(*pid)) {
+                    match command_line(pid) {
+                        Ok(command_line) => raw_store.add_process(pid, command_line)?,
+                        Err(e) => debug!("Couldn't get command line of process {}: {}", pid, e),
+                    }
+                }
+                raw_store.write_deferred(&sample)?;
+                self.summary
+                    .lock()
+                    .unwrap()
+                    .add_thread(trace.thread_label());
             }
-            last_result = result;
+            Ok(())
+        })();
+        if written.is_err() {
+            self.stop();
+        }
+        // Finish writing whatever was recorded, even if writing a sample failed
+        let completed = raw_store.complete();
+        written?;
+        completed?;
+
+        thread_results(result_receiver)
+    }
+
+    fn new_outputs(&self) -> Vec<Output> {
+        (0..self.outputs.len())
+            .map(|i| match self.per_thread {
+                true => Output::PerThread(HashMap::new()),
+                false if self.streams_to_stdout(i) => Output::Streamed(std::io::stdout()),
+                false => Output::Combined(self.outputter(i)),
+            })
+            .collect()
+    }
+
+    fn new_raw_store(&self, rotation: usize) -> Result<Option<Store>, Error> {
+        match self.raw_path(rotation) {
+            Some(raw_path) => Ok(Some(Store::new(
+                &raw_path,
+                self.sample_rate,
+                self.raw_compression,
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    // The path of the raw output file for the `rotation`th interval
+    fn raw_path(&self, rotation: usize) -> Option<PathBuf> {
+        let raw_path = self.raw_path.as_ref()?;
+        Some(match self.rotate_interval {
+            Some(_) => rotated_path(
+                raw_path,
+                &format!("{:04}", rotation),
+                self.raw_compression.extension(),
+            ),
+            None => raw_path.clone(),
+        })
+    }
+
+    // The path of the `i`th formatted output file for the `rotation`th interval
+    fn out_path(&self, i: usize, rotation: usize) -> PathBuf {
+        let (format, out_path) = &self.outputs[i];
+        match self.rotate_interval.is_some() && self.rotate_output {
+            true => rotated_path(out_path, &format!("{:04}", rotation), &format.extension()),
+            false => out_path.clone(),
         }
+    }
+
+    // Creates an outputter for the `i`th formatted output
+    fn outputter(&self, i: usize) -> Box<dyn Outputter> {
+        if let (0, Some(outputter)) = (i, &self.outputter) {
+            return outputter();
+        }
+        self.outputs[i].0.clone().outputter(
+            self.flame_min_width,
+            &self.options,
+            Some(self.sample_rate),
+        )
+    }
+
+    // Collapsed stacks and JSON lines don't need to be aggregated to be useful, so when they're
+    // written to standard output, each trace is written as soon as it arrives (with a count of 1,
+    // for collapsed stacks)
+    fn streams_to_stdout(&self, i: usize) -> bool {
+        let (format, out_path) = &self.outputs[i];
+        !(i == 0 && self.outputter.is_some())
+            && matches!(
+                format,
+                crate::core::types::OutputFormat::collapsed
+                    | crate::core::types::OutputFormat::jsonl
+            )
+            && out_path == Path::new("-")
+    }
 
-        match num_ok {
-            0 => last_result,
-            _ => Ok(()),
+    fn write_output(
+        &self,
+        outs: Vec<Output>,
+        raw_store: Option<Store>,
+        rotation: usize,
+    ) -> Result<(), Error> {
+        for (i, out) in outs.into_iter().enumerate() {
+            let out_path = self.out_path(i, rotation);
+            match out {
+                Output::Combined(out) => write_file(out, &out_path)?,
+                Output::PerThread(outs) => {
+                    for (thread_id, ThreadOutput { name, out }) in outs {
+                        let mut label = match thread_id {
+                            Some(thread_id) => thread_id.to_string(),
+                            None => "unknown".to_string(),
+                        };
+                        if let Some(name) = name {
+                            // Thread names can contain anything, so keep only the characters that are
+                            // safe to use in file names
+                            let name: String = name
+                                .chars()
+                                .map(|c| match c {
+                                    'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+                                    _ => '_',
+                                })
+                                .collect();
+                            label = format!("{}-{}", label, name);
+                        }
+                        write_file(out, &thread_output_path(&out_path, &label))?;
+                    }
+                }
+                Output::Streamed(_) => {}
+            }
         }
+        if let Some(raw_store) = raw_store {
+            raw_store.complete()?;
+        }
+        Ok(())
     }
 
     /// Stops the recorder
@@ -152,8 +615,100 @@ impl Recorder {
         self.sampler.stop();
     }
 
+    /// Samples at the burst rate for a while. See `Config::burst_rate`.
+    pub fn burst(&self) {
+        self.sampler.burst();
+    }
+
+    /// Writes a flamegraph of the traces collected so far. Returns `Ok(false)` without writing
+    /// anything if there aren't any traces yet. Requires `Config::live_flamegraph`.
+    pub fn write_flamegraph(&self, w: &mut dyn std::io::Write, collapsed: bool) -> Result<bool> {
+        let live = self.live_flamegraph.as_ref().ok_or_else(|| {
+            format_err!("The recorder wasn't configured to keep a live flamegraph")
+        })?;
+        // Copy the counts so that traces can keep being aggregated while the flamegraph is drawn
+        let stats = live.lock().unwrap().clone();
+        if stats.is_empty() {
+            return Ok(false);
+        }
+        match collapsed {
+            true => stats.write_collapsed(w)?,
+            false => stats.write_flamegraph(
+                w,
+                self.flame_min_width,
+                self.options.title.as_deref(),
+                self.options.subtitle.as_deref(),
+                self.options.reverse,
+            )?,
+        }
+        Ok(true)
+    }
+
+    /// The functions with the most samples so far, for exporting to a metrics system
+    pub fn top_functions(&self, n: usize) -> Vec<summary::FunctionStats> {
+        self.summary.lock().unwrap().top_functions(n)
+    }
+
+    /// How recording is going so far, for tools that run rbspy and want to show its progress
+    pub fn progress(&self) -> Progress {
+        let summary = self.summary.lock().unwrap();
+        Progress {
+            elapsed_secs: summary.elapsed_time().as_secs_f64(),
+            total_traces: self.sampler.total_traces(),
+            timing_errors: self.sampler.timing_error_traces(),
+            top_function: summary.top_functions(1).into_iter().next(),
+        }
+    }
+
     /// Writes a summary of collected traces
     pub fn write_summary(&self, w: &mut dyn std::io::Write) -> Result<(), Error> {
+        self.write_summary_after(w, false)
+    }
+
+    /// Writes a summary of all of the collected traces, for when recording has stopped
+    pub fn write_final_summary(&self, w: &mut dyn std::io::Write) -> Result<(), Error> {
+        self.write_summary_after(w, true)
+    }
+
+    /// Writes counters that describe how well sampling went, such as how many stack traces
+    /// couldn't be read and why, and how long reading them took. These are mostly useful in bug
+    /// reports.
+    pub fn write_diagnostics(&self, w: &mut dyn std::io::Write) -> Result<(), Error> {
+        let diagnostics = self.sampler.diagnostics();
+        writeln!(w, "Diagnostics:")?;
+        writeln!(w, "  Samples: {}", self.sampler.total_traces())?;
+        writeln!(
+            w,
+            "  Samples taken late: {}",
+            self.sampler.timing_error_traces()
+        )?;
+        writeln!(
+            w,
+            "  Stack traces dropped: {}",
+            diagnostics.errors.values().sum::<usize>()
+        )?;
+        for (kind, count) in &diagnostics.errors {
+            writeln!(w, "    {}: {}", kind, count)?;
+        }
+        writeln!(w, "  Lock retries: {}", diagnostics.lock_retries)?;
+        writeln!(w, "  Re-attaches: {}", diagnostics.reattaches)?;
+        let cost = |percentile| match self.sampler.sample_cost(percentile) {
+            Some(cost) => format!("{:.3}ms", cost.as_secs_f64() * 1000.0),
+            None => "-".to_string(),
+        };
+        writeln!(
+            w,
+            "  Sample cost over {} stack traces: {} median, {} 90th percentile, {} 99th percentile, {} max",
+            self.sampler.sample_cost_count(),
+            cost(0.5),
+            cost(0.9),
+            cost(0.99),
+            cost(1.0)
+        )?;
+        Ok(())
+    }
+
+    fn write_summary_after(&self, w: &mut dyn std::io::Write, stopped: bool) -> Result<(), Error> {
         let width = match terminal_size::terminal_size() {
             Some((w, _)) => Some(w.0 as usize),
             None => None,
@@ -163,15 +718,33 @@ impl Recorder {
         let percent_timing_error = (timing_error_traces as f64) / (total_traces as f64) * 100.0;
 
         let summary = self.summary.lock().unwrap();
-        writeln!(
-            w,
-            "Time since start: {}s. Press Ctrl+C to stop.",
-            summary.elapsed_time().as_secs()
-        )?;
-
-        writeln!(w, "Summary of profiling data so far:")?;
+        if stopped {
+            writeln!(w, "Recorded for {}s.", summary.elapsed_time().as_secs())?;
+            writeln!(w, "Summary of profiling data:")?;
+        } else {
+            writeln!(
+                w,
+                "Time since start: {}s. Press Ctrl+C to stop.",
+                summary.elapsed_time().as_secs()
+            )?;
+            writeln!(w, "Summary of profiling data so far:")?;
+        }
         summary.write_top_n(w, 20, width)?;
+        summary.write_top_threads(w, 5)?;
 
+        if stopped {
+            if let (Some(median), Some(p99)) = (
+                self.sampler.sample_cost(0.5),
+                self.sampler.sample_cost(0.99),
+            ) {
+                writeln!(
+                    w,
+                    "Median sample cost: {:.2}ms (99th percentile: {:.2}ms).",
+                    median.as_secs_f64() * 1000.0,
+                    p99.as_secs_f64() * 1000.0
+                )?;
+            }
+        }
         if total_traces > 100 && percent_timing_error > 0.5 {
             // Only include this warning if timing errors are more than 0.5% of total traces. rbspy
             // is a statistical profiler, so smaller differences don't really matter.
@@ -181,8 +754,221 @@ impl Recorder {
     }
 }
 
+/// A snapshot of how recording is going. See `Recorder::progress`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Progress {
+    /// How long the recorder has been running
+    pub elapsed_secs: f64,
+    /// How many times the sampler has tried to take a sample, across all processes
+    pub total_traces: usize,
+    /// How many of those samples were taken late because the sampler couldn't keep up
+    pub timing_errors: usize,
+    /// The function that was running in the most samples, if any have been taken
+    pub top_function: Option<summary::FunctionStats>,
+}
+
+// Formatted output, either for all traces or for each thread separately
+enum Output {
+    Combined(Box<dyn Outputter>),
+    PerThread(HashMap<Option<u64>, ThreadOutput>),
+    // Collapsed stacks or JSON lines, written to standard output as the traces arrive
+    Streamed(std::io::Stdout),
+}
+
+struct ThreadOutput {
+    // The thread's most recent name, since threads can be renamed while they run
+    name: Option<String>,
+    out: Box<dyn Outputter>,
+}
+
+// Checks the results of the sampler's threads. Errors are ignored unless every single thread
+// returned an error, in which case the last error is returned. This lets rbspy successfully record
+// processes even if the parent thread isn't a Ruby process.
+fn thread_results(
+    result_receiver: std::sync::mpsc::Receiver<Result<(), Error>>,
+) -> Result<(), Error> {
+    let mut num_ok = 0;
+    let mut last_result = Ok(());
+    for result in result_receiver {
+        if result.is_ok() {
+            num_ok += 1;
+        }
+        last_result = result;
+    }
+
+    match num_ok {
+        0 => last_result,
+        _ => Ok(()),
+    }
+}
+
+fn write_file(mut out: Box<dyn Outputter>, out_path: &Path) -> Result<(), Error> {
+    if out_path.display().to_string() == "-" {
+        out.complete(&mut std::io::stdout())?;
+    } else {
+        let mut out_file = File::create(out_path).context(format!(
+            "Failed to create output file {}",
+            &out_path.display()
+        ))?;
+        out.complete(&mut out_file)?;
+    }
+    Ok(())
+}
+
+/// Returns the path of the per-thread output file for thread `label` (a thread ID, optionally
+/// followed by the thread's name, or `unknown`), e.g. `out.tid-12345.svg` for `out.svg`.
+pub fn thread_output_path(out_path: &Path, label: &str) -> PathBuf {
+    let stem = out_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match out_path.extension() {
+        Some(extension) => format!("{}.tid-{}.{}", stem, label, extension.to_string_lossy()),
+        None => format!("{}.tid-{}", stem, label),
+    };
+    out_path.with_file_name(name)
+}
+
+/// Returns the path of the file for the `label`th rotation (see `Config::rotate_interval`), which
+/// goes before `extension` if `path` ends with it, e.g. `out.0001.raw.gz` for `out.raw.gz` and
+/// `raw.gz`, or before the last extension otherwise.
+pub fn rotated_path(path: &Path, label: &str, extension: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match name.strip_suffix(&format!(".{}", extension)) {
+        Some(stem) => format!("{}.{}.{}", stem, label, extension),
+        None => match name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => {
+                format!("{}.{}.{}", stem, label, extension)
+            }
+            _ => format!("{}.{}", name, label),
+        },
+    };
+    path.with_file_name(name)
+}
+
 impl Drop for Recorder {
     fn drop(&mut self) {
         self.stop();
     }
 }
+
+fn command_line(pid: crate::core::process::Pid) -> Result<Vec<String>, Error> {
+    Ok(crate::core::process::Process::new(pid)?.cmdline()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::recorder::record::*;
+
+    #[test]
+    fn test_thread_output_path() {
+        assert_eq!(
+            thread_output_path(Path::new("/tmp/out.svg"), "12345"),
+            PathBuf::from("/tmp/out.tid-12345.svg")
+        );
+        assert_eq!(
+            thread_output_path(Path::new("profile.flamegraph.svg"), "unknown"),
+            PathBuf::from("profile.flamegraph.tid-unknown.svg")
+        );
+        assert_eq!(
+            thread_output_path(Path::new("out"), "7"),
+            PathBuf::from("out.tid-7")
+        );
+    }
+
+    #[test]
+    fn test_rotated_path() {
+        assert_eq!(
+            rotated_path(Path::new("/tmp/out.raw.gz"), "0001", "raw.gz"),
+            PathBuf::from("/tmp/out.0001.raw.gz")
+        );
+        assert_eq!(
+            rotated_path(Path::new("profile.svg"), "0012", "flamegraph.svg"),
+            PathBuf::from("profile.0012.svg")
+        );
+        assert_eq!(
+            rotated_path(Path::new("out"), "0003", "raw"),
+            PathBuf::from("out.0003")
+        );
+    }
+
+    #[test]
+    fn test_progress_json() {
+        let progress = Progress {
+            elapsed_secs: 1.5,
+            total_traces: 150,
+            timing_errors: 2,
+            top_function: Some(summary::FunctionStats {
+                name: "work - app.rb".to_string(),
+                self_percent: 40.0,
+                total_percent: 90.0,
+            }),
+        };
+        assert_eq!(
+            serde_json::to_string(&progress).unwrap(),
+            r#"{"elapsed_secs":1.5,"total_traces":150,"timing_errors":2,"top_function":{"name":"work - app.rb","self_percent":40.0,"total_percent":90.0}}"#
+        );
+    }
+
+    struct CountTraces(usize);
+
+    impl Outputter for CountTraces {
+        fn record(&mut self, _stack: &crate::core::types::StackTrace) -> Result<()> {
+            self.0 += 1;
+            Ok(())
+        }
+
+        fn complete(&mut self, write: &mut dyn std::io::Write) -> Result<()> {
+            writeln!(write, "{} traces", self.0)?;
+            Ok(())
+        }
+    }
+
+    fn config() -> Config {
+        Config {
+            out_path: Some(PathBuf::from("-")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_custom_outputter() {
+        let recorder = Recorder::new(Config {
+            outputter: Some(Box::new(|| Box::new(CountTraces(0)))),
+            ..config()
+        });
+        let mut out = recorder.outputter(0);
+        let trace = crate::core::types::StackTrace::from(vec![]);
+        out.record(&trace).unwrap();
+        out.record(&trace).unwrap();
+        let mut written = vec![];
+        out.complete(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), "2 traces\n");
+    }
+
+    #[test]
+    fn test_colliding_output_paths() {
+        use crate::core::types::OutputFormat;
+
+        let recorder = Recorder::new(Config {
+            out_path: Some(PathBuf::from("out.txt")),
+            extra_outputs: vec![(OutputFormat::summary, PathBuf::from("out.txt"))],
+            ..config()
+        });
+        let err = recorder.record().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "More than one output would be written to out.txt"
+        );
+
+        let recorder = Recorder::new(Config {
+            raw_path: Some(PathBuf::from("out.raw.gz")),
+            out_path: Some(PathBuf::from("out.raw.gz")),
+            ..config()
+        });
+        assert!(recorder.record().is_err());
+    }
+}
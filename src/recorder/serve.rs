@@ -0,0 +1,114 @@
+use anyhow::{Context, Result};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::recorder::Recorder;
+
+/// Serves the flamegraph of the traces collected so far over HTTP at `address` (e.g.
+/// `127.0.0.1:8080`), so that it can be watched in a browser while recording. `/` returns the
+/// flamegraph as SVG and `/collapsed` returns the collapsed stacks. The recorder must have been
+/// created with `RecordConfig::live_flamegraph`.
+///
+/// The server runs on a background thread until the process exits. Binding to the address
+/// happens before this returns, so that a bad address is reported right away.
+pub fn serve(recorder: Arc<Recorder>, address: &str) -> Result<()> {
+    let listener =
+        TcpListener::bind(address).context(format!("Failed to listen on {}", address))?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            // Requests are handled one at a time, which is plenty for someone watching a
+            // recording, and keeps a busy browser from competing with the recorder for CPU
+            let result = stream
+                .map_err(anyhow::Error::from)
+                .and_then(|stream| respond(&recorder, stream));
+            if let Err(e) = result {
+                debug!("Failed to serve flamegraph: {:?}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn respond(recorder: &Recorder, mut stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    // Only the request line matters, and it fits in the first read from any browser or curl
+    let mut request = [0; 4096];
+    let len = stream.read(&mut request)?;
+    let request = String::from_utf8_lossy(&request[..len]);
+    let mut parts = request.lines().next().unwrap_or_default().split(' ');
+    let (method, path) = (parts.next(), parts.next());
+
+    let mut body = vec![];
+    let (status, content_type) = match (method, path) {
+        (Some("GET"), Some(path @ "/")) | (Some("GET"), Some(path @ "/collapsed")) => {
+            let collapsed = path == "/collapsed";
+            if recorder.write_flamegraph(&mut body, collapsed)? {
+                let content_type = match collapsed {
+                    true => "text/plain; charset=utf-8",
+                    false => "image/svg+xml",
+                };
+                ("200 OK", content_type)
+            } else {
+                body = b"No samples have been collected yet\n".to_vec();
+                ("503 Service Unavailable", "text/plain; charset=utf-8")
+            }
+        }
+        (Some("GET"), _) => {
+            body = b"Not found. Try / or /collapsed\n".to_vec();
+            ("404 Not Found", "text/plain; charset=utf-8")
+        }
+        _ => ("405 Method Not Allowed", "text/plain; charset=utf-8"),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::recorder::serve::*;
+    use crate::recorder::RecordConfig;
+
+    fn recorder() -> Recorder {
+        Recorder::new(RecordConfig {
+            live_flamegraph: true,
+            ..Default::default()
+        })
+    }
+
+    fn get(recorder: &Recorder, request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        respond(recorder, stream).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_responses() {
+        let recorder = recorder();
+        let response = get(&recorder, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.ends_with("\r\n\r\nNo samples have been collected yet\n"));
+        let response = get(&recorder, "GET /other HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let response = get(&recorder, "POST / HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+
+    #[test]
+    fn test_serve_bad_address() {
+        assert!(serve(Arc::new(recorder()), "not an address").is_err());
+    }
+}
@@ -1,14 +1,219 @@
 use crate::core::process::Pid;
-use crate::core::ruby_spy::RubySpy;
-use crate::core::types::StackTrace;
+use crate::core::ruby_spy::{RubySpy, RubySpyConfig};
+use crate::core::types::{FrameLocals, MemoryCopyError, StackTrace};
 use anyhow::{Error, Result};
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
 
 /// Captures a single trace from the process belonging to `pid`
 pub fn snapshot(
     pid: Pid,
     lock_process: bool,
     force_version: Option<String>,
+    ruby_binary: Option<PathBuf>,
     on_cpu_only: bool,
+    idle_samples: bool,
 ) -> Result<Option<StackTrace>, Error> {
-    RubySpy::retry_new(pid, 10, force_version, on_cpu_only)?.get_stack_trace(lock_process)
+    Snapshotter::new(
+        pid,
+        force_version,
+        ruby_binary,
+        on_cpu_only,
+        idle_samples,
+        vec![],
+    )?
+    .snapshot(lock_process)
+}
+
+/// A trace captured by `Snapshotter::snapshot_with_locals`, along with the local variables of
+/// each of its frames
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct SnapshotWithLocals {
+    #[serde(flatten)]
+    pub trace: StackTrace,
+    /// The locals of each frame, in the same order as `trace.trace`
+    pub locals: FrameLocals,
+}
+
+impl fmt::Display for SnapshotWithLocals {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frames = self.trace.iter().zip(self.locals.iter()).rev();
+        for (i, (frame, locals)) in frames.enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", frame)?;
+            for local in locals {
+                write!(f, "\n    {} = {}", local.name, local.value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A line that says which thread a snapshot was taken from and what it was doing, e.g.
+/// `Thread 1234 "worker" (runnable)`, since rbspy only reads the thread that's running Ruby code
+/// (or last ran it), which may not be the one that was expected. The status is only known if the
+/// snapshot checked whether the thread was running.
+pub fn thread_header(trace: &StackTrace) -> String {
+    let id = trace
+        .thread_id
+        .map_or("unknown".to_string(), |id| id.to_string());
+    let name = match trace.thread_name {
+        Some(ref name) => format!(" {:?}", name),
+        None => "".to_string(),
+    };
+    let status = match trace.on_cpu {
+        Some(true) => "runnable",
+        Some(false) => "sleeping",
+        None => "status not checked",
+    };
+    format!("Thread {}{} ({})", id, name, status)
+}
+
+/// Captures traces from a process on demand. Finding the Ruby version and VM addresses is the
+/// expensive part of taking a snapshot, so it's only done once, when the snapshotter is created.
+pub struct Snapshotter {
+    spy: RubySpy,
+    thread_ids: Vec<u64>,
+    // Whether traces' `on_cpu` says if the thread was running, rather than being a placeholder
+    checks_status: bool,
+}
+
+impl Snapshotter {
+    pub fn new(
+        pid: Pid,
+        force_version: Option<String>,
+        ruby_binary: Option<PathBuf>,
+        on_cpu_only: bool,
+        idle_samples: bool,
+        thread_ids: Vec<u64>,
+    ) -> Result<Self, Error> {
+        let spy = RubySpy::retry_new(
+            pid,
+            crate::sampler::DEFAULT_ATTACH_TIMEOUT,
+            &RubySpyConfig {
+                force_version,
+                ruby_binary,
+                on_cpu_only,
+                idle_samples,
+                ..Default::default()
+            },
+        )?;
+        Ok(Self {
+            spy,
+            thread_ids,
+            checks_status: on_cpu_only || idle_samples,
+        })
+    }
+
+    /// Captures a single trace. Returns `None` if the thread that's running Ruby code isn't one
+    /// of the snapshotter's `thread_ids`.
+    pub fn snapshot(&mut self, lock_process: bool) -> Result<Option<StackTrace>, Error> {
+        Ok(self
+            .spy
+            .get_stack_trace(lock_process)?
+            .filter(|trace| trace.is_from_threads(&self.thread_ids))
+            .map(|trace| self.with_status(trace)))
+    }
+
+    /// Like `snapshot`, but also reads the local variables of each of the trace's Ruby frames.
+    /// This is experimental and best-effort, and is only supported for Ruby 3.x.
+    pub fn snapshot_with_locals(
+        &mut self,
+        lock_process: bool,
+    ) -> Result<Option<SnapshotWithLocals>, Error> {
+        Ok(self
+            .spy
+            .get_stack_trace_with_locals(lock_process)?
+            .filter(|(trace, _)| trace.is_from_threads(&self.thread_ids))
+            .map(|(trace, locals)| SnapshotWithLocals {
+                trace: self.with_status(trace),
+                locals,
+            }))
+    }
+
+    // Unless `on_cpu_only` or `idle_samples` is set, the thread's status isn't read, so `on_cpu`
+    // doesn't mean anything
+    fn with_status(&self, trace: StackTrace) -> StackTrace {
+        match self.checks_status {
+            true => trace,
+            false => StackTrace {
+                on_cpu: None,
+                ..trace
+            },
+        }
+    }
+
+    /// Captures `count` traces, waiting `interval` between them. If the process exits part way
+    /// through, the traces captured so far are returned.
+    pub fn snapshots(
+        &mut self,
+        lock_process: bool,
+        count: usize,
+        interval: Duration,
+    ) -> Result<Vec<Option<StackTrace>>, Error> {
+        self.repeat(count, interval, |s| s.snapshot(lock_process))
+    }
+
+    /// Like `snapshots`, but with the local variables of each frame
+    pub fn snapshots_with_locals(
+        &mut self,
+        lock_process: bool,
+        count: usize,
+        interval: Duration,
+    ) -> Result<Vec<Option<SnapshotWithLocals>>, Error> {
+        self.repeat(count, interval, |s| s.snapshot_with_locals(lock_process))
+    }
+
+    fn repeat<S>(
+        &mut self,
+        count: usize,
+        interval: Duration,
+        mut take: impl FnMut(&mut Self) -> Result<Option<S>, Error>,
+    ) -> Result<Vec<Option<S>>, Error> {
+        let mut traces = Vec::with_capacity(count);
+        for i in 0..count {
+            if i > 0 {
+                std::thread::sleep(interval);
+            }
+            match take(self) {
+                Ok(trace) => traces.push(trace),
+                Err(e) => {
+                    if let Some(MemoryCopyError::ProcessEnded) = e.downcast_ref() {
+                        if !traces.is_empty() {
+                            debug!("Process ended after {} snapshots", traces.len());
+                            break;
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(traces)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::types::StackTrace;
+    use crate::recorder::snapshot::thread_header;
+
+    #[test]
+    fn test_thread_header() {
+        let mut trace = StackTrace::new_empty();
+        assert_eq!(thread_header(&trace), "Thread unknown (status not checked)");
+
+        trace.thread_id = Some(1234);
+        trace.on_cpu = Some(true);
+        assert_eq!(thread_header(&trace), "Thread 1234 (runnable)");
+
+        trace.thread_name = Some("puma worker 1".to_string());
+        trace.on_cpu = Some(false);
+        assert_eq!(
+            thread_header(&trace),
+            "Thread 1234 \"puma worker 1\" (sleeping)"
+        );
+    }
 }
@@ -1,59 +1,159 @@
-use anyhow::{Context, Error, Result};
-use std::collections::HashSet;
+use anyhow::{format_err, Context, Error, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::{Sender, SyncSender};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 #[cfg(windows)]
 use winapi::um::timeapi;
 
-use crate::core::process::{Pid, Process, ProcessRetry};
-use crate::core::types::{MemoryCopyError, StackTrace};
+use crate::core::process::{LockFailed, Pid, Process, ProcessRetry};
+use crate::core::ruby_spy::{RubySpy, RubySpyConfig};
+use crate::core::types::{DeferredSample, MemoryCopyError, StackFrame, StackTrace};
 
 #[derive(Debug)]
 pub struct Sampler {
-    done: Arc<AtomicBool>,
-    lock_process: bool,
     root_pid: Pid,
-    sample_rate: u32,
-    time_limit: Option<Duration>,
+    config: SamplerConfig,
+    state: SharedState,
+}
+
+/// Options for a `Sampler`
+#[derive(Clone, Debug)]
+pub struct SamplerConfig {
+    /// The number of traces that should be collected each second. Default: `100`.
+    pub sample_rate: u32,
+    /// Whether to pause the process while each trace is read. Default: `true`.
+    pub lock_process: bool,
+    /// How many more times to try locking the process when it fails. Default: `0`.
+    pub lock_retries: u32,
+    /// How long to sample for. Default: none (until the process exits or `stop` is called).
+    pub time_limit: Option<Duration>,
+    /// The number of traces after which sampling stops, across all processes. Default: none.
+    pub max_samples: Option<usize>,
+    /// Whether to sample the process's child processes too. Default: `false`.
+    pub with_subprocesses: bool,
+    /// How often to look for new child processes. Default: 1 second.
+    pub subprocess_poll_interval: Duration,
+    /// Assume that the process runs this Ruby version instead of detecting it. Default: none.
+    pub force_version: Option<String>,
+    /// Read symbols only from this ruby binary or libruby. Default: none.
+    pub ruby_binary: Option<PathBuf>,
+    /// Only keep traces that were sampled while the process was using the CPU. Default: `false`.
+    pub on_cpu_only: bool,
+    /// Send an `(idle)` trace for samples taken while the process wasn't using the CPU.
+    /// Default: `false`.
+    pub idle_samples: bool,
+    /// Decide whether the process is on the CPU from the OS alone, without checking the Ruby
+    /// thread's status. Default: `false`.
+    pub force_on_cpu_samples: bool,
+    /// How long to keep trying to attach to the process. Default: `DEFAULT_ATTACH_TIMEOUT`.
+    pub attach_timeout: Duration,
+    /// The sample rate during a burst (see `Sampler::burst`). Default: none (no bursts).
+    pub burst_rate: Option<u32>,
+    /// How long a burst lasts. Default: 10 seconds.
+    pub burst_duration: Duration,
+    /// Lower the sample rate while the process is idle. Default: `false`.
+    pub adaptive: bool,
+    /// Only keep traces from these OS thread IDs. Default: empty (all threads).
+    pub thread_ids: Vec<u64>,
+    /// Read every Ruby thread on each sample. Default: `false`.
+    pub all_threads: bool,
+    /// Read the process's resident set size on each sample. Default: `false`.
+    pub with_rss: bool,
+    /// How many stack traces can fail to be read before sampling a process stops with an error.
+    /// Default: `ErrorTolerance::default()`.
+    pub error_tolerance: ErrorTolerance,
+}
+
+impl Default for SamplerConfig {
+    fn default() -> Self {
+        SamplerConfig {
+            sample_rate: 100,
+            lock_process: true,
+            lock_retries: 0,
+            time_limit: None,
+            max_samples: None,
+            with_subprocesses: false,
+            subprocess_poll_interval: Duration::from_secs(1),
+            force_version: None,
+            ruby_binary: None,
+            on_cpu_only: false,
+            idle_samples: false,
+            force_on_cpu_samples: false,
+            attach_timeout: DEFAULT_ATTACH_TIMEOUT,
+            burst_rate: None,
+            burst_duration: Duration::from_secs(10),
+            adaptive: false,
+            thread_ids: vec![],
+            all_threads: false,
+            with_rss: false,
+            error_tolerance: ErrorTolerance::default(),
+        }
+    }
+}
+
+// What the `Sampler` shares with its sampling threads
+#[derive(Clone, Debug, Default)]
+struct SharedState {
+    done: Arc<AtomicBool>,
+    // The number of traces sent so far, for `max_samples`
+    samples: Arc<AtomicUsize>,
     timing_error_traces: Arc<AtomicUsize>,
     total_traces: Arc<AtomicUsize>,
-    with_subprocesses: bool,
-    force_version: Option<String>,
-    on_cpu_only: bool,
+    sample_costs: Arc<Mutex<SampleCosts>>,
+    diagnostics: Arc<Mutex<Diagnostics>>,
+    burst_until: Arc<Mutex<Option<Instant>>>,
 }
 
 impl Sampler {
-    pub fn new(
-        pid: Pid,
-        sample_rate: u32,
-        lock_process: bool,
-        time_limit: Option<Duration>,
-        with_subprocesses: bool,
-        force_version: Option<String>,
-        on_cpu_only: bool,
-    ) -> Self {
+    pub fn new(pid: Pid, config: SamplerConfig) -> Self {
         Sampler {
-            done: Arc::new(AtomicBool::new(false)),
-            lock_process,
             root_pid: pid,
-            sample_rate,
-            time_limit,
-            timing_error_traces: Arc::new(AtomicUsize::new(0)),
-            total_traces: Arc::new(AtomicUsize::new(0)),
-            with_subprocesses,
-            force_version,
-            on_cpu_only,
+            config,
+            state: SharedState::default(),
         }
     }
 
     pub fn total_traces(&self) -> usize {
-        self.total_traces.load(Ordering::Relaxed)
+        self.state.total_traces.load(Ordering::Relaxed)
     }
 
     pub fn timing_error_traces(&self) -> usize {
-        self.timing_error_traces.load(Ordering::Relaxed)
+        self.state.timing_error_traces.load(Ordering::Relaxed)
+    }
+
+    /// Estimates how long reading a stack trace takes, as the given percentile (from 0 to 1) of
+    /// all of the reads so far. Returns `None` until a stack trace has been read.
+    pub fn sample_cost(&self, percentile: f64) -> Option<Duration> {
+        self.state
+            .sample_costs
+            .lock()
+            .unwrap()
+            .percentile(percentile)
+    }
+
+    /// How many stack traces have been read so far, which is the number of measurements behind
+    /// `sample_cost`
+    pub fn sample_cost_count(&self) -> u64 {
+        self.state.sample_costs.lock().unwrap().count
+    }
+
+    /// Counters describing what went wrong while sampling, across all of the sampled processes
+    pub fn diagnostics(&self) -> Diagnostics {
+        self.state.diagnostics.lock().unwrap().clone()
+    }
+
+    /// Temporarily samples at the burst rate for the configured burst duration, after which
+    /// sampling returns to the baseline rate. Calling this during a burst extends it. Does nothing
+    /// if no burst rate was configured.
+    pub fn burst(&self) {
+        if self.config.burst_rate.is_none() {
+            warn!("Ignoring burst request because no burst rate is configured");
+            return;
+        }
+        *self.state.burst_until.lock().unwrap() = Some(Instant::now() + self.config.burst_duration);
     }
 
     /// Start thread(s) recording a PID and possibly its children. Tracks new processes
@@ -63,24 +163,50 @@ impl Sampler {
         trace_sender: SyncSender<StackTrace>,
         result_sender: Sender<Result<(), Error>>,
     ) -> Result<(), Error> {
-        let done = self.done.clone();
-        let root_pid = self.root_pid.clone();
-        let sample_rate = self.sample_rate.clone();
-        let maybe_stop_time = match self.time_limit {
-            Some(duration) => Some(std::time::Instant::now() + duration),
-            None => None,
-        };
-        let lock_process = self.lock_process.clone();
-        let force_version = self.force_version.clone();
-        let on_cpu_only = self.on_cpu_only.clone();
-        let result_sender = result_sender.clone();
-        let timing_error_traces = self.timing_error_traces.clone();
-        let total_traces = self.total_traces.clone();
-
-        if self.with_subprocesses {
+        self.start_sending(trace_sender, result_sender)
+    }
+
+    /// Like `start`, but with deferred symbolization: the traces' frames aren't named while
+    /// sampling, which makes each sample much cheaper to take. Each `DeferredSample` has the
+    /// addresses of its trace's frames, along with the memory that's needed to name the frames
+    /// that haven't been seen before. Only supported on Linux, and can't be combined with
+    /// `all_threads` or `adaptive`.
+    pub fn start_deferred(
+        &self,
+        sample_sender: SyncSender<DeferredSample>,
+        result_sender: Sender<Result<(), Error>>,
+    ) -> Result<(), Error> {
+        if !cfg!(target_os = "linux") {
+            return Err(format_err!(
+                "Deferred symbolization is only supported on Linux"
+            ));
+        }
+        if self.config.all_threads || self.config.adaptive {
+            return Err(format_err!(
+                "Deferred symbolization can't be combined with sampling all threads or adaptive sampling"
+            ));
+        }
+        self.start_sending(sample_sender, result_sender)
+    }
+
+    fn start_sending<S: Sample>(
+        &self,
+        trace_sender: SyncSender<S>,
+        result_sender: Sender<Result<(), Error>>,
+    ) -> Result<(), Error> {
+        let root_pid = self.root_pid;
+        let config = self.config.clone();
+        let state = self.state.clone();
+        let maybe_stop_time = self
+            .config
+            .time_limit
+            .map(|duration| Instant::now() + duration);
+
+        if self.config.with_subprocesses {
             // Start a thread which watches for new descendents and starts new recorders when they
             // appear
-            let done_clone = self.done.clone();
+            let done = self.state.done.clone();
+            let poll_interval = self.config.subprocess_poll_interval;
             std::thread::spawn(move || {
                 let process = Process::new_with_retry(root_pid)
                     .expect("couldn't attach to process (is it running?)");
@@ -88,7 +214,7 @@ impl Sampler {
                 // we need to exit this loop when the process we're monitoring exits, otherwise the
                 // sender channels won't get closed and rbspy will hang. So we check the done
                 // mutex.
-                while !done_clone.load(Ordering::Relaxed) {
+                while !done.load(Ordering::Relaxed) {
                     let mut descendents: Vec<Pid> = process
                         .child_processes()
                         .expect("Error finding descendents of pid")
@@ -103,27 +229,13 @@ impl Sampler {
                             continue;
                         }
                         pids.insert(pid);
-                        let done_root = done.clone();
-                        let done_thread = done.clone();
+                        let config = config.clone();
+                        let state = state.clone();
                         let result_sender = result_sender.clone();
-                        let timing_error_traces = timing_error_traces.clone();
-                        let total_traces = total_traces.clone();
-                        let trace_sender_clone = trace_sender.clone();
-                        let force_version = force_version.clone();
-                        let on_cpu_only = on_cpu_only.clone();
+                        let trace_sender = trace_sender.clone();
                         std::thread::spawn(move || {
-                            let result = sample(
-                                pid,
-                                sample_rate,
-                                maybe_stop_time,
-                                done_thread,
-                                timing_error_traces,
-                                total_traces,
-                                trace_sender_clone,
-                                lock_process,
-                                force_version,
-                                on_cpu_only,
-                            );
+                            let done_root = state.done.clone();
+                            let result = sample(pid, &config, maybe_stop_time, state, trace_sender);
                             result_sender.send(result).expect("couldn't send error");
                             drop(result_sender);
 
@@ -135,25 +247,13 @@ impl Sampler {
                             }
                         });
                     }
-                    // TODO: Parameterize subprocess check interval
-                    std::thread::sleep(Duration::from_secs(1));
+                    std::thread::sleep(poll_interval);
                 }
             });
         } else {
             // Start a single recorder thread
             std::thread::spawn(move || {
-                let result = sample(
-                    root_pid,
-                    sample_rate,
-                    maybe_stop_time,
-                    done,
-                    timing_error_traces,
-                    total_traces,
-                    trace_sender,
-                    lock_process,
-                    force_version,
-                    on_cpu_only,
-                );
+                let result = sample(root_pid, &config, maybe_stop_time, state, trace_sender);
                 result_sender.send(result).unwrap();
                 drop(result_sender);
             });
@@ -163,50 +263,190 @@ impl Sampler {
     }
 
     pub fn stop(&self) {
-        self.done.store(true, Ordering::Relaxed);
+        self.state.done.store(true, Ordering::Relaxed);
+    }
+}
+
+/// What the sampling threads send for each trace: a `StackTrace`, or a `DeferredSample` when
+/// symbolization is deferred
+trait Sample: Send + Sync + 'static {
+    /// Reads the traces of `process` for one sample
+    fn read(
+        process: &mut RubySpy,
+        lock_process: bool,
+        all_threads: bool,
+    ) -> Result<Option<Vec<Self>>>
+    where
+        Self: Sized;
+
+    fn trace_mut(&mut self) -> &mut StackTrace;
+
+    /// Keeps what later samples need from `earlier`, a sample that won't be sent
+    fn absorb(&mut self, earlier: Self);
+}
+
+impl Sample for StackTrace {
+    fn read(
+        process: &mut RubySpy,
+        lock_process: bool,
+        all_threads: bool,
+    ) -> Result<Option<Vec<Self>>> {
+        match all_threads {
+            true => process.get_all_stack_traces(lock_process).map(Some),
+            false => process
+                .get_stack_trace(lock_process)
+                .map(|trace| trace.map(|trace| vec![trace])),
+        }
+    }
+
+    fn trace_mut(&mut self) -> &mut StackTrace {
+        self
+    }
+
+    fn absorb(&mut self, _earlier: Self) {}
+}
+
+impl Sample for DeferredSample {
+    fn read(
+        process: &mut RubySpy,
+        lock_process: bool,
+        _all_threads: bool,
+    ) -> Result<Option<Vec<Self>>> {
+        process
+            .get_deferred_sample(lock_process)
+            .map(|sample| sample.map(|sample| vec![sample]))
+    }
+
+    fn trace_mut(&mut self) -> &mut StackTrace {
+        &mut self.trace.trace
+    }
+
+    fn absorb(&mut self, earlier: Self) {
+        DeferredSample::absorb(self, earlier)
     }
 }
 
 /// Samples stack traces and sends them to a channel in another thread where they can be aggregated
-fn sample(
+fn sample<S: Sample>(
     pid: Pid,
-    sample_rate: u32,
+    config: &SamplerConfig,
     maybe_stop_time: Option<Instant>,
-    done: Arc<AtomicBool>,
-    timing_error_traces: Arc<AtomicUsize>,
-    total_traces: Arc<AtomicUsize>,
-    sender: SyncSender<StackTrace>,
-    lock_process: bool,
-    force_version: Option<String>,
-    on_cpu_only: bool,
+    state: SharedState,
+    sender: SyncSender<S>,
 ) -> Result<(), Error> {
-    let mut process =
-        crate::core::ruby_spy::RubySpy::retry_new(pid, 10, force_version, on_cpu_only)
-            .context("new spy")?;
+    let SamplerConfig {
+        sample_rate,
+        lock_process,
+        lock_retries,
+        max_samples,
+        ref force_version,
+        ref ruby_binary,
+        on_cpu_only,
+        idle_samples,
+        force_on_cpu_samples,
+        attach_timeout,
+        burst_rate,
+        adaptive,
+        ref thread_ids,
+        all_threads,
+        with_rss,
+        error_tolerance,
+        ..
+    } = *config;
+    let SharedState {
+        done,
+        samples,
+        timing_error_traces,
+        total_traces,
+        sample_costs,
+        diagnostics,
+        burst_until,
+    } = state;
+    let spy_config = RubySpyConfig {
+        force_version: force_version.clone(),
+        ruby_binary: ruby_binary.clone(),
+        on_cpu_only,
+        idle_samples,
+        force_on_cpu_samples,
+        lock_retries,
+    };
+    let mut process = RubySpy::retry_new(pid, attach_timeout, &spy_config).context("new spy")?;
+    if process.inspect().jit_code_detected {
+        warn!(
+            "Process {} is running JIT-compiled code (e.g. with YJIT). On Ruby 3.3 and \
+            later, methods that YJIT runs are shown as [JIT] <method>, and their line numbers \
+            may be missing or out of date. Other Ruby versions may show gaps in their stacks",
+            pid
+        );
+    }
 
     let mut total = 0;
     let mut errors = 0;
+    let mut consecutive_errors = 0;
 
     let mut sample_time = SampleTime::new(sample_rate);
+    let mut adaptive_rate = AdaptiveRate::new();
+    // A sample that was read but not sent, e.g. because it's from another thread
+    let mut unsent: Option<S> = None;
     #[cfg(windows)]
-    {
-        // This changes a system-wide setting on Windows so that the OS wakes up every 1ms
-        // instead of the default 15.6ms. This is required to have a sleep call
-        // take less than 15ms, which we need since we usually profile at more than 64hz.
-        // The downside is that this will increase power usage: good discussions are:
-        // https://randomascii.wordpress.com/2013/07/08/windows-timer-resolution-megawatts-wasted/
-        // and http://www.belshe.com/2010/06/04/chrome-cranking-up-the-clock/
-        unsafe {
-            timeapi::timeBeginPeriod(1);
-        }
-    }
+    let _timer_resolution = TimerResolution::new();
 
-    while !done.load(Ordering::Relaxed) {
+    'sampling: while !done.load(Ordering::Relaxed) {
         total += 1;
-        let trace = process.get_stack_trace(lock_process);
-        match trace {
-            Ok(Some(ok_trace)) => {
-                sender.send(ok_trace).context("send trace")?;
+        let started = Instant::now();
+        let traces = S::read(&mut process, lock_process, all_threads);
+        let lock_retries_used = process.take_lock_retries();
+        if lock_retries_used > 0 {
+            diagnostics.lock().unwrap().lock_retries += lock_retries_used;
+        }
+        match traces {
+            Ok(Some(traces)) => {
+                consecutive_errors = 0;
+                sample_costs.lock().unwrap().add(started.elapsed());
+                // Read once per sample, since all of the sample's traces are from the same process
+                let rss = match with_rss {
+                    true => crate::core::process::resident_memory(pid)
+                        .map_err(|e| debug!("Couldn't read RSS of process {}: {:?}", pid, e))
+                        .ok(),
+                    false => None,
+                };
+                let monotonic_ns = crate::core::process::monotonic_ns()
+                    .map_err(|e| debug!("Couldn't read the monotonic clock: {:?}", e))
+                    .ok();
+                for mut sampled in traces {
+                    if let Some(earlier) = unsent.take() {
+                        sampled.absorb(earlier);
+                    }
+                    let ok_trace = sampled.trace_mut();
+                    // Unless all threads are read, only the thread that's running Ruby code is
+                    // sampled, so traces from other threads are dropped after the fact
+                    if !ok_trace.is_from_threads(thread_ids) {
+                        unsent = Some(sampled);
+                        continue;
+                    }
+                    ok_trace.rss = rss;
+                    ok_trace.monotonic_ns = monotonic_ns;
+                    // The count is shared with the threads sampling other processes, so that
+                    // exactly `max_samples` traces are sent in total
+                    let sample = samples.fetch_add(1, Ordering::Relaxed) + 1;
+                    if max_samples.map_or(false, |max| sample > max) {
+                        done.store(true, Ordering::Relaxed);
+                        break 'sampling;
+                    }
+                    if adaptive {
+                        // Checking whether the process is on the CPU takes another look at all of
+                        // its threads, so only do it when the stack hasn't changed
+                        let idle = adaptive_rate.is_repeat(ok_trace)
+                            && !process.is_on_cpu().unwrap_or(true);
+                        adaptive_rate.observe(ok_trace, idle);
+                    }
+                    sender.send(sampled).context("send trace")?;
+                    if max_samples == Some(sample) {
+                        // Stop the other sampling threads too
+                        done.store(true, Ordering::Relaxed);
+                        break 'sampling;
+                    }
+                }
             }
             Ok(None) => {
                 break;
@@ -218,10 +458,33 @@ fn sample(
                 }
 
                 errors += 1;
-                if errors > 20 && (errors as f64) / (total as f64) > 0.5 {
-                    // TODO: Return error type instead of printing here
-                    print_errors(errors, total);
-                    return Err(e);
+                consecutive_errors += 1;
+                *diagnostics
+                    .lock()
+                    .unwrap()
+                    .errors
+                    .entry(error_kind(&e).to_string())
+                    .or_insert(0) += 1;
+                // A process that exec's another program keeps its PID but gets a new address
+                // space, e.g. `bundle exec` replacing itself with the app's ruby, so the VM
+                // addresses we found are stale. Look for the Ruby VM again in that case.
+                if consecutive_errors % REATTACH_AFTER_ERRORS == 0 {
+                    match RubySpy::new(pid, &spy_config) {
+                        Ok(spy) => {
+                            diagnostics.lock().unwrap().reattaches += 1;
+                            info!("Re-attached to process {}, which may have exec'd", pid);
+                            process = spy;
+                        }
+                        Err(e) => debug!("Failed to re-attach to process {}: {:?}", pid, e),
+                    }
+                }
+                if error_tolerance.exceeded(errors, total) {
+                    return Err(SamplerError {
+                        dropped: errors,
+                        total,
+                        cause: e,
+                    }
+                    .into());
                 }
             }
         }
@@ -232,6 +495,14 @@ fn sample(
                 break;
             }
         }
+        // Switch between the baseline and burst rates if a burst has started or ended
+        let rate = match (burst_rate, *burst_until.lock().unwrap()) {
+            (Some(burst_rate), Some(until)) if Instant::now() < until => burst_rate,
+            _ if adaptive => adaptive_rate.rate(sample_rate),
+            _ => sample_rate,
+        };
+        sample_time.set_rate(rate);
+
         // Sleep until the next expected sample time
         total_traces.fetch_add(1, Ordering::Relaxed);
         match sample_time.get_sleep_time() {
@@ -243,27 +514,120 @@ fn sample(
             }
         }
     }
+    Ok(())
+}
 
-    // reset time period calls
-    #[cfg(windows)]
-    {
+/// Raises the resolution of the system timer for as long as it's alive, which is while a process
+/// is being sampled. Restoring it when it's dropped means that it's restored however sampling
+/// ends, including when the process exits or sampling fails.
+///
+/// This changes a system-wide setting on Windows so that the OS wakes up every 1ms instead of the
+/// default 15.6ms. This is required to have a sleep call take less than 15ms, which we need since
+/// we usually profile at more than 64hz. The downside is that this will increase power usage:
+/// good discussions are:
+/// https://randomascii.wordpress.com/2013/07/08/windows-timer-resolution-megawatts-wasted/
+/// and http://www.belshe.com/2010/06/04/chrome-cranking-up-the-clock/
+#[cfg(windows)]
+struct TimerResolution;
+
+#[cfg(windows)]
+impl TimerResolution {
+    fn new() -> Self {
+        unsafe {
+            timeapi::timeBeginPeriod(1);
+        }
+        TimerResolution
+    }
+}
+
+#[cfg(windows)]
+impl Drop for TimerResolution {
+    fn drop(&mut self) {
         unsafe {
             timeapi::timeEndPeriod(1);
         }
     }
-    Ok(())
 }
 
-fn print_errors(errors: usize, total: usize) {
-    if errors > 0 {
-        eprintln!(
-            "Dropped {}/{} stack traces because of errors. Please consider reporting a GitHub issue -- this isn't normal.",
-            errors,
-            total
-        );
+/// Sampling a process stopped because most of its stack traces couldn't be read. `cause` is the
+/// error from the last attempt.
+#[derive(thiserror::Error, Debug)]
+#[error("Dropped {dropped}/{total} stack traces because of errors. Please consider reporting a GitHub issue -- this isn't normal.")]
+pub struct SamplerError {
+    pub dropped: usize,
+    pub total: usize,
+    #[source]
+    pub cause: Error,
+}
+
+/// How many stack traces can fail to be read before sampling a process gives up with a
+/// `SamplerError`. Sampling stops once more than `min_errors` traces have failed and the failures
+/// are more than `max_error_rate` (from 0 to 1) of all of the attempts, so a rate of 1 never
+/// stops. Occasional errors are normal, but a process whose traces mostly can't be read is
+/// usually running a Ruby version that rbspy can't read.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct ErrorTolerance {
+    pub min_errors: usize,
+    pub max_error_rate: f64,
+}
+
+impl Default for ErrorTolerance {
+    fn default() -> Self {
+        ErrorTolerance {
+            min_errors: 20,
+            max_error_rate: 0.5,
+        }
     }
 }
 
+impl ErrorTolerance {
+    /// Whether sampling should stop after `errors` of `total` attempts to read a trace failed
+    pub fn exceeded(&self, errors: usize, total: usize) -> bool {
+        errors > self.min_errors && (errors as f64) / (total as f64) > self.max_error_rate
+    }
+}
+
+/// Counters describing what went wrong while sampling, for bug reports and for checking whether
+/// rbspy is keeping up with a process. See `Sampler::diagnostics`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diagnostics {
+    /// How many times the Ruby VM was looked for again because stack traces stopped being
+    /// readable, e.g. after the process exec'd
+    pub reattaches: usize,
+    /// How many times locking the process failed and was retried. See `lock_retries`.
+    pub lock_retries: usize,
+    /// The number of stack traces that couldn't be read, by the kind of error (see `error_kind`)
+    pub errors: BTreeMap<String, usize>,
+}
+
+/// A short name for the kind of error that stopped a stack trace from being read
+fn error_kind(error: &Error) -> &'static str {
+    if error.downcast_ref::<LockFailed>().is_some() {
+        return "process lock";
+    }
+    if let Some(e) = error.downcast_ref::<MemoryCopyError>() {
+        return match e {
+            MemoryCopyError::PermissionDenied => "permission denied",
+            MemoryCopyError::InvalidAddressError(_) => "invalid address",
+            MemoryCopyError::Message(_) => "inconsistent stack",
+            _ => "memory read",
+        };
+    }
+    match error.downcast_ref::<remoteprocess::Error>() {
+        Some(_) => "memory read",
+        None => "other",
+    }
+}
+
+/// How long to keep trying to attach to a process before giving up, by default. It usually takes
+/// a few tries, e.g. while rbenv execs the right ruby or the dynamic linker maps libruby.
+pub const DEFAULT_ATTACH_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// How many stack traces in a row have to fail before the sampler looks for the Ruby VM again,
+/// in case the process has exec'd. Occasional errors are normal, e.g. when a thread exits while
+/// it's being read, and re-attaching is too slow to do after each of them.
+const REATTACH_AFTER_ERRORS: usize = 5;
+
 // This SampleTime struct helps us sample on a regular schedule ("exactly" 100 times per second, if
 // the sample rate is 100).
 // What we do is -- when doing the 1234th sample, we calculate the exact time the 1234th sample
@@ -271,21 +635,45 @@ fn print_errors(errors: usize, total: usize) {
 // time
 struct SampleTime {
     start_time: Instant,
+    rate: u32,
     nanos_between_samples: u64,
     num_samples: u64,
 }
 
 const BILLION: u64 = 1000 * 1000 * 1000; // for nanosleep
 
+/// The shortest sleep that the OS timer reliably honors. On Windows this is the 1ms period that
+/// we request with `timeBeginPeriod`; elsewhere it's roughly the default timer slack on Linux.
+#[cfg(windows)]
+const TIMER_RESOLUTION_NANOS: u64 = 1000 * 1000;
+#[cfg(not(windows))]
+const TIMER_RESOLUTION_NANOS: u64 = 50 * 1000;
+
+/// The highest sample rate that the OS timer can deliver. Above this, the time between samples is
+/// shorter than the shortest sleep we can get, so `SampleTime` falls behind its schedule and most
+/// samples are counted as timing errors.
+pub fn max_reliable_rate() -> u32 {
+    (BILLION / TIMER_RESOLUTION_NANOS) as u32
+}
+
 impl SampleTime {
     pub fn new(rate: u32) -> SampleTime {
         SampleTime {
             start_time: Instant::now(),
+            rate,
             nanos_between_samples: BILLION / u64::from(rate),
             num_samples: 0,
         }
     }
 
+    /// Changes the sample rate. The schedule restarts from now so that samples taken at the old
+    /// rate don't count as being late or early for the new one.
+    pub fn set_rate(&mut self, rate: u32) {
+        if rate != self.rate {
+            *self = SampleTime::new(rate);
+        }
+    }
+
     pub fn get_sleep_time(&mut self) -> Result<u32, u32> {
         // Returns either the amount of time to sleep (Ok(x)) until next sample time or an error of
         // how far we're behind if we're behind the expected next sample time
@@ -301,6 +689,86 @@ impl SampleTime {
     }
 }
 
+// Lowers the sample rate while the process is idle, i.e. while consecutive samples show the same
+// stack and none of the process's threads are running. The rate halves after every
+// `IDLE_SAMPLES_PER_STEP` idle samples, down to 1/2^`MAX_STEPS` of the baseline rate, so that the
+// back-off takes longer at each step. It returns to the baseline rate as soon as the stack changes
+// or the process is running again, although activity that starts and ends between two slow
+// samples is missed.
+struct AdaptiveRate {
+    last: Option<(Option<u64>, Vec<StackFrame>)>,
+    idle_samples: u32,
+}
+
+impl AdaptiveRate {
+    const IDLE_SAMPLES_PER_STEP: u32 = 10;
+    const MAX_STEPS: u32 = 4;
+
+    fn new() -> AdaptiveRate {
+        AdaptiveRate {
+            last: None,
+            idle_samples: 0,
+        }
+    }
+
+    // Whether `trace` has the same thread and stack as the previous sample
+    fn is_repeat(&self, trace: &StackTrace) -> bool {
+        match &self.last {
+            Some((thread_id, frames)) => *thread_id == trace.thread_id && *frames == trace.trace,
+            None => false,
+        }
+    }
+
+    fn observe(&mut self, trace: &StackTrace, idle: bool) {
+        match idle {
+            true => self.idle_samples += 1,
+            false => self.idle_samples = 0,
+        }
+        if !self.is_repeat(trace) {
+            self.last = Some((trace.thread_id, trace.trace.clone()));
+        }
+    }
+
+    fn rate(&self, sample_rate: u32) -> u32 {
+        let steps = (self.idle_samples / Self::IDLE_SAMPLES_PER_STEP).min(Self::MAX_STEPS);
+        (sample_rate >> steps).max(1)
+    }
+}
+
+// A histogram of how long each stack trace took to read. Each bucket is 5% wider than the one
+// before, so percentiles can be estimated to within a few percent without keeping every
+// measurement.
+#[derive(Debug, Default)]
+struct SampleCosts {
+    buckets: std::collections::BTreeMap<u32, u64>,
+    count: u64,
+}
+
+impl SampleCosts {
+    const BUCKET_GROWTH: f64 = 1.05;
+
+    fn add(&mut self, cost: Duration) {
+        let nanos = cost.as_nanos().max(1) as f64;
+        let bucket = nanos.log(Self::BUCKET_GROWTH) as u32;
+        *self.buckets.entry(bucket).or_insert(0) += 1;
+        self.count += 1;
+    }
+
+    // The middle of the bucket that the given fraction of the costs are at or below
+    fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let rank = ((self.count as f64) * percentile).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (&bucket, &count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                let nanos = Self::BUCKET_GROWTH.powf(bucket as f64 + 0.5);
+                return Some(Duration::from_nanos(nanos as u64));
+            }
+        }
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[cfg(not(target_os = "windows"))]
@@ -308,8 +776,150 @@ mod tests {
     #[cfg(unix)]
     use std::process::Command;
 
+    use crate::core::process::LockFailed;
     use crate::core::process::{tests::RubyScript, Pid};
-    use crate::sampler::Sampler;
+    use crate::core::types::MemoryCopyError;
+    use crate::core::types::{StackFrame, StackTrace};
+    use crate::sampler::{
+        error_kind, max_reliable_rate, AdaptiveRate, ErrorTolerance, SampleCosts, SampleTime,
+        Sampler, SamplerConfig, SamplerError,
+    };
+    use anyhow::{format_err, Error};
+    use std::time::Duration;
+
+    #[test]
+    fn test_sample_time_set_rate() {
+        let mut sample_time = SampleTime::new(100);
+        sample_time.get_sleep_time().unwrap();
+        assert_eq!(sample_time.num_samples, 1);
+
+        // Setting the same rate keeps the current schedule
+        sample_time.set_rate(100);
+        assert_eq!(sample_time.num_samples, 1);
+
+        sample_time.set_rate(1000);
+        assert_eq!(sample_time.num_samples, 0);
+        assert_eq!(sample_time.nanos_between_samples, 1_000_000);
+    }
+
+    #[test]
+    fn test_max_reliable_rate() {
+        // The default rate and typical burst rates shouldn't trigger a warning on any platform
+        assert!(max_reliable_rate() >= 1000);
+        let sample_time = SampleTime::new(max_reliable_rate());
+        assert!(sample_time.nanos_between_samples >= super::TIMER_RESOLUTION_NANOS);
+    }
+
+    #[test]
+    fn test_sample_costs() {
+        let mut costs = SampleCosts::default();
+        assert_eq!(costs.percentile(0.5), None);
+
+        for micros in 1..=100 {
+            costs.add(Duration::from_micros(micros * 10));
+        }
+        costs.add(Duration::from_secs(1));
+        let within_5_percent = |estimate: Option<Duration>, expected: Duration| {
+            let ratio = estimate.unwrap().as_secs_f64() / expected.as_secs_f64();
+            assert!((0.95..1.05).contains(&ratio), "{:?}", estimate);
+        };
+        within_5_percent(costs.percentile(0.5), Duration::from_micros(510));
+        within_5_percent(costs.percentile(0.99), Duration::from_micros(1000));
+        within_5_percent(costs.percentile(1.0), Duration::from_secs(1));
+        within_5_percent(costs.percentile(0.0), Duration::from_micros(10));
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let lock_error = Error::from(remoteprocess::Error::Other("busy".to_string()))
+            .context(LockFailed { retries: 3 })
+            .context("locking process during stack trace retrieval");
+        assert_eq!(error_kind(&lock_error), "process lock");
+        let read_error = Error::from(remoteprocess::Error::Other("unreadable".to_string()));
+        assert_eq!(error_kind(&read_error), "memory read");
+        let invalid_address =
+            Error::from(MemoryCopyError::InvalidAddressError(0x10)).context("get ruby stack trace");
+        assert_eq!(error_kind(&invalid_address), "invalid address");
+        assert_eq!(error_kind(&format_err!("something else")), "other");
+    }
+
+    #[test]
+    fn test_sampler_error() {
+        let error: Error = SamplerError {
+            dropped: 30,
+            total: 40,
+            cause: format_err!("couldn't read thread"),
+        }
+        .into();
+        let messages: Vec<String> = error.chain().map(|e| e.to_string()).collect();
+        assert!(messages[0].starts_with("Dropped 30/40 stack traces because of errors."));
+        assert_eq!(messages[1], "couldn't read thread");
+        assert_eq!(error.downcast_ref::<SamplerError>().unwrap().dropped, 30);
+    }
+
+    #[test]
+    fn test_error_tolerance() {
+        let tolerance = ErrorTolerance::default();
+        assert!(!tolerance.exceeded(20, 21));
+        assert!(!tolerance.exceeded(21, 42));
+        assert!(tolerance.exceeded(21, 41));
+
+        let tolerance = ErrorTolerance {
+            min_errors: 5,
+            max_error_rate: 0.1,
+        };
+        assert!(!tolerance.exceeded(5, 6));
+        assert!(tolerance.exceeded(6, 50));
+        assert!(!tolerance.exceeded(6, 60));
+
+        let never = ErrorTolerance {
+            min_errors: 0,
+            max_error_rate: 1.0,
+        };
+        assert!(!never.exceeded(100, 100));
+    }
+
+    #[test]
+    fn test_adaptive_rate() {
+        let trace = |name: &str| StackTrace {
+            trace: vec![StackFrame {
+                name: name.to_string(),
+                relative_path: "a.rb".to_string(),
+                absolute_path: None,
+                lineno: Some(1),
+            }],
+            pid: Some(1),
+            thread_id: Some(1),
+            time: None,
+            on_cpu: None,
+            holds_gvl: None,
+            thread_name: None,
+            rss: None,
+            monotonic_ns: None,
+        };
+        let mut adaptive = AdaptiveRate::new();
+        assert!(!adaptive.is_repeat(&trace("sleep")));
+        adaptive.observe(&trace("sleep"), false);
+        assert!(adaptive.is_repeat(&trace("sleep")));
+        assert!(!adaptive.is_repeat(&trace("work")));
+
+        for _ in 0..9 {
+            adaptive.observe(&trace("sleep"), true);
+        }
+        assert_eq!(adaptive.rate(100), 100);
+        adaptive.observe(&trace("sleep"), true);
+        assert_eq!(adaptive.rate(100), 50);
+        for _ in 0..100 {
+            adaptive.observe(&trace("sleep"), true);
+        }
+        assert_eq!(adaptive.rate(100), 6);
+        assert_eq!(adaptive.rate(3), 1);
+
+        // Any activity goes straight back to the baseline rate
+        adaptive.observe(&trace("work"), false);
+        assert_eq!(adaptive.rate(100), 100);
+        assert!(adaptive.is_repeat(&trace("work")));
+    }
 
     #[test]
     fn test_sample_single_process() {
@@ -322,7 +932,7 @@ mod tests {
         let mut process = RubyScript::new("ci/ruby-programs/infinite_on_cpu.rb");
         let pid = process.id() as Pid;
 
-        let sampler = Sampler::new(pid, 100, true, None, false, None, false);
+        let sampler = Sampler::new(pid, SamplerConfig::default());
         let (trace_sender, trace_receiver) = std::sync::mpsc::sync_channel(100);
         let (result_sender, result_receiver) = std::sync::mpsc::channel();
         sampler
@@ -338,6 +948,50 @@ mod tests {
         result.expect("unexpected error");
     }
 
+    #[test]
+    fn test_sample_process_that_execs() {
+        #[cfg(target_os = "macos")]
+        if !nix::unistd::Uid::effective().is_root() {
+            println!("Skipping test because we're not running as root");
+            return;
+        }
+
+        let mut process =
+            RubyScript::new_with_args("ci/ruby-programs/ruby_exec.rb", &["ruby".to_string()]);
+        let pid = process.id() as Pid;
+
+        let sampler = Sampler::new(pid, SamplerConfig::default());
+        let (trace_sender, trace_receiver) = std::sync::mpsc::sync_channel(100);
+        let (result_sender, result_receiver) = std::sync::mpsc::channel();
+        sampler
+            .start(trace_sender, result_sender)
+            .expect("sampler failed to start");
+
+        trace_receiver.recv().expect("failed to receive trace");
+        use std::io::Write;
+        process
+            .child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(b"\n")
+            .expect("failed to write to stdin");
+
+        // The sampler should find the Ruby VM of the program that replaced the first one
+        let exec_trace = trace_receiver.iter().find(|trace| {
+            trace
+                .trace
+                .iter()
+                .any(|frame| frame.relative_path.contains("infinite_on_cpu.rb"))
+        });
+        assert!(exec_trace.is_some());
+
+        process.kill().expect("failed to kill process");
+
+        let result = result_receiver.recv().expect("failed to receive result");
+        result.expect("unexpected error");
+    }
+
     #[test]
     fn test_sample_single_process_with_time_limit() {
         #[cfg(target_os = "macos")]
@@ -351,12 +1005,10 @@ mod tests {
 
         let sampler = Sampler::new(
             pid,
-            100,
-            true,
-            Some(std::time::Duration::from_millis(500)),
-            false,
-            None,
-            false,
+            SamplerConfig {
+                time_limit: Some(std::time::Duration::from_millis(500)),
+                ..Default::default()
+            },
         );
         let (trace_sender, trace_receiver) = std::sync::mpsc::sync_channel(100);
         let (result_sender, result_receiver) = std::sync::mpsc::channel();
@@ -375,6 +1027,39 @@ mod tests {
         result.expect("unexpected error");
     }
 
+    #[test]
+    fn test_sample_single_process_with_max_samples() {
+        #[cfg(target_os = "macos")]
+        if !nix::unistd::Uid::effective().is_root() {
+            println!("Skipping test because we're not running as root");
+            return;
+        }
+
+        let mut process = RubyScript::new("ci/ruby-programs/infinite_on_cpu.rb");
+        let pid = process.id() as Pid;
+
+        let sampler = Sampler::new(
+            pid,
+            SamplerConfig {
+                time_limit: Some(std::time::Duration::from_secs(60)),
+                max_samples: Some(10),
+                ..Default::default()
+            },
+        );
+        let (trace_sender, trace_receiver) = std::sync::mpsc::sync_channel(100);
+        let (result_sender, result_receiver) = std::sync::mpsc::channel();
+        sampler
+            .start(trace_sender, result_sender)
+            .expect("sampler failed to start");
+
+        assert_eq!(trace_receiver.iter().count(), 10);
+
+        process.kill().expect("failed to kill process");
+
+        let result = result_receiver.recv().expect("failed to receive result");
+        result.expect("unexpected error");
+    }
+
     // TODO: Find a more reliable way to test this on Windows hosts
     #[cfg(not(target_os = "windows"))]
     #[test]
@@ -413,7 +1098,14 @@ mod tests {
             .unwrap();
         let pid = process.id() as Pid;
 
-        let sampler = Sampler::new(pid, 5, true, None, true, None, false);
+        let sampler = Sampler::new(
+            pid,
+            SamplerConfig {
+                sample_rate: 5,
+                with_subprocesses: true,
+                ..Default::default()
+            },
+        );
         let (trace_sender, trace_receiver) = std::sync::mpsc::sync_channel(100);
         let (result_sender, result_receiver) = std::sync::mpsc::channel();
         sampler
@@ -11,55 +11,221 @@
 /// The use of b'\n' as a terminator effectively reserves a byte, and provides
 /// flexibility to go to a different version encoding scheme if this format
 /// changes _way_ too much.
+///
+/// Version 3 files contain a header line, followed by one line per trace or piece of metadata (see
+/// `v3::Record`). Version 4 files are laid out the same way, but are written when symbolization is
+/// deferred, and have traces whose frames are named when the file is read (see `v4::Record`).
+///
+/// Files are usually gzipped, but can also be written uncompressed. Readers check for the gzip
+/// magic number, so both kinds of file can be read.
 extern crate anyhow;
 extern crate flate2;
 
+use std::collections::HashMap;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::io::BufReader;
 use std::path::Path;
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 
+use crate::core::process::Pid;
+use crate::core::types::DeferredSample;
 use crate::core::types::Header;
+use crate::core::types::RawCompression;
+use crate::core::types::StackFrame;
 use crate::core::types::StackTrace;
 
 use self::flate2::Compression;
 
-use anyhow::{Error, Result};
+use anyhow::{format_err, Error, Result};
+use serde::Serialize;
 use thiserror::Error;
 
 mod v0;
-mod v1;
-mod v2;
+mod v3;
+mod v4;
+
+/// How often buffered traces are flushed to disk, so that as little data as possible is lost if
+/// rbspy is killed before it can finish writing the file.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// The first two bytes of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
 
 pub struct Store {
-    encoder: flate2::write::GzEncoder<File>,
+    encoder: Encoder,
+    last_flush: Instant,
+    thread_names: HashMap<u64, String>,
+}
+
+enum Encoder {
+    Gzip(flate2::write::GzEncoder<File>),
+    Plain(io::BufWriter<File>),
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Gzip(e) => e.write(buf),
+            Encoder::Plain(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(e) => e.flush(),
+            Encoder::Plain(w) => w.flush(),
+        }
+    }
 }
 
 impl Store {
-    pub fn new(out_path: &Path, sample_rate: u32) -> Result<Store, io::Error> {
+    pub fn new(
+        out_path: &Path,
+        sample_rate: u32,
+        compression: RawCompression,
+    ) -> Result<Store, io::Error> {
+        Store::with_header(
+            out_path,
+            &Header {
+                sample_rate: Some(sample_rate),
+                rbspy_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                start_time: Some(SystemTime::now()),
+                ..Default::default()
+            },
+            compression,
+        )
+    }
+
+    /// Creates a raw data file for recording with deferred symbolization, which is written with
+    /// `write_deferred`. It's a version 4 file, which older versions of rbspy can't read.
+    pub fn new_deferred(
+        out_path: &Path,
+        sample_rate: u32,
+        compression: RawCompression,
+    ) -> Result<Store, io::Error> {
+        Store::create(
+            out_path,
+            &Header {
+                sample_rate: Some(sample_rate),
+                rbspy_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                start_time: Some(SystemTime::now()),
+                ..Default::default()
+            },
+            compression,
+            Version(4),
+        )
+    }
+
+    /// Creates a raw data file that starts with `header`, e.g. to write traces that were read from
+    /// other files
+    pub fn with_header(
+        out_path: &Path,
+        header: &Header,
+        compression: RawCompression,
+    ) -> Result<Store, io::Error> {
+        Store::create(out_path, header, compression, Version(3))
+    }
+
+    fn create(
+        out_path: &Path,
+        header: &Header,
+        compression: RawCompression,
+        version: Version,
+    ) -> Result<Store, io::Error> {
         let file = File::create(out_path)?;
-        let mut encoder = flate2::write::GzEncoder::new(file, Compression::default());
-        encoder.write_all("rbspy02\n".as_bytes())?;
-
-        let json = serde_json::to_string(&Header {
-            sample_rate: Some(sample_rate),
-            rbspy_version: Some(env!("CARGO_PKG_VERSION").to_string()),
-            start_time: Some(SystemTime::now()),
-        })?;
+        let mut encoder = match compression {
+            RawCompression::None => Encoder::Plain(io::BufWriter::new(file)),
+            RawCompression::Gzip(level) => {
+                Encoder::Gzip(flate2::write::GzEncoder::new(file, Compression::new(level)))
+            }
+        };
+        writeln!(&mut encoder, "rbspy{:02}", version.0)?;
+
+        let json = serde_json::to_string(header)?;
         writeln!(&mut encoder, "{}", json)?;
 
-        Ok(Store { encoder })
+        Ok(Store {
+            encoder,
+            last_flush: Instant::now(),
+            thread_names: HashMap::new(),
+        })
     }
 
     pub fn write(&mut self, trace: &StackTrace) -> Result<(), Error> {
-        let json = serde_json::to_string(trace)?;
+        if let (Some(thread_id), Some(name)) = (trace.thread_id, &trace.thread_name) {
+            if self.thread_names.get(&thread_id) != Some(name) {
+                self.thread_names.insert(thread_id, name.clone());
+                self.write_record(&v3::Record::Thread {
+                    thread_id,
+                    name: name.clone(),
+                })?;
+            }
+        }
+        self.write_record(&v3::Record::Trace(trace.clone()))?;
+        if self.last_flush.elapsed() > FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes a sample that was recorded with deferred symbolization, along with the memory that's
+    /// needed to name its frames. Only files created with `new_deferred` can be read back
+    /// afterwards.
+    pub fn write_deferred(&mut self, sample: &DeferredSample) -> Result<(), Error> {
+        let pid = sample
+            .trace
+            .trace
+            .pid
+            .ok_or_else(|| format_err!("Deferred sample doesn't have a PID"))?;
+        if let Some((version, global_symbols)) = &sample.ruby {
+            self.write_record(&v4::Record::Ruby {
+                pid,
+                version: version.clone(),
+                global_symbols: *global_symbols,
+            })?;
+        }
+        for (addr, bytes) in &sample.memory {
+            self.write_record(&v4::Record::Memory {
+                pid,
+                addr: *addr,
+                bytes: v4::to_hex(bytes),
+            })?;
+        }
+        self.write_record(&v4::Record::RawTrace(sample.trace.clone()))?;
+        if self.last_flush.elapsed() > FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Records the command line of a process that traces are being recorded from
+    pub fn add_process(&mut self, pid: Pid, command_line: Vec<String>) -> Result<(), Error> {
+        self.write_record(&v3::Record::Process { pid, command_line })
+    }
+
+    fn write_record<T: Serialize>(&mut self, record: &T) -> Result<(), Error> {
+        let json = serde_json::to_string(record)?;
         writeln!(&mut self.encoder, "{}", json)?;
         Ok(())
     }
 
-    pub fn complete(self) {
-        drop(self.encoder)
+    /// Writes all buffered traces to disk. A file that ends after a flush can be read back even
+    /// though it's missing the gzip trailer.
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.last_flush = Instant::now();
+        self.encoder.flush()
+    }
+
+    pub fn complete(self) -> Result<(), io::Error> {
+        match self.encoder {
+            Encoder::Gzip(e) => {
+                e.finish()?;
+            }
+            Encoder::Plain(mut w) => w.flush()?,
+        }
+        Ok(())
     }
 }
 
@@ -85,6 +251,10 @@ impl Version {
             Ok(Version(1))
         } else if &b[0..3] == "02\n".as_bytes() {
             Ok(Version(2))
+        } else if &b[0..3] == "03\n".as_bytes() {
+            Ok(Version(3))
+        } else if &b[0..3] == "04\n".as_bytes() {
+            Ok(Version(4))
         } else {
             Err(StorageError::Invalid)
         }
@@ -104,42 +274,392 @@ pub(crate) enum StorageError {
     Io(io::Error),
 }
 
-/// Types that can be deserialized from an `io::Read` into something convertible
-/// to the current internal form.
-pub(crate) trait Storage: Into<v2::Data> {
-    fn from_reader<R: Read>(r: R) -> Result<Self>;
-    #[allow(dead_code)]
-    fn version() -> Version;
+/// The contents of a raw data file, decompressed if the file is gzipped.
+pub(crate) enum Decoder<R: Read> {
+    Gzip(flate2::read::GzDecoder<Input<io::Chain<io::Cursor<Vec<u8>>, R>>>),
+    Plain(io::Chain<io::Cursor<Vec<u8>>, R>),
+}
+
+impl<R: Read> Decoder<R> {
+    fn new(mut r: R) -> Result<Self, StorageError> {
+        // Put back the bytes we looked at, so that the rest of the stream is unchanged
+        let mut magic = Vec::with_capacity(GZIP_MAGIC.len());
+        r.by_ref()
+            .take(GZIP_MAGIC.len() as u64)
+            .read_to_end(&mut magic)
+            .map_err(StorageError::Io)?;
+        let gzipped = magic == GZIP_MAGIC;
+        let r = io::Cursor::new(magic).chain(r);
+        Ok(match gzipped {
+            true => Decoder::Gzip(flate2::read::GzDecoder::new(Input {
+                inner: r,
+                exhausted: false,
+            })),
+            false => Decoder::Plain(r),
+        })
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Decoder::Gzip(d) => d.read(buf).map_err(|e| {
+                // flate2 reports a stream that stops partway through as corrupt
+                if e.kind() == io::ErrorKind::InvalidInput && d.get_ref().exhausted {
+                    io::Error::new(io::ErrorKind::UnexpectedEof, e)
+                } else {
+                    e
+                }
+            }),
+            Decoder::Plain(r) => r.read(buf),
+        }
+    }
+}
+
+/// A reader that remembers whether it has reached the end of its input.
+pub(crate) struct Input<R: Read> {
+    inner: R,
+    exhausted: bool,
+}
+
+impl<R: Read> Read for Input<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.exhausted = true;
+        }
+        Ok(n)
+    }
 }
 
 fn read_version(r: &mut dyn Read) -> Result<Version, StorageError> {
     let mut buf = [0u8; 8];
-    // TODO: I don't know how to failure good, so this doesn't work.
-    r.read(&mut buf).map_err(StorageError::Io)?;
+    r.read_exact(&mut buf).map_err(|e| match e.kind() {
+        // Too short to be an rbspy file
+        io::ErrorKind::UnexpectedEof => StorageError::Invalid,
+        _ => StorageError::Io(e),
+    })?;
     match &buf[..5] {
         b"rbspy" => Ok(Version::try_from(&buf[5..])?),
         _ => Err(StorageError::Invalid),
     }
 }
 
-pub(crate) fn from_reader<R: Read>(r: R) -> Result<v2::Data, Error> {
-    // This will read 8 bytes, leaving the reader's cursor at the start of the
-    // "real" data.
-    let mut reader = flate2::read::GzDecoder::new(r);
+/// An iterator over the traces in a raw file that decodes one line at a time, so
+/// that large recordings don't have to be held in memory all at once.
+///
+/// Version 3 files can contain metadata, like thread names, between the traces. It's added to
+/// `header` as the stream reaches it, so the header is only complete once the stream has ended.
+/// The frames of version 4 files' traces are named as the stream reaches them.
+pub struct TraceStream<R: Read> {
+    pub header: Header,
+    version: Version,
+    lines: io::Lines<BufReader<Decoder<R>>>,
+    symbolizer: v4::Symbolizer,
+}
+
+impl<R: Read> TraceStream<R> {
+    pub(crate) fn version(&self) -> Version {
+        self.version
+    }
+}
+
+impl<R: Read> Iterator for TraceStream<R> {
+    type Item = Result<StackTrace>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    // The file was truncated, most likely because rbspy was killed while recording.
+                    // Everything up to the last flush is still usable.
+                    warn!(
+                        "Raw data file ended unexpectedly ({}); some traces may be missing",
+                        e
+                    );
+                    return None;
+                }
+                Err(e) => return Some(Err(e.into())),
+            };
+            let trace = match self.version {
+                // Version 0 files only stored the frames of each trace
+                Version(0) => serde_json::from_str::<Vec<StackFrame>>(&line).map(StackTrace::from),
+                Version(1) | Version(2) => serde_json::from_str::<StackTrace>(&line),
+                Version(3) => match serde_json::from_str::<v3::Record>(&line) {
+                    Ok(record) => match record.apply(&mut self.header) {
+                        Some(trace) => Ok(trace),
+                        None => continue,
+                    },
+                    Err(e) => Err(e),
+                },
+                _ => {
+                    let applied = serde_json::from_str::<v4::Record>(&line)
+                        .map_err(Error::from)
+                        .and_then(|record| self.symbolizer.apply(record, &mut self.header));
+                    match applied {
+                        Ok(Some(trace)) => return Some(Ok(trace)),
+                        Ok(None) => continue,
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+            };
+            return Some(trace.map_err(Error::from));
+        }
+    }
+}
+
+/// Reads a raw data file recorded by rbspy (gzipped or not), returning its header and an iterator
+/// over its traces.
+///
+/// ```no_run
+/// let file = std::fs::File::open("rbspy.raw.gz")?;
+/// let stream = rbspy::stream_from_reader(file)?;
+/// println!("Sample rate: {:?}", stream.header.sample_rate);
+/// let header = stream.header.clone();
+/// for (i, trace) in stream.enumerate() {
+///     let trace = trace?;
+///     println!("{:?}: {} frames", header.elapsed(&trace, i), trace.trace.len());
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn stream_from_reader<R: Read>(r: R) -> Result<TraceStream<R>, Error> {
+    let mut reader = Decoder::new(r)?;
     let version = read_version(&mut reader)?;
-    match version {
-        Version(0) => {
-            let intermediate = v0::Data::from_reader(reader)?;
-            Ok(intermediate.into())
+    let mut lines = BufReader::new(reader).lines();
+    let header = match version {
+        Version(0) | Version(1) => Header::default(),
+        Version(2) | Version(3) | Version(4) => match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => return Err(StorageError::Invalid.into()),
+        },
+        v => return Err(StorageError::UnknownVersion(v).into()),
+    };
+    Ok(TraceStream {
+        header,
+        version,
+        lines,
+        symbolizer: Default::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::types::{RawFrame, RawStackTrace};
+
+    fn trace(name: &str) -> StackTrace {
+        StackTrace {
+            trace: vec![StackFrame {
+                name: name.to_string(),
+                relative_path: "a.rb".to_string(),
+                absolute_path: None,
+                lineno: Some(1),
+            }],
+            pid: Some(1),
+            thread_id: Some(2),
+            time: None,
+            on_cpu: None,
+            holds_gvl: None,
+            thread_name: None,
+            rss: None,
+            monotonic_ns: None,
         }
-        Version(1) => {
-            let intermediate = v1::Data::from_reader(reader)?;
-            Ok(intermediate.into())
+    }
+
+    #[test]
+    fn test_stream_from_reader() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rbspy.raw.gz");
+        let mut store = Store::new(&path, 99, RawCompression::default()).unwrap();
+        for name in ["a", "b", "c"] {
+            store.write(&trace(name)).unwrap();
+        }
+        store.complete().unwrap();
+
+        let stream = stream_from_reader(File::open(&path).unwrap()).unwrap();
+        assert_eq!(stream.header.sample_rate, Some(99));
+        let traces: Vec<StackTrace> = stream.map(|t| t.unwrap()).collect();
+        assert_eq!(traces, vec![trace("a"), trace("b"), trace("c")]);
+    }
+
+    #[test]
+    fn test_stream_from_reader_reads_unfinished_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rbspy.raw.gz");
+        let mut store = Store::new(&path, 99, RawCompression::default()).unwrap();
+        store.write(&trace("a")).unwrap();
+        store.write(&trace("b")).unwrap();
+        store.flush().unwrap();
+        // Simulate rbspy being killed before it could complete the file
+        std::mem::forget(store);
+
+        let stream = stream_from_reader(File::open(&path).unwrap()).unwrap();
+        let traces: Vec<StackTrace> = stream.map(|t| t.unwrap()).collect();
+        assert_eq!(traces, vec![trace("a"), trace("b")]);
+    }
+
+    #[test]
+    fn test_stream_from_reader_reports_corrupt_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rbspy.raw.gz");
+        // Without compression the traces are stored as they are, so that changing a byte of one of
+        // them still leaves valid JSON and only the gzip checksum can tell that it's corrupt
+        let mut store = Store::new(&path, 99, RawCompression::Gzip(0)).unwrap();
+        for i in 0..1000 {
+            store.write(&trace(&format!("method_{}", i))).unwrap();
         }
-        Version(2) => {
-            let intermediate = v2::Data::from_reader(reader)?;
-            Ok(intermediate)
+        store.complete().unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        let changed = bytes.windows(10).position(|w| w == b"method_500").unwrap();
+        bytes[changed] ^= 0x01;
+
+        let stream = stream_from_reader(&bytes[..]).unwrap();
+        let results: Vec<Result<StackTrace>> = stream.collect();
+        assert!(results.last().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_stream_from_reader_rejects_invalid_files() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"notrbspy\n").unwrap();
+        let bytes = encoder.finish().unwrap();
+        assert!(stream_from_reader(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn test_raw_compression() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sizes = vec![];
+        for compression in [RawCompression::None, RawCompression::Gzip(1)] {
+            let path = dir.path().join(compression.extension());
+            let mut store = Store::new(&path, 99, compression).unwrap();
+            for name in ["a", "b", "c"] {
+                store.write(&trace(name)).unwrap();
+            }
+            store.complete().unwrap();
+
+            let stream = stream_from_reader(File::open(&path).unwrap()).unwrap();
+            assert_eq!(stream.version(), Version(3));
+            let traces: Vec<StackTrace> = stream.map(|t| t.unwrap()).collect();
+            assert_eq!(traces, vec![trace("a"), trace("b"), trace("c")]);
+            sizes.push(std::fs::metadata(&path).unwrap().len());
         }
-        v => Err(StorageError::UnknownVersion(v).into()),
+        assert!(std::fs::read(dir.path().join("raw"))
+            .unwrap()
+            .starts_with(b"rbspy03\n"));
+        assert!(sizes[0] > sizes[1]);
+    }
+
+    #[test]
+    fn test_thread_names_and_command_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rbspy.raw.gz");
+        let mut store = Store::new(&path, 99, RawCompression::default()).unwrap();
+        store
+            .add_process(1, vec!["ruby".to_string(), "app.rb".to_string()])
+            .unwrap();
+        let named = StackTrace {
+            thread_name: Some("worker".to_string()),
+            ..trace("a")
+        };
+        store.write(&named).unwrap();
+        store.write(&named).unwrap();
+        store.write(&trace("b")).unwrap();
+        store.complete().unwrap();
+
+        let mut stream = stream_from_reader(File::open(&path).unwrap()).unwrap();
+        assert_eq!(stream.version(), Version(3));
+        let traces: Vec<StackTrace> = stream.by_ref().map(|t| t.unwrap()).collect();
+        assert_eq!(traces, vec![named.clone(), named, trace("b")]);
+        assert_eq!(stream.header.command_lines[&1], vec!["ruby", "app.rb"]);
+        assert_eq!(stream.header.thread_names[&2], "worker");
+    }
+
+    #[test]
+    fn test_deferred_symbolization() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rbspy.raw.gz");
+        let mut store = Store::new_deferred(&path, 99, RawCompression::default()).unwrap();
+        store
+            .add_process(1, vec!["ruby".to_string(), "app.rb".to_string()])
+            .unwrap();
+        let mut raw = RawStackTrace::from(trace("a"));
+        // Frames whose memory wasn't recorded can't be named: unknown C functions are kept, like
+        // when recording without deferring, and Ruby frames are dropped
+        raw.frames.push(RawFrame::CFunc {
+            method_entry: 0x1000,
+        });
+        raw.frames.push(RawFrame::Ruby {
+            iseq: 0x2000,
+            pc: 0x3000,
+            jit: false,
+        });
+        store
+            .write_deferred(&DeferredSample {
+                trace: raw.clone(),
+                ruby: Some(("3.3.0".to_string(), None)),
+                memory: vec![(0x4000, vec![0xab, 0x01])],
+            })
+            .unwrap();
+        store
+            .write_deferred(&DeferredSample {
+                trace: RawStackTrace::from(trace("b")),
+                ruby: None,
+                memory: vec![],
+            })
+            .unwrap();
+        store.complete().unwrap();
+
+        let mut expected = trace("a");
+        expected.trace.push(StackFrame::unknown_c_function());
+        let mut stream = stream_from_reader(File::open(&path).unwrap()).unwrap();
+        assert_eq!(stream.version(), Version(4));
+        let traces: Vec<StackTrace> = stream.by_ref().map(|t| t.unwrap()).collect();
+        assert_eq!(traces, vec![expected, trace("b")]);
+        assert_eq!(stream.header.command_lines[&1], vec!["ruby", "app.rb"]);
+
+        // Traces can't be named without knowing which Ruby version their process runs
+        let path = dir.path().join("no-ruby.raw.gz");
+        let mut store = Store::new_deferred(&path, 99, RawCompression::default()).unwrap();
+        store
+            .write_deferred(&DeferredSample {
+                trace: raw,
+                ruby: None,
+                memory: vec![],
+            })
+            .unwrap();
+        store.complete().unwrap();
+        let stream = stream_from_reader(File::open(&path).unwrap()).unwrap();
+        assert!(stream.collect::<Result<Vec<StackTrace>>>().is_err());
+    }
+
+    #[test]
+    fn test_read_traces_without_holds_gvl() {
+        // Files written before `holds_gvl`, `rss` and `monotonic_ns` were added don't have them
+        let line =
+            r#"{"trace":[],"pid":1,"thread_id":2,"time":null,"on_cpu":true,"thread_name":null}"#;
+        let trace: StackTrace = serde_json::from_str(line).unwrap();
+        assert_eq!(trace.on_cpu, Some(true));
+        assert_eq!(trace.holds_gvl, None);
+        assert_eq!(trace.rss, None);
+        assert_eq!(trace.monotonic_ns, None);
+    }
+
+    #[test]
+    fn test_read_version_2() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"rbspy02\n").unwrap();
+        encoder
+            .write_all(b"{\"sample_rate\":99,\"rbspy_version\":\"0.29.0\",\"start_time\":null}\n")
+            .unwrap();
+        writeln!(encoder, "{}", serde_json::to_string(&trace("a")).unwrap()).unwrap();
+        let bytes = encoder.finish().unwrap();
+
+        let stream = stream_from_reader(&bytes[..]).unwrap();
+        assert_eq!(stream.header.sample_rate, Some(99));
+        assert!(stream.header.command_lines.is_empty());
+        let traces: Vec<StackTrace> = stream.map(|t| t.unwrap()).collect();
+        assert_eq!(traces, vec![trace("a")]);
     }
 }
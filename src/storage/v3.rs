@@ -0,0 +1,29 @@
+use crate::core::process::Pid;
+use crate::core::types::{Header, StackTrace};
+
+/// A line of a version 3 file, after the header. Metadata that's only discovered while recording,
+/// like the command lines of subprocesses, is written as it's found and collected into the header
+/// when the file is read.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Record {
+    Trace(StackTrace),
+    Process { pid: Pid, command_line: Vec<String> },
+    Thread { thread_id: u64, name: String },
+}
+
+impl Record {
+    /// Adds the record's metadata to `header`, returning the trace if it's a trace
+    pub fn apply(self, header: &mut Header) -> Option<StackTrace> {
+        match self {
+            Record::Trace(trace) => return Some(trace),
+            Record::Process { pid, command_line } => {
+                header.command_lines.insert(pid, command_line);
+            }
+            Record::Thread { thread_id, name } => {
+                header.thread_names.insert(thread_id, name);
+            }
+        }
+        None
+    }
+}
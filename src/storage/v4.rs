@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use anyhow::format_err;
+
+use crate::core::memory_snapshot::MemorySnapshot;
+use crate::core::process::Pid;
+use crate::core::types::{Header, RawFrame, RawStackTrace, ResolveFrameFn, StackFrame, StackTrace};
+
+use super::*;
+
+/// A line of a version 4 file, after the header. Version 4 files are written when symbolization
+/// is deferred: their traces only have the addresses of their frames, and the process memory
+/// that's needed to name the frames is recorded alongside them. The frames are named as the file
+/// is read, so readers get the same `StackTrace`s as from a version 3 file.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Record {
+    RawTrace(RawStackTrace),
+    /// The Ruby version of a process and the address of its global symbol table, which come
+    /// before its memory and traces. It's recorded again when rbspy re-attaches to the process,
+    /// e.g. after it exec'd, and the memory recorded before that no longer applies.
+    Ruby {
+        pid: Pid,
+        version: String,
+        global_symbols: Option<usize>,
+    },
+    /// Memory that was read from a process, as hex
+    Memory {
+        pid: Pid,
+        addr: usize,
+        bytes: String,
+    },
+    /// Anything that version 3 files have
+    #[serde(untagged)]
+    V3(v3::Record),
+}
+
+/// Names the frames of raw traces from the memory that was recorded before them
+#[derive(Default)]
+pub(crate) struct Symbolizer {
+    processes: HashMap<Pid, ProcessSymbols>,
+}
+
+struct ProcessSymbols {
+    resolve_frame: ResolveFrameFn,
+    global_symbols: Option<usize>,
+    memory: MemorySnapshot,
+    frames: HashMap<RawFrame, StackFrame>,
+}
+
+impl Symbolizer {
+    /// Adds the record's metadata to `header`, and its memory to what's used to name frames,
+    /// returning the trace if it's a trace
+    pub fn apply(&mut self, record: Record, header: &mut Header) -> Result<Option<StackTrace>> {
+        match record {
+            Record::V3(record) => return Ok(record.apply(header)),
+            Record::Ruby {
+                pid,
+                version,
+                global_symbols,
+            } => {
+                let ruby_version =
+                    crate::core::supported_ruby_versions::get(&semver::Version::parse(&version)?)?;
+                self.processes.insert(
+                    pid,
+                    ProcessSymbols {
+                        resolve_frame: ruby_version.resolve_frame_fn,
+                        global_symbols,
+                        memory: MemorySnapshot::new(),
+                        frames: HashMap::new(),
+                    },
+                );
+            }
+            Record::Memory { pid, addr, bytes } => {
+                self.process(pid)?.memory.add(addr, from_hex(&bytes)?);
+            }
+            Record::RawTrace(trace) => {
+                let pid = trace
+                    .trace
+                    .pid
+                    .ok_or_else(|| format_err!("Raw trace doesn't have a PID"))?;
+                return Ok(Some(self.process(pid)?.resolve(trace)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn process(&mut self, pid: Pid) -> Result<&mut ProcessSymbols> {
+        self.processes
+            .get_mut(&pid)
+            .ok_or_else(|| format_err!("Process {} doesn't have a Ruby version", pid))
+    }
+}
+
+impl ProcessSymbols {
+    fn resolve(&mut self, raw: RawStackTrace) -> StackTrace {
+        let mut trace = raw.trace;
+        for frame in raw.frames {
+            if let RawFrame::Known(frame) = frame {
+                trace.trace.push(frame);
+                continue;
+            }
+            if let Some(named) = self.frames.get(&frame) {
+                trace.trace.push(named.clone());
+                continue;
+            }
+            // A frame's memory is recorded just before the first trace that it's in, so it's
+            // named then. The memory at its addresses may be recorded again later for other
+            // frames, which could name it differently.
+            match (self.resolve_frame)(&frame, self.global_symbols, &self.memory) {
+                Ok(named) => {
+                    trace.trace.push(named.clone());
+                    self.frames.insert(frame, named);
+                }
+                // Like when recording without deferring, C functions that can't be named are
+                // still shown, and other frames are skipped
+                Err(e) => {
+                    debug!("Couldn't name frame {:?}: {:?}", frame, e);
+                    if let RawFrame::CFunc { .. } = frame {
+                        trace.trace.push(StackFrame::unknown_c_function());
+                    }
+                }
+            }
+        }
+        trace
+    }
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.is_ascii() || hex.len() % 2 != 0 {
+        return Err(format_err!("Memory isn't valid hex"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|e| format_err!("Memory isn't valid hex: {}", e))
+        })
+        .collect()
+}
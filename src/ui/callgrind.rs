@@ -435,4 +435,52 @@ fn=func3
         let actual = String::from_utf8(buf).expect("Callgrind output not utf8");
         assert_eq!(actual, expected, "Unexpected callgrind output");
     }
+
+    // Test that each function's self cost is reported separately from the cost of the calls it
+    // makes, at the lines of the frames.
+    #[test]
+    fn stats_write_self_and_callee_costs() {
+        let frame = |name: &str, lineno: usize| StackFrame {
+            name: name.to_owned(),
+            relative_path: "app.rb".to_owned(),
+            absolute_path: None,
+            lineno: Some(lineno),
+        };
+        let (a, b, c) = (frame("a", 10), frame("b", 20), frame("c", 30));
+        let mut stats = Stats::new();
+        stats.add(&[b.clone(), a.clone()]);
+        stats.add(&[b.clone(), a.clone()]);
+        stats.add(&[a.clone()]);
+        stats.add(&[c, b, a]);
+        stats.finish();
+
+        let expected = "# callgrind format
+version: 1
+creator: rbspy
+events: Samples
+
+fl=app.rb
+fn=a
+10 1
+cfl=app.rb
+cfn=b
+calls=2 20
+10 3
+
+fl=app.rb
+fn=b
+20 2
+cfl=app.rb
+cfn=c
+calls=1 30
+20 1
+
+fl=app.rb
+fn=c
+30 1
+";
+        let mut buf: Vec<u8> = Vec::new();
+        stats.write(&mut buf).expect("Callgrind write failed");
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+    }
 }
@@ -0,0 +1,293 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+use crate::core::process::Pid;
+use crate::core::types::{StackFrame, StackTrace};
+
+/*
+ * Writes traces in the Chrome trace event format, for viewing on a timeline in chrome://tracing
+ * or https://ui.perfetto.dev, with one lane per thread.
+ *
+ * The format is described at
+ * https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+ *
+ * Samples don't say when a function started or returned, so a function is assumed to run from the
+ * first of a series of consecutive samples it appears in until the next sample it doesn't appear
+ * in. Each function call becomes a "complete" event with a start time and duration.
+ */
+
+#[derive(Debug, Serialize)]
+struct Event {
+    name: String,
+    ph: &'static str,
+    // Timestamps and durations are in microseconds
+    ts: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dur: Option<f64>,
+    pid: Pid,
+    tid: u64,
+    args: serde_json::Value,
+}
+
+// A thread's calls that haven't returned yet, outermost first, with their start times and the
+// monotonic clock's time when they started, if it was recorded
+#[derive(Default)]
+struct Lane {
+    open: Vec<(StackFrame, f64, Option<u64>)>,
+    last: f64,
+    samples: u64,
+    name: Option<String>,
+}
+
+pub struct Stats {
+    interval: Duration,
+    start: Option<SystemTime>,
+    lanes: HashMap<(Pid, u64), Lane>,
+    events: Vec<Event>,
+}
+
+impl Stats {
+    /// `interval` is the time between samples, which is used for the length of the last sample
+    /// and for traces without timestamps.
+    pub fn new(interval: Duration) -> Stats {
+        Stats {
+            interval,
+            start: None,
+            lanes: HashMap::new(),
+            events: vec![],
+        }
+    }
+
+    pub fn record(&mut self, stack: &StackTrace) -> Result<()> {
+        let (pid, tid) = (stack.pid.unwrap_or(0), stack.thread_id.unwrap_or(0));
+        let lane = self.lanes.entry((pid, tid)).or_default();
+        let ts = match stack.time {
+            Some(time) => {
+                let start = *self.start.get_or_insert(time);
+                micros(time.duration_since(start).unwrap_or_default())
+            }
+            // Old raw files don't have timestamps, so assume that samples were evenly spaced
+            None => lane.samples as f64 * micros(self.interval),
+        };
+        // Traces from different threads can arrive slightly out of order
+        let ts = ts.max(lane.last);
+
+        let frames: Vec<&StackFrame> = stack.trace.iter().rev().collect();
+        let common = lane
+            .open
+            .iter()
+            .zip(&frames)
+            .take_while(|((open, _, _), frame)| same_function(open, frame))
+            .count();
+        close(&mut self.events, lane, pid, tid, common, ts);
+        lane.open.extend(
+            frames[common..]
+                .iter()
+                .map(|&frame| (frame.clone(), ts, stack.monotonic_ns)),
+        );
+        lane.last = ts;
+        lane.samples += 1;
+        if stack.thread_name.is_some() {
+            lane.name = stack.thread_name.clone();
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, w: &mut dyn Write) -> Result<()> {
+        let mut keys: Vec<(Pid, u64)> = self.lanes.keys().cloned().collect();
+        keys.sort();
+        for (pid, tid) in keys {
+            let lane = self.lanes.get_mut(&(pid, tid)).unwrap();
+            let end = lane.last + micros(self.interval);
+            close(&mut self.events, lane, pid, tid, 0, end);
+            if let Some(name) = &lane.name {
+                self.events.push(Event {
+                    name: "thread_name".to_string(),
+                    ph: "M",
+                    ts: 0.0,
+                    dur: None,
+                    pid,
+                    tid,
+                    args: serde_json::json!({ "name": name }),
+                });
+            }
+        }
+        let events = std::mem::take(&mut self.events);
+        serde_json::to_writer(
+            &mut *w,
+            &serde_json::json!({ "traceEvents": events, "displayTimeUnit": "ms" }),
+        )?;
+        writeln!(w)?;
+        Ok(())
+    }
+}
+
+// Ends the calls in `lane` after the first `keep` at `ts`, innermost first
+fn close(events: &mut Vec<Event>, lane: &mut Lane, pid: Pid, tid: u64, keep: usize, ts: f64) {
+    while lane.open.len() > keep {
+        let (frame, start, monotonic_ns) = lane.open.pop().unwrap();
+        let mut args = serde_json::json!({ "file": frame.path(), "line": frame.lineno });
+        if let Some(ns) = monotonic_ns {
+            args["monotonic_ns"] = ns.into();
+        }
+        events.push(Event {
+            name: frame.name.clone(),
+            ph: "X",
+            ts: start,
+            dur: Some(ts - start),
+            pid,
+            tid,
+            args,
+        });
+    }
+}
+
+// The line number changes as a function runs, so it's not part of what identifies a call
+fn same_function(a: &StackFrame, b: &StackFrame) -> bool {
+    a.name == b.name && a.path() == b.path()
+}
+
+fn micros(d: Duration) -> f64 {
+    d.as_secs_f64() * 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ui::chrometrace::*;
+
+    fn f(name: &str, line: usize) -> StackFrame {
+        StackFrame {
+            name: name.to_string(),
+            relative_path: "a.rb".to_string(),
+            absolute_path: None,
+            lineno: Some(line),
+        }
+    }
+
+    fn trace(ms: Option<u64>, thread_id: u64, frames: Vec<StackFrame>) -> StackTrace {
+        StackTrace {
+            trace: frames,
+            pid: Some(1),
+            thread_id: Some(thread_id),
+            time: ms.map(|ms| SystemTime::UNIX_EPOCH + Duration::from_millis(ms)),
+            on_cpu: None,
+            holds_gvl: None,
+            thread_name: None,
+            rss: None,
+            monotonic_ns: None,
+        }
+    }
+
+    // (name, tid, start, duration) for each complete event, in milliseconds
+    fn spans(stats: &mut Stats) -> Vec<(String, u64, f64, f64)> {
+        let mut buf = vec![];
+        stats.write(&mut buf).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        let mut spans: Vec<(String, u64, f64, f64)> = json["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|e| e["ph"] == "X")
+            .map(|e| {
+                (
+                    e["name"].as_str().unwrap().to_string(),
+                    e["tid"].as_u64().unwrap(),
+                    e["ts"].as_f64().unwrap() / 1000.0,
+                    e["dur"].as_f64().unwrap() / 1000.0,
+                )
+            })
+            .collect();
+        spans.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        spans
+    }
+
+    #[test]
+    fn test_durations_from_timestamps() {
+        let mut stats = Stats::new(Duration::from_millis(10));
+        stats
+            .record(&trace(Some(1000), 1, vec![f("b", 2), f("a", 1)]))
+            .unwrap();
+        stats
+            .record(&trace(Some(1010), 1, vec![f("b", 3), f("a", 1)]))
+            .unwrap();
+        stats
+            .record(&trace(Some(1015), 2, vec![f("a", 1)]))
+            .unwrap();
+        stats
+            .record(&trace(Some(1020), 1, vec![f("c", 5), f("a", 1)]))
+            .unwrap();
+        assert_eq!(
+            spans(&mut stats),
+            vec![
+                ("a".to_string(), 1, 0.0, 30.0),
+                ("a".to_string(), 2, 15.0, 10.0),
+                ("b".to_string(), 1, 0.0, 20.0),
+                ("c".to_string(), 1, 20.0, 10.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_durations_without_timestamps() {
+        let mut stats = Stats::new(Duration::from_millis(10));
+        stats
+            .record(&trace(None, 1, vec![f("b", 2), f("a", 1)]))
+            .unwrap();
+        stats.record(&trace(None, 1, vec![f("a", 1)])).unwrap();
+        assert_eq!(
+            spans(&mut stats),
+            vec![
+                ("a".to_string(), 1, 0.0, 20.0),
+                ("b".to_string(), 1, 0.0, 10.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_thread_names() {
+        let mut stats = Stats::new(Duration::from_millis(10));
+        let mut named = trace(Some(0), 1, vec![f("a", 1)]);
+        named.thread_name = Some("worker".to_string());
+        stats.record(&named).unwrap();
+        let mut buf = vec![];
+        stats.write(&mut buf).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        let metadata: Vec<&serde_json::Value> = json["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|e| e["ph"] == "M")
+            .collect();
+        assert_eq!(metadata.len(), 1);
+        assert_eq!(metadata[0]["args"]["name"], "worker");
+        assert_eq!(metadata[0]["tid"], 1);
+    }
+
+    #[test]
+    fn test_monotonic_timestamps() {
+        let mut stats = Stats::new(Duration::from_millis(10));
+        let mut first = trace(Some(0), 1, vec![f("b", 2), f("a", 1)]);
+        first.monotonic_ns = Some(5_000_000);
+        let mut second = trace(Some(10), 1, vec![f("c", 3), f("a", 1)]);
+        second.monotonic_ns = Some(15_000_000);
+        stats.record(&first).unwrap();
+        stats.record(&second).unwrap();
+        stats.record(&trace(Some(20), 1, vec![f("d", 4)])).unwrap();
+        let mut buf = vec![];
+        stats.write(&mut buf).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        let starts: HashMap<&str, &serde_json::Value> = json["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| (e["name"].as_str().unwrap(), &e["args"]["monotonic_ns"]))
+            .collect();
+        // Each call has the timestamp of the sample where it started
+        assert_eq!(starts["a"], 5_000_000);
+        assert_eq!(starts["b"], 5_000_000);
+        assert_eq!(starts["c"], 15_000_000);
+        assert!(starts["d"].is_null());
+    }
+}
@@ -0,0 +1,218 @@
+use anyhow::{format_err, Result};
+use inferno::flamegraph::{Direction, Options};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::Write;
+
+use crate::core::types::{DiffFormat, StackFrame};
+use crate::ui::flamegraph;
+
+/*
+ * Compares the stacks of two recordings ("before" and "after"). The recordings usually didn't
+ * run for the same amount of time or at the same sample rate, so the counts of the recording with
+ * fewer samples are scaled up until both recordings have the same number of samples. That way a
+ * stack that took the same share of time in both recordings shows no difference.
+ */
+
+#[derive(Default)]
+pub struct Stats {
+    before: flamegraph::Stats,
+    after: flamegraph::Stats,
+    before_total: usize,
+    after_total: usize,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Default::default()
+    }
+
+    pub fn record_before(&mut self, stack: &[StackFrame]) -> Result<()> {
+        self.before_total += 1;
+        self.before.record(stack)
+    }
+
+    pub fn record_after(&mut self, stack: &[StackFrame]) -> Result<()> {
+        self.after_total += 1;
+        self.after.record(stack)
+    }
+
+    pub fn write(&self, w: &mut dyn Write, format: DiffFormat, min_width: f64) -> Result<()> {
+        if self.before_total == 0 || self.after_total == 0 {
+            return Err(format_err!(
+                "Can't compare recordings because one of them has no samples"
+            ));
+        }
+        match format {
+            DiffFormat::flamegraph => self.write_flamegraph(w, min_width),
+            DiffFormat::collapsed => self.write_collapsed(w),
+            DiffFormat::summary => self.write_summary(w),
+        }
+    }
+
+    // Multipliers for the before and after counts that give both the same total
+    fn scales(&self) -> (f64, f64) {
+        let total = self.before_total.max(self.after_total) as f64;
+        (
+            total / self.before_total as f64,
+            total / self.after_total as f64,
+        )
+    }
+
+    // Lines in the differential folded format: `stack before_count after_count`
+    fn get_lines(&self) -> Vec<String> {
+        let (before_scale, after_scale) = self.scales();
+        let stacks: BTreeSet<&String> = self
+            .before
+            .counts
+            .keys()
+            .chain(self.after.counts.keys())
+            .collect();
+        stacks
+            .into_iter()
+            .map(|stack| {
+                let before = self.before.counts.get(stack).copied().unwrap_or(0);
+                let after = self.after.counts.get(stack).copied().unwrap_or(0);
+                format!(
+                    "{} {} {}",
+                    stack,
+                    (before as f64 * before_scale).round(),
+                    (after as f64 * after_scale).round()
+                )
+            })
+            .collect()
+    }
+
+    fn write_collapsed(&self, w: &mut dyn Write) -> Result<()> {
+        for line in self.get_lines() {
+            writeln!(w, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    fn write_flamegraph(&self, w: &mut dyn Write, min_width: f64) -> Result<()> {
+        let mut opts = Options::default();
+        opts.direction = Direction::Inverted;
+        opts.min_width = min_width;
+        opts.title = "Differential Flame Graph".to_string();
+        opts.subtitle = Some("Red frames grew and blue frames shrank".to_string());
+        let lines = self.get_lines();
+        inferno::flamegraph::from_lines(&mut opts, lines.iter().map(|x| x.as_str()), w)?;
+        Ok(())
+    }
+
+    // Compares the percentage of samples that each function was running in, largest changes first
+    fn write_summary(&self, w: &mut dyn Write) -> Result<()> {
+        let before = self_percentages(&self.before.counts, self.before_total);
+        let after = self_percentages(&self.after.counts, self.after_total);
+        let names: HashSet<&String> = before.keys().chain(after.keys()).collect();
+        let mut rows: Vec<(f64, f64, &String)> = names
+            .into_iter()
+            .map(|name| {
+                (
+                    before.get(name).copied().unwrap_or(0.0),
+                    after.get(name).copied().unwrap_or(0.0),
+                    name,
+                )
+            })
+            .collect();
+        rows.sort_by(|a, b| {
+            (b.1 - b.0)
+                .abs()
+                .partial_cmp(&(a.1 - a.0).abs())
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.2.cmp(b.2))
+        });
+
+        writeln!(w, "% self before  % self after   change  name")?;
+        for (before, after, name) in rows {
+            writeln!(
+                w,
+                "{:>13.2} {:>13.2} {:>+8.2}  {}",
+                before,
+                after,
+                after - before,
+                name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// The percentage of samples in which each function was the innermost frame
+fn self_percentages(counts: &HashMap<String, usize>, total: usize) -> HashMap<String, f64> {
+    let mut percentages: HashMap<String, f64> = HashMap::new();
+    for (stack, count) in counts {
+        let name = stack.rsplit(';').next().unwrap_or_default().to_string();
+        *percentages.entry(name).or_insert(0.0) += 100.0 * *count as f64 / total as f64;
+    }
+    percentages
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ui::diff::*;
+
+    // Build a test stackframe
+    fn f(i: usize) -> StackFrame {
+        StackFrame {
+            name: format!("func{}", i),
+            relative_path: format!("file{}.rb", i),
+            absolute_path: None,
+            lineno: Some(i),
+        }
+    }
+
+    // The after recording is twice as long, and func3 takes a bigger share of it
+    fn build_stats() -> Stats {
+        let mut stats = Stats::new();
+        stats.record_before(&[f(2), f(1)]).unwrap();
+        stats.record_before(&[f(2), f(1)]).unwrap();
+        stats.record_before(&[f(3), f(1)]).unwrap();
+        stats.record_before(&[f(1)]).unwrap();
+        for _ in 0..4 {
+            stats.record_after(&[f(2), f(1)]).unwrap();
+        }
+        for _ in 0..4 {
+            stats.record_after(&[f(3), f(1)]).unwrap();
+        }
+        stats
+    }
+
+    fn write(stats: &Stats, format: DiffFormat) -> String {
+        let mut buf: Vec<u8> = Vec::new();
+        stats.write(&mut buf, format, 0.1).expect("write failed");
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_collapsed_is_normalized() {
+        let expected = "func1 - file1.rb:1 2 0
+func1 - file1.rb:1;func2 - file2.rb:2 4 4
+func1 - file1.rb:1;func3 - file3.rb:3 2 4
+";
+        assert_eq!(write(&build_stats(), DiffFormat::collapsed), expected);
+    }
+
+    #[test]
+    fn test_summary() {
+        let expected = "% self before  % self after   change  name
+        25.00          0.00   -25.00  func1 - file1.rb:1
+        25.00         50.00   +25.00  func3 - file3.rb:3
+        50.00         50.00    +0.00  func2 - file2.rb:2
+";
+        assert_eq!(write(&build_stats(), DiffFormat::summary), expected);
+    }
+
+    #[test]
+    fn test_flamegraph() {
+        assert!(write(&build_stats(), DiffFormat::flamegraph).contains("<svg"));
+    }
+
+    #[test]
+    fn test_empty_recording() {
+        let mut stats = Stats::new();
+        stats.record_before(&[f(1)]).unwrap();
+        let mut buf: Vec<u8> = Vec::new();
+        assert!(stats.write(&mut buf, DiffFormat::summary, 0.1).is_err());
+    }
+}
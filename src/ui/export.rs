@@ -0,0 +1,246 @@
+use anyhow::{format_err, Context, Result};
+use std::io::{Read, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::time::Duration;
+
+use crate::ui::summary::FunctionStats;
+
+/*
+ * Live export of the functions that are using the most time while recording, for continuous
+ * profiling dashboards. Each function gets two gauges: the percentage of samples in which it was
+ * running (self) and the percentage in which it was on the stack (total).
+ */
+
+/// Sends function stats to a metrics system
+pub trait Exporter: Send {
+    fn export(&mut self, functions: &[FunctionStats]) -> Result<()>;
+}
+
+/// Creates an exporter from a URL like `statsd://localhost:8125` or
+/// `prometheus-pushgateway://localhost:9091`. A pushgateway URL can include the path to push to,
+/// which defaults to `/metrics/job/rbspy`.
+pub fn from_url(url: &str) -> Result<Box<dyn Exporter>> {
+    let (scheme, rest) = url
+        .split_once("://")
+        .ok_or_else(|| format_err!("Export URL {} doesn't have a scheme", url))?;
+    let (address, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, ""),
+    };
+    if address.is_empty() {
+        return Err(format_err!("Export URL {} doesn't have a host", url));
+    }
+    match scheme {
+        "statsd" => Ok(Box::new(Statsd::new(address)?)),
+        "prometheus-pushgateway" => Ok(Box::new(Pushgateway {
+            address: address.to_string(),
+            path: match path {
+                "" | "/" => "/metrics/job/rbspy".to_string(),
+                path => path.to_string(),
+            },
+        })),
+        _ => Err(format_err!(
+            "Unknown export scheme {}; expected statsd or prometheus-pushgateway",
+            scheme
+        )),
+    }
+}
+
+pub struct Statsd {
+    socket: UdpSocket,
+}
+
+impl Statsd {
+    pub fn new(address: &str) -> Result<Statsd> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket
+            .connect(address)
+            .context(format!("Failed to resolve StatsD address {}", address))?;
+        Ok(Statsd { socket })
+    }
+
+    fn lines(functions: &[FunctionStats]) -> Vec<String> {
+        functions
+            .iter()
+            .flat_map(|f| {
+                let name = metric_name(&f.name);
+                [
+                    format!(
+                        "rbspy.function.{}.self_percent:{:.2}|g",
+                        name, f.self_percent
+                    ),
+                    format!(
+                        "rbspy.function.{}.total_percent:{:.2}|g",
+                        name, f.total_percent
+                    ),
+                ]
+            })
+            .collect()
+    }
+}
+
+impl Exporter for Statsd {
+    fn export(&mut self, functions: &[FunctionStats]) -> Result<()> {
+        // One metric per packet keeps packets well under the usual MTU
+        for line in Statsd::lines(functions) {
+            self.socket.send(line.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Pushgateway {
+    address: String,
+    path: String,
+}
+
+impl Pushgateway {
+    fn body(functions: &[FunctionStats]) -> String {
+        let mut body = String::new();
+        for (metric, value) in [
+            (
+                "rbspy_function_self_percent",
+                (|f: &FunctionStats| f.self_percent) as fn(&FunctionStats) -> f64,
+            ),
+            ("rbspy_function_total_percent", |f| f.total_percent),
+        ] {
+            body.push_str(&format!("# TYPE {} gauge\n", metric));
+            for f in functions {
+                body.push_str(&format!(
+                    "{}{{function=\"{}\"}} {:.2}\n",
+                    metric,
+                    label_value(&f.name),
+                    value(f)
+                ));
+            }
+        }
+        body
+    }
+}
+
+impl Exporter for Pushgateway {
+    fn export(&mut self, functions: &[FunctionStats]) -> Result<()> {
+        // PUT replaces all of the metrics in the group, so functions that dropped out of the top
+        // list don't linger
+        let body = Pushgateway::body(functions);
+        let mut stream = TcpStream::connect(&self.address)
+            .context(format!("Failed to connect to pushgateway {}", self.address))?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        write!(
+            stream,
+            "PUT {} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.address,
+            body.len(),
+            body
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let status = response.lines().next().unwrap_or_default();
+        match status.split(' ').nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(format_err!("Pushgateway responded with {:?}", status)),
+        }
+    }
+}
+
+// StatsD metric names are dot-separated, and some servers don't allow much besides letters,
+// numbers and underscores
+fn metric_name(name: &str) -> String {
+    let mut metric = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            metric.push(c);
+        } else if !metric.ends_with('_') {
+            metric.push('_');
+        }
+    }
+    metric.trim_matches('_').to_string()
+}
+
+fn label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ui::export::*;
+    use std::net::TcpListener;
+
+    fn functions() -> Vec<FunctionStats> {
+        vec![
+            FunctionStats {
+                name: "block in work - app/\"worker\".rb:3".to_string(),
+                self_percent: 60.0,
+                total_percent: 75.5,
+            },
+            FunctionStats {
+                name: "<main> - app.rb:1".to_string(),
+                self_percent: 0.0,
+                total_percent: 100.0,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_from_url() {
+        assert!(from_url("statsd://127.0.0.1:8125").is_ok());
+        assert!(from_url("prometheus-pushgateway://localhost:9091").is_ok());
+        assert!(from_url("localhost:8125").is_err());
+        assert!(from_url("http://localhost:8125").is_err());
+        assert!(from_url("statsd://").is_err());
+    }
+
+    #[test]
+    fn test_statsd() {
+        assert_eq!(
+            Statsd::lines(&functions()),
+            vec![
+                "rbspy.function.block_in_work_app_worker_rb_3.self_percent:60.00|g",
+                "rbspy.function.block_in_work_app_worker_rb_3.total_percent:75.50|g",
+                "rbspy.function.main_app_rb_1.self_percent:0.00|g",
+                "rbspy.function.main_app_rb_1.total_percent:100.00|g",
+            ]
+        );
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let url = format!("statsd://{}", server.local_addr().unwrap());
+        from_url(&url).unwrap().export(&functions()[1..]).unwrap();
+        let mut buf = [0; 100];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"rbspy.function.main_app_rb_1.self_percent:0.00|g"
+        );
+    }
+
+    #[test]
+    fn test_pushgateway() {
+        assert_eq!(
+            Pushgateway::body(&functions()),
+            "# TYPE rbspy_function_self_percent gauge
+rbspy_function_self_percent{function=\"block in work - app/\\\"worker\\\".rb:3\"} 60.00
+rbspy_function_self_percent{function=\"<main> - app.rb:1\"} 0.00
+# TYPE rbspy_function_total_percent gauge
+rbspy_function_total_percent{function=\"block in work - app/\\\"worker\\\".rb:3\"} 75.50
+rbspy_function_total_percent{function=\"<main> - app.rb:1\"} 100.00
+"
+        );
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("prometheus-pushgateway://{}", server.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = server.accept().unwrap();
+            let mut request = vec![0; 4096];
+            let len = stream.read(&mut request).unwrap();
+            stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+            String::from_utf8_lossy(&request[..len]).into_owned()
+        });
+        from_url(&url).unwrap().export(&functions()).unwrap();
+        let request = handle.join().unwrap();
+        assert!(request.starts_with("PUT /metrics/job/rbspy HTTP/1.1\r\n"));
+    }
+}
@@ -1,30 +1,53 @@
 use anyhow::Result;
+use inferno::flamegraph::color::{Color, PaletteMap};
 use inferno::flamegraph::{Direction, Options};
 use std::collections::HashMap;
 use std::io::Write;
 
-use crate::core::types::StackFrame;
+use crate::core::gems::{self, Origin};
+use crate::core::types::{ColorBy, StackFrame};
 
 // Simple counter that maps stacks to flamegraph collapsed format
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Stats {
     pub counts: HashMap<String, usize>,
+    color_by: ColorBy,
+    // The color of each frame, unless inferno chooses them
+    palette: PaletteMap,
 }
 
 impl Stats {
-    pub fn record(&mut self, stack: &[StackFrame]) -> Result<()> {
-        let frame = stack
-            .iter()
-            .rev()
-            .map(|frame| format!("{}", frame))
-            .collect::<Vec<String>>()
-            .join(";");
+    /// Creates an empty `Stats` whose flamegraph colors its frames as `color_by` says.
+    pub fn with_colors(color_by: ColorBy) -> Stats {
+        Stats {
+            color_by,
+            ..Default::default()
+        }
+    }
 
-        *self.counts.entry(frame).or_insert(0) += 1;
+    pub fn record(&mut self, stack: &[StackFrame]) -> Result<()> {
+        *self.counts.entry(collapse(stack)).or_insert(0) += 1;
+        if self.color_by != ColorBy::none {
+            for frame in stack {
+                let name = frame.to_string();
+                if self.palette.get(&name).is_none() {
+                    self.palette.insert(name, frame_color(frame, self.color_by));
+                }
+            }
+        }
         Ok(())
     }
 
-    pub fn write_flamegraph<W: Write>(&self, w: W, min_width: f64) -> Result<()> {
+    /// Writes the flamegraph as SVG. Inferno's default title is used if `title` isn't given. With
+    /// `reverse`, stacks are merged from the function that was running instead of from the root.
+    pub fn write_flamegraph<W: Write>(
+        &self,
+        w: W,
+        min_width: f64,
+        title: Option<&str>,
+        subtitle: Option<&str>,
+        reverse: bool,
+    ) -> Result<()> {
         if self.is_empty() {
             eprintln!("Warning: no profile samples were collected");
         } else {
@@ -32,6 +55,15 @@ impl Stats {
             opts.direction = Direction::Inverted;
             opts.hash = true;
             opts.min_width = min_width;
+            if let Some(title) = title {
+                opts.title = title.to_string();
+            }
+            opts.subtitle = subtitle.map(|s| s.to_string());
+            opts.reverse_stack_order = reverse;
+            let mut palette = self.palette.clone();
+            if self.color_by != ColorBy::none {
+                opts.palette_map = Some(&mut palette);
+            }
             inferno::flamegraph::from_lines(
                 &mut opts,
                 self.get_lines().iter().map(|x| x.as_str()),
@@ -42,7 +74,7 @@ impl Stats {
         Ok(())
     }
 
-    pub fn write_collapsed<W: Write>(&self, w: &mut W) -> Result<()> {
+    pub fn write_collapsed<W: Write + ?Sized>(&self, w: &mut W) -> Result<()> {
         if self.is_empty() {
             eprintln!("Warning: no profile samples were collected");
         } else {
@@ -53,18 +85,81 @@ impl Stats {
         Ok(())
     }
 
+    // Sorted by stack so that the output is the same every time for the same samples
     fn get_lines(&self) -> Vec<String> {
-        self.counts
+        let mut lines: Vec<String> = self
+            .counts
             .iter()
             .map(|(frame, count)| format!("{} {}", frame, count))
-            .collect()
+            .collect();
+        lines.sort();
+        lines
     }
 
-    fn is_empty(&self) -> bool {
+    pub fn is_empty(&self) -> bool {
         self.counts.is_empty()
     }
 }
 
+/// Joins a stack into a line of collapsed output (without the count), starting from the root
+pub fn collapse(stack: &[StackFrame]) -> String {
+    crate::core::types::fold_frames(stack)
+}
+
+// Colors frames like inferno's palettes do, by the kind of code they're in: red and orange for
+// the application, blue for gems, green for the standard library and yellow for C functions. With
+// `ColorBy::gem`, each gem gets its own shade between blue and purple instead. The shades vary a
+// little with the function, so that neighbouring frames can be told apart.
+fn frame_color(frame: &StackFrame, color_by: ColorBy) -> Color {
+    let name = frame.to_string();
+    let v = |salt| fraction(&name, salt);
+    let rgb = |r: f64, g: f64, b: f64| Color {
+        r: r as u8,
+        g: g as u8,
+        b: b as u8,
+    };
+    match gems::origin(frame) {
+        Origin::Application => rgb(205.0 + 50.0 * v(1), 230.0 * v(2), 55.0 * v(3)),
+        Origin::Gem(gem) if color_by == ColorBy::gem => {
+            let hue = 180.0 + 120.0 * fraction(&gem, 0);
+            hsl(hue, 0.55, 0.55 + 0.15 * v(1))
+        }
+        Origin::Gem(_) => rgb(50.0 + 60.0 * v(1), 110.0 + 60.0 * v(1), 205.0 + 50.0 * v(2)),
+        Origin::Stdlib => rgb(50.0 + 60.0 * v(1), 200.0 + 55.0 * v(1), 50.0 + 60.0 * v(1)),
+        Origin::CFunction => rgb(175.0 + 55.0 * v(1), 175.0 + 55.0 * v(1), 50.0 + 20.0 * v(2)),
+    }
+}
+
+// A number in [0, 1) that depends only on `s` and `salt` (FNV-1a), so colors are the same in
+// every flamegraph
+fn fraction(s: &str, salt: u8) -> f64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in std::iter::once(salt).chain(s.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn hsl(hue: f64, saturation: f64, lightness: f64) -> Color {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let x = chroma * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 / 60 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    Color {
+        r: ((r + m) * 255.0) as u8,
+        g: ((g + m) * 255.0) as u8,
+        b: ((b + m) * 255.0) as u8,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::ui::flamegraph::*;
@@ -117,11 +212,41 @@ mod tests {
         let mut writer = Cursor::new(Vec::<u8>::new());
         stats.write_collapsed(&mut writer)?;
         let collapsed_text = std::str::from_utf8(writer.get_ref())?;
-        assert!(collapsed_text.contains("func1 - file1.rb:1 1"));
-        assert!(
-            collapsed_text.contains("func1 - file1.rb:1;func3 - file3.rb:3;func2 - file2.rb:2 3")
+        assert_eq!(
+            collapsed_text,
+            "func1 - file1.rb:1 1
+func1 - file1.rb:1;func2 - file2.rb:2 2
+func1 - file1.rb:1;func3 - file3.rb:3;func2 - file2.rb:2 3
+"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_collapse() {
+        assert_eq!(
+            collapse(&[f(2), f(3), f(1)]),
+            "func1 - file1.rb:1;func3 - file3.rb:3;func2 - file2.rb:2"
         );
-        assert!(collapsed_text.contains("func1 - file1.rb:1;func2 - file2.rb:2 2"));
+        assert_eq!(collapse(&[]), "");
+    }
+
+    #[test]
+    fn test_reverse_flamegraph() -> Result<()> {
+        let stats = build_stats()?;
+
+        let mut svg = vec![];
+        stats.write_flamegraph(&mut svg, 0.1, None, None, false)?;
+        let svg = String::from_utf8(svg)?;
+        assert!(svg.contains("func2 - file2.rb:2 (3 samples"));
+        assert!(!svg.contains("func2 - file2.rb:2 (5 samples"));
+
+        // func2 was running in 5 of the samples, but it was called from two places
+        let mut svg = vec![];
+        stats.write_flamegraph(&mut svg, 0.1, None, None, true)?;
+        let svg = String::from_utf8(svg)?;
+        assert!(svg.contains("func2 - file2.rb:2 (5 samples"));
 
         Ok(())
     }
@@ -143,4 +268,57 @@ mod tests {
 
         Ok(())
     }
+
+    fn frame_in(name: &str, path: &str) -> StackFrame {
+        StackFrame {
+            name: name.to_string(),
+            relative_path: path.to_string(),
+            absolute_path: Some(path.to_string()),
+            lineno: Some(1),
+        }
+    }
+
+    #[test]
+    fn test_frame_colors() {
+        let app = frame_color(
+            &frame_in("index", "/app/app/controllers/a.rb"),
+            ColorBy::path,
+        );
+        assert!(app.r >= 205);
+        let rack = frame_in("call", "/gems/3.3.0/gems/rack-3.0.8/lib/rack.rb");
+        let gem = frame_color(&rack, ColorBy::path);
+        assert!(gem.b >= 205 && gem.b > gem.r);
+        let stdlib = frame_color(
+            &frame_in("add", "/usr/lib/ruby/3.3.0/set.rb"),
+            ColorBy::path,
+        );
+        assert!(stdlib.g >= 200 && stdlib.g > stdlib.b);
+        let c = frame_color(&StackFrame::unknown_c_function(), ColorBy::path);
+        assert!(c.r >= 175 && c.g >= 175 && c.b <= 70);
+
+        // Each gem gets its own color
+        let puma = frame_in("run", "/gems/3.3.0/gems/puma-6.4.0/lib/puma.rb");
+        assert_ne!(
+            frame_color(&rack, ColorBy::gem),
+            frame_color(&puma, ColorBy::gem)
+        );
+        // Colors don't change between runs
+        assert_eq!(
+            frame_color(&rack, ColorBy::gem),
+            frame_color(&rack, ColorBy::gem)
+        );
+    }
+
+    #[test]
+    fn test_colored_flamegraph() -> Result<()> {
+        let app = frame_in("index", "/app/app/controllers/a.rb");
+        let mut stats = Stats::with_colors(ColorBy::path);
+        stats.record(&[app.clone()])?;
+        let mut svg = vec![];
+        stats.write_flamegraph(&mut svg, 0.1, None, None, false)?;
+        let color = frame_color(&app, ColorBy::path);
+        let fill = format!("rgb({},{},{})", color.r, color.g, color.b);
+        assert!(String::from_utf8(svg)?.contains(&fill));
+        Ok(())
+    }
 }
@@ -1,6 +1,11 @@
 pub mod callgrind;
+pub mod chrometrace;
+pub mod diff;
+#[cfg(feature = "export")]
+pub mod export;
 pub mod flamegraph;
 pub mod output;
 pub mod pprof;
 pub mod speedscope;
 pub mod summary;
+pub mod tree;
@@ -1,36 +1,106 @@
+use std::collections::BTreeSet;
 use std::io::Write;
+use std::time::SystemTime;
 
-use crate::core::types::{StackFrame, StackTrace};
-use crate::ui::{callgrind, flamegraph, pprof, speedscope, summary};
+use crate::core::process::Pid;
+use crate::core::types::{ColorBy, StackFrame, StackTrace, SummaryFormat};
+use crate::ui::{callgrind, chrometrace, flamegraph, pprof, speedscope, summary, tree};
 
 use anyhow::Result;
 
+/// Aggregates stack traces into an output format. Library users can implement this to write
+/// traces somewhere rbspy doesn't support, and pass it to the recorder with
+/// `RecordConfig::outputter`.
 pub trait Outputter {
+    /// Adds a stack trace to the output
     fn record(&mut self, stack: &StackTrace) -> Result<()>;
+    /// Writes the output for all of the recorded stack traces. Called once, after the last trace.
     fn complete(&mut self, write: &mut dyn Write) -> Result<()>;
 }
 
+/// Creates a new, empty `Outputter` each time it's called
+pub type OutputterFactory = Box<dyn Fn() -> Box<dyn Outputter> + Send + Sync>;
+
 // Uses Inferno to visualize stack traces
 pub struct Flamegraph {
     stats: flamegraph::Stats,
     min_width: f64,
+    title: Option<String>,
+    subtitle: Option<String>,
+    reverse: bool,
+    sample_rate: Option<u32>,
+    pids: BTreeSet<Pid>,
+    // The times of the first and last traces
+    times: Option<(SystemTime, SystemTime)>,
 }
 
 impl Outputter for Flamegraph {
     fn record(&mut self, stack: &StackTrace) -> Result<()> {
+        if let Some(pid) = stack.pid {
+            self.pids.insert(pid);
+        }
+        if let Some(time) = stack.time {
+            self.times = Some(match self.times {
+                Some((first, last)) => (first.min(time), last.max(time)),
+                None => (time, time),
+            });
+        }
         self.stats.record(&stack.trace)
     }
 
     fn complete(&mut self, write: &mut dyn Write) -> Result<()> {
-        self.stats.write_flamegraph(write, self.min_width)
+        let subtitle = self.subtitle.clone().or_else(|| self.default_subtitle());
+        self.stats.write_flamegraph(
+            write,
+            self.min_width,
+            self.title.as_deref(),
+            subtitle.as_deref(),
+            self.reverse,
+        )
     }
 }
 
 impl Flamegraph {
-    pub fn new(min_width: f64) -> Flamegraph {
+    pub fn new(
+        min_width: f64,
+        title: Option<String>,
+        subtitle: Option<String>,
+        reverse: bool,
+        color_by: ColorBy,
+        sample_rate: Option<u32>,
+    ) -> Flamegraph {
         Flamegraph {
             min_width,
-            stats: Default::default(),
+            title,
+            subtitle,
+            reverse,
+            sample_rate,
+            stats: flamegraph::Stats::with_colors(color_by),
+            pids: BTreeSet::new(),
+            times: None,
+        }
+    }
+
+    // Describes where the traces came from, so that a flamegraph still makes sense once it's been
+    // shared
+    fn default_subtitle(&self) -> Option<String> {
+        let mut parts = vec![];
+        let pids: Vec<String> = self.pids.iter().map(|pid| pid.to_string()).collect();
+        match pids.len() {
+            0 => {}
+            1 => parts.push(format!("PID {}", pids[0])),
+            _ => parts.push(format!("PIDs {}", pids.join(", "))),
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            parts.push(format!("{} samples per second", sample_rate));
+        }
+        if let Some((first, last)) = self.times {
+            let duration = last.duration_since(first).unwrap_or_default();
+            parts.push(format!("{:.1} seconds", duration.as_secs_f64()));
+        }
+        match parts.is_empty() {
+            true => None,
+            false => Some(parts.join(" | ")),
         }
     }
 }
@@ -64,7 +134,9 @@ impl Outputter for Callgrind {
     }
 }
 
-pub struct Summary(pub summary::Stats);
+// The counts by function, with the table format and how many of the most expensive functions to
+// show (all of them if it's `None`)
+pub struct Summary(pub summary::Stats, pub SummaryFormat, pub Option<usize>);
 
 impl Outputter for Summary {
     fn record(&mut self, stack: &StackTrace) -> Result<()> {
@@ -73,11 +145,72 @@ impl Outputter for Summary {
     }
 
     fn complete(&mut self, mut write: &mut dyn Write) -> Result<()> {
-        self.0.write(&mut write)
+        self.0.write_as(&mut write, self.1, self.2)
+    }
+}
+
+// The summary's counts by function, as CSV with the file and line in their own columns
+pub struct Csv(pub summary::Stats);
+
+impl Outputter for Csv {
+    fn record(&mut self, stack: &StackTrace) -> Result<()> {
+        self.0.add_function_name(&filter_unknown(&stack.trace));
+        Ok(())
+    }
+
+    fn complete(&mut self, mut write: &mut dyn Write) -> Result<()> {
+        self.0.write_functions_csv(&mut write)
+    }
+}
+
+// Each stack trace as a line of JSON, serialized the same way as in raw data files but without
+// their header and thread name records, for tools that want the individual samples
+#[derive(Default)]
+pub struct Jsonl(pub Vec<String>);
+
+impl Outputter for Jsonl {
+    fn record(&mut self, stack: &StackTrace) -> Result<()> {
+        self.0.push(serde_json::to_string(stack)?);
+        Ok(())
+    }
+
+    fn complete(&mut self, write: &mut dyn Write) -> Result<()> {
+        for line in &self.0 {
+            writeln!(write, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+// The process's resident memory over time, as CSV with a row for each trace that has it, for
+// recordings made with `--with-rss`. Times are in seconds since the first trace.
+#[derive(Default)]
+pub struct Rss {
+    start: Option<SystemTime>,
+    rows: Vec<(f64, Option<Pid>, u64)>,
+}
+
+impl Outputter for Rss {
+    fn record(&mut self, stack: &StackTrace) -> Result<()> {
+        if let (Some(rss), Some(time)) = (stack.rss, stack.time) {
+            let start = *self.start.get_or_insert(time);
+            let elapsed = time.duration_since(start).unwrap_or_default();
+            self.rows.push((elapsed.as_secs_f64(), stack.pid, rss));
+        }
+        Ok(())
+    }
+
+    fn complete(&mut self, write: &mut dyn Write) -> Result<()> {
+        writeln!(write, "seconds,pid,rss_bytes")?;
+        for (seconds, pid, rss) in &self.rows {
+            let pid = pid.map(|pid| pid.to_string()).unwrap_or_default();
+            writeln!(write, "{:.3},{},{}", seconds, pid, rss)?;
+        }
+        Ok(())
     }
 }
 
-pub struct SummaryLine(pub summary::Stats);
+pub struct SummaryLine(pub summary::Stats, pub SummaryFormat, pub Option<usize>);
 
 impl Outputter for SummaryLine {
     fn record(&mut self, stack: &StackTrace) -> Result<()> {
@@ -86,7 +219,20 @@ impl Outputter for SummaryLine {
     }
 
     fn complete(&mut self, mut write: &mut dyn Write) -> Result<()> {
-        self.0.write(&mut write)
+        self.0.write_as(&mut write, self.1, self.2)
+    }
+}
+
+pub struct SummaryGem(pub summary::Stats, pub SummaryFormat, pub Option<usize>);
+
+impl Outputter for SummaryGem {
+    fn record(&mut self, stack: &StackTrace) -> Result<()> {
+        self.0.add_gem(&filter_unknown(&stack.trace));
+        Ok(())
+    }
+
+    fn complete(&mut self, mut write: &mut dyn Write) -> Result<()> {
+        self.0.write_as(&mut write, self.1, self.2)
     }
 }
 
@@ -114,6 +260,42 @@ impl Outputter for Pprof {
     }
 }
 
+pub struct Chrometrace(pub chrometrace::Stats);
+
+impl Outputter for Chrometrace {
+    fn record(&mut self, stack: &StackTrace) -> Result<()> {
+        self.0.record(stack)
+    }
+
+    fn complete(&mut self, write: &mut dyn Write) -> Result<()> {
+        self.0.write(write)
+    }
+}
+
+pub struct TreeJson(pub tree::Stats);
+
+impl Outputter for TreeJson {
+    fn record(&mut self, stack: &StackTrace) -> Result<()> {
+        self.0.record(&stack.trace)
+    }
+
+    fn complete(&mut self, write: &mut dyn Write) -> Result<()> {
+        self.0.write(write)
+    }
+}
+
+pub struct D3Flamegraph(pub tree::Stats);
+
+impl Outputter for D3Flamegraph {
+    fn record(&mut self, stack: &StackTrace) -> Result<()> {
+        self.0.record(&stack.trace)
+    }
+
+    fn complete(&mut self, write: &mut dyn Write) -> Result<()> {
+        self.0.write_d3(write)
+    }
+}
+
 /// Filter out unknown functions from stack trace before reporting.
 /// Most of the time it isn't useful to include the "unknown C function" stacks.
 fn filter_unknown(trace: &[StackFrame]) -> Vec<StackFrame> {
@@ -125,3 +307,92 @@ fn filter_unknown(trace: &[StackFrame]) -> Vec<StackFrame> {
         vec
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::ui::output::*;
+    use std::time::Duration;
+
+    fn trace(pid: Pid, time: SystemTime) -> StackTrace {
+        StackTrace {
+            trace: vec![StackFrame::unknown_c_function()],
+            pid: Some(pid),
+            thread_id: None,
+            time: Some(time),
+            on_cpu: None,
+            holds_gvl: None,
+            thread_name: None,
+            rss: None,
+            monotonic_ns: None,
+        }
+    }
+
+    fn flamegraph_svg(title: Option<&str>, subtitle: Option<&str>, pids: &[Pid]) -> String {
+        let mut outputter = Flamegraph::new(
+            0.1,
+            title.map(|s| s.to_string()),
+            subtitle.map(|s| s.to_string()),
+            false,
+            ColorBy::none,
+            Some(100),
+        );
+        let start = SystemTime::UNIX_EPOCH;
+        for (i, pid) in pids.iter().enumerate() {
+            let time = start + Duration::from_millis(1500 * i as u64);
+            outputter.record(&trace(*pid, time)).unwrap();
+        }
+        let mut svg = vec![];
+        outputter.complete(&mut svg).unwrap();
+        String::from_utf8(svg).unwrap()
+    }
+
+    #[test]
+    fn test_flamegraph_titles() {
+        let svg = flamegraph_svg(None, None, &[1234, 1234, 1234]);
+        assert!(svg.contains("PID 1234 | 100 samples per second | 3.0 seconds"));
+
+        let svg = flamegraph_svg(None, None, &[1234, 5678]);
+        assert!(svg.contains("PIDs 1234, 5678 | 100 samples per second | 1.5 seconds"));
+
+        let svg = flamegraph_svg(Some("Checkout"), Some("production"), &[1234]);
+        assert!(svg.contains(">Checkout<"));
+        assert!(svg.contains(">production<"));
+        assert!(!svg.contains("PID 1234"));
+    }
+
+    #[test]
+    fn test_jsonl() {
+        let traces = vec![
+            trace(1234, SystemTime::UNIX_EPOCH),
+            trace(5678, SystemTime::UNIX_EPOCH + Duration::from_millis(10)),
+        ];
+        let mut outputter = Jsonl::default();
+        for trace in &traces {
+            outputter.record(trace).unwrap();
+        }
+        let mut output = vec![];
+        outputter.complete(&mut output).unwrap();
+        let parsed: Vec<StackTrace> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(parsed, traces);
+    }
+
+    #[test]
+    fn test_rss() {
+        let mut outputter = Rss::default();
+        for (ms, rss) in [(1000, Some(1024)), (1250, None), (1500, Some(2048))] {
+            let mut trace = trace(1234, SystemTime::UNIX_EPOCH + Duration::from_millis(ms));
+            trace.rss = rss;
+            outputter.record(&trace).unwrap();
+        }
+        let mut output = vec![];
+        outputter.complete(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "seconds,pid,rss_bytes\n0.000,1234,1024\n0.500,1234,2048\n"
+        );
+    }
+}
@@ -25,13 +25,21 @@ impl Stats {
     pub fn new() -> Stats {
         Stats {
             profile: Profile {
-                // string index 0 must point to "" according to the .proto spec, while "wall" and "nanoseconds" are for our sample_type field
+                // string index 0 must point to "" according to the .proto spec, while the others are for our sample_type field
                 string_table: vec![
                     "".to_string(),
+                    "samples".to_string(),
+                    "count".to_string(),
                     "wall".to_string(),
                     "nanoseconds".to_string(),
                 ],
-                sample_type: vec![ValueType { r#type: 1, unit: 2 }], // 1 and 2 are indexes from string_table
+                // Each sample counts once, and also covers the wall time since the previous sample.
+                // These are indexes into string_table.
+                sample_type: vec![
+                    ValueType { r#type: 1, unit: 2 },
+                    ValueType { r#type: 3, unit: 4 },
+                ],
+                default_sample_type: 3,
                 ..Profile::default()
             },
             ..Stats::default()
@@ -60,7 +68,7 @@ impl Stats {
     fn add_sample(&mut self, stack: &StackTrace, sample_time: i64) {
         let s = Sample {
             location_id: self.location_ids(stack),
-            value: vec![sample_time],
+            value: vec![1, sample_time],
             label: self.labels(stack),
         };
         self.profile.sample.push(s);
@@ -187,6 +195,10 @@ mod test {
             thread_id: Some(999),
             time: Some(time),
             on_cpu: None,
+            holds_gvl: None,
+            thread_name: None,
+            rss: None,
+            monotonic_ns: None,
         }
     }
 
@@ -249,20 +261,23 @@ mod test {
 
         let actual = pprofs::Profile::decode(&*stats_buf).expect("decode failed");
         let expected = Profile {
-            sample_type: vec![ValueType { r#type: 1, unit: 2 }],
+            sample_type: vec![
+                ValueType { r#type: 1, unit: 2 },
+                ValueType { r#type: 3, unit: 4 },
+            ],
             sample: vec![
                 Sample {
                     location_id: vec![1],
-                    value: vec![0],
+                    value: vec![1, 0],
                     label: vec![
                         Label {
-                            key: 5,
+                            key: 7,
                             str: 0,
                             num: 9,
                             num_unit: 0,
                         },
                         Label {
-                            key: 6,
+                            key: 8,
                             str: 0,
                             num: 999,
                             num_unit: 0,
@@ -271,16 +286,16 @@ mod test {
                 },
                 Sample {
                     location_id: vec![2, 3, 1],
-                    value: vec![200],
+                    value: vec![1, 200],
                     label: vec![
                         Label {
-                            key: 5,
+                            key: 7,
                             str: 0,
                             num: 9,
                             num_unit: 0,
                         },
                         Label {
-                            key: 6,
+                            key: 8,
                             str: 0,
                             num: 999,
                             num_unit: 0,
@@ -289,16 +304,16 @@ mod test {
                 },
                 Sample {
                     location_id: vec![3, 1],
-                    value: vec![400],
+                    value: vec![1, 400],
                     label: vec![
                         Label {
-                            key: 5,
+                            key: 7,
                             str: 0,
                             num: 9,
                             num_unit: 0,
                         },
                         Label {
-                            key: 6,
+                            key: 8,
                             str: 0,
                             num: 999,
                             num_unit: 0,
@@ -307,16 +322,16 @@ mod test {
                 },
                 Sample {
                     location_id: vec![2, 1],
-                    value: vec![600],
+                    value: vec![1, 600],
                     label: vec![
                         Label {
-                            key: 5,
+                            key: 7,
                             str: 0,
                             num: 9,
                             num_unit: 0,
                         },
                         Label {
-                            key: 6,
+                            key: 8,
                             str: 0,
                             num: 999,
                             num_unit: 0,
@@ -325,16 +340,16 @@ mod test {
                 },
                 Sample {
                     location_id: vec![3, 1],
-                    value: vec![800],
+                    value: vec![1, 800],
                     label: vec![
                         Label {
-                            key: 5,
+                            key: 7,
                             str: 0,
                             num: 9,
                             num_unit: 0,
                         },
                         Label {
-                            key: 6,
+                            key: 8,
                             str: 0,
                             num: 999,
                             num_unit: 0,
@@ -343,16 +358,16 @@ mod test {
                 },
                 Sample {
                     location_id: vec![2, 4, 1],
-                    value: vec![1000],
+                    value: vec![1, 1000],
                     label: vec![
                         Label {
-                            key: 5,
+                            key: 7,
                             str: 0,
                             num: 9,
                             num_unit: 0,
                         },
                         Label {
-                            key: 6,
+                            key: 8,
                             str: 0,
                             num: 999,
                             num_unit: 0,
@@ -406,35 +421,37 @@ mod test {
             function: vec![
                 Function {
                     id: 1,
-                    name: 3,
+                    name: 5,
                     system_name: 0,
-                    filename: 4,
+                    filename: 6,
                     start_line: 0,
                 },
                 Function {
                     id: 2,
-                    name: 7,
+                    name: 9,
                     system_name: 0,
-                    filename: 8,
+                    filename: 10,
                     start_line: 0,
                 },
                 Function {
                     id: 3,
-                    name: 9,
+                    name: 11,
                     system_name: 0,
-                    filename: 10,
+                    filename: 12,
                     start_line: 0,
                 },
                 Function {
                     id: 4,
-                    name: 11,
+                    name: 13,
                     system_name: 0,
-                    filename: 4,
+                    filename: 6,
                     start_line: 0,
                 },
             ],
             string_table: vec![
                 "".to_string(),
+                "samples".to_string(),
+                "count".to_string(),
                 "wall".to_string(),
                 "nanoseconds".to_string(),
                 "func1".to_string(),
@@ -454,7 +471,7 @@ mod test {
             period_type: None,
             period: 0,
             comment: vec![],
-            default_sample_type: 0,
+            default_sample_type: 3,
         };
         assert_eq!(actual, expected, "stats don't match");
     }
@@ -96,12 +96,10 @@ enum ValueUnit {
 }
 
 impl SpeedscopeFile {
-    pub fn new(
-        samples: HashMap<Option<Pid>, Vec<Vec<usize>>>,
-        frames: Vec<Frame>,
-        weights: Vec<f64>,
-    ) -> SpeedscopeFile {
-        let end_value = samples.len();
+    pub fn new(samples: &HashMap<Option<Pid>, Samples>, frames: Vec<Frame>) -> SpeedscopeFile {
+        // Order profiles by pid so that the output doesn't depend on hash order
+        let mut pids: Vec<&Option<Pid>> = samples.keys().collect();
+        pids.sort();
 
         SpeedscopeFile {
             // This is always the same
@@ -113,22 +111,25 @@ impl SpeedscopeFile {
 
             exporter: Some(format!("rbspy@{}", env!("CARGO_PKG_VERSION"))),
 
-            profiles: samples
-                .iter()
-                .map(|(option_pid, samples)| Profile {
-                    profile_type: ProfileType::Sampled,
+            profiles: pids
+                .into_iter()
+                .map(|option_pid| {
+                    let samples = &samples[option_pid];
+                    Profile {
+                        profile_type: ProfileType::Sampled,
 
-                    name: option_pid.map_or("rbspy profile".to_string(), |pid| {
-                        format!("rbspy profile - pid {}", pid)
-                    }),
+                        name: option_pid.map_or("rbspy profile".to_string(), |pid| {
+                            format!("rbspy profile - pid {}", pid)
+                        }),
 
-                    unit: ValueUnit::Seconds,
+                        unit: ValueUnit::Seconds,
 
-                    start_value: 0.0,
-                    end_value: end_value as f64,
+                        start_value: 0.0,
+                        end_value: samples.weights.iter().sum(),
 
-                    samples: samples.clone(),
-                    weights: weights.clone(),
+                        samples: samples.stacks.clone(),
+                        weights: samples.weights.clone(),
+                    }
                 })
                 .collect(),
 
@@ -148,13 +149,20 @@ impl Frame {
     }
 }
 
+// The samples for a single process, in the order that they were recorded. Each stack has a
+// weight, which is the time since the previous sample from the same process.
+#[derive(Default)]
+struct Samples {
+    stacks: Vec<Vec<usize>>,
+    weights: Vec<f64>,
+    prev_time: Option<SystemTime>,
+}
+
 #[derive(Default)]
 pub struct Stats {
-    samples: HashMap<Option<Pid>, Vec<Vec<usize>>>,
+    samples: HashMap<Option<Pid>, Samples>,
     frames: Vec<Frame>,
     frame_to_index: HashMap<StackFrame, usize>,
-    weights: Vec<f64>,
-    prev_time: Option<SystemTime>,
 }
 
 impl Stats {
@@ -177,35 +185,85 @@ impl Stats {
             .collect();
         frame_indices.reverse();
 
-        self.samples
-            .entry(stack.pid)
-            .or_insert_with(|| vec![])
-            .push(frame_indices);
+        let samples = self.samples.entry(stack.pid).or_default();
+        samples.stacks.push(frame_indices);
 
         if let Some(time) = stack.time {
-            if let Some(prev_time) = self.prev_time {
-                let delta = time.duration_since(prev_time)?;
-                self.weights.push(delta.as_secs_f64());
+            if let Some(prev_time) = samples.prev_time {
+                // Traces from different threads can arrive slightly out of order
+                let delta = time.duration_since(prev_time).unwrap_or_default();
+                samples.weights.push(delta.as_secs_f64());
             } else {
                 // drop first sample, since we have no delta to compare against
-                self.weights.push(0.0);
+                samples.weights.push(0.0);
             }
-            self.prev_time = stack.time;
+            samples.prev_time = samples.prev_time.max(stack.time);
         } else {
             // support for import from old profiles that have no timestamps
-            self.weights.push(1.0);
+            samples.weights.push(1.0);
         }
 
         Ok(())
     }
 
     pub fn write(&self, mut w: &mut dyn Write) -> Result<()> {
-        let json = serde_json::to_string(&SpeedscopeFile::new(
-            self.samples.clone(),
-            self.frames.clone(),
-            self.weights.clone(),
-        ))?;
+        let json = serde_json::to_string(&SpeedscopeFile::new(&self.samples, self.frames.clone()))?;
         writeln!(&mut w, "{}", json)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::ui::speedscope::*;
+
+    fn f(i: usize) -> StackFrame {
+        StackFrame {
+            name: format!("func{}", i),
+            relative_path: format!("file{}.rb", i),
+            absolute_path: None,
+            lineno: Some(i),
+        }
+    }
+
+    fn trace(pid: Pid, ms: u64, frames: Vec<StackFrame>) -> StackTrace {
+        StackTrace {
+            trace: frames,
+            pid: Some(pid),
+            thread_id: None,
+            time: Some(SystemTime::UNIX_EPOCH + Duration::from_millis(ms)),
+            on_cpu: None,
+            holds_gvl: None,
+            thread_name: None,
+            rss: None,
+            monotonic_ns: None,
+        }
+    }
+
+    #[test]
+    fn test_weights_per_process() {
+        let mut stats = Stats::new();
+        stats.record(&trace(2, 1000, vec![f(2), f(1)])).unwrap();
+        stats.record(&trace(1, 1000, vec![f(1)])).unwrap();
+        stats.record(&trace(2, 1500, vec![f(1)])).unwrap();
+        stats.record(&trace(1, 1250, vec![f(3), f(1)])).unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        stats.write(&mut buf).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+
+        let profiles = json["profiles"].as_array().unwrap();
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[0]["name"], "rbspy profile - pid 1");
+        assert_eq!(profiles[0]["samples"], serde_json::json!([[1], [1, 2]]));
+        assert_eq!(profiles[0]["weights"], serde_json::json!([0.0, 0.25]));
+        assert_eq!(profiles[0]["endValue"], 0.25);
+        assert_eq!(profiles[1]["name"], "rbspy profile - pid 2");
+        assert_eq!(profiles[1]["samples"], serde_json::json!([[1, 0], [1]]));
+        assert_eq!(profiles[1]["weights"], serde_json::json!([0.0, 0.5]));
+        assert_eq!(profiles[1]["endValue"], 0.5);
+        assert_eq!(json["shared"]["frames"].as_array().unwrap().len(), 3);
+    }
+}
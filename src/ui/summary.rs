@@ -2,15 +2,29 @@ use anyhow::Result;
 use std::collections::{HashMap, HashSet};
 use std::io;
 
-use crate::core::types::StackFrame;
+use crate::core::gems;
+use crate::core::types::{StackFrame, SummaryFormat};
 
 struct Counts {
     self_: u64,
     total: u64,
+    // The frame that the name was made from, if the name is for a single function or line
+    frame: Option<StackFrame>,
+}
+
+/// How much time was spent in a function, as percentages of all samples
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FunctionStats {
+    pub name: String,
+    /// Percentage of samples where the function was running
+    pub self_percent: f64,
+    /// Percentage of samples where the function was anywhere on the stack
+    pub total_percent: f64,
 }
 
 pub struct Stats {
     counts: HashMap<String, Counts>,
+    threads: HashMap<String, u64>,
     start_time: std::time::Instant,
     total_traces: u32,
 }
@@ -21,24 +35,27 @@ impl Stats {
     pub fn new() -> Stats {
         Stats {
             counts: HashMap::new(),
+            threads: HashMap::new(),
             start_time: std::time::Instant::now(),
             total_traces: 0,
         }
     }
 
-    fn inc_self(&mut self, name: String) {
-        let entry = self
-            .counts
-            .entry(name)
-            .or_insert(Counts { self_: 0, total: 0 });
+    fn inc_self(&mut self, name: String, frame: Option<&StackFrame>) {
+        let entry = self.counts.entry(name).or_insert_with(|| Counts {
+            self_: 0,
+            total: 0,
+            frame: frame.cloned(),
+        });
         entry.self_ += 1;
     }
 
-    fn inc_tot(&mut self, name: String) {
-        let entry = self
-            .counts
-            .entry(name)
-            .or_insert(Counts { self_: 0, total: 0 });
+    fn inc_tot(&mut self, name: String, frame: Option<&StackFrame>) {
+        let entry = self.counts.entry(name).or_insert_with(|| Counts {
+            self_: 0,
+            total: 0,
+            frame: frame.cloned(),
+        });
         entry.total += 1;
     }
 
@@ -60,13 +77,13 @@ impl Stats {
             return;
         }
         self.total_traces += 1;
-        self.inc_self(Stats::name_function(&stack[0]));
-        let mut set: HashSet<String> = HashSet::new();
+        self.inc_self(Stats::name_function(&stack[0]), Some(&stack[0]));
+        let mut set: HashMap<String, &StackFrame> = HashMap::new();
         for frame in stack {
-            set.insert(Stats::name_function(frame));
+            set.entry(Stats::name_function(frame)).or_insert(frame);
         }
-        for name in set.into_iter() {
-            self.inc_tot(name);
+        for (name, frame) in set.into_iter() {
+            self.inc_tot(name, Some(frame));
         }
     }
 
@@ -76,14 +93,56 @@ impl Stats {
             return;
         }
         self.total_traces += 1;
-        self.inc_self(Stats::name_lineno(&stack[0]));
+        self.inc_self(Stats::name_lineno(&stack[0]), Some(&stack[0]));
         let mut set: HashSet<&StackFrame> = HashSet::new();
         for frame in stack {
             set.insert(&frame);
         }
         for frame in set {
-            self.inc_tot(Stats::name_lineno(frame));
+            self.inc_tot(Stats::name_lineno(frame), Some(frame));
+        }
+    }
+
+    // Aggregate by the gem that each frame belongs to
+    pub fn add_gem(&mut self, stack: &[StackFrame]) {
+        if stack.is_empty() {
+            return;
+        }
+        self.total_traces += 1;
+        self.inc_self(gems::group_name(&stack[0]), None);
+        let set: HashSet<String> = stack.iter().map(gems::group_name).collect();
+        for name in set.into_iter() {
+            self.inc_tot(name, None);
+        }
+    }
+
+    // Count samples by thread, using `StackTrace::thread_label`
+    pub fn add_thread(&mut self, label: String) {
+        *self.threads.entry(label).or_insert(0) += 1;
+    }
+
+    /// Writes the threads with the most samples on one line, if samples came from more than one
+    /// thread
+    pub fn write_top_threads(&self, w: &mut dyn io::Write, n: usize) -> Result<()> {
+        if self.threads.len() < 2 {
+            return Ok(());
         }
+        let total: u64 = self.threads.values().sum();
+        let mut threads: Vec<(&u64, &String)> = self
+            .threads
+            .iter()
+            .map(|(name, count)| (count, name))
+            .collect();
+        threads.sort_unstable_by(|a, b| b.cmp(a));
+        let top: Vec<String> = threads
+            .iter()
+            .take(n)
+            .map(|(count, name)| {
+                format!("{} ({:.0}%)", name, 100.0 * **count as f64 / total as f64)
+            })
+            .collect();
+        writeln!(w, "Busiest threads: {}", top.join(", "))?;
+        Ok(())
     }
 
     pub fn write(&self, w: &mut dyn io::Write) -> Result<()> {
@@ -99,10 +158,54 @@ impl Stats {
         self.write_counts(w, Some(n), truncate)
     }
 
+    /// Writes the counts as a table in the given format, e.g. for pasting into an issue. Only the
+    /// `top` most expensive names are written, if it's given.
+    pub fn write_as(
+        &self,
+        w: &mut dyn io::Write,
+        format: SummaryFormat,
+        top: Option<usize>,
+    ) -> Result<()> {
+        match format {
+            SummaryFormat::text => self.write_counts(w, top, None),
+            SummaryFormat::markdown => self.write_markdown(w, top),
+            SummaryFormat::csv => self.write_csv(w, top),
+        }
+    }
+
+    /// The `n` functions with the most samples, most expensive first
+    pub fn top_functions(&self, n: usize) -> Vec<FunctionStats> {
+        self.sorted_counts()
+            .into_iter()
+            .take(n)
+            .map(|(self_, total, name)| FunctionStats {
+                name: name.to_string(),
+                self_percent: self.percent(self_),
+                total_percent: self.percent(total),
+            })
+            .collect()
+    }
+
     pub fn elapsed_time(&self) -> std::time::Duration {
         std::time::Instant::now() - self.start_time
     }
 
+    // Sorted from most to least expensive
+    fn sorted_counts(&self) -> Vec<(u64, u64, &str)> {
+        let mut sorted: Vec<(u64, u64, &str)> = self
+            .counts
+            .iter()
+            .map(|(x, y)| (y.self_, y.total, x.as_ref()))
+            .collect();
+        sorted.sort_unstable();
+        sorted.reverse();
+        sorted
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        100.0 * (count as f64) / f64::from(self.total_traces)
+    }
+
     fn write_counts(
         &self,
         w: &mut dyn io::Write,
@@ -110,27 +213,94 @@ impl Stats {
         truncate: Option<usize>,
     ) -> Result<()> {
         let top = top.unwrap_or(::std::usize::MAX);
-        let truncate = truncate.unwrap_or(::std::usize::MAX);
-        let mut sorted: Vec<(u64, u64, &str)> = self
-            .counts
-            .iter()
-            .map(|(x, y)| (y.self_, y.total, x.as_ref()))
-            .collect();
-        sorted.sort_unstable();
-        let counts = sorted.iter().rev().take(top);
+        // The formatter's precision can't be arbitrarily large, so don't truncate names at all
+        // unless we were asked to
+        let truncate = truncate.map_or(u16::MAX as usize, |t| t.saturating_sub(14 + 3));
         writeln!(w, "{}", Stats::HEADER)?;
-        for &(self_, total, name) in counts {
+        for (self_, total, name) in self.sorted_counts().into_iter().take(top) {
             writeln!(
                 w,
                 "{:>6.2} {:>8.2}  {:.*}",
-                100.0 * (self_ as f64) / f64::from(self.total_traces),
-                100.0 * (total as f64) / f64::from(self.total_traces),
-                truncate - 14 - 3,
+                self.percent(self_),
+                self.percent(total),
+                truncate,
                 name
             )?;
         }
         Ok(())
     }
+
+    fn write_markdown(&self, w: &mut dyn io::Write, top: Option<usize>) -> Result<()> {
+        writeln!(w, "| % self | % total | name |")?;
+        writeln!(w, "| -----: | ------: | ---- |")?;
+        let top = top.unwrap_or(usize::MAX);
+        for (self_, total, name) in self.sorted_counts().into_iter().take(top) {
+            writeln!(
+                w,
+                "| {:.2} | {:.2} | `{}` |",
+                self.percent(self_),
+                self.percent(total),
+                name.replace('|', "\\|").replace('`', "'")
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes all counts as CSV, with the function's name, file and line in separate columns so
+    /// that they're easy to work with in a spreadsheet. The file and line are empty for names
+    /// that aren't for a single function, e.g. gems.
+    pub fn write_functions_csv(&self, w: &mut dyn io::Write) -> Result<()> {
+        writeln!(
+            w,
+            "function,self_samples,total_samples,self_percent,total_percent,relative_path,lineno"
+        )?;
+        for (self_, total, name) in self.sorted_counts() {
+            let frame = self.counts[name].frame.as_ref();
+            writeln!(
+                w,
+                "{},{},{},{:.2},{:.2},{},{}",
+                csv_field(frame.map_or(name, |f| &f.name)),
+                self_,
+                total,
+                self.percent(self_),
+                self.percent(total),
+                csv_field(frame.map_or("", |f| &f.relative_path)),
+                frame
+                    .and_then(|f| f.lineno)
+                    .map_or("".to_string(), |l| l.to_string())
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_csv(&self, w: &mut dyn io::Write, top: Option<usize>) -> Result<()> {
+        writeln!(
+            w,
+            "self_samples,total_samples,self_percent,total_percent,name"
+        )?;
+        let top = top.unwrap_or(usize::MAX);
+        for (self_, total, name) in self.sorted_counts().into_iter().take(top) {
+            writeln!(
+                w,
+                "{},{},{:.2},{:.2},{}",
+                self_,
+                total,
+                self.percent(self_),
+                self.percent(total),
+                csv_field(name)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Quotes a CSV field if needed, as described in RFC 4180
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
 }
 
 #[cfg(test)]
@@ -190,4 +360,147 @@ mod tests {
         let actual = String::from_utf8(buf).expect("summary output not utf8");
         assert_eq!(actual, expected, "Unexpected summary output");
     }
+
+    #[test]
+    fn stats_top_n_truncated() {
+        let mut stats = Stats::new();
+
+        stats.add_function_name(&vec![f(2), f(1)]);
+        stats.add_function_name(&vec![f(1)]);
+
+        let expected = "% self  % total  name
+ 50.00   100.00  func1 - f
+ 50.00    50.00  func2 - f
+";
+
+        let mut buf: Vec<u8> = Vec::new();
+        stats
+            .write_top_n(&mut buf, 5, Some(26))
+            .expect("summary write failed");
+        let actual = String::from_utf8(buf).expect("summary output not utf8");
+        assert_eq!(actual, expected, "Unexpected summary output");
+    }
+
+    #[test]
+    fn stats_top_threads() {
+        let mut stats = Stats::new();
+        let mut buf: Vec<u8> = Vec::new();
+
+        stats.add_thread("puma srv".to_string());
+        stats.write_top_threads(&mut buf, 2).unwrap();
+        assert!(buf.is_empty());
+
+        stats.add_thread("12345".to_string());
+        stats.add_thread("puma srv".to_string());
+        stats.add_thread("puma srv".to_string());
+        stats.add_thread("unknown".to_string());
+        stats.write_top_threads(&mut buf, 2).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "Busiest threads: puma srv (60%), unknown (20%)\n"
+        );
+    }
+
+    #[test]
+    fn stats_as_markdown() {
+        let mut stats = Stats::new();
+        let mut odd = f(3);
+        odd.name = "a|b".to_string();
+
+        stats.add_function_name(&vec![f(2), f(1)]);
+        stats.add_function_name(&vec![odd, f(1)]);
+
+        let expected = "| % self | % total | name |
+| -----: | ------: | ---- |
+| 50.00 | 50.00 | `func2 - file2.rb:2` |
+| 50.00 | 50.00 | `a\\|b - file3.rb:3` |
+| 0.00 | 100.00 | `func1 - file1.rb:1` |
+";
+
+        let mut buf: Vec<u8> = Vec::new();
+        stats
+            .write_as(&mut buf, SummaryFormat::markdown, None)
+            .expect("summary write failed");
+        let actual = String::from_utf8(buf).expect("summary output not utf8");
+        assert_eq!(actual, expected, "Unexpected summary output");
+    }
+
+    #[test]
+    fn stats_as_csv() {
+        let mut stats = Stats::new();
+        let mut odd = f(3);
+        odd.name = "a,\"b\"".to_string();
+
+        stats.add_function_name(&vec![f(2), f(1)]);
+        stats.add_function_name(&vec![odd, f(1)]);
+
+        let expected = "self_samples,total_samples,self_percent,total_percent,name
+1,1,50.00,50.00,func2 - file2.rb:2
+1,1,50.00,50.00,\"a,\"\"b\"\" - file3.rb:3\"
+0,2,0.00,100.00,func1 - file1.rb:1
+";
+
+        let mut buf: Vec<u8> = Vec::new();
+        stats
+            .write_as(&mut buf, SummaryFormat::csv, None)
+            .expect("summary write failed");
+        let actual = String::from_utf8(buf).expect("summary output not utf8");
+        assert_eq!(actual, expected, "Unexpected summary output");
+    }
+
+    #[test]
+    fn stats_as_functions_csv() {
+        let mut stats = Stats::new();
+        let mut odd = f(3);
+        odd.name = "a,b".to_string();
+        odd.lineno = None;
+
+        stats.add_function_name(&vec![f(2), f(1)]);
+        stats.add_function_name(&vec![odd, f(1)]);
+
+        let expected =
+            "function,self_samples,total_samples,self_percent,total_percent,relative_path,lineno
+func2,1,1,50.00,50.00,file2.rb,2
+\"a,b\",1,1,50.00,50.00,file3.rb,
+func1,0,2,0.00,100.00,file1.rb,1
+";
+
+        let mut buf: Vec<u8> = Vec::new();
+        stats
+            .write_functions_csv(&mut buf)
+            .expect("summary write failed");
+        let actual = String::from_utf8(buf).expect("summary output not utf8");
+        assert_eq!(actual, expected, "Unexpected summary output");
+    }
+
+    #[test]
+    fn stats_by_gem() {
+        let mut stats = Stats::new();
+        let gem = |name: &str| StackFrame {
+            name: "call".to_string(),
+            relative_path: format!("/gems/3.3.0/gems/{}/lib/{}.rb", name, name),
+            absolute_path: None,
+            lineno: Some(1),
+        };
+
+        stats.add_gem(&vec![gem("rack-3.0.8"), f(1)]);
+        stats.add_gem(&vec![gem("activerecord-7.1.2"), gem("rack-3.0.8"), f(1)]);
+        stats.add_gem(&vec![
+            gem("activerecord-7.1.2"),
+            gem("activerecord-7.1.2"),
+            f(1),
+        ]);
+        stats.add_gem(&vec![f(2), f(1)]);
+
+        let expected = "% self  % total  name
+ 50.00    50.00  activerecord
+ 25.00   100.00  (application)
+ 25.00    50.00  rack
+";
+
+        let mut buf: Vec<u8> = Vec::new();
+        stats.write(&mut buf).expect("summary write failed");
+        let actual = String::from_utf8(buf).expect("summary output not utf8");
+        assert_eq!(actual, expected, "Unexpected summary output");
+    }
 }
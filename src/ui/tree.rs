@@ -0,0 +1,264 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::core::types::StackFrame;
+
+/*
+ * Aggregates stack traces into a call tree and writes it as JSON. Every node has the number of
+ * samples in which it was the innermost frame (`self_count`), the number of samples that passed
+ * through it (`total_count`), and its callees. The root node represents all samples.
+ *
+ * The same tree can also be written in the format of the d3-flame-graph JavaScript library
+ * (https://github.com/spiermar/d3-flame-graph), where each node only has a `name`, a `value`
+ * (its `total_count`) and its `children`.
+ */
+
+// Frames are merged by name, file and line
+type NodeKey = (String, Option<String>, Option<usize>);
+
+#[derive(Debug, Serialize)]
+struct Node {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    self_count: usize,
+    total_count: usize,
+    children: Vec<Node>,
+    #[serde(skip)]
+    child_indexes: HashMap<NodeKey, usize>,
+}
+
+impl Node {
+    fn new(name: String, file: Option<String>, line: Option<usize>) -> Node {
+        Node {
+            name,
+            file,
+            line,
+            self_count: 0,
+            total_count: 0,
+            children: Vec::new(),
+            child_indexes: HashMap::new(),
+        }
+    }
+
+    fn key(&self) -> NodeKey {
+        (self.name.clone(), self.file.clone(), self.line)
+    }
+
+    fn child(&mut self, frame: &StackFrame) -> &mut Node {
+        let key = (
+            frame.name.clone(),
+            Some(frame.relative_path.clone()),
+            frame.lineno,
+        );
+        let index = match self.child_indexes.get(&key) {
+            Some(&index) => index,
+            None => {
+                self.children
+                    .push(Node::new(key.0.clone(), key.1.clone(), key.2));
+                self.child_indexes.insert(key, self.children.len() - 1);
+                self.children.len() - 1
+            }
+        };
+        &mut self.children[index]
+    }
+
+    // Put the most expensive callees first so that the output is stable and easy to skim
+    fn sort(&mut self) {
+        self.children.sort_by(|a, b| {
+            b.total_count
+                .cmp(&a.total_count)
+                .then_with(|| a.name.cmp(&b.name))
+                .then_with(|| a.file.cmp(&b.file))
+                .then_with(|| a.line.cmp(&b.line))
+        });
+        self.child_indexes.clear();
+        for (index, child) in self.children.iter_mut().enumerate() {
+            self.child_indexes.insert(child.key(), index);
+            child.sort();
+        }
+    }
+}
+
+// A node of the tree in d3-flame-graph's format
+#[derive(Debug, Serialize)]
+struct D3Node {
+    name: String,
+    value: usize,
+    children: Vec<D3Node>,
+}
+
+impl D3Node {
+    fn new(node: &Node) -> D3Node {
+        let name = match (&node.file, node.line) {
+            (Some(file), Some(line)) => format!("{} - {}:{}", node.name, file, line),
+            (Some(file), None) => format!("{} - {}", node.name, file),
+            _ => node.name.clone(),
+        };
+        // Sort callees alphabetically, like flamegraphs do, so that frames stay in the same place
+        // from one recording to the next
+        let mut children: Vec<&Node> = node.children.iter().collect();
+        children.sort_by(|a, b| {
+            a.name
+                .cmp(&b.name)
+                .then_with(|| a.file.cmp(&b.file))
+                .then_with(|| a.line.cmp(&b.line))
+        });
+        D3Node {
+            name,
+            value: node.total_count,
+            children: children.into_iter().map(D3Node::new).collect(),
+        }
+    }
+}
+
+pub struct Stats {
+    root: Node,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats::new()
+    }
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            root: Node::new("(root)".to_string(), None, None),
+        }
+    }
+
+    pub fn record(&mut self, stack: &[StackFrame]) -> Result<()> {
+        let mut node = &mut self.root;
+        node.total_count += 1;
+        for frame in stack.iter().rev() {
+            node = node.child(frame);
+            node.total_count += 1;
+        }
+        node.self_count += 1;
+        Ok(())
+    }
+
+    pub fn write(&mut self, w: &mut dyn Write) -> Result<()> {
+        self.root.sort();
+        serde_json::to_writer(&mut *w, &self.root)?;
+        writeln!(w)?;
+        Ok(())
+    }
+
+    pub fn write_d3(&self, w: &mut dyn Write) -> Result<()> {
+        serde_json::to_writer(&mut *w, &D3Node::new(&self.root))?;
+        writeln!(w)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ui::tree::*;
+
+    // Build a test stackframe
+    fn f(i: usize) -> StackFrame {
+        StackFrame {
+            name: format!("func{}", i),
+            relative_path: format!("file{}.rb", i),
+            absolute_path: None,
+            lineno: Some(i),
+        }
+    }
+
+    #[test]
+    fn test_tree_json() {
+        let mut stats = Stats::new();
+        stats.record(&vec![f(1)]).unwrap();
+        stats.record(&vec![f(3), f(2), f(1)]).unwrap();
+        stats.record(&vec![f(2), f(1)]).unwrap();
+        stats.record(&vec![f(3), f(1)]).unwrap();
+        stats.record(&vec![f(2), f(1)]).unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        stats.write(&mut buf).expect("write failed");
+        let actual: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        let expected = serde_json::json!({
+            "name": "(root)",
+            "self_count": 0,
+            "total_count": 5,
+            "children": [{
+                "name": "func1",
+                "file": "file1.rb",
+                "line": 1,
+                "self_count": 1,
+                "total_count": 5,
+                "children": [
+                    {
+                        "name": "func2",
+                        "file": "file2.rb",
+                        "line": 2,
+                        "self_count": 2,
+                        "total_count": 3,
+                        "children": [{
+                            "name": "func3",
+                            "file": "file3.rb",
+                            "line": 3,
+                            "self_count": 1,
+                            "total_count": 1,
+                            "children": [],
+                        }],
+                    },
+                    {
+                        "name": "func3",
+                        "file": "file3.rb",
+                        "line": 3,
+                        "self_count": 1,
+                        "total_count": 1,
+                        "children": [],
+                    },
+                ],
+            }],
+        });
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_d3_flamegraph() {
+        let mut stats = Stats::new();
+        stats.record(&vec![f(1)]).unwrap();
+        stats.record(&vec![f(3), f(1)]).unwrap();
+        stats.record(&vec![f(3), f(2), f(1)]).unwrap();
+        stats.record(&vec![f(3), f(1)]).unwrap();
+
+        let mut buf: Vec<u8> = Vec::new();
+        stats.write_d3(&mut buf).expect("write failed");
+        let actual: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        // Children are sorted by name rather than by value
+        let expected = serde_json::json!({
+            "name": "(root)",
+            "value": 4,
+            "children": [{
+                "name": "func1 - file1.rb:1",
+                "value": 4,
+                "children": [
+                    {
+                        "name": "func2 - file2.rb:2",
+                        "value": 1,
+                        "children": [{
+                            "name": "func3 - file3.rb:3",
+                            "value": 1,
+                            "children": [],
+                        }],
+                    },
+                    {
+                        "name": "func3 - file3.rb:3",
+                        "value": 2,
+                        "children": [],
+                    },
+                ],
+            }],
+        });
+        assert_eq!(actual, expected);
+    }
+}